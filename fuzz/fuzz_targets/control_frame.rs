@@ -0,0 +1,22 @@
+//! 任意输入按 JSON / MessagePack / CBOR 解析为控制消息：不得 panic，结果须在长度上限内
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+#[path = "../../src/control_frame.rs"]
+#[allow(dead_code)]
+mod control_frame;
+
+use control_frame::{ControlFrame, MAX_TARGET_LEN, MAX_VERSIONS};
+
+fuzz_target!(|data: &[u8]| {
+    let json = std::str::from_utf8(data).ok().and_then(ControlFrame::from_json);
+    let frames = [json, ControlFrame::from_msgpack(data), ControlFrame::from_cbor(data)];
+    for frame in frames.into_iter().flatten() {
+        match frame {
+            ControlFrame::Switch { target } => assert!(target.len() <= MAX_TARGET_LEN),
+            ControlFrame::Hello { versions } => assert!(versions.len() <= MAX_VERSIONS),
+            ControlFrame::Ack { .. } => {}
+        }
+    }
+});
@@ -0,0 +1,129 @@
+//! 访问日志
+//!
+//! 与运行日志分开，写入独立的滚动文件 `<dir>/<prefix>.<日期>.log`。
+//! 每个 WS 会话结束时、每个 REST 请求完成时各记录一行：
+//!
+//! ```json
+//! {"ts":"2026-01-01T00:00:00.000Z","session_id":"3f2a...","kind":"ws","user":"alice","client_ip":"1.2.3.4","country":"CN","target":"wss://...","duration_ms":1234,"bytes_up":100,"bytes_down":2048,"close_reason":"client_closed"}
+//! ```
+//!
+//! `/ws` 会话另有 `frames_up`、`frames_down`、`msgs_per_sec_avg`、`msgs_per_sec_peak`、`close_initiator`（见 [`SessionSummary`]，仅 JSON 格式）。
+//! `country` 仅在配置了 `[geoip]` 且查到国家时输出。`format = "text"` 时为空格分隔的同序字段（`country` 缺失时为 `-`）。REST 请求的 `close_reason` 为响应状态码。
+
+use std::{io::Write, net::IpAddr};
+
+use anyhow::Result;
+use chrono::{SecondsFormat, Utc};
+use rand::Rng;
+use serde::Serialize;
+use tracing::info;
+use tracing_appender::{
+    non_blocking::{NonBlocking, WorkerGuard},
+    rolling::{RollingFileAppender, Rotation},
+};
+
+use crate::{
+    config::{AccessLogConfig, AccessLogFormat, LogRotation},
+    stats::SessionSummary,
+};
+
+/// 一条访问记录
+#[derive(Serialize)]
+pub struct AccessRecord<'a> {
+    pub session_id: &'a str,
+    pub kind: &'static str,
+    pub user: &'a str,
+    pub client_ip: IpAddr,
+    /// 客户端所在国家/地区（配置了 `[geoip]` 时）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub country: Option<&'static str>,
+    pub target: &'a str,
+    pub duration_ms: u64,
+    pub bytes_up: u64,
+    pub bytes_down: u64,
+    pub close_reason: &'a str,
+    /// 会话统计摘要（`/ws` 会话）
+    #[serde(flatten)]
+    pub summary: Option<SessionSummary>,
+}
+
+#[derive(Serialize)]
+struct Entry<'a> {
+    ts: String,
+    #[serde(flatten)]
+    record: &'a AccessRecord<'a>,
+}
+
+impl Entry<'_> {
+    fn to_text(&self) -> String {
+        let r = self.record;
+        format!(
+            "{} {} {} {} {} {} {} {} {} {} {}",
+            self.ts,
+            r.session_id,
+            r.kind,
+            r.user,
+            r.client_ip,
+            r.country.unwrap_or("-"),
+            r.target,
+            r.duration_ms,
+            r.bytes_up,
+            r.bytes_down,
+            r.close_reason
+        )
+    }
+}
+
+/// 访问日志写入器（写文件在后台线程中进行）
+pub struct AccessLog {
+    format: AccessLogFormat,
+    writer: NonBlocking,
+    _guard: WorkerGuard,
+}
+
+impl AccessLog {
+    pub fn new(config: &AccessLogConfig) -> Result<Self> {
+        let rotation = match config.rotation {
+            LogRotation::Hourly => Rotation::HOURLY,
+            LogRotation::Daily => Rotation::DAILY,
+            LogRotation::Never => Rotation::NEVER,
+        };
+        let mut builder = RollingFileAppender::builder()
+            .rotation(rotation)
+            .filename_prefix(&config.prefix)
+            .filename_suffix("log");
+        if let Some(n) = config.max_files {
+            builder = builder.max_log_files(n);
+        }
+        let appender = builder.build(&config.dir)?;
+        let (writer, guard) = tracing_appender::non_blocking(appender);
+
+        info!("访问日志: {}/{}.*.log", config.dir, config.prefix);
+        Ok(Self {
+            format: config.format,
+            writer,
+            _guard: guard,
+        })
+    }
+
+    pub fn record(&self, record: &AccessRecord) {
+        let entry = Entry {
+            ts: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            record,
+        };
+        let mut line = match self.format {
+            AccessLogFormat::Json => match serde_json::to_string(&entry) {
+                Ok(l) => l,
+                Err(_) => return,
+            },
+            AccessLogFormat::Text => entry.to_text(),
+        };
+        line.push('\n');
+        let _ = self.writer.clone().write_all(line.as_bytes());
+    }
+}
+
+/// 生成会话 ID（16 位十六进制）
+pub fn new_session_id() -> String {
+    format!("{:016x}", rand::thread_rng().gen::<u64>())
+}
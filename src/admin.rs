@@ -0,0 +1,560 @@
+//! 管理 API
+//!
+//! 所有请求需携带 Header `X-Admin-Token`。用户修改需要配置 `users_db`，
+//! 仅使用配置文件时用户列表只读。`auth_mode = "hashed"` 时 API 收发明文 token，库中存摘要。
+//! `/admin/stats/*` 提供消息大小分布、按吞吐排序的活跃会话、最近异常与缓冲池命中率。
+//! `/admin/maintenance` 切换维护模式（POST 进入、DELETE 退出），维护中拒绝新会话，现有会话不受影响。
+//! `/admin/bans/{name}` 封禁（POST）/ 解封（DELETE）用户，配置 `[cluster]` 时同步到所有节点。
+//! `/admin/drain`、`/admin/users/{name}/drain` 排空整个节点 / 单个用户：拒绝新会话，
+//! 带 `grace_secs` 时到期终止剩余会话。
+//! `/admin/trace/users/{name}`、`/admin/trace/sessions/{id}` 开启（POST）/ 关闭（DELETE）消息级跟踪。
+
+use axum::{
+    extract::{ConnectInfo, Path, Query, Request, State},
+    http::StatusCode,
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
+    Json, Router,
+};
+use chrono::{DateTime, Duration, Utc};
+use rand::{distributions::Alphanumeric, Rng};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::{error, info, warn};
+
+use std::net::SocketAddr;
+
+use crate::{
+    audit::AuditEvent,
+    auth::hash_token,
+    buffer_pool, capture,
+    config::{AuthMode, ExtraToken, User},
+    error, logging,
+    secret::SecretString,
+    state::AppState,
+    stats::TerminateReason,
+};
+
+/// 生成的 token 长度
+const TOKEN_LEN: usize = 32;
+
+/// 管理路由
+pub fn router(state: AppState) -> Router<AppState> {
+    Router::new()
+        .route("/admin/users", get(list_users).post(create_user))
+        .route("/admin/users/{name}", delete(delete_user))
+        .route("/admin/users/{name}/disable", post(disable_user))
+        .route("/admin/users/{name}/enable", post(enable_user))
+        .route("/admin/users/{name}/rotate", post(rotate_token))
+        .route("/admin/users/{name}/drain", post(drain_user).delete(undrain_user))
+        .route("/admin/stats/messages", get(message_sizes))
+        .route("/admin/stats/top", get(top_talkers))
+        .route("/admin/stats/errors", get(recent_errors))
+        .route("/admin/stats/buffers", get(buffer_stats))
+        .route("/admin/stats/capture", get(capture_stats))
+        .route(
+            "/admin/maintenance",
+            get(maintenance_status).post(enter_maintenance).delete(leave_maintenance),
+        )
+        .route("/admin/drain", get(drain_status).post(drain_node).delete(undrain_node))
+        .route("/admin/trace", get(list_traced))
+        .route("/admin/trace/users/{name}", post(trace_user).delete(untrace_user))
+        .route("/admin/trace/sessions/{id}", post(trace_session).delete(untrace_session))
+        .route("/admin/bans", get(list_bans))
+        .route("/admin/bans/{name}", post(ban_user).delete(unban_user))
+        .route_layer(middleware::from_fn_with_state(state, auth))
+}
+
+/// 管理 token 校验，每次调用记入审计日志
+async fn auth(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let expected = state.config.admin.as_ref().map(|a| &a.token);
+    let token = req.headers().get("x-admin-token").and_then(|v| v.to_str().ok());
+    let method = req.method().to_string();
+    let path = req.uri().path().to_string();
+
+    let response = match (expected, token) {
+        (Some(e), Some(t)) if e.matches(t) => next.run(req).await,
+        _ => StatusCode::UNAUTHORIZED.into_response(),
+    };
+    state.audit(&AuditEvent::Admin {
+        client_ip: addr.ip(),
+        method: &method,
+        path: &path,
+        status: response.status().as_u16(),
+    });
+    response
+}
+
+/// GET /admin/users
+async fn list_users(State(state): State<AppState>) -> Response {
+    blocking(move || {
+        let users = match state.user_db {
+            Some(ref db) => match db.load_all() {
+                Ok(u) => u,
+                Err(e) => return internal_error(e),
+            },
+            None => state.config.users.clone(),
+        };
+        Json(users.into_iter().map(redact).collect::<Vec<_>>()).into_response()
+    })
+    .await
+}
+
+/// POST /admin/users，body 为用户字段（不提供 token 时自动生成）
+async fn create_user(State(state): State<AppState>, Json(body): Json<Value>) -> Response {
+    blocking(move || insert_user(&state, body)).await
+}
+
+fn insert_user(state: &AppState, mut body: Value) -> Response {
+    let Some(db) = state.user_db.as_deref() else {
+        return no_user_db();
+    };
+
+    if let Some(obj) = body.as_object_mut() {
+        obj.entry("token").or_insert_with(|| generate_token().into());
+    }
+    let mut user: User = match serde_json::from_value(body) {
+        Ok(u) => u,
+        Err(e) => return error::response(StatusCode::BAD_REQUEST, "BAD_REQUEST", &e.to_string()),
+    };
+    let token = user.token.clone();
+    store_tokens(state.config.auth_mode, &mut user);
+
+    if let Err(e) = db.insert(&user) {
+        return if is_constraint_violation(&e) {
+            error::response(StatusCode::CONFLICT, "USER_EXISTS", "用户名或 token 已存在")
+        } else {
+            internal_error(e)
+        };
+    }
+
+    info!("管理 API: 新增用户 {}", user.name);
+    apply(state, json!({ "name": user.name, "token": token }))
+}
+
+/// DELETE /admin/users/{name}
+async fn delete_user(State(state): State<AppState>, Path(name): Path<String>) -> Response {
+    let Some(db) = state.user_db.clone() else {
+        return no_user_db();
+    };
+
+    blocking(move || match db.delete(&name) {
+        Ok(true) => {
+            info!("管理 API: 删除用户 {}", name);
+            apply(&state, json!({ "name": name }))
+        }
+        Ok(false) => user_not_found(),
+        Err(e) => internal_error(e),
+    })
+    .await
+}
+
+/// POST /admin/users/{name}/disable
+async fn disable_user(State(state): State<AppState>, Path(name): Path<String>) -> Response {
+    set_disabled(state, name, true).await
+}
+
+/// POST /admin/users/{name}/enable
+async fn enable_user(State(state): State<AppState>, Path(name): Path<String>) -> Response {
+    set_disabled(state, name, false).await
+}
+
+async fn set_disabled(state: AppState, name: String, disabled: bool) -> Response {
+    blocking(move || {
+        modify_user(
+            &state,
+            &name,
+            |u| u.disabled = disabled,
+            |u| json!({ "name": u.name, "disabled": u.disabled }),
+        )
+    })
+    .await
+}
+
+#[derive(Deserialize)]
+struct RotateQuery {
+    /// 旧 token 继续有效的秒数
+    #[serde(default)]
+    grace_secs: u64,
+}
+
+/// POST /admin/users/{name}/rotate?grace_secs=3600，返回新 token；
+/// `grace_secs` 大于 0 时旧 token 移入 `tokens` 并在到期后失效
+async fn rotate_token(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Query(q): Query<RotateQuery>,
+) -> Response {
+    let token = generate_token();
+    let stored = stored_token(state.config.auth_mode, &token);
+    let now = Utc::now();
+    let expires_at = i64::try_from(q.grace_secs)
+        .ok()
+        .and_then(Duration::try_seconds)
+        .and_then(|d| now.checked_add_signed(d))
+        .unwrap_or(DateTime::<Utc>::MAX_UTC);
+    blocking(move || {
+        modify_user(
+            &state,
+            &name,
+            |u| {
+                u.tokens.retain(|t| t.expires_at().is_none_or(|at| at > now));
+                let old = std::mem::replace(&mut u.token, stored);
+                if q.grace_secs > 0 && !old.is_empty() {
+                    u.tokens.push(ExtraToken::Expiring { token: old, expires_at });
+                }
+            },
+            |u| json!({ "name": u.name, "token": token }),
+        )
+    })
+    .await
+}
+
+/// 在阻塞线程池中执行用户库操作（rusqlite 为同步 I/O，不能占用运行时工作线程）
+async fn blocking(f: impl FnOnce() -> Response + Send + 'static) -> Response {
+    tokio::task::spawn_blocking(f)
+        .await
+        .unwrap_or_else(|e| internal_error(e.into()))
+}
+
+/// 读取-修改-写回单个用户，`respond` 生成成功时的响应体（在 [`blocking`] 中调用）
+fn modify_user(
+    state: &AppState,
+    name: &str,
+    f: impl FnOnce(&mut User),
+    respond: impl FnOnce(&User) -> Value,
+) -> Response {
+    let Some(db) = state.user_db.as_deref() else {
+        return no_user_db();
+    };
+
+    let mut user = match db.get(name) {
+        Ok(Some(u)) => u,
+        Ok(None) => return user_not_found(),
+        Err(e) => return internal_error(e),
+    };
+    f(&mut user);
+
+    match db.update(&user) {
+        Ok(true) => {
+            info!("管理 API: 更新用户 {}", name);
+            apply(state, respond(&user))
+        }
+        Ok(false) => user_not_found(),
+        Err(e) => internal_error(e),
+    }
+}
+
+/// GET /admin/stats/messages，每个用户的 text / binary 消息大小分布
+async fn message_sizes(State(state): State<AppState>) -> Response {
+    Json(state.stats.message_sizes()).into_response()
+}
+
+#[derive(Deserialize)]
+struct TopQuery {
+    #[serde(default = "default_top_limit")]
+    limit: usize,
+}
+
+fn default_top_limit() -> usize {
+    10
+}
+
+/// GET /admin/stats/top?limit=10，按平均吞吐降序的活跃会话
+async fn top_talkers(State(state): State<AppState>, Query(q): Query<TopQuery>) -> Response {
+    Json(state.stats.top_talkers(q.limit)).into_response()
+}
+
+/// GET /admin/stats/errors，最近异常结束的会话与 5xx 请求，新的在前
+async fn recent_errors(State(state): State<AppState>) -> Response {
+    Json(state.stats.recent_errors()).into_response()
+}
+
+/// GET /admin/stats/buffers，消息缓冲池命中率
+async fn buffer_stats() -> Response {
+    Json(buffer_pool::snapshot()).into_response()
+}
+
+/// GET /admin/stats/capture，会话录制写入与丢弃的帧数
+async fn capture_stats() -> Response {
+    Json(capture::snapshot()).into_response()
+}
+
+/// GET /admin/maintenance
+async fn maintenance_status(State(state): State<AppState>) -> Response {
+    Json(json!({ "maintenance": state.health.in_maintenance() })).into_response()
+}
+
+/// POST /admin/maintenance
+async fn enter_maintenance(State(state): State<AppState>) -> Response {
+    if !state.health.set_maintenance(true) {
+        warn!("管理 API: 进入维护模式，拒绝新会话");
+    }
+    Json(json!({ "maintenance": true })).into_response()
+}
+
+/// DELETE /admin/maintenance
+async fn leave_maintenance(State(state): State<AppState>) -> Response {
+    if state.health.set_maintenance(false) {
+        info!("管理 API: 退出维护模式");
+    }
+    Json(json!({ "maintenance": false })).into_response()
+}
+
+#[derive(Deserialize)]
+struct DrainQuery {
+    /// 到期后终止剩余会话，不设置则等待会话自然结束
+    grace_secs: Option<u64>,
+}
+
+/// GET /admin/drain
+async fn drain_status(State(state): State<AppState>) -> Response {
+    Json(json!({
+        "node": state.health.in_maintenance(),
+        "users": state.health.drained_users(),
+        "sessions": state.stats.session_count(),
+    }))
+    .into_response()
+}
+
+/// POST /admin/drain?grace_secs=300，进入维护模式，到期终止剩余会话
+async fn drain_node(State(state): State<AppState>, Query(q): Query<DrainQuery>) -> Response {
+    let id = state.health.drain_node();
+    warn!("管理 API: 排空节点，拒绝新会话");
+    if let Some(grace) = q.grace_secs {
+        schedule_termination(
+            &state,
+            grace,
+            move |s| s.health.node_drain_is(id),
+            |s| s.stats.terminate_all(TerminateReason::Drained),
+            "节点".to_string(),
+        );
+    }
+    let sessions = state.stats.session_count();
+    Json(json!({ "node": true, "sessions": sessions, "grace_secs": q.grace_secs })).into_response()
+}
+
+/// DELETE /admin/drain，退出维护模式并取消计划的终止
+async fn undrain_node(State(state): State<AppState>) -> Response {
+    if state.health.set_maintenance(false) {
+        info!("管理 API: 取消节点排空");
+    }
+    Json(json!({ "node": false })).into_response()
+}
+
+/// POST /admin/users/{name}/drain?grace_secs=300，拒绝该用户的新会话，到期终止剩余会话
+async fn drain_user(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Query(q): Query<DrainQuery>,
+) -> Response {
+    let id = state.health.drain_user(&name);
+    warn!("管理 API: 排空用户 {}，拒绝其新会话", name);
+    if let Some(grace) = q.grace_secs {
+        let user = name.clone();
+        let check = user.clone();
+        schedule_termination(
+            &state,
+            grace,
+            move |s| s.health.user_drain_is(&check, id),
+            move |s| s.stats.terminate_user(&user, TerminateReason::Drained),
+            format!("用户 {}", name),
+        );
+    }
+    let sessions = state.stats.user_sessions(&name);
+    Json(json!({ "name": name, "draining": true, "sessions": sessions, "grace_secs": q.grace_secs })).into_response()
+}
+
+/// DELETE /admin/users/{name}/drain，恢复接受新会话并取消计划的终止
+async fn undrain_user(State(state): State<AppState>, Path(name): Path<String>) -> Response {
+    if state.health.undrain_user(&name) {
+        info!("管理 API: 取消排空用户 {}", name);
+    }
+    Json(json!({ "name": name, "draining": false })).into_response()
+}
+
+/// `grace_secs` 后若排空仍有效（未取消、未重新排空）则终止剩余会话
+fn schedule_termination(
+    state: &AppState,
+    grace_secs: u64,
+    still_draining: impl Fn(&AppState) -> bool + Send + 'static,
+    terminate: impl Fn(&AppState) -> usize + Send + 'static,
+    scope: String,
+) {
+    let state = state.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(std::time::Duration::from_secs(grace_secs)).await;
+        if still_draining(&state) {
+            let n = terminate(&state);
+            warn!("{}: 排空期限已到，终止 {} 个活跃会话", scope, n);
+        }
+    });
+}
+
+/// GET /admin/bans
+async fn list_bans(State(state): State<AppState>) -> Response {
+    let cluster = &state.cluster;
+    Json(json!({
+        "users": cluster.banned_users(),
+        "cluster": { "enabled": cluster.is_enabled(), "connected": cluster.is_connected() },
+    }))
+    .into_response()
+}
+
+/// POST /admin/bans/{name}，立即终止本节点上该用户的会话
+async fn ban_user(State(state): State<AppState>, Path(name): Path<String>) -> Response {
+    state.cluster.set_banned(&name, true);
+    let n = state.stats.terminate_user(&name, TerminateReason::Banned);
+    warn!("管理 API: 封禁用户 {}，终止 {} 个活跃会话", name, n);
+    Json(json!({ "name": name, "banned": true, "terminated": n })).into_response()
+}
+
+/// DELETE /admin/bans/{name}
+async fn unban_user(State(state): State<AppState>, Path(name): Path<String>) -> Response {
+    state.cluster.set_banned(&name, false);
+    info!("管理 API: 解封用户 {}", name);
+    Json(json!({ "name": name, "banned": false })).into_response()
+}
+
+/// GET /admin/trace，开启了消息级跟踪的用户与会话
+async fn list_traced() -> Response {
+    Json(logging::traced()).into_response()
+}
+
+/// POST /admin/trace/users/{name}
+async fn trace_user(Path(name): Path<String>) -> Response {
+    logging::trace_user(&name, true);
+    info!("管理 API: 开启用户 {} 的消息级跟踪", name);
+    Json(logging::traced()).into_response()
+}
+
+/// DELETE /admin/trace/users/{name}
+async fn untrace_user(Path(name): Path<String>) -> Response {
+    logging::trace_user(&name, false);
+    info!("管理 API: 关闭用户 {} 的消息级跟踪", name);
+    Json(logging::traced()).into_response()
+}
+
+/// POST /admin/trace/sessions/{id}
+async fn trace_session(Path(id): Path<String>) -> Response {
+    logging::trace_session(&id, true);
+    info!("管理 API: 开启会话 {} 的消息级跟踪", id);
+    Json(logging::traced()).into_response()
+}
+
+/// DELETE /admin/trace/sessions/{id}
+async fn untrace_session(Path(id): Path<String>) -> Response {
+    logging::trace_session(&id, false);
+    info!("管理 API: 关闭会话 {} 的消息级跟踪", id);
+    Json(logging::traced()).into_response()
+}
+
+/// 写入用户库的 token（hashed 模式存摘要）
+fn stored_token(mode: AuthMode, token: &str) -> SecretString {
+    if mode == AuthMode::Hashed {
+        hash_token(token).into()
+    } else {
+        token.into()
+    }
+}
+
+/// 转换新用户的主 token 与 `tokens` 中的额外 token
+fn store_tokens(mode: AuthMode, user: &mut User) {
+    user.token = stored_token(mode, user.token.expose());
+    for extra in &mut user.tokens {
+        match extra {
+            ExtraToken::Plain(token) | ExtraToken::Expiring { token, .. } => *token = stored_token(mode, token.expose()),
+        }
+    }
+}
+
+/// 刷新认证表并返回结果
+fn apply(state: &AppState, body: Value) -> Response {
+    match state.reload_users() {
+        Ok(()) => Json(body).into_response(),
+        Err(e) => internal_error(e),
+    }
+}
+
+fn no_user_db() -> Response {
+    error::response(
+        StatusCode::CONFLICT,
+        "NO_USER_DB",
+        "未配置 users_db，用户只能在配置文件中修改",
+    )
+}
+
+/// 列表中不返回 token 与 tokens
+fn redact(user: User) -> Value {
+    let mut v = serde_json::to_value(user).unwrap_or_default();
+    if let Some(obj) = v.as_object_mut() {
+        obj.remove("token");
+        obj.remove("tokens");
+    }
+    v
+}
+
+fn generate_token() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(TOKEN_LEN)
+        .map(char::from)
+        .collect()
+}
+
+fn is_constraint_violation(e: &anyhow::Error) -> bool {
+    matches!(
+        e.downcast_ref::<rusqlite::Error>(),
+        Some(rusqlite::Error::SqliteFailure(f, _)) if f.code == rusqlite::ErrorCode::ConstraintViolation
+    )
+}
+
+fn user_not_found() -> Response {
+    error::response(StatusCode::NOT_FOUND, "USER_NOT_FOUND", "用户不存在")
+}
+
+fn internal_error(e: anyhow::Error) -> Response {
+    error!("管理 API 错误: {:#}", e);
+    error::response(StatusCode::INTERNAL_SERVER_ERROR, "INTERNAL", "内部错误")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        auth::{AuthState, Credentials},
+        config::Config,
+    };
+
+    #[tokio::test]
+    async fn hashed_mode_stores_extra_tokens() {
+        let mut user: User = serde_json::from_value(json!({
+            "name": "alice",
+            "token": "primary",
+            "tokens": ["extra", { "token": "rotating", "expires_at": "2999-01-01T00:00:00Z" }],
+        }))
+        .unwrap();
+        store_tokens(AuthMode::Hashed, &mut user);
+        assert_eq!(user.tokens[0].token(), hash_token("extra"));
+
+        let config: Config = toml::from_str(
+            "auth_mode = \"hashed\"\n[server]\nhost = \"0.0.0.0\"\nport = 443\ntls_cert = \"c\"\ntls_key = \"k\"",
+        )
+        .unwrap();
+        let auth = AuthState::new(&config, &[user]).unwrap();
+        for token in ["primary", "extra", "rotating"] {
+            let creds = Credentials {
+                token,
+                target: "wss://a.example.com/",
+                client_ip: [127, 0, 0, 1].into(),
+            };
+            assert_eq!(auth.authenticate(&creds).await.unwrap().name, "alice");
+        }
+    }
+}
@@ -0,0 +1,257 @@
+//! 反向隧道（agent）
+//!
+//! 暴露 NAT 之后的服务：内网的 ws-relay 实例配置 `[agent]` 后主动连接公网 relay 的 `/agent`，
+//! 以 `name` 注册并保持长连接；公网 relay 收到目标为 `agent:<name>:<port>` 的 `/ws` 会话时，
+//! 在该连接上打开一个虚拟流（见 [`crate::mux`]），agent 再连接本机 `host:port`，
+//! 之后 WS binary 消息 ↔ TCP 字节流，与 `tcp://` 目标相同。
+//!
+//! - 注册须使用 `agent_names` 包含该名称的用户 token；同名 agent 重复注册时新连接替换旧连接
+//! - agent 只连接 `ports` 中列出的端口，连接失败时虚拟流随即关闭
+//! - agent 断线后按 1s、2s … 30s 退避重连
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use axum::{
+    extract::{
+        ws::{Message, WebSocket},
+        Extension, State, WebSocketUpgrade,
+    },
+    http::{HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use futures_util::{SinkExt, StreamExt};
+use tokio::{
+    sync::{mpsc, Notify},
+    time::{interval, sleep},
+};
+use tokio_tungstenite::tungstenite::Message as TungMessage;
+use tracing::{debug, info, warn};
+
+use crate::{
+    config::{AgentConfig, User},
+    dns, error,
+    mux::{Frame, Mux},
+    state::AppState,
+    ws::{self, Dial, TargetRx, TargetTx},
+};
+
+/// 待发送帧队列长度
+const OUT_QUEUE: usize = 256;
+
+/// agent 发送 ping 的间隔，超过 3 个间隔未收到任何消息视为断线
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+const RECONNECT_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_MAX: Duration = Duration::from_secs(30);
+
+/// 已注册的 agent 连接
+struct Tunnel {
+    mux: Arc<Mux>,
+    /// 被同名新连接替换
+    replaced: Notify,
+}
+
+/// 公网 relay 上已注册的 agent
+#[derive(Default)]
+pub struct Registry {
+    tunnels: Mutex<HashMap<String, Arc<Tunnel>>>,
+}
+
+impl Registry {
+    fn register(&self, name: &str, mux: Arc<Mux>) -> Arc<Tunnel> {
+        let tunnel = Arc::new(Tunnel {
+            mux,
+            replaced: Notify::new(),
+        });
+        if let Some(old) = self.tunnels.lock().unwrap().insert(name.to_string(), tunnel.clone()) {
+            old.replaced.notify_one();
+        }
+        tunnel
+    }
+
+    fn unregister(&self, name: &str, tunnel: &Arc<Tunnel>) {
+        let mut tunnels = self.tunnels.lock().unwrap();
+        if tunnels.get(name).is_some_and(|t| Arc::ptr_eq(t, tunnel)) {
+            tunnels.remove(name);
+        }
+    }
+
+    /// 打开 `agent:<name>:<port>` 目标（`target` 不含 `agent:` 前缀）
+    pub async fn open(&self, target: &str) -> Result<(TargetTx, TargetRx)> {
+        let (name, port) = target
+            .rsplit_once(':')
+            .filter(|(_, p)| p.parse::<u16>().is_ok())
+            .ok_or_else(|| anyhow!("agent 目标须为 agent:<name>:<port>"))?;
+        let tunnel = self.tunnels.lock().unwrap().get(name).cloned();
+        let tunnel = tunnel.ok_or_else(|| anyhow!("agent 未连接: {}", name))?;
+        tunnel.mux.open(port).await
+    }
+}
+
+/// agent 注册入口
+/// 路由: /agent + Header X-Agent-Name
+pub async fn handler(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    ws: WebSocketUpgrade,
+    headers: HeaderMap,
+) -> Response {
+    let Some(name) = headers.get("X-Agent-Name").and_then(|v| v.to_str().ok()) else {
+        return (StatusCode::BAD_REQUEST, "Missing X-Agent-Name header").into_response();
+    };
+    if !user.agent_names.iter().any(|n| n == name) {
+        warn!("[{}] 不允许注册 agent: {}", user.name, name);
+        return error::response(StatusCode::FORBIDDEN, "AGENT_NOT_ALLOWED", "不允许注册该 agent 名称");
+    }
+    let name = name.to_string();
+    ws.on_upgrade(move |socket| serve(socket, name, user, state))
+}
+
+async fn serve(socket: WebSocket, name: String, user: User, state: AppState) {
+    let (out_tx, mut out_rx) = mpsc::channel(OUT_QUEUE);
+    let mux = Mux::new(out_tx, 1);
+    let tunnel = state.agents.register(&name, mux.clone());
+    info!("[{}] agent 已注册: {}", user.name, name);
+
+    let (mut tx, mut rx) = socket.split();
+    loop {
+        tokio::select! {
+            Some(frame) = out_rx.recv() => {
+                if tx.send(Message::Binary(frame.encode())).await.is_err() {
+                    break;
+                }
+            }
+            msg = rx.next() => match msg {
+                Some(Ok(Message::Binary(data))) => match Frame::decode(data) {
+                    // agent 不能发起流，打开即关闭
+                    Some(frame) => drop(mux.dispatch(frame).await),
+                    None => {
+                        warn!("agent 发送了无效的帧: {}", name);
+                        break;
+                    }
+                },
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+            _ = tunnel.replaced.notified() => {
+                info!("agent 已被新连接替换: {}", name);
+                break;
+            }
+        }
+    }
+
+    state.agents.unregister(&name, &tunnel);
+    info!("[{}] agent 已断开: {}（关闭 {} 个流）", user.name, name, mux.stream_count());
+    mux.close_all();
+}
+
+/// 在后台连接公网 relay，断线后重连
+pub fn spawn(config: &AgentConfig) {
+    let config = config.clone();
+    info!("agent: {} → {}", config.name, config.relay);
+    tokio::spawn(async move {
+        let mut delay = RECONNECT_MIN;
+        loop {
+            let started = Instant::now();
+            match run(&config).await {
+                Ok(()) => info!("agent 连接已断开，{:?} 后重连", delay),
+                Err(e) => warn!("agent 连接失败，{:?} 后重连: {:#}", delay, e),
+            }
+            // 连接维持过一段时间则从最短间隔重新退避
+            if started.elapsed() > RECONNECT_MAX {
+                delay = RECONNECT_MIN;
+            }
+            sleep(delay).await;
+            delay = (delay * 2).min(RECONNECT_MAX);
+        }
+    });
+}
+
+/// 建立一次 agent 连接，直到断开
+async fn run(config: &AgentConfig) -> Result<()> {
+    let mut headers = HeaderMap::new();
+    headers.insert("x-token", HeaderValue::from_str(config.token.expose())?);
+    headers.insert("x-agent-name", HeaderValue::from_str(&config.name)?);
+    let dial = Dial {
+        sni: None,
+        bind: None,
+        http2: false,
+        header_rules: &[],
+        headers: Some(&headers),
+        insecure_hosts: &[],
+    };
+    let (mut tx, mut rx) = ws::open_target(&config.relay, &dial)
+        .await
+        .with_context(|| format!("无法连接 {}", config.relay))?;
+    info!("agent 已连接: {}", config.relay);
+
+    let (out_tx, mut out_rx) = mpsc::channel(OUT_QUEUE);
+    let mux = Mux::new(out_tx, 2);
+    let mut ping = interval(PING_INTERVAL);
+    let mut last_seen = Instant::now();
+    let result = loop {
+        tokio::select! {
+            Some(frame) = out_rx.recv() => {
+                if let Err(e) = tx.send(TungMessage::Binary(frame.encode())).await {
+                    break Err(e.into());
+                }
+            }
+            msg = rx.next() => {
+                last_seen = Instant::now();
+                match msg {
+                    Some(Ok(TungMessage::Binary(data))) => {
+                        let Some(frame) = Frame::decode(data) else {
+                            break Err(anyhow!("relay 发送了无效的帧"));
+                        };
+                        if let Some((port, tx, rx)) = mux.dispatch(frame).await {
+                            tokio::spawn(bridge(port, tx, rx, config.clone()));
+                        }
+                    }
+                    Some(Ok(TungMessage::Close(_))) | None => break Ok(()),
+                    Some(Err(e)) => break Err(e.into()),
+                    Some(Ok(_)) => {}
+                }
+            }
+            _ = ping.tick() => {
+                if last_seen.elapsed() > PING_INTERVAL * 3 {
+                    break Err(anyhow!("relay 无响应"));
+                }
+                if let Err(e) = tx.send(TungMessage::Ping(Default::default())).await {
+                    break Err(e.into());
+                }
+            }
+        }
+    };
+    mux.close_all();
+    result
+}
+
+/// 连接本机端口并与虚拟流双向转发
+async fn bridge(port: String, stream_tx: TargetTx, stream_rx: TargetRx, config: AgentConfig) {
+    let local = match connect_local(&port, &config).await {
+        Ok(s) => s,
+        Err(e) => {
+            warn!("agent 连接本机端口失败: {:#}", e);
+            return;
+        }
+    };
+    debug!("agent 已连接本机端口: {}:{}", config.host, port);
+    let (local_tx, local_rx) = ws::tcp_messages(local);
+    tokio::select! {
+        _ = stream_rx.forward(local_tx) => {}
+        _ = local_rx.forward(stream_tx) => {}
+    }
+}
+
+async fn connect_local(port: &str, config: &AgentConfig) -> Result<tokio::net::TcpStream> {
+    let port: u16 = port.parse().with_context(|| format!("无效的端口: {}", port))?;
+    if !config.ports.contains(&port) {
+        bail!("端口不在 ports 中: {}", port);
+    }
+    Ok(dns::connect(&config.host, port, None).await?)
+}
@@ -0,0 +1,287 @@
+//! 审计日志
+//!
+//! 与运行日志、访问日志分开，只追加写入单个 JSONL 文件，每次事件同步落盘：
+//!
+//! | event | 记录时机 |
+//! |------|------|
+//! | `auth` | 每次认证（`/ws`、`/rest`），成功或失败 |
+//! | `admin` | 每次管理 API 调用（含认证失败） |
+//! | `reload` | 每次重新加载配置 |
+//! | `disconnect` | relay 主动终止 WS 会话（配额、时长、过期等） |
+//!
+//! ```json
+//! {"client_ip":"1.2.3.4","event":"auth","result":"ok","target":"wss://...","ts":"2026-01-01T00:00:00.000Z","user":"alice"}
+//! ```
+//!
+//! `hash_chain = true` 时每条记录带 `prev_hash` 与 `hash`：`hash` 为
+//! `SHA-256(不含 hash 字段的记录)`，`prev_hash` 为上一条的 `hash`（首条为 64 个 0）。
+//! 修改、删除或插入记录都会使之后的链断开，可用 `ws-relay-core verify-audit <文件>` 检查。
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    net::IpAddr,
+    sync::Mutex,
+};
+
+use anyhow::{bail, Context, Result};
+use chrono::{SecondsFormat, Utc};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use tracing::{error, info};
+
+use crate::config::AuditLogConfig;
+
+/// 首条记录的 `prev_hash`
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// 启动时查找上一条哈希所读取的文件末尾长度
+const TAIL_BYTES: u64 = 64 * 1024;
+
+/// 审计事件
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AuditEvent<'a> {
+    Auth {
+        client_ip: IpAddr,
+        /// 认证失败时为空
+        user: Option<&'a str>,
+        target: &'a str,
+        /// `ok` / `denied`
+        result: &'static str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        reason: Option<&'a str>,
+    },
+    Admin {
+        client_ip: IpAddr,
+        method: &'a str,
+        path: &'a str,
+        status: u16,
+    },
+    Reload {
+        /// `sighup` / `watch` / `control`
+        source: &'a str,
+        /// `ok` / `error`
+        result: &'static str,
+        detail: &'a str,
+    },
+    Disconnect {
+        session_id: &'a str,
+        user: &'a str,
+        target: &'a str,
+        reason: &'a str,
+    },
+}
+
+struct Writer {
+    file: File,
+    /// 上一条记录的哈希（`hash_chain` 开启时）
+    last_hash: Option<String>,
+}
+
+pub struct AuditLog {
+    path: String,
+    writer: Mutex<Writer>,
+}
+
+impl AuditLog {
+    pub fn open(config: &AuditLogConfig) -> Result<Self> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(&config.path)
+            .with_context(|| format!("无法打开审计日志: {}", config.path))?;
+        let last_hash = match config.hash_chain {
+            true => Some(last_hash(&mut file)?.unwrap_or_else(|| GENESIS_HASH.to_string())),
+            false => None,
+        };
+        info!("审计日志: {}", config.path);
+        Ok(Self {
+            path: config.path.clone(),
+            writer: Mutex::new(Writer { file, last_hash }),
+        })
+    }
+
+    pub fn record(&self, event: &AuditEvent) {
+        let Ok(Value::Object(mut entry)) = serde_json::to_value(event) else {
+            return;
+        };
+        entry.insert(
+            "ts".into(),
+            Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true).into(),
+        );
+
+        let mut writer = self.writer.lock().unwrap();
+        let mut line = match writer.last_hash {
+            Some(ref prev) => {
+                entry.insert("prev_hash".into(), prev.clone().into());
+                let body = Value::Object(entry).to_string();
+                let hash = hex::encode(Sha256::digest(body.as_bytes()));
+                let line = with_hash(&body, &hash);
+                writer.last_hash = Some(hash);
+                line
+            }
+            None => Value::Object(entry).to_string(),
+        };
+        line.push('\n');
+        if let Err(e) = writer.file.write_all(line.as_bytes()).and_then(|_| writer.file.sync_data()) {
+            error!("写入审计日志失败: {} - {}", self.path, e);
+        }
+    }
+}
+
+/// 在记录末尾加上 `hash` 字段
+fn with_hash(body: &str, hash: &str) -> String {
+    format!("{},\"hash\":\"{}\"}}", &body[..body.len() - 1], hash)
+}
+
+/// 文件中最后一条记录的 `hash`
+fn last_hash(file: &mut File) -> Result<Option<String>> {
+    let len = file.metadata()?.len();
+    file.seek(SeekFrom::Start(len.saturating_sub(TAIL_BYTES)))?;
+    let mut tail = String::new();
+    file.read_to_string(&mut tail).context("审计日志末尾不是有效的 UTF-8")?;
+    let Some(line) = tail.lines().rev().find(|l| !l.trim().is_empty()) else {
+        return Ok(None);
+    };
+    let entry: Map<String, Value> = serde_json::from_str(line).context("审计日志最后一行不是有效的 JSON")?;
+    match entry.get("hash").and_then(Value::as_str) {
+        Some(hash) => Ok(Some(hash.to_string())),
+        None => bail!("审计日志最后一行没有 hash，无法继续哈希链（可换用新文件）"),
+    }
+}
+
+/// 校验哈希链，返回记录数
+pub fn verify(path: &str) -> Result<usize> {
+    let file = File::open(path).with_context(|| format!("无法打开审计日志: {}", path))?;
+    let mut prev = GENESIS_HASH.to_string();
+    let mut count = 0;
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let n = i + 1;
+        let entry: Map<String, Value> =
+            serde_json::from_str(&line).with_context(|| format!("第 {} 行不是有效的 JSON", n))?;
+        let (Some(hash), Some(prev_hash)) = (
+            entry.get("hash").and_then(Value::as_str),
+            entry.get("prev_hash").and_then(Value::as_str),
+        ) else {
+            bail!("第 {} 行缺少 hash / prev_hash", n);
+        };
+        if prev_hash != prev {
+            bail!("第 {} 行 prev_hash 与上一条不符（记录被删除或插入）", n);
+        }
+        let suffix = format!(",\"hash\":\"{}\"}}", hash);
+        let body = line
+            .strip_suffix(&suffix)
+            .map(|b| format!("{}}}", b))
+            .with_context(|| format!("第 {} 行格式无效", n))?;
+        if hex::encode(Sha256::digest(body.as_bytes())) != hash {
+            bail!("第 {} 行哈希不符（记录被修改）", n);
+        }
+        prev = hash.to_string();
+        count += 1;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reload(detail: &str) -> AuditEvent<'_> {
+        AuditEvent::Reload {
+            source: "control",
+            result: "ok",
+            detail,
+        }
+    }
+
+    /// 临时文件中的哈希链审计日志
+    fn chained(name: &str) -> AuditLogConfig {
+        let path = std::env::temp_dir().join(format!("ws-relay-audit-{}-{}.jsonl", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        AuditLogConfig {
+            path: path.to_str().unwrap().to_string(),
+            hash_chain: true,
+        }
+    }
+
+    fn read(path: &str) -> Vec<String> {
+        std::fs::read_to_string(path).unwrap().lines().map(str::to_string).collect()
+    }
+
+    fn rewrite(path: &str, lines: &[String]) {
+        std::fs::write(path, lines.join("\n") + "\n").unwrap();
+    }
+
+    #[test]
+    fn with_hash_appends_field() {
+        let body = r#"{"event":"reload","prev_hash":"00"}"#;
+        let line = with_hash(body, "abc");
+        assert_eq!(line, r#"{"event":"reload","prev_hash":"00","hash":"abc"}"#);
+        let entry: Map<String, Value> = serde_json::from_str(&line).unwrap();
+        assert_eq!(entry["hash"], "abc");
+    }
+
+    #[test]
+    fn chain_resumes_after_reopen() {
+        let config = chained("resume");
+        AuditLog::open(&config).unwrap().record(&reload("a"));
+        AuditLog::open(&config).unwrap().record(&reload("b"));
+        let log = AuditLog::open(&config).unwrap();
+        log.record(&reload("c"));
+        log.record(&reload("\"quoted\" }"));
+        assert_eq!(verify(&config.path).unwrap(), 4);
+
+        let lines = read(&config.path);
+        let first: Map<String, Value> = serde_json::from_str(&lines[0]).unwrap();
+        let second: Map<String, Value> = serde_json::from_str(&lines[1]).unwrap();
+        assert_eq!(first["prev_hash"], GENESIS_HASH);
+        assert_eq!(second["prev_hash"], first["hash"]);
+        std::fs::remove_file(&config.path).unwrap();
+    }
+
+    #[test]
+    fn verify_detects_tampering() {
+        let config = chained("tamper");
+        let log = AuditLog::open(&config).unwrap();
+        for detail in ["a", "b", "c"] {
+            log.record(&reload(detail));
+        }
+        let lines = read(&config.path);
+
+        let mut modified = lines.clone();
+        modified[1] = modified[1].replace("\"detail\":\"b\"", "\"detail\":\"x\"");
+        rewrite(&config.path, &modified);
+        assert!(verify(&config.path).unwrap_err().to_string().contains("第 2 行哈希不符"));
+
+        let mut deleted = lines.clone();
+        deleted.remove(1);
+        rewrite(&config.path, &deleted);
+        assert!(verify(&config.path).unwrap_err().to_string().contains("第 2 行 prev_hash"));
+
+        let mut inserted = lines.clone();
+        inserted.insert(1, lines[0].clone());
+        rewrite(&config.path, &inserted);
+        assert!(verify(&config.path).unwrap_err().to_string().contains("第 2 行 prev_hash"));
+
+        rewrite(&config.path, &lines);
+        assert_eq!(verify(&config.path).unwrap(), 3);
+        std::fs::remove_file(&config.path).unwrap();
+    }
+
+    #[test]
+    fn reopen_without_hash_fails() {
+        let mut config = chained("plain");
+        config.hash_chain = false;
+        AuditLog::open(&config).unwrap().record(&reload("a"));
+        config.hash_chain = true;
+        assert!(AuditLog::open(&config).is_err());
+        std::fs::remove_file(&config.path).unwrap();
+    }
+}
@@ -1,30 +1,277 @@
-//! 认证中间件
+//! 认证
+//!
+//! 各认证方式实现 `Authenticator`，按 `auth_mode` 组装成认证链，依次尝试，首个通过即认证成功：
+//!
+//! | auth_mode | 认证链 |
+//! |------|------|
+//! | `static` | 明文 token 表 → webhook（如配置） |
+//! | `hashed` | SHA-256 token 表 → webhook（如配置） |
+//! | `jwt` | JWT |
+//! | `introspection` | OAuth2 token introspection |
+//! | `webhook` | webhook |
+//!
+//! 本地用户可通过 `tokens` 持有多个 token（可带过期时间），轮换期间新旧 token 同时有效；
+//! 用户的 `valid_from` / `expires_at` 在认证时检查，后台任务定期终止已过期用户的活跃会话。
 
 use axum::{
-    extract::{Query, Request, State},
+    extract::{ConnectInfo, Query, Request, State},
     http::StatusCode,
     middleware::Next,
-    response::Response,
+    response::{IntoResponse, Response},
 };
 use serde::Deserialize;
-use std::{collections::HashSet, sync::Arc};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{HashMap, HashSet},
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use tracing::{debug, info_span, warn, Instrument};
+
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde_json::{Map, Value};
+
+use crate::{
+    audit::AuditEvent,
+    auth_webhook::AuthWebhook,
+    config::{AuthMode, Config, User},
+    error, geoip,
+    introspection::Introspection,
+    jwt::JwtAuth,
+    resources, secret,
+    state::AppState,
+    stats::{Stats, TerminateReason},
+};
+
+/// 检查用户过期的间隔
+const EXPIRY_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// 客户端提交的认证信息
+pub struct Credentials<'a> {
+    pub token: &'a str,
+    pub target: &'a str,
+    pub client_ip: IpAddr,
+}
+
+/// 认证方式
+#[async_trait]
+pub trait Authenticator: Send + Sync {
+    /// 认证通过返回用户，否则返回拒绝原因
+    async fn authenticate(&self, creds: &Credentials<'_>) -> Result<User>;
+}
+
+/// 本地 token 表（`[[users]]` / 用户库），可在运行时替换
+#[derive(Clone, Default)]
+pub struct TokenTable {
+    /// 为 true 时 `token` 字段存放 SHA-256 十六进制摘要
+    hashed: bool,
+    /// token 的 SHA-256 摘要 → 用户（按摘要查找，比较的不是 token 原值）
+    tokens: Arc<RwLock<HashMap<String, TokenEntry>>>,
+}
 
-use crate::config::User;
+struct TokenEntry {
+    user: User,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+impl TokenTable {
+    fn new(hashed: bool) -> Self {
+        Self {
+            hashed,
+            tokens: Arc::default(),
+        }
+    }
+
+    /// 替换全部用户（已停用的用户不参与认证）
+    fn replace(&self, users: &[User]) {
+        let tokens = users
+            .iter()
+            .filter(|u| !u.disabled)
+            .flat_map(|u| {
+                u.all_tokens().map(move |(token, expires_at)| {
+                    let key = if self.hashed { token.to_lowercase() } else { hash_token(token) };
+                    let entry = TokenEntry {
+                        user: u.clone(),
+                        expires_at,
+                    };
+                    (key, entry)
+                })
+            })
+            .collect();
+        *self.tokens.write().unwrap() = tokens;
+    }
+}
+
+#[async_trait]
+impl Authenticator for TokenTable {
+    async fn authenticate(&self, creds: &Credentials<'_>) -> Result<User> {
+        let tokens = self.tokens.read().unwrap();
+        let entry = tokens.get(&hash_token(creds.token)).ok_or_else(|| anyhow!("未知 token"))?;
+        let now = Utc::now();
+        if entry.expires_at.is_some_and(|at| at <= now) {
+            bail!("token 已过期");
+        }
+        if entry.user.is_expired(now) {
+            bail!("用户已过期");
+        }
+        if entry.user.is_pending(now) {
+            bail!("用户尚未生效");
+        }
+        Ok(entry.user.clone())
+    }
+}
 
 /// 认证状态
 #[derive(Clone)]
 pub struct AuthState {
-    tokens: Arc<HashSet<String>>,
+    table: TokenTable,
+    chain: Arc<Vec<Arc<dyn Authenticator>>>,
 }
 
 impl AuthState {
-    pub fn new(users: &[User]) -> Self {
-        Self {
-            tokens: Arc::new(users.iter().map(|u| u.token.clone()).collect()),
+    pub fn new(config: &Config, users: &[User]) -> Result<Self> {
+        let table = TokenTable::new(config.auth_mode == AuthMode::Hashed);
+        table.replace(users);
+
+        let webhook = match config.auth_webhook {
+            Some(ref w) => Some(Arc::new(AuthWebhook::new(w)?) as Arc<dyn Authenticator>),
+            None => None,
+        };
+
+        let mut chain: Vec<Arc<dyn Authenticator>> = Vec::new();
+        match config.auth_mode {
+            AuthMode::Static | AuthMode::Hashed => {
+                chain.push(Arc::new(table.clone()));
+                chain.extend(webhook);
+            }
+            AuthMode::Jwt => {
+                let jwt = config.jwt.as_ref().context("auth_mode = \"jwt\" 需要 [jwt] 配置")?;
+                chain.push(Arc::new(JwtAuth::new(jwt)?));
+            }
+            AuthMode::Introspection => {
+                let i = config
+                    .introspection
+                    .as_ref()
+                    .context("auth_mode = \"introspection\" 需要 [introspection] 配置")?;
+                chain.push(Arc::new(Introspection::new(i)?));
+            }
+            AuthMode::Webhook => {
+                chain.push(webhook.context("auth_mode = \"webhook\" 需要 [auth_webhook] 配置")?);
+            }
+        }
+
+        Ok(Self {
+            table,
+            chain: Arc::new(chain),
+        })
+    }
+
+    /// 替换本地用户
+    pub fn replace(&self, users: &[User]) {
+        self.table.replace(users);
+    }
+
+    /// 本地可认证用户数
+    pub fn user_count(&self) -> usize {
+        let tokens = self.table.tokens.read().unwrap();
+        tokens.values().map(|e| e.user.name.as_str()).collect::<HashSet<_>>().len()
+    }
+
+    /// 已过期的本地用户
+    fn expired_users(&self, now: DateTime<Utc>) -> HashSet<String> {
+        let tokens = self.table.tokens.read().unwrap();
+        tokens
+            .values()
+            .filter(|e| e.user.is_expired(now))
+            .map(|e| e.user.name.clone())
+            .collect()
+    }
+
+    /// 依次尝试认证链
+    pub async fn authenticate(&self, creds: &Credentials<'_>) -> Result<User> {
+        let mut last_err = None;
+        for auth in self.chain.iter() {
+            match auth.authenticate(creds).await {
+                Ok(user) => return Ok(user),
+                Err(e) => last_err = Some(e),
+            }
+        }
+        match last_err {
+            Some(e) => Err(e),
+            None => bail!("未配置认证方式"),
         }
     }
 }
 
+/// 后台定期终止已过期用户的活跃会话
+pub fn spawn_expiry_check(auth: AuthState, stats: Arc<Stats>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(EXPIRY_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            for name in auth.expired_users(Utc::now()) {
+                let n = stats.terminate_user(&name, TerminateReason::Expired);
+                if n > 0 {
+                    warn!("[{}] 用户已过期，终止 {} 个活跃会话", name, n);
+                }
+            }
+        }
+    });
+}
+
+/// token 的 SHA-256 十六进制摘要（`auth_mode = "hashed"` 时存储此值）
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// 由 JWT / introspection 的 claims 构造用户，`exp` 作为会话时长上限
+///
+/// 空的 `allowed_targets` 表示不限制目标，因此缺少目标 claim 时使用 `default_targets`，
+/// 两者都没有（或 claim 为空列表）时拒绝，避免 token 漏带 claim 即获得所有目标
+pub fn user_from_claims(
+    token: &str,
+    claims: &Map<String, Value>,
+    name_claim: &str,
+    targets_claim: &str,
+    default_targets: &[String],
+) -> Result<User> {
+    let name = claims
+        .get(name_claim)
+        .and_then(Value::as_str)
+        .ok_or_else(|| anyhow!("缺少 claim: {}", name_claim))?;
+
+    let allowed_targets: Vec<String> = match claims.get(targets_claim) {
+        Some(v) => v
+            .as_array()
+            .ok_or_else(|| anyhow!("claim 格式错误: {}", targets_claim))?
+            .iter()
+            .filter_map(Value::as_str)
+            .map(String::from)
+            .collect(),
+        None if default_targets.is_empty() => return Err(anyhow!("缺少 claim: {}", targets_claim)),
+        None => default_targets.to_vec(),
+    };
+    if allowed_targets.is_empty() {
+        return Err(anyhow!("claim 未授予任何目标: {}", targets_claim));
+    }
+
+    let max_session_secs = claims
+        .get("exp")
+        .and_then(Value::as_i64)
+        .map(|exp| (exp - Utc::now().timestamp()).max(0) as u64);
+
+    Ok(User {
+        name: name.to_string(),
+        token: token.into(),
+        allowed_targets,
+        max_session_secs,
+        ..Default::default()
+    })
+}
+
 /// Query 参数
 #[derive(Deserialize, Default)]
 pub struct TokenQuery {
@@ -32,13 +279,15 @@ pub struct TokenQuery {
 }
 
 /// 认证中间件
-/// 从 Query(?token=xxx) 或 Header(X-Token: xxx) 提取 token
+/// 从 Query(?token=xxx) 或 Header(X-Token: xxx) 提取 token 交给认证链，
+/// 认证通过后将匹配的 `User` 放入 request extensions，结果记入审计日志
 pub async fn middleware(
-    State(state): State<AuthState>,
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
     Query(query): Query<TokenQuery>,
-    req: Request,
+    mut req: Request,
     next: Next,
-) -> Result<Response, StatusCode> {
+) -> Response {
     // header 优先（REST 常用），其次 query（WS 常用）
     let token = req
         .headers()
@@ -47,8 +296,143 @@ pub async fn middleware(
         .map(String::from)
         .or(query.token);
 
-    match token {
-        Some(ref t) if state.tokens.contains(t) => Ok(next.run(req).await),
-        _ => Err(StatusCode::UNAUTHORIZED),
+    let target = req
+        .headers()
+        .get("x-target-url")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    let audit = |user: Option<&str>, reason: Option<&str>| {
+        state.audit(&AuditEvent::Auth {
+            client_ip: addr.ip(),
+            user,
+            target: &secret::redact_url(&target),
+            result: if reason.is_none() { "ok" } else { "denied" },
+            reason,
+        })
+    };
+
+    if state.health.in_maintenance() {
+        audit(None, Some("维护中"));
+        return error::response(StatusCode::SERVICE_UNAVAILABLE, "MAINTENANCE", "服务维护中，请稍后重试");
+    }
+
+    if resources::fd_exhausted() {
+        audit(None, Some("文件描述符接近上限"));
+        return error::response(StatusCode::SERVICE_UNAVAILABLE, "TOO_MANY_OPEN_FILES", "连接数已达上限，请稍后重试");
+    }
+
+    if resources::overloaded() {
+        audit(None, Some("内存用量过高"));
+        return error::response(StatusCode::SERVICE_UNAVAILABLE, "OVERLOADED", "服务负载过高，请稍后重试");
+    }
+
+    if !geoip::allows_client(addr.ip()) {
+        warn!("客户端所在国家/地区不允许访问: {}（{}）", addr.ip(), geoip::country(addr.ip()).unwrap_or("未知"));
+        audit(None, Some("客户端所在国家/地区不允许访问"));
+        return error::response(StatusCode::FORBIDDEN, "COUNTRY_NOT_ALLOWED", "客户端所在国家/地区不允许访问");
+    }
+
+    let Some(token) = token else {
+        audit(None, Some("缺少 token"));
+        return StatusCode::UNAUTHORIZED.into_response();
+    };
+
+    let creds = Credentials {
+        token: &token,
+        target: &target,
+        client_ip: addr.ip(),
+    };
+    let span = info_span!("auth", client_ip = %addr.ip(), country = geoip::country(addr.ip()).unwrap_or_default());
+    let user = match state.auth.authenticate(&creds).instrument(span).await {
+        Ok(u) => u,
+        Err(e) => {
+            debug!("认证失败 ({}): {:#}", addr.ip(), e);
+            audit(None, Some(&format!("{:#}", e)));
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+    };
+
+    if user.suspended {
+        warn!("[{}] 用户已暂停", user.name);
+        audit(Some(&user.name), Some("用户已暂停"));
+        return error::response(StatusCode::FORBIDDEN, "USER_SUSPENDED", "用户已暂停");
+    }
+
+    if state.cluster.is_banned(&user.name) {
+        warn!("[{}] 用户已被封禁", user.name);
+        audit(Some(&user.name), Some("用户已被封禁"));
+        return error::response(StatusCode::FORBIDDEN, "USER_BANNED", "用户已被封禁");
+    }
+
+    if state.health.is_user_drained(&user.name) {
+        audit(Some(&user.name), Some("用户排空中"));
+        return error::response(StatusCode::SERVICE_UNAVAILABLE, "USER_DRAINING", "用户排空中，请稍后重试");
+    }
+
+    if !user.allows_ip(addr.ip()) {
+        warn!("[{}] 客户端地址不在允许列表: {}", user.name, addr.ip());
+        audit(Some(&user.name), Some("客户端地址不在允许列表"));
+        return error::response(StatusCode::FORBIDDEN, "IP_NOT_ALLOWED", "客户端地址不在允许列表");
+    }
+
+    if !geoip::allows_user(&user, addr.ip()) {
+        warn!("[{}] 客户端所在国家/地区不允许访问: {}", user.name, addr.ip());
+        audit(Some(&user.name), Some("客户端所在国家/地区不允许访问"));
+        return error::response(StatusCode::FORBIDDEN, "COUNTRY_NOT_ALLOWED", "客户端所在国家/地区不允许访问");
+    }
+
+    // 未指定目标的请求（如 `/agent`）由处理器自行校验
+    if !target.is_empty() && !user.allows_target(&target) {
+        warn!("[{}] 目标不在允许列表: {}", user.name, secret::redact_url(&target));
+        audit(Some(&user.name), Some("目标不在允许列表"));
+        return error::response(StatusCode::FORBIDDEN, "TARGET_NOT_ALLOWED", "目标不在允许列表");
+    }
+    audit(Some(&user.name), None);
+
+    req.extensions_mut().insert(user);
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claims(value: Value) -> Map<String, Value> {
+        value.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn targets_from_claim() {
+        let c = claims(serde_json::json!({ "sub": "alice", "targets": ["wss://a/*"] }));
+        let user = user_from_claims("t", &c, "sub", "targets", &[]).unwrap();
+        assert_eq!(user.name, "alice");
+        assert_eq!(user.allowed_targets, ["wss://a/*"]);
+        assert!(user.allows_target("wss://a/ws"));
+        assert!(!user.allows_target("wss://b/ws"));
+    }
+
+    #[test]
+    fn missing_targets_claim_is_denied() {
+        let c = claims(serde_json::json!({ "sub": "alice" }));
+        assert!(user_from_claims("t", &c, "sub", "targets", &[]).is_err());
+        let c = claims(serde_json::json!({ "sub": "alice", "targets": [] }));
+        assert!(user_from_claims("t", &c, "sub", "targets", &["*".into()]).is_err());
+        let c = claims(serde_json::json!({ "sub": "alice", "targets": "wss://a/*" }));
+        assert!(user_from_claims("t", &c, "sub", "targets", &[]).is_err());
+    }
+
+    #[test]
+    fn missing_targets_claim_uses_default() {
+        let c = claims(serde_json::json!({ "sub": "alice" }));
+        let user = user_from_claims("t", &c, "sub", "targets", &["wss://a/*".into()]).unwrap();
+        assert!(user.allows_target("wss://a/ws"));
+        assert!(!user.allows_target("wss://b/ws"));
+    }
+
+    #[test]
+    fn missing_name_claim_is_denied() {
+        let c = claims(serde_json::json!({ "targets": ["*"] }));
+        assert!(user_from_claims("t", &c, "sub", "targets", &[]).is_err());
     }
 }
@@ -0,0 +1,131 @@
+//! 外部认证 webhook
+//!
+//! 本地未找到 token 时，向配置的 URL POST：
+//!
+//! ```json
+//! {"token":"xxx","target":"wss://...","client_ip":"1.2.3.4"}
+//! ```
+//!
+//! 期望响应：
+//!
+//! ```json
+//! {"allow":true,"user":"alice","limits":{"monthly_quota_bytes":1073741824,"max_session_secs":3600}}
+//! ```
+//!
+//! `limits` 中的字段与 `[[users]]` 相同。允许的结果按 (token, target, client_ip) 缓存 `cache_ttl_secs`。
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tracing::{error, info};
+
+use crate::{
+    auth::{hash_token, Authenticator, Credentials},
+    config::{AuthWebhookConfig, User},
+};
+
+/// 缓存条目超过此数量时清理过期项
+const CACHE_SWEEP_THRESHOLD: usize = 10_000;
+
+/// (token 摘要, 目标, 客户端地址)
+type CacheKey = (String, String, IpAddr);
+
+#[derive(Serialize)]
+struct WebhookRequest<'a> {
+    token: &'a str,
+    target: &'a str,
+    client_ip: IpAddr,
+}
+
+#[derive(Deserialize)]
+struct WebhookResponse {
+    allow: bool,
+    user: Option<String>,
+    #[serde(default)]
+    limits: Map<String, Value>,
+}
+
+pub struct AuthWebhook {
+    url: String,
+    ttl: Duration,
+    client: Client,
+    cache: Mutex<HashMap<CacheKey, (User, Instant)>>,
+}
+
+impl AuthWebhook {
+    pub fn new(config: &AuthWebhookConfig) -> Result<Self> {
+        let client = Client::builder()
+            .timeout(Duration::from_millis(config.timeout_ms))
+            .build()?;
+        Ok(Self {
+            url: config.url.clone(),
+            ttl: Duration::from_secs(config.cache_ttl_secs),
+            client,
+            cache: Mutex::default(),
+        })
+    }
+
+    async fn call(&self, token: &str, target: &str, client_ip: IpAddr) -> Result<Option<User>> {
+        let body = serde_json::to_vec(&WebhookRequest { token, target, client_ip })?;
+        let resp = self
+            .client
+            .post(&self.url)
+            .header("content-type", "application/json")
+            .body(body)
+            .send()
+            .await?;
+        if !resp.status().is_success() {
+            bail!("HTTP {}", resp.status());
+        }
+
+        let decision: WebhookResponse = serde_json::from_slice(&resp.bytes().await?)?;
+        if !decision.allow {
+            return Ok(None);
+        }
+
+        // limits 与用户字段同名，直接反序列化为 User
+        let mut fields = decision.limits;
+        fields.insert("name".into(), decision.user.unwrap_or_else(|| "webhook".into()).into());
+        fields.insert("token".into(), token.into());
+        Ok(Some(serde_json::from_value(Value::Object(fields))?))
+    }
+}
+
+#[async_trait]
+impl Authenticator for AuthWebhook {
+    async fn authenticate(&self, creds: &Credentials<'_>) -> Result<User> {
+        let key = (hash_token(creds.token), creds.target.to_string(), creds.client_ip);
+        if let Some((user, expires)) = self.cache.lock().unwrap().get(&key) {
+            if *expires > Instant::now() {
+                return Ok(user.clone());
+            }
+        }
+
+        let user = match self.call(creds.token, creds.target, creds.client_ip).await {
+            Ok(Some(user)) => user,
+            Ok(None) => bail!("webhook 拒绝"),
+            Err(e) => {
+                error!("webhook 认证失败: {} - {:#}", self.url, e);
+                return Err(e);
+            }
+        };
+
+        info!("webhook 认证通过: {} ({})", user.name, creds.client_ip);
+        let mut cache = self.cache.lock().unwrap();
+        if cache.len() >= CACHE_SWEEP_THRESHOLD {
+            let now = Instant::now();
+            cache.retain(|_, (_, expires)| *expires > now);
+        }
+        cache.insert(key, (user.clone(), Instant::now() + self.ttl));
+        Ok(user)
+    }
+}
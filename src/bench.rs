@@ -0,0 +1,251 @@
+//! 压测
+//!
+//! `ws-relay-core bench` 按 `--rate` 逐个建立到 relay `/ws` 的会话，直到 `--connections` 个；
+//! 每个会话握手后循环发送 `--size` 字节的 text（`--binary` 时为 binary）消息并等待回复
+//! （目标须原样或逐条回复，默认 `internal://echo`），最多 `--window` 条未回复，持续到 `--duration` 结束。结束后输出：
+//!
+//! - 握手延迟（TCP + TLS + WS 升级 + relay 连接目标）的 p50 / p90 / p99 / max
+//! - 消息往返延迟分位数、吞吐（消息数 / 字节数每秒）
+//! - 按原因分类的错误数（`HTTP 403`、`响应超时` 等）
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+use axum::http::{HeaderMap, HeaderValue};
+use futures_util::{SinkExt, StreamExt};
+use tokio::{
+    sync::mpsc,
+    time::{interval, timeout, MissedTickBehavior},
+};
+use tokio_tungstenite::tungstenite::{Error as WsError, Message as TungMessage};
+
+use crate::ws::{self, Dial, TargetRx};
+
+/// 压测参数
+pub struct Options {
+    /// relay 的 `/ws` 地址
+    pub url: String,
+    pub token: String,
+    pub target: String,
+    /// 会话总数
+    pub connections: usize,
+    /// 每秒新建会话数（0 为不限）
+    pub rate: u32,
+    /// 压测时长
+    pub duration: Duration,
+    /// 消息大小（字节）
+    pub size: usize,
+    /// 发送 binary 消息
+    pub binary: bool,
+    /// 每个会话未回复消息数上限
+    pub window: usize,
+    /// 握手与单条消息回复的超时
+    pub timeout: Duration,
+}
+
+/// 单个会话的结果
+#[derive(Default)]
+struct Outcome {
+    handshake: Option<Duration>,
+    rtts: Vec<Duration>,
+    bytes: u64,
+    error: Option<String>,
+}
+
+pub async fn run(opts: Options) -> Result<()> {
+    if opts.connections == 0 {
+        bail!("--connections 须大于 0");
+    }
+    if opts.window == 0 {
+        bail!("--window 须大于 0");
+    }
+    let mut headers = HeaderMap::new();
+    headers.insert("x-token", HeaderValue::from_str(&opts.token)?);
+    headers.insert("x-target-url", HeaderValue::from_str(&opts.target)?);
+    let payload = if opts.binary {
+        TungMessage::Binary(vec![b'x'; opts.size].into())
+    } else {
+        TungMessage::Text("x".repeat(opts.size).into())
+    };
+    println!(
+        "压测 {} → {}：{} 个会话，每秒新建 {}，持续 {:?}，{} 消息 {} 字节，窗口 {}",
+        opts.url,
+        opts.target,
+        opts.connections,
+        if opts.rate == 0 { "不限".to_string() } else { opts.rate.to_string() },
+        opts.duration,
+        if opts.binary { "binary" } else { "text" },
+        opts.size,
+        opts.window
+    );
+
+    let started = Instant::now();
+    let deadline = tokio::time::Instant::now() + opts.duration;
+    let opts = Arc::new(opts);
+    let headers = Arc::new(headers);
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut ticker = (opts.rate > 0).then(|| {
+        let mut t = interval(Duration::from_secs_f64(1.0 / opts.rate as f64));
+        t.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        t
+    });
+    let mut opened = 0;
+    while opened < opts.connections && tokio::time::Instant::now() < deadline {
+        if let Some(ref mut t) = ticker {
+            t.tick().await;
+        }
+        let (opts, headers, payload, tx) = (opts.clone(), headers.clone(), payload.clone(), tx.clone());
+        tokio::spawn(async move {
+            let _ = tx.send(session(&opts, &headers, payload, deadline).await);
+        });
+        opened += 1;
+    }
+    drop(tx);
+
+    let mut outcomes = Vec::with_capacity(opened);
+    while let Some(outcome) = rx.recv().await {
+        outcomes.push(outcome);
+    }
+    report(&outcomes, started.elapsed());
+    Ok(())
+}
+
+async fn session(opts: &Options, headers: &HeaderMap, payload: TungMessage, deadline: tokio::time::Instant) -> Outcome {
+    let mut outcome = Outcome::default();
+    let dial = Dial {
+        sni: None,
+        bind: None,
+        http2: false,
+        header_rules: &[],
+        headers: Some(headers),
+        insecure_hosts: &[],
+    };
+    let started = Instant::now();
+    let (mut tx, mut rx) = match timeout(opts.timeout, ws::open_target(&opts.url, &dial)).await {
+        Ok(Ok(pair)) => pair,
+        Ok(Err(e)) => {
+            outcome.error = Some(classify(&e));
+            return outcome;
+        }
+        Err(_) => {
+            outcome.error = Some("握手超时".into());
+            return outcome;
+        }
+    };
+    outcome.handshake = Some(started.elapsed());
+
+    // 未回复消息的发送时间
+    let mut inflight = VecDeque::with_capacity(opts.window);
+    while tokio::time::Instant::now() < deadline {
+        while inflight.len() < opts.window {
+            if tx.feed(payload.clone()).await.is_err() {
+                outcome.error = Some("发送失败".into());
+                return outcome;
+            }
+            inflight.push_back(Instant::now());
+        }
+        match timeout(opts.timeout, tx.flush()).await {
+            Ok(Ok(())) => {}
+            Ok(Err(_)) => {
+                outcome.error = Some("发送失败".into());
+                break;
+            }
+            Err(_) => {
+                outcome.error = Some("发送超时".into());
+                break;
+            }
+        }
+        match timeout(opts.timeout, next_reply(&mut rx)).await {
+            Ok(Some(len)) => {
+                let sent = inflight.pop_front().unwrap_or_else(Instant::now);
+                outcome.rtts.push(sent.elapsed());
+                outcome.bytes += (payload.len() + len) as u64;
+            }
+            Ok(None) => {
+                outcome.error = Some("连接被关闭".into());
+                break;
+            }
+            Err(_) => {
+                outcome.error = Some("响应超时".into());
+                break;
+            }
+        }
+    }
+    let _ = tx.send(TungMessage::Close(None)).await;
+    outcome
+}
+
+/// 等待下一条 text / binary 消息，返回其长度；连接关闭时返回 `None`
+async fn next_reply(rx: &mut TargetRx) -> Option<usize> {
+    loop {
+        match rx.next().await? {
+            Ok(TungMessage::Text(t)) => return Some(t.len()),
+            Ok(TungMessage::Binary(b)) => return Some(b.len()),
+            Ok(TungMessage::Close(_)) | Err(_) => return None,
+            Ok(_) => {}
+        }
+    }
+}
+
+/// 握手错误分类
+fn classify(e: &anyhow::Error) -> String {
+    match e.downcast_ref::<WsError>() {
+        Some(WsError::Http(resp)) => format!("HTTP {}", resp.status().as_u16()),
+        Some(WsError::Io(e)) => format!("连接失败: {:?}", e.kind()),
+        _ => match e.downcast_ref::<std::io::Error>() {
+            Some(e) => format!("连接失败: {:?}", e.kind()),
+            None => "握手失败".into(),
+        },
+    }
+}
+
+fn report(outcomes: &[Outcome], elapsed: Duration) {
+    let mut handshakes: Vec<_> = outcomes.iter().filter_map(|o| o.handshake).collect();
+    let mut rtts: Vec<_> = outcomes.iter().flat_map(|o| o.rtts.iter().copied()).collect();
+    let bytes: u64 = outcomes.iter().map(|o| o.bytes).sum();
+    let mut errors = BTreeMap::new();
+    for e in outcomes.iter().filter_map(|o| o.error.as_ref()) {
+        *errors.entry(e.as_str()).or_insert(0) += 1;
+    }
+
+    let secs = elapsed.as_secs_f64();
+    println!();
+    println!("会话: {} 成功 / {} 总数（耗时 {:.1}s）", handshakes.len(), outcomes.len(), secs);
+    println!("握手延迟: {}", percentiles(&mut handshakes));
+    println!("消息往返: {}", percentiles(&mut rtts));
+    println!(
+        "吞吐: {:.0} msg/s，{:.2} MB/s（共 {} 条消息，{} 字节）",
+        rtts.len() as f64 / secs,
+        bytes as f64 / secs / 1_000_000.0,
+        rtts.len(),
+        bytes
+    );
+    if errors.is_empty() {
+        println!("错误: 无");
+    } else {
+        println!("错误:");
+        for (reason, count) in errors {
+            println!("  {}: {}", reason, count);
+        }
+    }
+}
+
+/// p50 / p90 / p99 / max
+fn percentiles(samples: &mut [Duration]) -> String {
+    if samples.is_empty() {
+        return "-".into();
+    }
+    samples.sort_unstable();
+    let at = |p: f64| samples[((samples.len() as f64 * p).ceil() as usize).clamp(1, samples.len()) - 1];
+    format!(
+        "p50 {:.2?}  p90 {:.2?}  p99 {:.2?}  max {:.2?}",
+        at(0.50),
+        at(0.90),
+        at(0.99),
+        samples[samples.len() - 1]
+    )
+}
@@ -0,0 +1,160 @@
+//! 消息缓冲池
+//!
+//! 高消息速率下逐条分配负载缓冲的开销明显。以下位置从全局池取缓冲，消息发出后（最后一个 `Bytes` 释放时）归还：
+//!
+//! - `tcp://` 目标的读缓冲
+//! - `/mux` 与反向隧道的帧编码
+//! - 设置了 `rest.max_response_bytes` 时读取的 REST 响应体
+//!
+//! 按容量分为 4 KiB / 16 KiB / 64 KiB 三档，每档保留有限个空闲缓冲；超过 64 KiB 的请求直接分配，不入池。
+//! WS 消息的负载由 tungstenite 读缓冲切分而来，REST 流式响应直接转发上游的数据块，均无额外分配。
+//! 命中率见 `/admin/stats/buffers`。
+
+use std::{
+    collections::BTreeMap,
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use bytes::Bytes;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+
+/// (容量, 最多保留的空闲缓冲数)
+const CLASSES: [(usize, usize); 3] = [(4 << 10, 1024), (16 << 10, 512), (64 << 10, 128)];
+
+struct Class {
+    size: usize,
+    max_idle: usize,
+    idle: Mutex<Vec<Vec<u8>>>,
+}
+
+struct Pool {
+    classes: Vec<Class>,
+    /// 从池中取到空闲缓冲
+    hits: AtomicU64,
+    /// 池中没有空闲缓冲，新分配
+    misses: AtomicU64,
+    /// 超过最大档位，直接分配
+    oversized: AtomicU64,
+    /// 池已满或缓冲过大，归还时释放
+    discarded: AtomicU64,
+}
+
+static POOL: Lazy<Pool> = Lazy::new(|| Pool {
+    classes: CLASSES
+        .iter()
+        .map(|&(size, max_idle)| Class {
+            size,
+            max_idle,
+            idle: Mutex::new(Vec::new()),
+        })
+        .collect(),
+    hits: AtomicU64::new(0),
+    misses: AtomicU64::new(0),
+    oversized: AtomicU64::new(0),
+    discarded: AtomicU64::new(0),
+});
+
+/// 池中取出的缓冲，drop 时归还
+pub struct Buf(Vec<u8>);
+
+/// 取一个容量不小于 `capacity` 的空缓冲
+pub fn get(capacity: usize) -> Buf {
+    let Some(class) = POOL.classes.iter().find(|c| c.size >= capacity) else {
+        POOL.oversized.fetch_add(1, Ordering::Relaxed);
+        return Buf(Vec::with_capacity(capacity));
+    };
+    match class.idle.lock().unwrap().pop() {
+        Some(vec) => {
+            POOL.hits.fetch_add(1, Ordering::Relaxed);
+            Buf(vec)
+        }
+        None => {
+            POOL.misses.fetch_add(1, Ordering::Relaxed);
+            Buf(Vec::with_capacity(class.size))
+        }
+    }
+}
+
+impl Buf {
+    /// 转为 `Bytes`，全部引用释放后缓冲归还池中
+    pub fn freeze(self) -> Bytes {
+        Bytes::from_owner(self)
+    }
+}
+
+impl Deref for Buf {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.0
+    }
+}
+
+impl DerefMut for Buf {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.0
+    }
+}
+
+impl AsRef<[u8]> for Buf {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Drop for Buf {
+    fn drop(&mut self) {
+        let mut vec = std::mem::take(&mut self.0);
+        let capacity = vec.capacity();
+        // 归入容量所能满足的最大档位；扩容到最大档位两倍以上的缓冲不保留
+        let class = POOL.classes.iter().rev().find(|c| c.size <= capacity);
+        let Some(class) = class.filter(|_| capacity <= CLASSES[CLASSES.len() - 1].0 * 2) else {
+            if capacity > 0 {
+                POOL.discarded.fetch_add(1, Ordering::Relaxed);
+            }
+            return;
+        };
+        vec.clear();
+        let mut idle = class.idle.lock().unwrap();
+        if idle.len() < class.max_idle {
+            idle.push(vec);
+        } else {
+            POOL.discarded.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// `/admin/stats/buffers`
+#[derive(Serialize)]
+pub struct Snapshot {
+    pub hits: u64,
+    pub misses: u64,
+    pub oversized: u64,
+    pub discarded: u64,
+    /// hits / (hits + misses)
+    pub hit_rate: f64,
+    /// 各档位（字节）当前空闲缓冲数
+    pub idle: BTreeMap<usize, usize>,
+}
+
+pub fn snapshot() -> Snapshot {
+    let hits = POOL.hits.load(Ordering::Relaxed);
+    let misses = POOL.misses.load(Ordering::Relaxed);
+    Snapshot {
+        hits,
+        misses,
+        oversized: POOL.oversized.load(Ordering::Relaxed),
+        discarded: POOL.discarded.load(Ordering::Relaxed),
+        hit_rate: if hits + misses == 0 { 0.0 } else { hits as f64 / (hits + misses) as f64 },
+        idle: POOL
+            .classes
+            .iter()
+            .map(|c| (c.size, c.idle.lock().unwrap().len()))
+            .collect(),
+    }
+}
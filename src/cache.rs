@@ -0,0 +1,436 @@
+//! REST 响应缓存
+//!
+//! 只缓存 GET 请求的 200 响应（流式响应除外），遵循上游 `Cache-Control`：
+//!
+//! | 规则 | 说明 |
+//! |------|------|
+//! | `no-store` / `private` | 不缓存 |
+//! | `Authorization` / `Cookie` 请求 | 仅缓存 `public` 响应 |
+//! | 新鲜期 | `s-maxage` > `max-age` > `default_ttl_secs`，`no-cache` 为 0；`host_ttl` 覆盖以上 |
+//! | 重新验证 | 过期后带 `If-None-Match` / `If-Modified-Since` 请求上游，304 时沿用缓存 |
+//! | `Vary` | 列出的请求头参与匹配，`Vary: *` 不缓存 |
+//! | 用户隔离 | 条目按 relay 用户 + URL 存取，客户端或 `header_rules` 带上的凭据头不会把响应泄露给其他用户 |
+//!
+//! 请求带 `Cache-Control: no-cache` / `no-store` 或条件请求头时不查缓存。
+//! 占用超过 `max_bytes` 时淘汰最久未使用的条目；配置 `dir` 后条目同时写入磁盘，重启后恢复。
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{Context, Result};
+use axum::http::{header, HeaderMap, HeaderValue};
+use bytes::Bytes;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+
+use crate::config::CacheConfig;
+
+/// 缓存条目
+#[derive(Serialize, Deserialize)]
+pub struct Entry {
+    /// 填充缓存的 relay 用户，旧版本写入的文件没有此字段，加载时丢弃
+    #[serde(default)]
+    user: String,
+    url: String,
+    pub content_type: Option<String>,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    /// `Vary` 列出的请求头及缓存时的取值
+    vary: Vec<(String, Option<String>)>,
+    /// 过期时间（Unix 秒）
+    expires: i64,
+    #[serde(skip)]
+    pub body: Bytes,
+}
+
+impl Entry {
+    pub fn is_fresh(&self) -> bool {
+        Utc::now().timestamp() < self.expires
+    }
+
+    /// 是否可重新验证
+    pub fn has_validators(&self) -> bool {
+        self.etag.is_some() || self.last_modified.is_some()
+    }
+
+    /// 为重新验证请求加上条件请求头
+    pub fn add_validators(&self, headers: &mut HeaderMap) {
+        let pairs = [
+            (header::IF_NONE_MATCH, &self.etag),
+            (header::IF_MODIFIED_SINCE, &self.last_modified),
+        ];
+        for (name, value) in pairs {
+            if let Some(v) = value.as_deref().and_then(|v| HeaderValue::from_str(v).ok()) {
+                headers.insert(name, v);
+            }
+        }
+    }
+
+    fn matches(&self, req_headers: &HeaderMap) -> bool {
+        self.vary
+            .iter()
+            .all(|(name, value)| header_str(req_headers, name) == value.as_deref())
+    }
+
+    fn key(&self) -> String {
+        cache_key(&self.user, &self.url)
+    }
+
+    fn size(&self) -> usize {
+        self.user.len() + self.url.len() + self.body.len()
+    }
+}
+
+struct Slot {
+    entry: Arc<Entry>,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct Inner {
+    entries: HashMap<String, Slot>,
+    bytes: usize,
+    tick: u64,
+}
+
+pub struct RestCache {
+    config: CacheConfig,
+    inner: Mutex<Inner>,
+}
+
+impl RestCache {
+    pub fn new(config: &CacheConfig) -> Result<Self> {
+        let cache = Self {
+            config: config.clone(),
+            inner: Mutex::default(),
+        };
+        if let Some(ref dir) = config.dir {
+            fs::create_dir_all(dir).with_context(|| format!("创建缓存目录失败: {}", dir))?;
+            let mut loaded = 0;
+            for path in fs::read_dir(dir)?.filter_map(|e| e.ok()).map(|e| e.path()) {
+                match read_entry(&path) {
+                    Some(entry) if !entry.user.is_empty() && (entry.is_fresh() || entry.has_validators()) => {
+                        cache.insert(entry, false);
+                        loaded += 1;
+                    }
+                    _ => {
+                        let _ = fs::remove_file(&path);
+                    }
+                }
+            }
+            info!("REST 缓存: {}（已恢复 {} 条）", dir, loaded);
+        }
+        Ok(cache)
+    }
+
+    /// 请求是否跳过缓存查找
+    pub fn bypass(req_headers: &HeaderMap) -> bool {
+        let no_cache = directives(req_headers, header::CACHE_CONTROL)
+            .any(|(name, _)| name == "no-cache" || name == "no-store");
+        no_cache
+            || header_str(req_headers, "pragma") == Some("no-cache")
+            || req_headers.contains_key(header::IF_NONE_MATCH)
+            || req_headers.contains_key(header::IF_MODIFIED_SINCE)
+    }
+
+    pub fn get(&self, user: &str, url: &str, req_headers: &HeaderMap) -> Option<Arc<Entry>> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.tick += 1;
+        let tick = inner.tick;
+        let slot = inner.entries.get_mut(&cache_key(user, url))?;
+        if !slot.entry.matches(req_headers) {
+            return None;
+        }
+        slot.last_used = tick;
+        Some(slot.entry.clone())
+    }
+
+    /// 按响应头判断是否缓存
+    pub fn put(&self, user: &str, url: &str, req_headers: &HeaderMap, resp_headers: &HeaderMap, body: Bytes) {
+        if body.len() > self.config.max_entry_bytes {
+            return;
+        }
+        let cc: Vec<_> = directives(resp_headers, header::CACHE_CONTROL).collect();
+        let has = |d: &str| cc.iter().any(|(name, _)| name == d);
+        if has("no-store") || has("private") {
+            return;
+        }
+        let authorized = req_headers.contains_key(header::AUTHORIZATION) || req_headers.contains_key(header::COOKIE);
+        if authorized && !has("public") {
+            return;
+        }
+
+        let mut vary = Vec::new();
+        for (name, _) in directives(resp_headers, header::VARY) {
+            if name == "*" {
+                return;
+            }
+            let value = header_str(req_headers, &name).map(String::from);
+            vary.push((name, value));
+        }
+
+        let entry = Entry {
+            user: user.to_string(),
+            url: url.to_string(),
+            content_type: header_str(resp_headers, header::CONTENT_TYPE.as_str()).map(String::from),
+            etag: header_str(resp_headers, header::ETAG.as_str()).map(String::from),
+            last_modified: header_str(resp_headers, header::LAST_MODIFIED.as_str()).map(String::from),
+            vary,
+            expires: Utc::now().timestamp() + self.ttl(url, &cc),
+            body,
+        };
+        if entry.is_fresh() || entry.has_validators() {
+            self.insert(entry, true);
+        }
+    }
+
+    /// 重新验证得到 304：按新的响应头延长新鲜期
+    pub fn refresh(&self, entry: &Entry, resp_headers: &HeaderMap) -> Arc<Entry> {
+        let cc: Vec<_> = directives(resp_headers, header::CACHE_CONTROL).collect();
+        let entry = Entry {
+            user: entry.user.clone(),
+            url: entry.url.clone(),
+            content_type: entry.content_type.clone(),
+            etag: header_str(resp_headers, header::ETAG.as_str())
+                .map(String::from)
+                .or_else(|| entry.etag.clone()),
+            last_modified: entry.last_modified.clone(),
+            vary: entry.vary.clone(),
+            expires: Utc::now().timestamp() + self.ttl(&entry.url, &cc),
+            body: entry.body.clone(),
+        };
+        self.insert(entry, true)
+    }
+
+    /// 新鲜期（秒）
+    fn ttl(&self, url: &str, cc: &[(String, Option<String>)]) -> i64 {
+        let url = reqwest::Url::parse(url).ok();
+        if let Some(host) = url.as_ref().and_then(|u| u.host_str()) {
+            if let Some((_, ttl)) = self.config.host_ttl.iter().find(|(h, _)| h.eq_ignore_ascii_case(host)) {
+                return *ttl as i64;
+            }
+        }
+        let value = |d: &str| {
+            cc.iter()
+                .find(|(name, _)| name == d)
+                .and_then(|(_, v)| v.as_deref()?.parse::<i64>().ok())
+        };
+        if cc.iter().any(|(name, _)| name == "no-cache") {
+            return 0;
+        }
+        value("s-maxage")
+            .or_else(|| value("max-age"))
+            .unwrap_or(self.config.default_ttl_secs as i64)
+    }
+
+    fn insert(&self, entry: Entry, persist: bool) -> Arc<Entry> {
+        let entry = Arc::new(entry);
+        if persist {
+            if let Some(ref dir) = self.config.dir {
+                let path = entry_path(dir, &entry.key());
+                let entry = entry.clone();
+                tokio::task::spawn_blocking(move || {
+                    if let Err(e) = write_entry(&path, &entry) {
+                        warn!("写入缓存文件失败: {} - {}", path.display(), e);
+                    }
+                });
+            }
+        }
+
+        let evicted = {
+            let mut inner = self.inner.lock().unwrap();
+            inner.tick += 1;
+            let slot = Slot {
+                entry: entry.clone(),
+                last_used: inner.tick,
+            };
+            inner.bytes += entry.size();
+            if let Some(old) = inner.entries.insert(entry.key(), slot) {
+                inner.bytes -= old.entry.size();
+            }
+            if inner.bytes > self.config.max_bytes {
+                self.evict(&mut inner)
+            } else {
+                Vec::new()
+            }
+        };
+        match self.config.dir {
+            Some(ref dir) if !evicted.is_empty() => {
+                let paths: Vec<_> = evicted.iter().map(|key| entry_path(dir, key)).collect();
+                let remove = move || {
+                    for path in paths {
+                        let _ = fs::remove_file(path);
+                    }
+                };
+                // 启动时加载磁盘条目可能不在运行时中
+                match tokio::runtime::Handle::try_current() {
+                    Ok(handle) => {
+                        handle.spawn_blocking(remove);
+                    }
+                    Err(_) => remove(),
+                }
+            }
+            _ => {}
+        }
+        entry
+    }
+
+    /// 淘汰最久未使用的条目，直到占用降到上限的 90%；返回被淘汰的键，由调用方在锁外删除磁盘文件
+    fn evict(&self, inner: &mut Inner) -> Vec<String> {
+        let target = self.config.max_bytes / 10 * 9;
+        let mut order: Vec<_> = inner.entries.iter().map(|(k, s)| (s.last_used, k.clone())).collect();
+        order.sort_unstable();
+        let mut evicted = Vec::new();
+        for (_, key) in order {
+            if inner.bytes <= target {
+                break;
+            }
+            if let Some(slot) = inner.entries.remove(&key) {
+                inner.bytes -= slot.entry.size();
+                evicted.push(key);
+            }
+        }
+        evicted
+    }
+}
+
+/// 头部取值（非 ASCII 视为无）
+fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
+}
+
+/// 逗号分隔的指令（名称转小写），如 `max-age=60, public`
+fn directives(headers: &HeaderMap, name: header::HeaderName) -> impl Iterator<Item = (String, Option<String>)> + '_ {
+    headers
+        .get_all(name)
+        .into_iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .filter(|d| !d.is_empty())
+        .map(|d| match d.split_once('=') {
+            Some((k, v)) => (k.trim().to_ascii_lowercase(), Some(v.trim().trim_matches('"').to_string())),
+            None => (d.to_ascii_lowercase(), None),
+        })
+}
+
+/// 内存与磁盘条目的键：用户名 + URL
+fn cache_key(user: &str, url: &str) -> String {
+    format!("{}\n{}", user, url)
+}
+
+/// 磁盘文件：`<dir>/<sha256(key)>`，首行为 JSON 元数据，其后为响应体
+fn entry_path(dir: &str, key: &str) -> PathBuf {
+    Path::new(dir).join(hex::encode(Sha256::digest(key.as_bytes())))
+}
+
+fn write_entry(path: &Path, entry: &Entry) -> Result<()> {
+    let mut data = serde_json::to_vec(entry)?;
+    data.push(b'\n');
+    data.extend_from_slice(&entry.body);
+    fs::write(path, data)?;
+    Ok(())
+}
+
+fn read_entry(path: &Path) -> Option<Entry> {
+    let data = fs::read(path).ok()?;
+    let split = data.iter().position(|&b| b == b'\n')?;
+    let mut entry: Entry = serde_json::from_slice(&data[..split]).ok()?;
+    entry.body = Bytes::copy_from_slice(&data[split + 1..]);
+    Some(entry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache(config: &str) -> RestCache {
+        RestCache::new(&toml::from_str(config).unwrap()).unwrap()
+    }
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|&(k, v)| (header::HeaderName::from_static(k), HeaderValue::from_static(v)))
+            .collect()
+    }
+
+    const URL: &str = "https://api.example.com/v1/ticker";
+    const USER: &str = "alice";
+
+    #[test]
+    fn respects_cache_control() {
+        let cache = cache("");
+        cache.put(USER, URL, &HeaderMap::new(), &headers(&[("cache-control", "no-store, max-age=60")]), "a".into());
+        assert!(cache.get(USER, URL, &HeaderMap::new()).is_none());
+        cache.put(USER, URL, &HeaderMap::new(), &headers(&[("cache-control", "private, max-age=60")]), "a".into());
+        assert!(cache.get(USER, URL, &HeaderMap::new()).is_none());
+        cache.put(USER, URL, &HeaderMap::new(), &headers(&[("cache-control", "max-age=60")]), "a".into());
+        assert_eq!(cache.get(USER, URL, &HeaderMap::new()).unwrap().body, "a");
+    }
+
+    #[test]
+    fn authorized_requests_need_public() {
+        let cache = cache("");
+        for name in ["authorization", "cookie"] {
+            let req = headers(&[(name, "secret")]);
+            cache.put(USER, URL, &req, &headers(&[("cache-control", "max-age=60")]), "a".into());
+            assert!(cache.get(USER, URL, &req).is_none());
+        }
+        let req = headers(&[("authorization", "Bearer x")]);
+        cache.put(USER, URL, &req, &headers(&[("cache-control", "public, max-age=60")]), "a".into());
+        assert!(cache.get(USER, URL, &req).is_some());
+    }
+
+    #[test]
+    fn vary_headers_must_match() {
+        let cache = cache("");
+        let resp = headers(&[("cache-control", "max-age=60"), ("vary", "Accept-Encoding")]);
+        cache.put(USER, URL, &headers(&[("accept-encoding", "gzip")]), &resp, "gz".into());
+        assert!(cache.get(USER, URL, &headers(&[("accept-encoding", "gzip")])).is_some());
+        assert!(cache.get(USER, URL, &headers(&[("accept-encoding", "br")])).is_none());
+        assert!(cache.get(USER, URL, &HeaderMap::new()).is_none());
+
+        let resp = headers(&[("cache-control", "max-age=60"), ("vary", "*")]);
+        cache.put(USER, "https://api.example.com/other", &HeaderMap::new(), &resp, "a".into());
+        assert!(cache.get(USER, "https://api.example.com/other", &HeaderMap::new()).is_none());
+    }
+
+    #[test]
+    fn bypass_on_conditional_or_no_cache_requests() {
+        assert!(!RestCache::bypass(&HeaderMap::new()));
+        assert!(RestCache::bypass(&headers(&[("cache-control", "no-cache")])));
+        assert!(RestCache::bypass(&headers(&[("if-none-match", "\"v1\"")])));
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let cache = cache("max_bytes = 200");
+        let resp = headers(&[("cache-control", "max-age=60")]);
+        let body = Bytes::from(vec![b'x'; 60]);
+        for url in ["https://a/1", "https://a/2"] {
+            cache.put(USER, url, &HeaderMap::new(), &resp, body.clone());
+        }
+        cache.get(USER, "https://a/1", &HeaderMap::new());
+        cache.put(USER, "https://a/3", &HeaderMap::new(), &resp, body);
+        assert!(cache.get(USER, "https://a/1", &HeaderMap::new()).is_some());
+        assert!(cache.get(USER, "https://a/2", &HeaderMap::new()).is_none());
+        assert!(cache.get(USER, "https://a/3", &HeaderMap::new()).is_some());
+    }
+
+    #[test]
+    fn entries_are_per_user() {
+        let cache = cache("");
+        let req = headers(&[("x-api-key", "alice-key")]);
+        cache.put("alice", URL, &req, &headers(&[("cache-control", "max-age=60")]), "a".into());
+        assert!(cache.get("alice", URL, &req).is_some());
+        assert!(cache.get("bob", URL, &headers(&[("x-api-key", "bob-key")])).is_none());
+        assert!(cache.get("bob", URL, &HeaderMap::new()).is_none());
+    }
+}
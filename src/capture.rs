@@ -0,0 +1,237 @@
+//! 会话录制与回放
+//!
+//! 录制文件为 JSONL，每行一帧：
+//!
+//! ```json
+//! {"ts":1718000000123,"dir":"c2t","op":"text","data":"{\"op\":\"ping\"}"}
+//! ```
+//!
+//! - `ts`: Unix 毫秒时间戳
+//! - `dir`: `c2t`（客户端 → 目标）或 `t2c`（目标 → 客户端）
+//! - `op`: `text` / `binary` / `ping` / `pong` / `close`
+//! - `data`: text 帧为原文，其余为 base64
+//!
+//! 帧经有界队列（`queue_size`）交给后台任务写入，写入跟不上时丢弃新帧，丢弃数见 `/admin/stats/capture`。
+
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use futures_util::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs::{self, File},
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter},
+    sync::mpsc::{self, error::TrySendError},
+    time::{sleep_until, timeout, Instant},
+};
+use tokio_tungstenite::{connect_async, tungstenite::Message as TungMessage};
+use tracing::{error, info, warn};
+
+use crate::secret;
+
+/// 录制文件序号（同一毫秒内区分会话）
+static SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// 已写入的帧数
+static WRITTEN: AtomicU64 = AtomicU64::new(0);
+
+/// 队列已满被丢弃的帧数
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// 回放结束后等待目标剩余响应的时长
+const REPLAY_DRAIN: Duration = Duration::from_secs(2);
+
+/// 帧方向
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    C2t,
+    T2c,
+}
+
+/// 帧类型
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Opcode {
+    Text,
+    Binary,
+    Ping,
+    Pong,
+    Close,
+}
+
+/// 录制的一帧
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Frame {
+    pub ts: u64,
+    pub dir: Direction,
+    pub op: Opcode,
+    pub data: String,
+}
+
+impl Frame {
+    fn new(dir: Direction, msg: &TungMessage) -> Option<Self> {
+        let (op, data) = match msg {
+            TungMessage::Text(t) => (Opcode::Text, t.to_string()),
+            TungMessage::Binary(b) => (Opcode::Binary, BASE64.encode(b)),
+            TungMessage::Ping(p) => (Opcode::Ping, BASE64.encode(p)),
+            TungMessage::Pong(p) => (Opcode::Pong, BASE64.encode(p)),
+            TungMessage::Close(_) => (Opcode::Close, String::new()),
+            TungMessage::Frame(_) => return None,
+        };
+        Some(Self { ts: now_millis(), dir, op, data })
+    }
+
+    fn to_message(&self) -> Result<TungMessage> {
+        Ok(match self.op {
+            Opcode::Text => TungMessage::Text(self.data.clone().into()),
+            Opcode::Binary => TungMessage::Binary(BASE64.decode(&self.data)?.into()),
+            Opcode::Ping => TungMessage::Ping(BASE64.decode(&self.data)?.into()),
+            Opcode::Pong => TungMessage::Pong(BASE64.decode(&self.data)?.into()),
+            Opcode::Close => TungMessage::Close(None),
+        })
+    }
+}
+
+/// 会话录制器（写文件在后台任务中进行，不阻塞转发）
+pub struct Recorder {
+    tx: mpsc::Sender<Frame>,
+    path: PathBuf,
+    /// 本会话丢弃的帧数
+    dropped: AtomicU64,
+}
+
+impl Recorder {
+    /// 在 `dir` 下创建录制文件，`queue_size` 为待写入帧数上限
+    pub async fn create(dir: &str, queue_size: usize, target: &str) -> Result<Self> {
+        fs::create_dir_all(dir).await?;
+        let seq = SEQ.fetch_add(1, Ordering::Relaxed);
+        let path = Path::new(dir).join(format!("session-{}-{}.jsonl", now_millis(), seq));
+        let file = File::create(&path)
+            .await
+            .with_context(|| format!("创建录制文件失败: {}", path.display()))?;
+
+        info!("录制会话: {} -> {}", secret::redact_url(target), path.display());
+
+        let (tx, mut rx) = mpsc::channel::<Frame>(queue_size);
+        let file_path = path.clone();
+        tokio::spawn(async move {
+            let path = file_path;
+            let mut writer = BufWriter::new(file);
+            while let Some(frame) = rx.recv().await {
+                let mut line = match serde_json::to_vec(&frame) {
+                    Ok(l) => l,
+                    Err(_) => continue,
+                };
+                line.push(b'\n');
+                if let Err(e) = writer.write_all(&line).await {
+                    error!("写入录制文件失败: {} - {}", path.display(), e);
+                    return;
+                }
+                WRITTEN.fetch_add(1, Ordering::Relaxed);
+            }
+            let _ = writer.flush().await;
+        });
+
+        Ok(Self {
+            tx,
+            path,
+            dropped: AtomicU64::new(0),
+        })
+    }
+
+    /// 记录一帧，队列已满时丢弃
+    pub fn record(&self, dir: Direction, msg: &TungMessage) {
+        let Some(frame) = Frame::new(dir, msg) else {
+            return;
+        };
+        if let Err(TrySendError::Full(_)) = self.tx.try_send(frame) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        let dropped = self.dropped.load(Ordering::Relaxed);
+        if dropped > 0 {
+            warn!("录制文件写入跟不上，丢弃 {} 帧: {}", dropped, self.path.display());
+        }
+    }
+}
+
+/// `/admin/stats/capture`
+#[derive(Serialize)]
+pub struct Snapshot {
+    pub written: u64,
+    pub dropped: u64,
+}
+
+pub fn snapshot() -> Snapshot {
+    Snapshot {
+        written: WRITTEN.load(Ordering::Relaxed),
+        dropped: DROPPED.load(Ordering::Relaxed),
+    }
+}
+
+/// 按原始时间间隔将录制中的 c2t 帧回放到目标，目标返回的帧以同样格式输出到 stdout
+pub async fn replay(path: &str, target: &str) -> Result<()> {
+    let file = File::open(path)
+        .await
+        .with_context(|| format!("打开录制文件失败: {}", path))?;
+    let mut lines = BufReader::new(file).lines();
+    let mut frames = Vec::new();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let frame: Frame = serde_json::from_str(&line)
+            .with_context(|| format!("录制文件格式错误: {}", line))?;
+        if frame.dir == Direction::C2t {
+            frames.push(frame);
+        }
+    }
+
+    info!("回放 {} 帧: {} -> {}", frames.len(), path, secret::redact_url(target));
+
+    let (ws, _) = connect_async(target)
+        .await
+        .with_context(|| format!("连接目标失败: {}", target))?;
+    let (mut tx, mut rx) = ws.split();
+
+    // 目标 → stdout
+    let reader = tokio::spawn(async move {
+        while let Some(Ok(msg)) = rx.next().await {
+            if let Some(frame) = Frame::new(Direction::T2c, &msg) {
+                if let Ok(line) = serde_json::to_string(&frame) {
+                    println!("{}", line);
+                }
+            }
+        }
+    });
+
+    // 录制 → 目标（保持相对时间）
+    let start = Instant::now();
+    let base_ts = frames.first().map(|f| f.ts).unwrap_or_default();
+    for frame in &frames {
+        sleep_until(start + Duration::from_millis(frame.ts.saturating_sub(base_ts))).await;
+        tx.send(frame.to_message()?).await?;
+    }
+
+    let _ = timeout(REPLAY_DRAIN, reader).await;
+    info!("回放结束: {}", secret::redact_url(target));
+    Ok(())
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
@@ -0,0 +1,217 @@
+//! relay 级联
+//!
+//! `/ws` 目标可以是另一个 ws-relay，流量依次经过多个 relay（如 edge → region → origin）：
+//!
+//! ```text
+//! relay+wss://[token@]next-hop[:port][/path]?target=<百分号编码的最终目标>
+//! ```
+//!
+//! relay 把请求转为连接 `wss://next-hop[:port][/path]`（路径默认 `/ws`），握手时带上：
+//!
+//! | 请求头 | 值 |
+//! |------|------|
+//! | `X-Token` | URL 中的 token，没有时取 `[[relay_hops]]` 中匹配下一跳主机的 token |
+//! | `X-Target-URL` | `target` 参数，可以再次是 `relay+wss://` |
+//! | `X-Relay-Hops` | 已经过的 relay 标识（逗号分隔）加上本 relay 的 `server.relay_id` |
+//!
+//! 收到的 `X-Relay-Hops` 已包含本 relay 时以 508 `RELAY_LOOP` 拒绝，经过的 relay 达到 `server.max_relay_hops` 时
+//! 以 508 `RELAY_HOPS_EXCEEDED` 拒绝。
+
+use axum::http::{HeaderMap, HeaderValue};
+use once_cell::sync::Lazy;
+use percent_encoding::percent_decode_str;
+use reqwest::Url;
+
+use crate::{
+    config::{RelayHop, ServerConfig},
+    header_rules,
+};
+
+pub const HOPS_HEADER: &str = "X-Relay-Hops";
+
+pub const LOOP: (&str, &str) = ("RELAY_LOOP", "级联链路出现环路");
+pub const HOPS_EXCEEDED: (&str, &str) = ("RELAY_HOPS_EXCEEDED", "级联经过的 relay 过多");
+const INVALID_TARGET: (&str, &str) = ("INVALID_RELAY_TARGET", "级联目标格式错误");
+const MISSING_TOKEN: (&str, &str) = ("RELAY_TOKEN_MISSING", "未配置下一跳 relay 的 token");
+
+/// 未配置 `server.relay_id` 时的标识，进程内不变
+static DEFAULT_ID: Lazy<String> = Lazy::new(|| format!("relay-{:012x}", rand::random::<u64>() >> 16));
+
+/// 本 relay 的标识
+pub fn relay_id(server: &ServerConfig) -> &str {
+    server.relay_id.as_deref().unwrap_or(&DEFAULT_ID)
+}
+
+/// 读取请求经过的 relay，出现环路或超过跳数时返回 (错误码, 说明)
+pub fn incoming_hops(headers: &HeaderMap, server: &ServerConfig) -> Result<Vec<String>, (&'static str, &'static str)> {
+    let hops: Vec<String> = headers
+        .get_all(HOPS_HEADER)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .filter(|h| !h.is_empty())
+        .map(str::to_string)
+        .collect();
+    if hops.iter().any(|h| h == relay_id(server)) {
+        return Err(LOOP);
+    }
+    if hops.len() >= server.max_relay_hops {
+        return Err(HOPS_EXCEEDED);
+    }
+    Ok(hops)
+}
+
+/// 下一跳 relay
+pub struct NextHop {
+    /// 下一跳的 `/ws` 地址
+    pub url: String,
+    /// 握手请求头（`X-Token`、`X-Target-URL`、`X-Relay-Hops`）
+    pub headers: HeaderMap,
+}
+
+/// 解析 `relay+ws(s)://` 目标，其他目标返回 `None`
+pub fn next_hop(
+    target: &str,
+    rules: &[RelayHop],
+    hops: &[String],
+    server: &ServerConfig,
+) -> Option<Result<NextHop, (&'static str, &'static str)>> {
+    let scheme = if target.starts_with("relay+wss://") {
+        "wss"
+    } else if target.starts_with("relay+ws://") {
+        "ws"
+    } else {
+        return None;
+    };
+    Some(parse(scheme, target, rules, hops, server))
+}
+
+fn parse(
+    scheme: &str,
+    target: &str,
+    rules: &[RelayHop],
+    hops: &[String],
+    server: &ServerConfig,
+) -> Result<NextHop, (&'static str, &'static str)> {
+    let url = Url::parse(target).map_err(|_| INVALID_TARGET)?;
+    let host = url.host_str().filter(|h| !h.is_empty()).ok_or(INVALID_TARGET)?;
+    let inner = url
+        .query_pairs()
+        .find(|(k, _)| k == "target")
+        .map(|(_, v)| v.into_owned())
+        .ok_or(INVALID_TARGET)?;
+
+    let token = match url.username() {
+        "" => rules
+            .iter()
+            .find(|r| r.hosts.iter().any(|p| header_rules::host_matches(p, host)))
+            .map(|r| r.token.expose().to_string())
+            .ok_or(MISSING_TOKEN)?,
+        name => percent_decode_str(name).decode_utf8_lossy().into_owned(),
+    };
+
+    let path = match url.path() {
+        "" | "/" => "/ws",
+        p => p,
+    };
+    let authority = match url.port() {
+        Some(port) => format!("{host}:{port}"),
+        None => host.to_string(),
+    };
+
+    let mut chain = hops.to_vec();
+    chain.push(relay_id(server).to_string());
+    let mut headers = HeaderMap::new();
+    for (name, value) in [("x-token", token), ("x-target-url", inner), ("x-relay-hops", chain.join(","))] {
+        headers.insert(name, HeaderValue::from_str(&value).map_err(|_| INVALID_TARGET)?);
+    }
+    Ok(NextHop {
+        url: format!("{scheme}://{authority}{path}"),
+        headers,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server() -> ServerConfig {
+        toml::from_str(
+            "host = \"0.0.0.0\"\nport = 443\ntls_cert = \"c\"\ntls_key = \"k\"\nrelay_id = \"edge\"\nmax_relay_hops = 3",
+        )
+        .unwrap()
+    }
+
+    fn headers(values: &[&str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for v in values {
+            headers.append(HOPS_HEADER, HeaderValue::from_str(v).unwrap());
+        }
+        headers
+    }
+
+    fn rules() -> Vec<RelayHop> {
+        vec![RelayHop {
+            hosts: vec!["*.example.com".into()],
+            token: "rule-token".into(),
+        }]
+    }
+
+    fn hop(target: &str, rules: &[RelayHop]) -> Result<NextHop, (&'static str, &'static str)> {
+        next_hop(target, rules, &["a".to_string()], &server()).unwrap()
+    }
+
+    fn header<'a>(hop: &'a NextHop, name: &str) -> &'a str {
+        hop.headers.get(name).unwrap().to_str().unwrap()
+    }
+
+    #[test]
+    fn incoming_hops_parsing() {
+        let server = server();
+        assert_eq!(incoming_hops(&headers(&[]), &server), Ok(vec![]));
+        assert_eq!(incoming_hops(&headers(&["a, b"]), &server), Ok(vec!["a".into(), "b".into()]));
+        assert_eq!(incoming_hops(&headers(&["a", " b ,"]), &server), Ok(vec!["a".into(), "b".into()]));
+    }
+
+    #[test]
+    fn incoming_hops_rejects_loops_and_long_chains() {
+        let server = server();
+        assert_eq!(incoming_hops(&headers(&["a,edge"]), &server), Err(LOOP));
+        assert_eq!(incoming_hops(&headers(&["a", "edge"]), &server), Err(LOOP));
+        assert_eq!(incoming_hops(&headers(&["a,b,c"]), &server), Err(HOPS_EXCEEDED));
+        assert_eq!(incoming_hops(&headers(&["a,b", "c"]), &server), Err(HOPS_EXCEEDED));
+        // 环路优先于跳数
+        assert_eq!(incoming_hops(&headers(&["a,b,edge"]), &server), Err(LOOP));
+    }
+
+    #[test]
+    fn next_hop_token_sources() {
+        let from_url = hop("relay+wss://url%20token@region.example.com?target=wss%3A%2F%2Forigin%2Fws", &rules()).unwrap();
+        assert_eq!(header(&from_url, "x-token"), "url token");
+
+        let from_rule = hop("relay+wss://region.example.com?target=wss%3A%2F%2Forigin%2Fws", &rules()).unwrap();
+        assert_eq!(header(&from_rule, "x-token"), "rule-token");
+
+        assert_eq!(
+            hop("relay+wss://region.other.net?target=wss%3A%2F%2Forigin%2Fws", &rules()).err(),
+            Some(MISSING_TOKEN)
+        );
+        assert_eq!(hop("relay+wss://region.example.com?target=x", &[]).err(), Some(MISSING_TOKEN));
+    }
+
+    #[test]
+    fn next_hop_url_and_headers() {
+        let next = hop("relay+wss://t@region.example.com:8443?target=wss%3A%2F%2Forigin%3A9000%2Fws%3Fa%3D1", &[]).unwrap();
+        assert_eq!(next.url, "wss://region.example.com:8443/ws");
+        assert_eq!(header(&next, "x-target-url"), "wss://origin:9000/ws?a=1");
+        assert_eq!(header(&next, "x-relay-hops"), "a,edge");
+
+        let next = hop("relay+ws://t@region.example.com/custom/ws?target=relay%2Bwss%3A%2F%2Forigin%3Ftarget%3Dx", &[]).unwrap();
+        assert_eq!(next.url, "ws://region.example.com/custom/ws");
+        assert_eq!(header(&next, "x-target-url"), "relay+wss://origin?target=x");
+
+        assert_eq!(hop("relay+wss://t@region.example.com/ws", &[]).err(), Some(INVALID_TARGET));
+        assert!(next_hop("wss://region.example.com/ws", &[], &[], &server()).is_none());
+    }
+}
@@ -0,0 +1,126 @@
+//! 配置检查
+//!
+//! - `check`：检查监听地址、TLS 证书/私钥（可读且匹配）与版本/密码套件/ALPN 设置、认证配置、用户重复，失败时非零退出
+//! - `print-config`：输出补全默认值后的完整配置，密钥字段替换为 `***`，URL 中的凭据去掉
+
+use std::{borrow::Cow, collections::HashSet, net::SocketAddr};
+
+use anyhow::{bail, Context, Result};
+use rustls::{
+    crypto::ring::sign::any_supported_type,
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    sign::CertifiedKey,
+};
+
+use crate::{
+    auth::AuthState,
+    config::{Config, ServerConfig},
+    secret,
+};
+
+/// 输出时隐藏的字段
+const SECRET_KEYS: &[&str] = &["token", "tokens", "peer_token", "secret", "client_secret", "sentry_dsn"];
+
+/// 输出时隐藏全部值的表
+const SECRET_TABLES: &[&str] = &["headers"];
+
+/// 校验配置
+pub fn check(path: &str, config: &Config) -> Result<()> {
+    format!("{}:{}", config.server.host, config.server.port)
+        .parse::<SocketAddr>()
+        .context("server.host / server.port 无效")?;
+    if !config.server.tls_self_signed {
+        check_tls(&config.server)?;
+    }
+    crate::tls::load_tls_config(&config.server)?;
+    check_users(config)?;
+    AuthState::new(config, &config.users).context("认证配置无效")?;
+    crate::scripting::Scripts::load(&config.scripts)?;
+
+    println!("配置有效: {}", path);
+    Ok(())
+}
+
+/// 输出生效配置（密钥已隐藏）：`SecretString` 字段按类型隐藏，其余按字段名隐藏，URL 中的凭据去掉
+pub fn print(config: &Config) -> Result<()> {
+    let mut value = secret::redacted(|| toml::Value::try_from(config))?;
+    redact(&mut value);
+    print!("{}", toml::to_string_pretty(&value)?);
+    Ok(())
+}
+
+fn check_tls(server: &ServerConfig) -> Result<()> {
+    let certs = CertificateDer::pem_file_iter(&server.tls_cert)
+        .and_then(|iter| iter.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("无法读取证书: {}", server.tls_cert))?;
+    if certs.is_empty() {
+        bail!("证书文件中没有证书: {}", server.tls_cert);
+    }
+
+    let key = PrivateKeyDer::from_pem_file(&server.tls_key)
+        .with_context(|| format!("无法读取私钥: {}", server.tls_key))?;
+    let key = any_supported_type(&key).context("不支持的私钥类型")?;
+
+    CertifiedKey::new(certs, key)
+        .keys_match()
+        .context("证书与私钥不匹配")?;
+    Ok(())
+}
+
+fn check_users(config: &Config) -> Result<()> {
+    let mut names = HashSet::new();
+    let mut tokens = HashSet::new();
+    for u in &config.users {
+        if !names.insert(u.name.as_str()) {
+            bail!("用户名重复: {}", u.name);
+        }
+        if let (Some(from), Some(until)) = (u.valid_from, u.expires_at) {
+            if from >= until {
+                bail!("valid_from 须早于 expires_at: {}", u.name);
+            }
+        }
+        if u.all_tokens().next().is_none() {
+            bail!("用户没有 token: {}", u.name);
+        }
+        for (token, _) in u.all_tokens() {
+            if !tokens.insert(token) {
+                bail!("token 重复: {}", u.name);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn redact(value: &mut toml::Value) {
+    match value {
+        toml::Value::Table(table) => {
+            for (key, item) in table.iter_mut() {
+                if SECRET_KEYS.contains(&key.as_str()) {
+                    redact_secret(item);
+                } else if SECRET_TABLES.contains(&key.as_str()) {
+                    if let Some(t) = item.as_table_mut() {
+                        t.iter_mut().for_each(|(_, v)| *v = toml::Value::String("***".into()));
+                    }
+                } else {
+                    redact(item);
+                }
+            }
+        }
+        toml::Value::Array(items) => items.iter_mut().for_each(redact),
+        toml::Value::String(s) => {
+            if let Cow::Owned(redacted) = secret::redact_url(s) {
+                *s = redacted;
+            }
+        }
+        _ => {}
+    }
+}
+
+/// 隐藏密钥字段：字符串替换为 `***`，数组逐项处理，表递归处理
+fn redact_secret(value: &mut toml::Value) {
+    match value {
+        toml::Value::String(_) => *value = toml::Value::String("***".into()),
+        toml::Value::Array(items) => items.iter_mut().for_each(redact_secret),
+        _ => redact(value),
+    }
+}
@@ -0,0 +1,213 @@
+//! 命令行参数
+
+use anyhow::{Context, Result};
+use chrono::NaiveDate;
+use clap::{Parser, Subcommand};
+
+use crate::{
+    config::{Config, Overrides},
+    usage,
+};
+
+#[derive(Parser)]
+#[command(name = "ws-relay-core", version, about = "高性能 WebSocket + REST 中继代理")]
+pub struct Cli {
+    /// 配置文件
+    #[arg(short, long, global = true, default_value = "config.toml")]
+    pub config: String,
+    /// 覆盖 server.port
+    #[arg(short, long, global = true)]
+    pub port: Option<u16>,
+    /// 日志级别，如 info / debug / ws_relay_core=trace（优先于 RUST_LOG）
+    #[arg(long, global = true)]
+    pub log_level: Option<String>,
+    /// 不写 PID 文件（忽略 server.pid_file）
+    #[arg(long, global = true)]
+    pub no_pidfile: bool,
+    /// 后台运行（仅 Unix），stdout/stderr 写入 `<log-dir>/ws-relay-core.log`
+    #[arg(long)]
+    pub daemon: bool,
+    /// 后台运行时的日志目录
+    #[arg(long, default_value = "logs")]
+    pub log_dir: String,
+    /// 配置文件（兼容旧用法 `ws-relay-core config.toml`）
+    #[arg(hide = true)]
+    config_file: Option<String>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// 启动服务（默认）
+    Run,
+    /// 通过控制通道通知运行中的实例重新加载用户
+    Reload,
+    /// 校验配置（TLS 证书/私钥、认证配置等），失败时非零退出
+    Check {
+        /// 配置文件，默认为 `--config`
+        #[arg(id = "check_config", value_name = "CONFIG")]
+        config: Option<String>,
+    },
+    /// 输出补全默认值后的生效配置（密钥已隐藏）
+    PrintConfig {
+        /// 配置文件，默认为 `--config`
+        #[arg(id = "print_config", value_name = "CONFIG")]
+        config: Option<String>,
+    },
+    /// 回放录制的会话
+    Replay {
+        /// 录制文件（JSONL）
+        capture: String,
+        /// 目标 URL
+        target: String,
+    },
+    /// 校验审计日志的哈希链（`hash_chain = true`）
+    VerifyAudit {
+        /// 审计日志文件
+        file: String,
+    },
+    /// 压测 relay：并发建立 /ws 会话并统计握手延迟、吞吐与错误
+    Bench {
+        /// relay 的 /ws 地址，如 wss://relay.example.com/ws
+        #[arg(long)]
+        url: String,
+        /// 认证 token
+        #[arg(long)]
+        token: String,
+        /// 目标 URL，须原样回复消息
+        #[arg(long, default_value = "internal://echo")]
+        target: String,
+        /// 会话总数
+        #[arg(long, default_value_t = 100)]
+        connections: usize,
+        /// 每秒新建会话数（0 为不限）
+        #[arg(long, default_value_t = 50)]
+        rate: u32,
+        /// 压测时长（秒）
+        #[arg(long, default_value_t = 10)]
+        duration: u64,
+        /// 消息大小（字节）
+        #[arg(long, default_value_t = 64)]
+        size: usize,
+        /// 发送 binary 消息（默认 text）
+        #[arg(long)]
+        binary: bool,
+        /// 每个会话未收到回复的消息数上限（1 为逐条往返）
+        #[arg(long, default_value_t = 1)]
+        window: usize,
+        /// 握手与单条消息回复的超时（秒）
+        #[arg(long, default_value_t = 10)]
+        timeout: u64,
+    },
+    /// 用量统计（读取访问日志）
+    Usage {
+        #[command(subcommand)]
+        command: UsageCommand,
+    },
+    /// 在终端实时显示活跃会话、吞吐与最近异常（经管理 API）
+    Status {
+        /// relay 地址，默认 https://localhost:<server.port>
+        #[arg(long)]
+        url: Option<String>,
+        /// 管理 token，默认读取配置中的 `admin.token`
+        #[arg(long)]
+        token: Option<String>,
+        /// 刷新间隔（秒）
+        #[arg(long, default_value_t = 2)]
+        interval: u64,
+        /// 不校验 relay 的证书（自签名证书）
+        #[arg(long)]
+        insecure: bool,
+        /// 只输出一次
+        #[arg(long)]
+        once: bool,
+    },
+    /// 生成自签名证书与私钥（仅用于本地测试）
+    GenCert {
+        /// 证书包含的主机名 / IP
+        #[arg(default_values = crate::tls::SELF_SIGNED_NAMES)]
+        names: Vec<String>,
+        /// 证书输出路径
+        #[arg(long, default_value = "cert.pem")]
+        cert: String,
+        /// 私钥输出路径
+        #[arg(long, default_value = "key.pem")]
+        key: String,
+        /// 覆盖已存在的文件
+        #[arg(long)]
+        force: bool,
+    },
+    /// 输出版本
+    Version,
+}
+
+#[derive(Subcommand)]
+pub enum UsageCommand {
+    /// 按用户汇总日期范围内的会话数、字节数与时长，输出到 stdout
+    Export {
+        /// 起始日期（UTC，含），如 2024-06-01
+        #[arg(long)]
+        from: NaiveDate,
+        /// 结束日期（UTC，含）
+        #[arg(long)]
+        to: NaiveDate,
+        /// 输出格式
+        #[arg(long, value_enum, default_value_t = usage::Format::Csv)]
+        format: usage::Format,
+    },
+}
+
+impl Cli {
+    /// 配置文件路径：`check` / `print-config` 的参数优先，其次为旧用法的位置参数与 `--config`
+    pub fn config_path(&self) -> &str {
+        let subcommand = match &self.command {
+            Some(Command::Check { config } | Command::PrintConfig { config }) => config.as_deref(),
+            _ => None,
+        };
+        subcommand.or(self.config_file.as_deref()).unwrap_or(&self.config)
+    }
+
+    /// 命令行对配置的覆盖
+    pub fn overrides(&self) -> Overrides {
+        Overrides {
+            port: self.port,
+            no_pidfile: self.no_pidfile,
+        }
+    }
+
+    /// 加载配置并应用命令行覆盖
+    pub fn load_config(&self) -> Result<Config> {
+        let path = self.config_path();
+        let mut config = Config::load(path).with_context(|| format!("配置解析失败: {}", path))?;
+        self.overrides().apply(&mut config);
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Cli {
+        Cli::try_parse_from(std::iter::once("ws-relay-core").chain(args.iter().copied())).unwrap()
+    }
+
+    #[test]
+    fn check_takes_config_path() {
+        let cli = parse(&["check", "prod.toml"]);
+        assert!(matches!(cli.command, Some(Command::Check { .. })));
+        assert_eq!(cli.config_path(), "prod.toml");
+        assert_eq!(parse(&["print-config", "prod.toml"]).config_path(), "prod.toml");
+        assert_eq!(parse(&["check"]).config_path(), "config.toml");
+        assert_eq!(parse(&["--config", "a.toml", "check"]).config_path(), "a.toml");
+        assert_eq!(parse(&["check", "--config", "a.toml"]).config_path(), "a.toml");
+        assert_eq!(parse(&["--config", "a.toml", "check", "b.toml"]).config_path(), "b.toml");
+    }
+
+    #[test]
+    fn legacy_positional_config() {
+        assert_eq!(parse(&["legacy.toml"]).config_path(), "legacy.toml");
+        assert_eq!(parse(&["-p", "8443", "run"]).port, Some(8443));
+    }
+}
@@ -0,0 +1,328 @@
+//! 集群共享状态
+//!
+//! 多个 relay 实例部署在负载均衡之后时，`[cluster]` 通过 Redis 共享用户的会话数、配额用量与封禁列表：
+//!
+//! | 内容 | Redis key | 说明 |
+//! |------|------|------|
+//! | 会话数 | `<prefix>sessions:<node_id>`（hash，用户 → 会话数） | 各节点定期覆盖写入，`node_ttl_secs` 后过期 |
+//! | 配额用量 | `<prefix>quota:<周期>:<用户>` | 各节点以 INCRBY 汇总新增用量 |
+//! | 封禁 | `<prefix>bans`（set） | 管理 API 封禁 / 解封后由本节点写入 |
+//!
+//! 每 `sync_secs` 秒同步一次，期间按本地计数加上次同步的结果判断。Redis 不可用时降级为本地限制：
+//! 会话数只计本节点，配额按上次同步的合计加本地新增，封禁列表保持上次同步的结果；
+//! 未汇总的用量与封禁变更在恢复后补写。
+//!
+//! 不使用 Redis 时可配置 `peers`，节点之间直接同步（见 [`crate::peer_sync`]）。
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use redis::{aio::MultiplexedConnection, AsyncCommands};
+use serde::{Deserialize, Serialize};
+use tokio::time::timeout;
+use tracing::{debug, info, warn};
+
+use crate::{
+    chain,
+    config::{ClusterConfig, Config},
+    peer_sync::{self, PeerState},
+    state::AppState,
+    stats::TerminateReason,
+};
+
+/// 单次同步的最长时间
+const SYNC_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 配额用量 key 的保留时间（覆盖最长的月度周期）
+const QUOTA_TTL_SECS: i64 = 40 * 86400;
+
+/// 封禁 / 解封记录，节点间同步时取较新的记录
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BanRecord {
+    pub banned: bool,
+    /// 操作时间（Unix 毫秒）
+    pub at_ms: i64,
+}
+
+impl BanRecord {
+    /// 同一时间的封禁优先于解封
+    pub fn newer_than(&self, other: &BanRecord) -> bool {
+        (self.at_ms, self.banned) > (other.at_ms, other.banned)
+    }
+}
+
+#[derive(Default)]
+pub struct Cluster {
+    config: Option<ClusterConfig>,
+    redis: Option<redis::Client>,
+    pub(crate) node_id: String,
+    /// 其他节点的会话数（用户 → 会话数）
+    remote_sessions: Mutex<HashMap<String, usize>>,
+    bans: Mutex<HashSet<String>>,
+    /// 每个用户最近一次封禁 / 解封
+    ban_log: Mutex<HashMap<String, BanRecord>>,
+    /// 尚未写入 Redis 的封禁变更（用户 → 是否封禁）
+    pending_bans: Mutex<HashMap<String, bool>>,
+    /// 节点间同步（`peers`）收到的状态
+    pub(crate) peers: Mutex<PeerState>,
+    /// 最近一次同步成功
+    connected: AtomicBool,
+}
+
+impl Cluster {
+    pub fn new(config: &Config) -> Result<Self> {
+        let Some(ref cluster) = config.cluster else {
+            return Ok(Self::default());
+        };
+        let redis = match cluster.redis_url {
+            Some(ref url) => Some(redis::Client::open(url.expose()).context("cluster.redis_url 格式错误")?),
+            None => None,
+        };
+        let node_id = cluster
+            .node_id
+            .clone()
+            .unwrap_or_else(|| chain::relay_id(&config.server).to_string());
+        Ok(Self {
+            config: Some(cluster.clone()),
+            redis,
+            node_id,
+            ..Default::default()
+        })
+    }
+
+    /// 其他节点上该用户的会话数（未配置集群或 Redis 不可用时为 0）
+    pub fn remote_sessions(&self, user: &str) -> usize {
+        self.remote_sessions.lock().unwrap().get(user).copied().unwrap_or_default()
+    }
+
+    pub fn is_banned(&self, user: &str) -> bool {
+        self.bans.lock().unwrap().contains(user)
+    }
+
+    /// 本节点立即生效，其他节点在下次同步后生效
+    pub fn set_banned(&self, user: &str, banned: bool) {
+        let mut bans = self.bans.lock().unwrap();
+        if banned {
+            bans.insert(user.to_string());
+        } else {
+            bans.remove(user);
+        }
+        let record = BanRecord {
+            banned,
+            at_ms: Utc::now().timestamp_millis(),
+        };
+        self.ban_log.lock().unwrap().insert(user.to_string(), record);
+        if self.redis.is_some() {
+            self.pending_bans.lock().unwrap().insert(user.to_string(), banned);
+        }
+    }
+
+    pub(crate) fn ban_log(&self) -> HashMap<String, BanRecord> {
+        self.ban_log.lock().unwrap().clone()
+    }
+
+    /// 合并其他节点的封禁记录，返回新封禁的用户
+    pub(crate) fn merge_bans(&self, records: HashMap<String, BanRecord>) -> Vec<String> {
+        let mut bans = self.bans.lock().unwrap();
+        let mut log = self.ban_log.lock().unwrap();
+        let mut added = Vec::new();
+        for (user, record) in records {
+            if log.get(&user).is_some_and(|r| !record.newer_than(r)) {
+                continue;
+            }
+            if record.banned {
+                if bans.insert(user.clone()) {
+                    added.push(user.clone());
+                }
+            } else {
+                bans.remove(&user);
+            }
+            log.insert(user, record);
+        }
+        added
+    }
+
+    pub(crate) fn set_remote_sessions(&self, sessions: HashMap<String, usize>) {
+        *self.remote_sessions.lock().unwrap() = sessions;
+    }
+
+    pub(crate) fn set_connected(&self, connected: bool) {
+        self.connected.store(connected, Ordering::Relaxed);
+    }
+
+    pub fn banned_users(&self) -> Vec<String> {
+        let mut users: Vec<_> = self.bans.lock().unwrap().iter().cloned().collect();
+        users.sort();
+        users
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.is_some()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    fn key(&self, name: &str) -> String {
+        let prefix = self.config.as_ref().map_or("", |c| c.key_prefix.as_str());
+        format!("{prefix}{name}")
+    }
+
+    /// 与 Redis 同步一次
+    async fn sync(&self, conn: &mut MultiplexedConnection, state: &AppState, ttl: i64) -> Result<()> {
+        // 本节点的会话数
+        let own = self.key(&format!("sessions:{}", self.node_id));
+        let local: Vec<(String, usize)> = state.stats.sessions_by_user().into_iter().collect();
+        let mut pipe = redis::pipe();
+        pipe.atomic().del(&own).ignore();
+        if !local.is_empty() {
+            pipe.hset_multiple(&own, &local).ignore();
+        }
+        pipe.expire(&own, ttl).ignore();
+        pipe.query_async::<()>(conn).await?;
+
+        // 其他节点的会话数
+        let mut keys = Vec::new();
+        let mut iter = conn.scan_match::<_, String>(self.key("sessions:*")).await?;
+        while let Some(key) = iter.next_item().await {
+            keys.push(key);
+        }
+        drop(iter);
+        let mut remote = HashMap::new();
+        for key in keys.iter().filter(|k| **k != own) {
+            let counts: HashMap<String, usize> = conn.hgetall(key).await?;
+            for (user, n) in counts {
+                *remote.entry(user).or_default() += n;
+            }
+        }
+        *self.remote_sessions.lock().unwrap() = remote;
+
+        // 配额用量：写入本节点新增的用量，读回所有节点的合计
+        let (period, pending) = state.quota.take_pending();
+        let quota_prefix = self.key(&format!("quota:{period}:"));
+        if !pending.is_empty() {
+            let mut pipe = redis::pipe();
+            pipe.atomic();
+            for (user, bytes) in &pending {
+                let key = format!("{quota_prefix}{user}");
+                pipe.incr(&key, *bytes).ignore().expire(&key, QUOTA_TTL_SECS).ignore();
+            }
+            if let Err(e) = pipe.query_async::<()>(conn).await {
+                state.quota.restore_pending(&period, pending);
+                return Err(e.into());
+            }
+        }
+        let mut keys = Vec::new();
+        let mut iter = conn.scan_match::<_, String>(format!("{quota_prefix}*")).await?;
+        while let Some(key) = iter.next_item().await {
+            keys.push(key);
+        }
+        drop(iter);
+        if !keys.is_empty() {
+            let totals: Vec<Option<u64>> = redis::cmd("MGET").arg(&keys).query_async(conn).await?;
+            let global = keys
+                .iter()
+                .zip(totals)
+                .filter_map(|(k, v)| Some((k.strip_prefix(&quota_prefix)?.to_string(), v?)))
+                .collect();
+            state.quota.set_global(&period, global);
+        }
+
+        // 封禁列表：先写入本节点的变更，再以 Redis 为准
+        let changes = std::mem::take(&mut *self.pending_bans.lock().unwrap());
+        if !changes.is_empty() {
+            let mut pipe = redis::pipe();
+            for (user, banned) in &changes {
+                match banned {
+                    true => pipe.sadd(self.key("bans"), user).ignore(),
+                    false => pipe.srem(self.key("bans"), user).ignore(),
+                };
+            }
+            if let Err(e) = pipe.query_async::<()>(conn).await {
+                let mut pending = self.pending_bans.lock().unwrap();
+                for (user, banned) in changes {
+                    pending.entry(user).or_insert(banned);
+                }
+                return Err(e.into());
+            }
+        }
+        let bans: HashSet<String> = conn.smembers(self.key("bans")).await?;
+        let added: Vec<String> = {
+            let mut local = self.bans.lock().unwrap();
+            // 同步期间本节点又有变更的用户保持本地状态
+            let pending = self.pending_bans.lock().unwrap();
+            let mut merged = bans;
+            for (user, banned) in pending.iter() {
+                match banned {
+                    true => merged.insert(user.clone()),
+                    false => merged.remove(user),
+                };
+            }
+            let added = merged.difference(&local).cloned().collect();
+            *local = merged;
+            added
+        };
+        for user in added {
+            let n = state.stats.terminate_user(&user, TerminateReason::Banned);
+            if n > 0 {
+                warn!("[{}] 用户已被封禁，终止 {} 个活跃会话", user, n);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 后台定期同步，Redis 不可用时降级为本地限制并在恢复后继续
+pub fn spawn(state: AppState) {
+    let Some(config) = state.cluster.config.clone() else {
+        return;
+    };
+    let Some(client) = state.cluster.redis.clone() else {
+        peer_sync::spawn(state, config);
+        return;
+    };
+    info!("集群: 节点 {}，Redis 同步间隔 {} 秒", state.cluster.node_id, config.sync_secs);
+    tokio::spawn(async move {
+        let cluster = &state.cluster;
+        let ttl = config.node_ttl_secs as i64;
+        let mut conn: Option<MultiplexedConnection> = None;
+        let mut healthy = None;
+        let mut interval = tokio::time::interval(Duration::from_secs(config.sync_secs));
+        loop {
+            interval.tick().await;
+            let result = timeout(SYNC_TIMEOUT, async {
+                if conn.is_none() {
+                    conn = Some(client.get_multiplexed_async_connection().await?);
+                }
+                cluster.sync(conn.as_mut().unwrap(), &state, ttl).await
+            })
+            .await
+            .unwrap_or_else(|_| Err(anyhow::anyhow!("同步超时")));
+
+            cluster.set_connected(result.is_ok());
+            match result {
+                Ok(()) if healthy != Some(true) => info!("集群: 已连接 Redis"),
+                Ok(()) => {}
+                Err(e) => {
+                    conn = None;
+                    cluster.remote_sessions.lock().unwrap().clear();
+                    if healthy != Some(false) {
+                        warn!("集群: Redis 不可用，降级为本地限制 - {:#}", e);
+                    } else {
+                        debug!("集群: Redis 同步失败 - {:#}", e);
+                    }
+                }
+            }
+            healthy = Some(cluster.is_connected());
+        }
+    });
+}
@@ -0,0 +1,199 @@
+//! 重新加载时的配置差异
+//!
+//! 用户（`[[users]]` 或用户库）即时生效，其余配置项在运行时不会重新读取，变化时列为需要重启。
+//! 只输出变化的用户名与配置路径，不输出取值（可能包含密钥）。
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+};
+
+use crate::config::{Config, User};
+
+#[derive(Default)]
+pub struct ReloadSummary {
+    pub users_added: Vec<String>,
+    pub users_removed: Vec<String>,
+    /// (用户名, 变化的字段)
+    pub users_changed: Vec<(String, Vec<String>)>,
+    /// 需要重启才生效的配置路径，如 `server.port`
+    pub restart_required: Vec<String>,
+}
+
+impl ReloadSummary {
+    pub fn is_empty(&self) -> bool {
+        self.users_added.is_empty()
+            && self.users_removed.is_empty()
+            && self.users_changed.is_empty()
+            && self.restart_required.is_empty()
+    }
+}
+
+/// 单行摘要，用于日志与控制通道
+impl fmt::Display for ReloadSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "无变化");
+        }
+        let mut parts = Vec::new();
+        if !self.users_added.is_empty() {
+            parts.push(format!("新增用户: {}", self.users_added.join(", ")));
+        }
+        if !self.users_removed.is_empty() {
+            parts.push(format!("删除用户: {}", self.users_removed.join(", ")));
+        }
+        if !self.users_changed.is_empty() {
+            let changed: Vec<_> = self
+                .users_changed
+                .iter()
+                .map(|(name, fields)| format!("{}({})", name, fields.join(", ")))
+                .collect();
+            parts.push(format!("修改用户: {}", changed.join(", ")));
+        }
+        if !self.restart_required.is_empty() {
+            parts.push(format!("需重启生效: {}", self.restart_required.join(", ")));
+        }
+        write!(f, "{}", parts.join("; "))
+    }
+}
+
+/// 比较新旧配置（`users` 为各自生效的用户列表）
+pub fn diff(old: &Config, new: &Config) -> ReloadSummary {
+    let mut summary = ReloadSummary::default();
+
+    let old_users = by_name(&old.users);
+    let new_users = by_name(&new.users);
+    for (name, user) in &new_users {
+        match old_users.get(name) {
+            None => summary.users_added.push(name.to_string()),
+            Some(old) => {
+                let mut fields = Vec::new();
+                changed_paths("", old, user, &mut fields);
+                if !fields.is_empty() {
+                    summary.users_changed.push((name.to_string(), fields));
+                }
+            }
+        }
+    }
+    summary.users_removed = old_users
+        .keys()
+        .filter(|name| !new_users.contains_key(*name))
+        .map(|name| name.to_string())
+        .collect();
+
+    let (mut old, mut new) = (to_value(old), to_value(new));
+    for v in [&mut old, &mut new] {
+        if let toml::Value::Table(t) = v {
+            t.remove("users");
+        }
+    }
+    changed_paths("", &old, &new, &mut summary.restart_required);
+    summary
+}
+
+fn by_name(users: &[User]) -> BTreeMap<&str, toml::Value> {
+    users.iter().map(|u| (u.name.as_str(), to_value(u))).collect()
+}
+
+fn to_value<T: serde::Serialize>(v: &T) -> toml::Value {
+    toml::Value::try_from(v).unwrap_or(toml::Value::Table(Default::default()))
+}
+
+/// 递归比较表，收集取值不同的叶子路径（数组整体比较）
+fn changed_paths(prefix: &str, old: &toml::Value, new: &toml::Value, out: &mut Vec<String>) {
+    match (old, new) {
+        (toml::Value::Table(a), toml::Value::Table(b)) => {
+            let keys: BTreeSet<_> = a.keys().chain(b.keys()).collect();
+            for key in keys {
+                let path = match prefix {
+                    "" => key.clone(),
+                    _ => format!("{}.{}", prefix, key),
+                };
+                match (a.get(key), b.get(key)) {
+                    (Some(x), Some(y)) => changed_paths(&path, x, y, out),
+                    _ => out.push(path),
+                }
+            }
+        }
+        (a, b) if a != b => out.push(prefix.to_string()),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Overrides;
+
+    fn config(port: u16, users: &str) -> Config {
+        toml::from_str(&format!(
+            "[server]\nhost = \"0.0.0.0\"\nport = {}\ntls_cert = \"c\"\ntls_key = \"k\"\n{}",
+            port, users
+        ))
+        .unwrap()
+    }
+
+    const OLD_USERS: &str = r#"
+        [[users]]
+        name = "alice"
+        token = "alice_old_secret"
+        max_sessions = 1
+
+        [[users]]
+        name = "bob"
+        token = "bob_secret"
+    "#;
+
+    const NEW_USERS: &str = r#"
+        [[users]]
+        name = "alice"
+        token = "alice_new_secret"
+        max_sessions = 2
+
+        [[users]]
+        name = "carol"
+        token = "carol_secret"
+    "#;
+
+    #[test]
+    fn reports_users_and_restart_paths() {
+        let summary = diff(&config(443, OLD_USERS), &config(8443, NEW_USERS));
+        assert_eq!(summary.users_added, ["carol"]);
+        assert_eq!(summary.users_removed, ["bob"]);
+        assert_eq!(summary.users_changed.len(), 1);
+        assert_eq!(summary.users_changed[0].0, "alice");
+        assert_eq!(summary.users_changed[0].1, ["max_sessions", "token"]);
+        assert_eq!(summary.restart_required, ["server.port"]);
+    }
+
+    #[test]
+    fn summary_has_no_values() {
+        let text = diff(&config(443, OLD_USERS), &config(8443, NEW_USERS)).to_string();
+        assert!(text.contains("server.port"));
+        for value in ["alice_old_secret", "alice_new_secret", "carol_secret", "8443", "443"] {
+            assert!(!text.contains(value), "{}", text);
+        }
+    }
+
+    #[test]
+    fn unchanged_config() {
+        let summary = diff(&config(443, OLD_USERS), &config(443, OLD_USERS));
+        assert!(summary.is_empty());
+        assert_eq!(summary.to_string(), "无变化");
+    }
+
+    #[test]
+    fn cli_overrides_are_not_reported() {
+        let overrides = Overrides {
+            port: Some(8443),
+            no_pidfile: true,
+        };
+        let mut old = config(443, OLD_USERS);
+        old.server.pid_file = Some("relay.pid".into());
+        overrides.apply(&mut old);
+        let mut new = config(443, OLD_USERS);
+        new.server.pid_file = Some("relay.pid".into());
+        overrides.apply(&mut new);
+        assert!(diff(&old, &new).is_empty());
+    }
+}
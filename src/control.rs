@@ -0,0 +1,192 @@
+//! 本地控制通道
+//!
+//! 在 `[control] listen` 上接受 TCP 连接，每行一条命令，每条命令返回一行结果（`ok ...` / `error ...`）：
+//!
+//! | 命令 | 作用 |
+//! |------|------|
+//! | `reload` | 重新加载配置，返回变化摘要（用户即时生效，其余需重启） |
+//! | `status` | 返回 pid、运行时长、本地用户数、活跃会话数 / 上限、被隔离的会话 panic 数、打开的文件描述符 / 上限、常驻内存 |
+//! | `shutdown` | 优雅退出 |
+//!
+//! 不依赖 Unix 信号，Windows 下同样可用。单行超过 4096 字节时断开连接。配置了 `token` 时连接的第一行须为该 token；
+//! 能执行 `reload` / `shutdown`，因此监听非回环地址时必须配置 `token`（启动时校验）。
+//! `ws-relay-core reload` 即通过此通道发送 `reload`。
+
+use std::{sync::Arc, time::Instant};
+
+use anyhow::{bail, Context, Result};
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+use tracing::{debug, info, warn};
+
+use crate::{config::ControlConfig, health, panic_guard, resources, secret::SecretString, state::AppState, upgrade};
+
+/// 单行（token 或命令，含换行）的长度上限，超出时断开连接
+const MAX_LINE_BYTES: u64 = 4096;
+
+struct Control {
+    state: AppState,
+    config_path: String,
+    token: Option<SecretString>,
+    handle: axum_server::Handle,
+    started: Instant,
+}
+
+/// 绑定控制端口并在后台处理命令
+pub async fn spawn(
+    config: &ControlConfig,
+    state: AppState,
+    config_path: String,
+    handle: axum_server::Handle,
+) -> Result<()> {
+    let listener = upgrade::bind(upgrade::Role::Control, &config.listen).await?;
+    info!("控制通道: {}", config.listen);
+
+    let ctl = Arc::new(Control {
+        state,
+        config_path,
+        token: config.token.clone(),
+        handle,
+        started: Instant::now(),
+    });
+
+    tokio::spawn(async move {
+        loop {
+            let accepted = tokio::select! {
+                accepted = listener.accept() => accepted,
+                _ = upgrade::wait_handoff() => return,
+            };
+            let (stream, addr) = match accepted {
+                Ok(s) => s,
+                Err(e) => {
+                    warn!("控制通道 accept 失败: {}", e);
+                    continue;
+                }
+            };
+            let ctl = ctl.clone();
+            tokio::spawn(async move {
+                if let Err(e) = ctl.serve(stream).await {
+                    debug!("控制连接断开 ({}): {}", addr, e);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+/// 连接运行中的实例并执行一条命令，返回结果行
+pub async fn send(config: &ControlConfig, cmd: &str) -> Result<String> {
+    let stream = TcpStream::connect(&config.listen)
+        .await
+        .with_context(|| format!("无法连接控制通道: {}", config.listen))?;
+    let (r, mut w) = stream.into_split();
+
+    let mut request = String::new();
+    if let Some(ref token) = config.token {
+        request.push_str(token.expose());
+        request.push('\n');
+    }
+    request.push_str(cmd);
+    request.push('\n');
+    w.write_all(request.as_bytes()).await?;
+
+    let reply = BufReader::new(r)
+        .lines()
+        .next_line()
+        .await?
+        .context("控制通道无响应")?;
+    match reply.strip_prefix("error ") {
+        Some(e) => bail!("{}", e),
+        None => Ok(reply),
+    }
+}
+
+impl Control {
+    async fn serve(&self, stream: TcpStream) -> Result<()> {
+        let (r, mut w) = stream.into_split();
+        let mut reader = BufReader::new(r);
+
+        if let Some(ref token) = self.token {
+            let line = read_line(&mut reader).await?.unwrap_or_default();
+            if !token.matches(line.trim()) {
+                w.write_all(b"error unauthorized\n").await?;
+                return Ok(());
+            }
+        }
+
+        while let Some(line) = read_line(&mut reader).await? {
+            let cmd = line.trim();
+            if cmd.is_empty() {
+                continue;
+            }
+            let reply = self.execute(cmd).await;
+            w.write_all(format!("{}\n", reply).as_bytes()).await?;
+        }
+        Ok(())
+    }
+
+    async fn execute(&self, cmd: &str) -> String {
+        match cmd {
+            "reload" => match self.state.reload(&self.config_path, "control").await {
+                Ok(summary) => format!("ok {}", summary),
+                Err(e) => {
+                    warn!("控制通道: 重新加载失败: {:#}", e);
+                    format!("error {:#}", e)
+                }
+            },
+            "status" => format!(
+                "ok pid={} uptime={}s users={} sessions={}/{} panics={} fds={}/{} rss_mb={}",
+                std::process::id(),
+                self.started.elapsed().as_secs(),
+                self.state.auth.user_count(),
+                self.state.stats.session_count(),
+                self.state.session_cap.map_or("-".into(), |n| n.to_string()),
+                panic_guard::count(),
+                resources::fd_usage().0,
+                resources::fd_usage().1,
+                resources::rss() >> 20
+            ),
+            "shutdown" => {
+                info!("控制通道: 收到 shutdown");
+                tokio::spawn(health::shutdown(self.state.clone(), self.handle.clone()));
+                "ok".to_string()
+            }
+            _ => format!("error 未知命令: {}", cmd),
+        }
+    }
+}
+
+/// 读取一行（不含换行），连接关闭时返回 None；超过 [`MAX_LINE_BYTES`] 时报错，由调用方断开连接
+async fn read_line<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<Option<String>> {
+    let mut buf = Vec::new();
+    let n = reader.take(MAX_LINE_BYTES).read_until(b'\n', &mut buf).await?;
+    if n == 0 {
+        return Ok(None);
+    }
+    if buf.last() != Some(&b'\n') && n as u64 == MAX_LINE_BYTES {
+        bail!("单行超过 {} 字节", MAX_LINE_BYTES);
+    }
+    let line = String::from_utf8(buf).context("不是有效的 UTF-8")?;
+    Ok(Some(line.trim_end_matches(['\r', '\n']).to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn line_length_is_capped() {
+        let mut input: &[u8] = b"token\r\nstatus\nlast";
+        assert_eq!(read_line(&mut input).await.unwrap().as_deref(), Some("token"));
+        assert_eq!(read_line(&mut input).await.unwrap().as_deref(), Some("status"));
+        assert_eq!(read_line(&mut input).await.unwrap().as_deref(), Some("last"));
+        assert_eq!(read_line(&mut input).await.unwrap(), None);
+
+        let fits = format!("{}\n", "a".repeat(MAX_LINE_BYTES as usize - 1));
+        assert!(read_line(&mut fits.as_bytes()).await.unwrap().is_some());
+        let long = "a".repeat(MAX_LINE_BYTES as usize * 4);
+        assert!(read_line(&mut long.as_bytes()).await.is_err());
+    }
+}
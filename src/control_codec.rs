@@ -0,0 +1,76 @@
+//! `/ws` 控制消息编码
+//!
+//! relay 与客户端之间的控制消息（切换目标请求与回复、连接结果、错误）默认为 JSON text。
+//! 以 binary 为主的客户端可在握手时通过 `Sec-WebSocket-Protocol` 选择其他编码，relay 发出的控制消息改为 binary：
+//!
+//! | 子协议 | 编码 |
+//! |------|------|
+//! | 未指定 / `ws-relay.json` | JSON（text） |
+//! | `ws-relay.msgpack` | MessagePack（binary，map 按字段名编码） |
+//! | `ws-relay.cbor` | CBOR（binary） |
+//!
+//! 字段与 JSON 相同，由同一组 serde 结构解析。选择 MessagePack / CBOR 后仍接受 JSON text 形式的控制消息；
+//! 能按所选编码解析为控制消息的 binary 消息不再转发给目标（解析见 [`crate::control_frame`]）。
+
+use axum::{extract::ws::Message, http::HeaderValue};
+use tracing::debug;
+
+use crate::control_frame::ControlFrame;
+
+/// relay 支持的子协议，按客户端给出的顺序选择第一个匹配项
+pub const SUBPROTOCOLS: [&str; 3] = ["ws-relay.json", "ws-relay.msgpack", "ws-relay.cbor"];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ControlCodec {
+    #[default]
+    Json,
+    MsgPack,
+    Cbor,
+}
+
+impl ControlCodec {
+    /// 由握手选定的子协议确定编码
+    pub fn from_protocol(protocol: Option<&HeaderValue>) -> Self {
+        match protocol.and_then(|p| p.to_str().ok()) {
+            Some("ws-relay.msgpack") => Self::MsgPack,
+            Some("ws-relay.cbor") => Self::Cbor,
+            _ => Self::Json,
+        }
+    }
+
+    /// 解析客户端发来的控制消息，不是控制消息时返回 `None`
+    pub fn decode(self, msg: &Message) -> Option<ControlFrame> {
+        match (self, msg) {
+            (_, Message::Text(text)) => ControlFrame::from_json(text),
+            (Self::MsgPack, Message::Binary(data)) => ControlFrame::from_msgpack(data),
+            (Self::Cbor, Message::Binary(data)) => ControlFrame::from_cbor(data),
+            _ => None,
+        }
+    }
+
+    /// 把 JSON 形式的控制消息转为发给客户端的消息
+    pub fn encode(self, json: String) -> Message {
+        if self == Self::Json {
+            return Message::Text(json.into());
+        }
+        let value: serde_json::Value = match serde_json::from_str(&json) {
+            Ok(v) => v,
+            Err(e) => {
+                debug!("控制消息不是合法 JSON: {}", e);
+                return Message::Text(json.into());
+            }
+        };
+        let mut buf = Vec::new();
+        let encoded = match self {
+            Self::MsgPack => rmp_serde::encode::write_named(&mut buf, &value).map_err(|e| e.to_string()),
+            _ => ciborium::into_writer(&value, &mut buf).map_err(|e| e.to_string()),
+        };
+        match encoded {
+            Ok(()) => Message::Binary(buf.into()),
+            Err(e) => {
+                debug!("控制消息编码失败（{:?}）: {}", self, e);
+                Message::Text(json.into())
+            }
+        }
+    }
+}
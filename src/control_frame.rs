@@ -0,0 +1,128 @@
+//! `/ws` 客户端控制消息的解析
+//!
+//! 客户端发来的每条消息都要先判断是否为控制消息，解析前后都有上限，畸形输入只会被当作普通数据转发：
+//!
+//! | 限制 | 值 | 超出时 |
+//! |------|------|------|
+//! | 消息大小 | [`MAX_FRAME_BYTES`] | 不解析，按数据转发 |
+//! | 嵌套深度 | 由各格式的解析器限制（JSON 128 层，CBOR / MessagePack 同样有上限） | 解析失败，按数据转发 |
+//! | `target` 长度 | [`MAX_TARGET_LEN`] | 同上 |
+//! | `versions` 个数 | [`MAX_VERSIONS`] | 同上 |
+//!
+//! 只依赖 serde 与各编码库，`fuzz/` 下的模糊测试直接引用本文件（`cargo +nightly fuzz run control_frame`）。
+
+use serde::Deserialize;
+
+/// 超过此大小的消息不是控制消息
+pub const MAX_FRAME_BYTES: usize = 64 << 10;
+
+/// 切换目标的 URL 长度上限
+pub const MAX_TARGET_LEN: usize = 8 << 10;
+
+/// `hello` 中版本号的个数上限
+pub const MAX_VERSIONS: usize = 16;
+
+/// 客户端控制消息
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ControlFrame {
+    /// `{"type":"switch","target":"wss://..."}`
+    Switch { target: String },
+    /// `{"type":"ack","seq":N}`，累计确认
+    Ack { seq: u64 },
+    /// `{"type":"hello","versions":[1]}`
+    Hello { versions: Vec<u32> },
+}
+
+impl ControlFrame {
+    /// JSON text
+    pub fn from_json(text: &str) -> Option<Self> {
+        // 绝大多数数据消息不含 `"type"`，无需解析
+        if text.len() > MAX_FRAME_BYTES || !text.contains("\"type\"") {
+            return None;
+        }
+        serde_json::from_str::<Self>(text).ok()?.checked()
+    }
+
+    /// MessagePack（map 按字段名编码）
+    pub fn from_msgpack(data: &[u8]) -> Option<Self> {
+        if data.len() > MAX_FRAME_BYTES {
+            return None;
+        }
+        rmp_serde::from_slice::<Self>(data).ok()?.checked()
+    }
+
+    /// CBOR
+    pub fn from_cbor(data: &[u8]) -> Option<Self> {
+        if data.len() > MAX_FRAME_BYTES {
+            return None;
+        }
+        ciborium::from_reader::<Self, _>(data).ok()?.checked()
+    }
+
+    fn checked(self) -> Option<Self> {
+        let ok = match self {
+            Self::Switch { ref target } => target.len() <= MAX_TARGET_LEN,
+            Self::Ack { .. } => true,
+            Self::Hello { ref versions } => versions.len() <= MAX_VERSIONS,
+        };
+        ok.then_some(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_json_frames() {
+        assert_eq!(
+            ControlFrame::from_json(r#"{"type":"switch","target":"wss://a/"}"#),
+            Some(ControlFrame::Switch { target: "wss://a/".into() })
+        );
+        assert_eq!(ControlFrame::from_json(r#"{"type":"ack","seq":7}"#), Some(ControlFrame::Ack { seq: 7 }));
+        assert_eq!(
+            ControlFrame::from_json(r#"{"type":"hello","versions":[1,2]}"#),
+            Some(ControlFrame::Hello { versions: vec![1, 2] })
+        );
+    }
+
+    #[test]
+    fn data_messages_are_not_control_frames() {
+        assert_eq!(ControlFrame::from_json(r#"{"op":"subscribe"}"#), None);
+        assert_eq!(ControlFrame::from_json(r#"{"type":"subscribe"}"#), None);
+        assert_eq!(ControlFrame::from_json(r#"{"type":"ack","seq":-1}"#), None);
+        assert_eq!(ControlFrame::from_json(r#"{"type":"switch"}"#), None);
+        assert_eq!(ControlFrame::from_json("not json \"type\""), None);
+    }
+
+    #[test]
+    fn enforces_limits() {
+        let target = "x".repeat(MAX_TARGET_LEN + 1);
+        let json = format!(r#"{{"type":"switch","target":"{}"}}"#, target);
+        assert_eq!(ControlFrame::from_json(&json), None);
+        let versions = vec!["1"; MAX_VERSIONS + 1].join(",");
+        assert_eq!(ControlFrame::from_json(&format!(r#"{{"type":"hello","versions":[{}]}}"#, versions)), None);
+        let padded = format!(r#"{{"type":"ack","seq":1,"pad":"{}"}}"#, " ".repeat(MAX_FRAME_BYTES));
+        assert_eq!(ControlFrame::from_json(&padded), None);
+        let nested = format!(r#"{{"type":"ack","seq":1,"x":{}{}}}"#, "[".repeat(1000), "]".repeat(1000));
+        assert_eq!(ControlFrame::from_json(&nested), None);
+    }
+
+    #[test]
+    fn parses_binary_encodings() {
+        #[derive(serde::Serialize)]
+        struct Ack {
+            r#type: &'static str,
+            seq: u64,
+        }
+        let ack = Ack { r#type: "ack", seq: 3 };
+        let msgpack = rmp_serde::to_vec_named(&ack).unwrap();
+        assert_eq!(ControlFrame::from_msgpack(&msgpack), Some(ControlFrame::Ack { seq: 3 }));
+        let mut cbor = Vec::new();
+        ciborium::into_writer(&ack, &mut cbor).unwrap();
+        assert_eq!(ControlFrame::from_cbor(&cbor), Some(ControlFrame::Ack { seq: 3 }));
+        assert_eq!(ControlFrame::from_msgpack(b"\xc1"), None);
+        assert_eq!(ControlFrame::from_cbor(b"\xff"), None);
+    }
+}
@@ -0,0 +1,172 @@
+//! REST 代理的服务端 cookie jar
+//!
+//! 上游响应的 `Set-Cookie` 按 用户 + 目标主机 保存，之后同一用户访问同一主机时自动附带，
+//! 客户端无需自行管理登录态。
+//!
+//! - 只按主机保存（忽略 `Domain`），遵循 `Path`、`Secure`、`Max-Age` / `Expires`
+//! - 每个 cookie 最长保存 `ttl_secs`（会话 cookie 同样适用）
+//! - 每个 用户 + 主机 最多 `max_cookies` 个，超出时淘汰最早写入的
+
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use axum::http::HeaderMap;
+use chrono::{DateTime, Utc};
+use tracing::{debug, warn};
+
+use crate::config::CookieJarConfig;
+
+/// 单个 cookie 的 name=value 长度上限（RFC 6265 建议至少支持 4096）
+const MAX_COOKIE_BYTES: usize = 4096;
+
+/// jar（用户 + 主机）数量上限
+const MAX_JARS: usize = 10000;
+
+struct Cookie {
+    name: String,
+    value: String,
+    path: String,
+    secure: bool,
+    expires: Instant,
+}
+
+impl Cookie {
+    /// 请求路径是否在 cookie 路径下（RFC 6265 5.1.4）
+    fn path_matches(&self, path: &str) -> bool {
+        path == self.path
+            || (path.starts_with(&self.path) && (self.path.ends_with('/') || path[self.path.len()..].starts_with('/')))
+    }
+}
+
+pub struct CookieJar {
+    config: CookieJarConfig,
+    /// (用户, 主机) → cookie
+    jars: Mutex<HashMap<(String, String), Vec<Cookie>>>,
+}
+
+impl CookieJar {
+    pub fn new(config: &CookieJarConfig) -> Self {
+        Self {
+            config: config.clone(),
+            jars: Mutex::default(),
+        }
+    }
+
+    /// 生成发往 `url` 的 `Cookie` 头部取值
+    pub fn cookie_header(&self, user: &str, url: &reqwest::Url) -> Option<String> {
+        let host = url.host_str()?.to_ascii_lowercase();
+        let secure = url.scheme() == "https";
+        let now = Instant::now();
+        let mut jars = self.jars.lock().unwrap();
+        let cookies = jars.get_mut(&(user.to_string(), host))?;
+        cookies.retain(|c| c.expires > now);
+        let pairs: Vec<_> = cookies
+            .iter()
+            .filter(|c| (secure || !c.secure) && c.path_matches(url.path()))
+            .map(|c| format!("{}={}", c.name, c.value))
+            .collect();
+        (!pairs.is_empty()).then(|| pairs.join("; "))
+    }
+
+    /// 保存响应中的 `Set-Cookie`
+    pub fn store(&self, user: &str, url: &reqwest::Url, headers: &HeaderMap) {
+        let Some(host) = url.host_str() else {
+            return;
+        };
+        let parsed: Vec<_> = headers
+            .get_all(axum::http::header::SET_COOKIE)
+            .into_iter()
+            .filter_map(|v| v.to_str().ok())
+            .filter_map(|v| self.parse(v, url.path()))
+            .collect();
+        if parsed.is_empty() {
+            return;
+        }
+
+        let now = Instant::now();
+        let mut jars = self.jars.lock().unwrap();
+        let key = (user.to_string(), host.to_ascii_lowercase());
+        if jars.len() >= MAX_JARS && !jars.contains_key(&key) {
+            jars.retain(|_, cookies| {
+                cookies.retain(|c| c.expires > now);
+                !cookies.is_empty()
+            });
+            if jars.len() >= MAX_JARS {
+                warn!("cookie jar 数量已达上限 {}，忽略 {} 的 Set-Cookie", MAX_JARS, host);
+                return;
+            }
+        }
+        let cookies = jars.entry(key).or_default();
+        for cookie in parsed {
+            cookies.retain(|c| !(c.name == cookie.name && c.path == cookie.path));
+            // 已过期（Max-Age=0 等）即删除
+            if cookie.expires > now {
+                debug!("保存 cookie: {} {}", host, cookie.name);
+                cookies.push(cookie);
+            }
+        }
+        if cookies.len() > self.config.max_cookies {
+            let excess = cookies.len() - self.config.max_cookies;
+            cookies.drain(..excess);
+        }
+    }
+
+    fn parse(&self, header: &str, request_path: &str) -> Option<Cookie> {
+        let mut parts = header.split(';');
+        let (name, value) = parts.next()?.split_once('=')?;
+        let (name, value) = (name.trim(), value.trim());
+        if name.is_empty() || name.len() + value.len() > MAX_COOKIE_BYTES {
+            return None;
+        }
+
+        let now = Instant::now();
+        let ttl = self.config.ttl_secs;
+        let mut cookie = Cookie {
+            name: name.to_string(),
+            value: value.to_string(),
+            path: default_path(request_path),
+            secure: false,
+            expires: now + Duration::from_secs(ttl),
+        };
+        let mut max_age = None;
+        for attr in parts {
+            let (key, val) = attr.split_once('=').unwrap_or((attr, ""));
+            let val = val.trim();
+            match key.trim().to_ascii_lowercase().as_str() {
+                "path" if val.starts_with('/') => cookie.path = val.to_string(),
+                "secure" => cookie.secure = true,
+                "max-age" => max_age = val.parse::<i64>().ok(),
+                "expires" if max_age.is_none() => {
+                    if let Ok(at) = DateTime::parse_from_rfc2822(val) {
+                        cookie.expires = expiry(now, at.timestamp() - Utc::now().timestamp(), ttl);
+                    }
+                }
+                _ => {}
+            }
+        }
+        // Max-Age 优先于 Expires
+        if let Some(secs) = max_age {
+            cookie.expires = expiry(now, secs, ttl);
+        }
+        Some(cookie)
+    }
+}
+
+/// `now` 加上 `secs` 秒（不超过 `ttl`），非正数视为已过期
+fn expiry(now: Instant, secs: i64, ttl: u64) -> Instant {
+    match u64::try_from(secs) {
+        Ok(secs) if secs > 0 => now + Duration::from_secs(secs.min(ttl)),
+        _ => now,
+    }
+}
+
+/// 未指定 Path 时的默认路径：请求路径去掉最后一段（RFC 6265 5.1.4）
+fn default_path(request_path: &str) -> String {
+    match request_path.rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(i) => request_path[..i].to_string(),
+    }
+}
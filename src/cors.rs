@@ -0,0 +1,64 @@
+//! CORS
+//!
+//! 浏览器应用直接访问 `/rest`、`/ws` 时需要跨域头部。预检请求（`OPTIONS`）由该层直接应答，不经过认证。
+
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use axum::http::{HeaderName, HeaderValue, Method};
+use tower_http::cors::{AllowHeaders, AllowMethods, AllowOrigin, CorsLayer, ExposeHeaders};
+
+use crate::config::CorsConfig;
+
+pub fn layer(config: &CorsConfig) -> Result<CorsLayer> {
+    let any = |list: &[String]| list.iter().any(|v| v == "*");
+    if config.allow_credentials
+        && (any(&config.allowed_origins) || any(&config.allowed_methods) || any(&config.allowed_headers) || any(&config.expose_headers))
+    {
+        bail!("cors: allow_credentials 不能与 \"*\" 同时使用");
+    }
+
+    let origins = match any(&config.allowed_origins) {
+        true => AllowOrigin::any(),
+        false => AllowOrigin::list(
+            config
+                .allowed_origins
+                .iter()
+                .map(|o| HeaderValue::from_str(o).with_context(|| format!("cors: 无效的来源 {}", o)))
+                .collect::<Result<Vec<_>>>()?,
+        ),
+    };
+    let methods = match any(&config.allowed_methods) {
+        true => AllowMethods::any(),
+        false => AllowMethods::list(
+            config
+                .allowed_methods
+                .iter()
+                .map(|m| Method::from_bytes(m.as_bytes()).with_context(|| format!("cors: 无效的方法 {}", m)))
+                .collect::<Result<Vec<_>>>()?,
+        ),
+    };
+    let headers = match any(&config.allowed_headers) {
+        true => AllowHeaders::any(),
+        false => AllowHeaders::list(header_names(&config.allowed_headers)?),
+    };
+    let expose = match any(&config.expose_headers) {
+        true => ExposeHeaders::any(),
+        false => ExposeHeaders::list(header_names(&config.expose_headers)?),
+    };
+
+    Ok(CorsLayer::new()
+        .allow_origin(origins)
+        .allow_methods(methods)
+        .allow_headers(headers)
+        .expose_headers(expose)
+        .allow_credentials(config.allow_credentials)
+        .max_age(Duration::from_secs(config.max_age_secs)))
+}
+
+fn header_names(names: &[String]) -> Result<Vec<HeaderName>> {
+    names
+        .iter()
+        .map(|h| HeaderName::try_from(h.as_str()).with_context(|| format!("cors: 无效的头部名称 {}", h)))
+        .collect()
+}
@@ -0,0 +1,204 @@
+//! 目标地址解析与连接
+//!
+//! - `[dns_overrides]` 中的主机名直接使用配置的 IP（不区分大小写），不查询 DNS
+//! - 其余主机名使用 hickory 异步解析器（带缓存），可配置上游 DNS 服务器与缓存时长
+//! - WS 目标按 happy eyeballs（RFC 8305）连接：IPv6 / IPv4 地址交替尝试，
+//!   上一个地址在 `happy_eyeballs_delay_ms` 内未连上即并发尝试下一个，先连上者胜出
+//! - 指定出口地址时，连接前先将 socket 绑定到该地址（只尝试同一地址族的目标地址）
+//! - REST 客户端通过 [`ReqwestResolver`] 使用同一解析器（连接阶段的 happy eyeballs 由 hyper 完成）
+//!
+//! 未调用 `init`（如 `replay` 子命令）时回退到系统解析。
+
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use futures_util::{stream::FuturesUnordered, StreamExt};
+use hickory_resolver::{
+    config::{LookupIpStrategy, NameServerConfig, NameServerConfigGroup, ResolverConfig},
+    name_server::TokioConnectionProvider,
+    proto::xfer::Protocol,
+    Resolver, TokioResolver,
+};
+use once_cell::sync::OnceCell;
+use tokio::{
+    net::{TcpSocket, TcpStream},
+    time::sleep,
+};
+use tracing::{debug, info};
+
+use socket2::SockRef;
+
+use crate::{config::DnsConfig, tcp};
+
+struct Dns {
+    resolver: TokioResolver,
+    overrides: HashMap<String, IpAddr>,
+    happy_eyeballs_delay: Duration,
+}
+
+static DNS: OnceCell<Dns> = OnceCell::new();
+
+/// 按配置创建全局解析器（启动时调用一次）
+pub fn init(config: &DnsConfig, overrides: &HashMap<String, IpAddr>) -> Result<()> {
+    let mut builder = if config.servers.is_empty() {
+        Resolver::builder_tokio().context("读取系统 DNS 配置失败")?
+    } else {
+        let mut group = NameServerConfigGroup::new();
+        for s in &config.servers {
+            let addr = s
+                .parse::<SocketAddr>()
+                .or_else(|_| s.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, 53)))
+                .with_context(|| format!("DNS 服务器地址无效: {}", s))?;
+            group.push(NameServerConfig::new(addr, Protocol::Udp));
+            group.push(NameServerConfig::new(addr, Protocol::Tcp));
+        }
+        Resolver::builder_with_config(
+            ResolverConfig::from_parts(None, vec![], group),
+            TokioConnectionProvider::default(),
+        )
+    };
+
+    let opts = builder.options_mut();
+    opts.ip_strategy = LookupIpStrategy::Ipv4AndIpv6;
+    opts.cache_size = config.cache_size;
+    if let Some(ttl) = config.cache_ttl_secs {
+        opts.positive_max_ttl = Some(Duration::from_secs(ttl));
+        opts.negative_max_ttl = Some(Duration::from_secs(ttl));
+    }
+
+    let dns = Dns {
+        resolver: builder.build(),
+        overrides: overrides
+            .iter()
+            .map(|(host, ip)| (host.to_ascii_lowercase(), *ip))
+            .collect(),
+        happy_eyeballs_delay: Duration::from_millis(config.happy_eyeballs_delay_ms),
+    };
+    if DNS.set(dns).is_ok() {
+        match config.servers.is_empty() {
+            true => info!("DNS: 系统配置"),
+            false => info!("DNS: {}", config.servers.join(", ")),
+        }
+        for (host, ip) in overrides {
+            info!("DNS 覆盖: {} -> {}", host, ip);
+        }
+    }
+    Ok(())
+}
+
+/// 解析主机名，IP 字面量直接返回
+pub async fn resolve(host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+    // URL 中的 IPv6 字面量带方括号
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return Ok(vec![SocketAddr::new(ip, port)]);
+    }
+
+    let Some(dns) = DNS.get() else {
+        return Ok(tokio::net::lookup_host((host, port)).await?.collect());
+    };
+    if let Some(ip) = dns.overrides.get(&host.to_ascii_lowercase()) {
+        return Ok(vec![SocketAddr::new(*ip, port)]);
+    }
+    let lookup = dns
+        .resolver
+        .lookup_ip(host)
+        .await
+        .map_err(|e| io::Error::other(format!("解析 {} 失败: {}", host, e)))?;
+    Ok(lookup.iter().map(|ip| SocketAddr::new(ip, port)).collect())
+}
+
+/// 解析并按 happy eyeballs 建立 TCP 连接，`bind` 为出口地址
+pub async fn connect(host: &str, port: u16, bind: Option<IpAddr>) -> io::Result<TcpStream> {
+    let mut addrs = resolve(host, port).await?;
+    if let Some(local) = bind {
+        addrs.retain(|a| a.is_ipv6() == local.is_ipv6());
+    }
+    let addrs = interleave(addrs);
+    let delay = DNS
+        .get()
+        .map(|d| d.happy_eyeballs_delay)
+        .unwrap_or(Duration::from_millis(250));
+
+    let mut addrs = addrs.into_iter();
+    let mut attempts = FuturesUnordered::new();
+    let mut last_err = None;
+    loop {
+        // 每轮启动一个新的连接尝试（有剩余地址时）
+        if let Some(addr) = addrs.next() {
+            debug!("连接 {}", addr);
+            attempts.push(connect_one(addr, bind));
+        } else if attempts.is_empty() {
+            return Err(last_err.unwrap_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, format!("{} 无可用地址", host))
+            }));
+        }
+
+        // 等到任一尝试结束，或到达间隔后进入下一轮
+        tokio::select! {
+            Some(res) = attempts.next() => match res {
+                Ok(stream) => return Ok(stream),
+                Err(e) => last_err = Some(e),
+            },
+            _ = sleep(delay), if addrs.len() > 0 => {}
+        }
+    }
+}
+
+async fn connect_one(addr: SocketAddr, bind: Option<IpAddr>) -> io::Result<TcpStream> {
+    let socket = match addr {
+        SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+    if let Some(local) = bind {
+        socket.bind(SocketAddr::new(local, 0))?;
+    }
+    tcp::tune(SockRef::from(&socket));
+    socket.connect(addr).await
+}
+
+/// 地址按 IPv6 / IPv4 交替排列，首个地址族保持解析结果中的顺序
+fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let Some(first) = addrs.first() else {
+        return addrs;
+    };
+    let first_v6 = first.is_ipv6();
+    let (mut primary, mut secondary): (Vec<_>, Vec<_>) =
+        addrs.into_iter().partition(|a| a.is_ipv6() == first_v6);
+    let mut out = Vec::with_capacity(primary.len() + secondary.len());
+    primary.reverse();
+    secondary.reverse();
+    loop {
+        match (primary.pop(), secondary.pop()) {
+            (None, None) => return out,
+            (a, b) => out.extend(a.into_iter().chain(b)),
+        }
+    }
+}
+
+/// 供 reqwest 使用的解析器
+///
+/// 设置 `alias` 后，对其中第一个主机名的查询改为解析第二个主机名
+/// （SNI 覆盖时 URL 中为 SNI 主机名，实际连接原目标）。
+#[derive(Default)]
+pub struct ReqwestResolver {
+    pub alias: Option<(String, String)>,
+}
+
+impl reqwest::dns::Resolve for ReqwestResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        let host = match self.alias {
+            Some((ref from, ref to)) if from.eq_ignore_ascii_case(name.as_str()) => to.clone(),
+            _ => name.as_str().to_string(),
+        };
+        Box::pin(async move {
+            let addrs = resolve(&host, 0).await?;
+            Ok(Box::new(addrs.into_iter()) as reqwest::dns::Addrs)
+        })
+    }
+}
@@ -0,0 +1,166 @@
+//! TLS 1.3 0-RTT（early data）
+//!
+//! `server.max_early_data_size` 大于 0 时，恢复的会话可在握手完成前发送首个请求，省去一次往返。
+//! rustls 仅对有状态会话恢复接受 early data，每个会话票据只能使用一次。
+//!
+//! early data 可能被重放，因此只允许 WS 握手（`/ws` 升级请求）出现在 early data 中；
+//! 其他请求（`/rest`、管理 API 等）返回 425 Too Early（RFC 8470），客户端在握手完成后重试。
+//! 握手完成后收到的数据（包括 WS 消息）不受影响。
+
+use std::{
+    future::Future,
+    io::{self, Read},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
+
+use axum::{
+    extract::Request,
+    http::{header, Method, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use axum_server::{
+    accept::{Accept, DefaultAcceptor},
+    tls_rustls::RustlsAcceptor,
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_rustls::server::TlsStream;
+use tower_service::Service;
+use tracing::debug;
+
+use crate::error;
+
+/// 请求扩展：该请求（连接上的首个请求）位于 early data 中
+#[derive(Clone, Copy)]
+pub struct EarlyData;
+
+/// 在 TLS 握手后取出 early data，交给 HTTP 层先行读取
+#[derive(Clone)]
+pub struct EarlyDataAcceptor<A = DefaultAcceptor> {
+    inner: RustlsAcceptor<A>,
+}
+
+impl<A> EarlyDataAcceptor<A> {
+    pub fn new(inner: RustlsAcceptor<A>) -> Self {
+        Self { inner }
+    }
+}
+
+impl<A, I, S> Accept<I, S> for EarlyDataAcceptor<A>
+where
+    RustlsAcceptor<A>: Accept<I, S, Stream = TlsStream<I>, Service = S>,
+    <RustlsAcceptor<A> as Accept<I, S>>::Future: Send + 'static,
+    I: AsyncRead + AsyncWrite + Unpin,
+{
+    type Stream = EarlyDataStream<TlsStream<I>>;
+    type Service = MarkEarly<S>;
+    type Future = Pin<Box<dyn Future<Output = io::Result<(Self::Stream, Self::Service)>> + Send>>;
+
+    fn accept(&self, stream: I, service: S) -> Self::Future {
+        let handshake = self.inner.accept(stream, service);
+        Box::pin(async move {
+            let (mut stream, service) = handshake.await?;
+            let mut early = Vec::new();
+            if let Some(mut reader) = stream.get_mut().1.early_data() {
+                reader.read_to_end(&mut early)?;
+            }
+            if !early.is_empty() {
+                debug!("收到 early data: {} 字节", early.len());
+            }
+            let service = MarkEarly {
+                inner: service,
+                pending: Arc::new(AtomicBool::new(!early.is_empty())),
+            };
+            Ok((EarlyDataStream { early, pos: 0, inner: stream }, service))
+        })
+    }
+}
+
+/// 先返回 early data，再读取 TLS 流
+pub struct EarlyDataStream<T> {
+    early: Vec<u8>,
+    pos: usize,
+    inner: T,
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for EarlyDataStream<T> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.pos < this.early.len() {
+            let n = buf.remaining().min(this.early.len() - this.pos);
+            buf.put_slice(&this.early[this.pos..this.pos + n]);
+            this.pos += n;
+            if this.pos == this.early.len() {
+                this.early = Vec::new();
+                this.pos = 0;
+            }
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut this.inner).poll_read(cx, buf)
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for EarlyDataStream<T> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+/// 为连接上的首个请求加上 [`EarlyData`] 扩展
+#[derive(Clone)]
+pub struct MarkEarly<S> {
+    inner: S,
+    pending: Arc<AtomicBool>,
+}
+
+impl<S, B> Service<axum::http::Request<B>> for MarkEarly<S>
+where
+    S: Service<axum::http::Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: axum::http::Request<B>) -> Self::Future {
+        if self.pending.swap(false, Ordering::Relaxed) {
+            req.extensions_mut().insert(EarlyData);
+        }
+        self.inner.call(req)
+    }
+}
+
+/// early data 中只放行 WS 握手
+pub async fn guard(req: Request, next: Next) -> Response {
+    if req.extensions().get::<EarlyData>().is_some() && !is_ws_handshake(&req) {
+        debug!("early data 中的请求被拒绝: {} {}", req.method(), req.uri().path());
+        return error::response(StatusCode::TOO_EARLY, "TOO_EARLY", "请在 TLS 握手完成后重试");
+    }
+    next.run(req).await
+}
+
+/// HTTP/1.1 升级或 HTTP/2 扩展 CONNECT
+fn is_ws_handshake(req: &Request) -> bool {
+    let upgrade = req
+        .headers()
+        .get(header::UPGRADE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.eq_ignore_ascii_case("websocket"));
+    req.uri().path() == "/ws" && ((req.method() == Method::GET && upgrade) || req.method() == Method::CONNECT)
+}
@@ -0,0 +1,81 @@
+//! 消息编码转换
+//!
+//! 只能发送 text 帧的客户端在 `/ws` 请求头 `X-Message-Encoding` 中选择转换方式，relay 在客户端一侧转换：
+//!
+//! | 取值 | 客户端 → 目标 | 目标 → 客户端 |
+//! |------|------|------|
+//! | `base64` | text 消息 base64 解码为 binary | binary 编码为 base64 text |
+//! | `json` | `{"b64":"..."}` 解码为 binary，其他 text 原样转发 | binary 包装为 `{"b64":"..."}` |
+//!
+//! 目标发来的 text 消息不转换。无法解码的客户端消息丢弃并记录日志；
+//! 录制、脚本钩子与流量统计均按目标一侧（转换后）的消息。
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::{Deserialize, Serialize};
+use tokio_tungstenite::tungstenite::Message;
+use tracing::debug;
+
+/// 请求头名称
+pub const HEADER: &str = "X-Message-Encoding";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Encoding {
+    /// 不转换
+    #[default]
+    None,
+    Base64,
+    Json,
+}
+
+#[derive(Deserialize, Serialize)]
+struct Envelope<'a> {
+    b64: &'a str,
+}
+
+impl Encoding {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Some(Self::None),
+            "base64" => Some(Self::Base64),
+            "json" => Some(Self::Json),
+            _ => None,
+        }
+    }
+
+    /// 客户端 → 目标，无法解码时返回 `None`
+    pub fn decode(self, msg: Message) -> Option<Message> {
+        let Message::Text(ref text) = msg else {
+            return Some(msg);
+        };
+        let data = match self {
+            Self::None => return Some(msg),
+            Self::Base64 => text.as_str(),
+            Self::Json => match serde_json::from_str::<Envelope>(text) {
+                Ok(envelope) => envelope.b64,
+                Err(_) => return Some(msg),
+            },
+        };
+        match BASE64.decode(data) {
+            Ok(bytes) => Some(Message::Binary(bytes.into())),
+            Err(e) => {
+                debug!("丢弃无法解码的客户端消息（{:?}）: {}", self, e);
+                None
+            }
+        }
+    }
+
+    /// 目标 → 客户端
+    pub fn encode(self, msg: Message) -> Message {
+        let Message::Binary(ref data) = msg else {
+            return msg;
+        };
+        match self {
+            Self::None => msg,
+            Self::Base64 => Message::Text(BASE64.encode(data).into()),
+            Self::Json => {
+                let b64 = BASE64.encode(data);
+                Message::Text(serde_json::to_string(&Envelope { b64: &b64 }).unwrap_or_default().into())
+            }
+        }
+    }
+}
@@ -0,0 +1,53 @@
+//! 结构化错误
+//!
+//! HTTP 响应体与 WS 控制消息使用同一格式：
+//! `{"status":"error","code":"QUOTA_EXCEEDED","message":"..."}`
+//!
+//! 目标拒绝 WS 升级时另带 `upstream`（上游状态码与部分响应头）。
+
+use axum::{
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+
+#[derive(Serialize)]
+struct ErrorBody<'a, U: Serialize = ()> {
+    status: &'static str,
+    code: &'a str,
+    message: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    upstream: Option<U>,
+}
+
+/// 序列化错误消息
+pub fn to_json(code: &str, message: &str) -> String {
+    serde_json::to_string(&ErrorBody::<()> {
+        status: "error",
+        code,
+        message,
+        upstream: None,
+    })
+    .unwrap_or_default()
+}
+
+/// 序列化错误消息，附带上游响应信息
+pub fn to_json_upstream(code: &str, message: &str, upstream: &impl Serialize) -> String {
+    serde_json::to_string(&ErrorBody {
+        status: "error",
+        code,
+        message,
+        upstream: Some(upstream),
+    })
+    .unwrap_or_default()
+}
+
+/// JSON 错误响应
+pub fn response(status: StatusCode, code: &str, message: &str) -> Response {
+    (
+        status,
+        [(header::CONTENT_TYPE, "application/json")],
+        to_json(code, message),
+    )
+        .into_response()
+}
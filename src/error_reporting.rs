@@ -0,0 +1,279 @@
+//! 错误上报（Sentry / 通用 webhook）
+//!
+//! 配置 `[error_reporting]` 后，以下事件由后台任务发送到 Sentry（`sentry_dsn`）和 / 或 `webhook_url`：
+//!
+//! | 事件 | `kind` | 触发条件 |
+//! |------|------|------|
+//! | panic | `panic` | 任一线程 panic，带 panic 信息与位置 |
+//! | 连续连接目标失败 | `connect_failures` | 同一目标主机 `window_secs` 内失败达到 `connect_failures` 次，每个窗口上报一次 |
+//! | 目标 TLS 错误 | `tls_error` | 连接目标时 TLS 握手失败（证书无效等），同一主机每个窗口上报一次 |
+//!
+//! webhook 请求体：
+//!
+//! ```json
+//! {"kind":"connect_failures","ts":"2026-01-01T00:00:00.000Z","release":"ws-relay-core@0.2.0","environment":"prod",
+//!  "message":"连续连接目标失败: ws.example.com","user":"alice","target_host":"ws.example.com","count":5,
+//!  "error_chain":["目标拒绝 WS 升级: HTTP 503"]}
+//! ```
+//!
+//! 错误信息中的目标 URL 已去掉凭据。发送失败只记日志不重试，积压超过 `queue_size` 时丢弃新事件。
+
+use std::{
+    collections::HashMap,
+    error::Error as StdError,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+use chrono::{SecondsFormat, Utc};
+use rand::Rng;
+use reqwest::{Client, RequestBuilder, Url};
+use serde::Serialize;
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use crate::{config::ErrorReportingConfig, panic_guard, secret};
+
+/// 按主机统计失败次数的条目上限，超出时清空
+const MAX_HOSTS: usize = 10_000;
+
+/// 一条上报
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    kind: &'static str,
+    ts: String,
+    release: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    environment: Option<String>,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target_host: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    count: Option<u32>,
+    /// panic 位置（`file:line:col`）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    location: Option<String>,
+    error_chain: Vec<String>,
+}
+
+/// Sentry DSN 解析结果
+struct Sentry {
+    store_url: Url,
+    auth: String,
+}
+
+impl Sentry {
+    /// `https://<key>@<host>/<project>` → store API 地址与认证头
+    fn parse(dsn: &str) -> Result<Self> {
+        let url = Url::parse(dsn).context("sentry_dsn 不是有效的 URL")?;
+        let key = url.username();
+        if key.is_empty() {
+            bail!("sentry_dsn 缺少 key");
+        }
+        let path = url.path().trim_end_matches('/');
+        let (prefix, project) = path.rsplit_once('/').context("sentry_dsn 缺少项目 ID")?;
+        if project.is_empty() {
+            bail!("sentry_dsn 缺少项目 ID");
+        }
+        let mut store_url = url.clone();
+        let _ = store_url.set_username("");
+        store_url.set_path(&format!("{}/api/{}/store/", prefix, project));
+        let auth = format!(
+            "Sentry sentry_version=7, sentry_key={}, sentry_client=ws-relay-core/{}",
+            key,
+            env!("CARGO_PKG_VERSION")
+        );
+        Ok(Self { store_url, auth })
+    }
+}
+
+pub struct ErrorReporter {
+    config: ErrorReportingConfig,
+    tx: mpsc::Sender<Report>,
+    /// 目标主机 → (窗口开始时间, 失败次数)
+    failures: Mutex<HashMap<String, (Instant, u32)>>,
+}
+
+impl ErrorReporter {
+    pub fn new(config: &ErrorReportingConfig) -> Result<Self> {
+        let sentry = match config.sentry_dsn {
+            Some(ref dsn) => Some(Sentry::parse(dsn.expose())?),
+            None => None,
+        };
+        let client = Client::builder()
+            .timeout(Duration::from_millis(config.timeout_ms))
+            .build()?;
+        let (tx, rx) = mpsc::channel(config.queue_size.max(1));
+        tokio::spawn(deliver(client, sentry, config.webhook_url.clone(), rx));
+        let mut targets = Vec::new();
+        if config.sentry_dsn.is_some() {
+            targets.push("Sentry");
+        }
+        if let Some(ref url) = config.webhook_url {
+            targets.push(url);
+        }
+        info!("错误上报: {}", targets.join(", "));
+        Ok(Self {
+            config: config.clone(),
+            tx,
+            failures: Mutex::default(),
+        })
+    }
+
+    /// 连接目标失败：TLS 错误每个窗口上报一次，其余失败达到阈值时上报
+    pub fn connect_failed(&self, user: &str, target: &str, err: &(dyn StdError + 'static)) {
+        let host = target_host(target);
+        let tls = is_tls(err);
+        let count = {
+            let mut failures = self.failures.lock().unwrap();
+            if failures.len() >= MAX_HOSTS && !failures.contains_key(&host) {
+                failures.clear();
+            }
+            let window = Duration::from_secs(self.config.window_secs);
+            let entry = failures.entry(host.clone()).or_insert((Instant::now(), 0));
+            if entry.0.elapsed() >= window {
+                *entry = (Instant::now(), 0);
+            }
+            entry.1 += 1;
+            entry.1
+        };
+        let (kind, message) = match (tls, count) {
+            (true, 1) => ("tls_error", format!("目标 TLS 错误: {}", host)),
+            (false, n) if n == self.config.connect_failures => {
+                ("connect_failures", format!("连续连接目标失败: {}", host))
+            }
+            _ => return,
+        };
+        let redacted = secret::redact_url(target);
+        let chain = error_chain(err).into_iter().map(|m| m.replace(target, &redacted)).collect();
+        self.send(Report {
+            user: Some(user.to_string()),
+            target_host: Some(host),
+            count: Some(count),
+            ..self.report(kind, message, chain)
+        });
+    }
+
+    fn report(&self, kind: &'static str, message: String, error_chain: Vec<String>) -> Report {
+        Report {
+            kind,
+            ts: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            release: concat!("ws-relay-core@", env!("CARGO_PKG_VERSION")),
+            environment: self.config.environment.clone(),
+            message,
+            user: None,
+            target_host: None,
+            count: None,
+            location: None,
+            error_chain,
+        }
+    }
+
+    /// 加入发送队列，队列已满时丢弃
+    fn send(&self, report: Report) {
+        if self.tx.try_send(report).is_err() {
+            warn!("错误上报积压，丢弃事件");
+        }
+    }
+}
+
+/// 安装 panic hook：保留默认输出，同时上报
+pub fn install_panic_hook(reporter: Arc<ErrorReporter>) {
+    let default = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        default(info);
+        let thread = std::thread::current().name().unwrap_or("<unnamed>").to_string();
+        let message = format!("panic: {}", panic_guard::message(info.payload()));
+        let chain = vec![format!("线程 {}", thread)];
+        reporter.send(Report {
+            location: info.location().map(|l| l.to_string()),
+            ..reporter.report("panic", message, chain)
+        });
+    }));
+}
+
+/// URL 目标取主机名，SOCKS5 的 `host:port` 去掉端口
+fn target_host(target: &str) -> String {
+    match Url::parse(target).ok().and_then(|u| u.host_str().map(str::to_string)) {
+        Some(host) => host,
+        None => target.rsplit_once(':').map_or(target, |(host, _)| host).to_string(),
+    }
+}
+
+fn error_chain(err: &(dyn StdError + 'static)) -> Vec<String> {
+    std::iter::successors(Some(err), |&e| e.source()).map(|e| e.to_string()).collect()
+}
+
+/// 错误链中是否有 rustls 错误（tokio-rustls 包装在 io::Error 中）
+fn is_tls(err: &(dyn StdError + 'static)) -> bool {
+    std::iter::successors(Some(err), |&e| e.source()).any(|e| {
+        e.is::<rustls::Error>()
+            || e.downcast_ref::<std::io::Error>()
+                .and_then(|io| io.get_ref())
+                .is_some_and(|inner| inner.is::<rustls::Error>())
+    })
+}
+
+/// 后台逐条发送
+async fn deliver(client: Client, sentry: Option<Sentry>, webhook: Option<String>, mut rx: mpsc::Receiver<Report>) {
+    while let Some(report) = rx.recv().await {
+        if let Some(ref sentry) = sentry {
+            let request = client.post(sentry.store_url.clone()).header("X-Sentry-Auth", &sentry.auth);
+            if let Err(e) = post(request, sentry_event(&report).to_string()).await {
+                warn!("上报 Sentry 失败: {:#}", e);
+            }
+        }
+        if let Some(ref url) = webhook {
+            let body = serde_json::to_string(&report).unwrap_or_default();
+            if let Err(e) = post(client.post(url), body).await {
+                warn!("上报错误 webhook 失败: {} - {:#}", url, e);
+            }
+        }
+    }
+}
+
+async fn post(request: RequestBuilder, body: String) -> Result<()> {
+    let resp = request.header("content-type", "application/json").body(body).send().await?;
+    if !resp.status().is_success() {
+        bail!("HTTP {}", resp.status());
+    }
+    Ok(())
+}
+
+/// Sentry store API 的事件格式
+fn sentry_event(report: &Report) -> Value {
+    let mut tags = json!({ "kind": report.kind });
+    if let Some(ref host) = report.target_host {
+        tags["target_host"] = host.clone().into();
+    }
+    let mut event = json!({
+        "event_id": format!("{:032x}", rand::thread_rng().gen::<u128>()),
+        "timestamp": report.ts,
+        "level": if report.kind == "panic" { "fatal" } else { "error" },
+        "platform": "other",
+        "logger": "ws-relay-core",
+        "release": report.release,
+        "message": { "formatted": report.message },
+        "tags": tags,
+        "extra": {
+            "error_chain": report.error_chain,
+            "count": report.count,
+            "location": report.location,
+        },
+        "exception": { "values": [{
+            "type": report.kind,
+            "value": report.error_chain.first().unwrap_or(&report.message),
+        }] },
+    });
+    if let Some(ref env) = report.environment {
+        event["environment"] = env.clone().into();
+    }
+    if let Some(ref user) = report.user {
+        event["user"] = json!({ "username": user });
+    }
+    event
+}
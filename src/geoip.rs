@@ -0,0 +1,70 @@
+//! GeoIP 访问策略
+//!
+//! 配置 `[geoip]` 后用 MaxMind 数据库（GeoLite2 / GeoIP2 的 Country 或 City）查询客户端所在国家/地区：
+//!
+//! - 全局 `allowed_countries` / `denied_countries` 在认证前检查（HTTP 路由与 SOCKS5 入口）
+//! - 用户级 `allowed_countries` / `denied_countries` 在认证通过后检查
+//! - 访问日志记录 `country` 字段，OpenTelemetry 指标带 `country` 属性
+//!
+//! 国家代码为 ISO 3166-1 alpha-2（如 `CN`、`US`），不区分大小写；`denied_countries` 优先。
+//! 查不到国家的地址（内网、数据库未收录）按 `allow_unknown` 处理（默认允许）。
+//! 被拒绝时返回 403 `COUNTRY_NOT_ALLOWED`，数据库只在启动时加载。
+
+use std::net::IpAddr;
+
+use anyhow::{Context, Result};
+use maxminddb::{geoip2, Reader};
+use once_cell::sync::OnceCell;
+use tracing::info;
+
+use crate::config::{GeoIpConfig, User};
+
+struct GeoIp {
+    reader: Reader<Vec<u8>>,
+    config: GeoIpConfig,
+}
+
+static GEOIP: OnceCell<GeoIp> = OnceCell::new();
+
+/// 加载数据库（启动时调用一次）
+pub fn init(config: &GeoIpConfig) -> Result<()> {
+    let reader = Reader::open_readfile(&config.database)
+        .with_context(|| format!("加载 GeoIP 数据库失败: {}", config.database))?;
+    info!("GeoIP 数据库: {}（{}）", config.database, reader.metadata.database_type);
+    let _ = GEOIP.set(GeoIp {
+        reader,
+        config: config.clone(),
+    });
+    Ok(())
+}
+
+/// 客户端所在国家/地区的 ISO 代码，未配置或查不到时为 `None`
+pub fn country(ip: IpAddr) -> Option<&'static str> {
+    let record: geoip2::Country = GEOIP.get()?.reader.lookup(ip).ok()?;
+    record.country.or(record.registered_country)?.iso_code
+}
+
+/// 全局名单（认证前检查）
+pub fn allows_client(ip: IpAddr) -> bool {
+    GEOIP
+        .get()
+        .is_none_or(|g| allows(g, ip, &g.config.allowed_countries, &g.config.denied_countries))
+}
+
+/// 用户级名单（认证后检查）
+pub fn allows_user(user: &User, ip: IpAddr) -> bool {
+    GEOIP
+        .get()
+        .is_none_or(|g| allows(g, ip, &user.allowed_countries, &user.denied_countries))
+}
+
+fn allows(geoip: &GeoIp, ip: IpAddr, allowed: &[String], denied: &[String]) -> bool {
+    if allowed.is_empty() && denied.is_empty() {
+        return true;
+    }
+    let Some(country) = country(ip) else {
+        return geoip.config.allow_unknown;
+    };
+    !denied.iter().any(|c| c.eq_ignore_ascii_case(country))
+        && (allowed.is_empty() || allowed.iter().any(|c| c.eq_ignore_ascii_case(country)))
+}
@@ -0,0 +1,73 @@
+//! 头部改写规则
+//!
+//! `[[header_rules]]` 按配置顺序依次应用，可按路由（`rest` / `ws`）与目标主机名限定范围，
+//! 用于在服务端注入 API key 等客户端不应持有的头部：
+//!
+//! | 阶段 | /rest | /ws |
+//! |------|-------|-----|
+//! | `request` | 发往上游的请求头 | 与目标的握手请求头 |
+//! | `response` | 返回给客户端的响应头 | 不适用 |
+
+use anyhow::{Context, Result};
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+
+use crate::config::{HeaderOps, HeaderRule, Route};
+
+/// 启动时校验头部名称与取值
+pub fn validate(rules: &[HeaderRule]) -> Result<()> {
+    for ops in rules.iter().flat_map(|r| [&r.request, &r.response]) {
+        for name in ops.remove.iter().chain(ops.set.keys()).chain(ops.add.keys()) {
+            HeaderName::try_from(name.as_str()).with_context(|| format!("header_rules: 无效的头部名称 {}", name))?;
+        }
+        for (name, value) in ops.set.iter().chain(&ops.add) {
+            HeaderValue::try_from(value.as_str()).with_context(|| format!("header_rules: {} 的取值无效", name))?;
+        }
+    }
+    Ok(())
+}
+
+/// 改写发往目标的请求头，`host` 为目标主机名（Unix socket 目标为 None）
+pub fn apply_request(rules: &[HeaderRule], route: Route, host: Option<&str>, headers: &mut HeaderMap) {
+    for rule in rules.iter().filter(|r| applies(r, route, host)) {
+        apply(&rule.request, headers);
+    }
+}
+
+/// 改写返回给客户端的响应头
+pub fn apply_response(rules: &[HeaderRule], route: Route, host: Option<&str>, headers: &mut HeaderMap) {
+    for rule in rules.iter().filter(|r| applies(r, route, host)) {
+        apply(&rule.response, headers);
+    }
+}
+
+fn applies(rule: &HeaderRule, route: Route, host: Option<&str>) -> bool {
+    if rule.route.is_some_and(|r| r != route) {
+        return false;
+    }
+    rule.hosts.is_empty() || host.is_some_and(|h| rule.hosts.iter().any(|p| host_matches(p, h)))
+}
+
+/// `*.example.com` 匹配任意子域名（不含 example.com 本身），其余不区分大小写精确匹配
+pub fn host_matches(pattern: &str, host: &str) -> bool {
+    match pattern.strip_prefix('*') {
+        Some(suffix) => host.len() > suffix.len() && host.to_ascii_lowercase().ends_with(&suffix.to_ascii_lowercase()),
+        None => pattern.eq_ignore_ascii_case(host),
+    }
+}
+
+fn apply(ops: &HeaderOps, headers: &mut HeaderMap) {
+    for name in &ops.remove {
+        headers.remove(name.as_str());
+    }
+    // 名称与取值已在加载配置时校验
+    for (name, value) in &ops.set {
+        if let (Ok(name), Ok(value)) = (HeaderName::try_from(name.as_str()), HeaderValue::try_from(value.as_str())) {
+            headers.insert(name, value);
+        }
+    }
+    for (name, value) in &ops.add {
+        if let (Ok(name), Ok(value)) = (HeaderName::try_from(name.as_str()), HeaderValue::try_from(value.as_str())) {
+            headers.append(name, value);
+        }
+    }
+}
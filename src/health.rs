@@ -0,0 +1,164 @@
+//! 健康检查与版本
+//!
+//! | 路由 | 说明 |
+//! |------|------|
+//! | `GET /healthz` | 存活：进程能响应即 200 |
+//! | `GET /readyz` | 就绪：监听端口已绑定且未进入退出流程时 200，否则 503 |
+//! | `GET /version` | 名称与版本 |
+//!
+//! 这些路由不需要认证，供负载均衡与 k8s 探针使用。
+//!
+//! 优雅退出（SIGTERM / Ctrl+C / 控制通道 `shutdown`）时 `/readyz` 先转为 503，
+//! 等待 `server.drain_secs` 让负载均衡摘除本实例后才停止接受新连接，期间 `/healthz` 保持 200。
+//! 平滑升级（`SIGUSR2`，见 [`crate::upgrade`]）时新进程已在同一端口监听，旧进程立即停止接受新连接。
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use axum::{extract::State, http::StatusCode, response::IntoResponse, routing::get, Json, Router};
+use serde_json::json;
+use tracing::info;
+
+use crate::{state::AppState, SHUTDOWN_GRACE};
+
+/// 就绪状态
+#[derive(Default)]
+pub struct Health {
+    listening: AtomicBool,
+    draining: AtomicBool,
+    /// 维护模式：拒绝新会话，现有会话不受影响
+    maintenance: AtomicBool,
+    /// 排空中的用户 → 排空编号（取消或再次排空后，之前计划的终止不再执行）
+    drained_users: Mutex<HashMap<String, u64>>,
+    /// 节点排空编号，0 表示未经 `/admin/drain` 进入维护模式
+    node_drain: AtomicU64,
+    drain_seq: AtomicU64,
+}
+
+impl Health {
+    /// 监听端口已绑定
+    pub fn set_listening(&self) {
+        self.listening.store(true, Ordering::Relaxed);
+    }
+
+    /// 进入或退出维护模式，返回之前的状态
+    pub fn set_maintenance(&self, on: bool) -> bool {
+        if !on {
+            self.node_drain.store(0, Ordering::Relaxed);
+        }
+        self.maintenance.swap(on, Ordering::Relaxed)
+    }
+
+    pub fn in_maintenance(&self) -> bool {
+        self.maintenance.load(Ordering::Relaxed)
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+
+    /// 排空整个节点（进入维护模式），返回排空编号
+    pub fn drain_node(&self) -> u64 {
+        let id = self.drain_seq.fetch_add(1, Ordering::Relaxed) + 1;
+        self.node_drain.store(id, Ordering::Relaxed);
+        self.maintenance.store(true, Ordering::Relaxed);
+        id
+    }
+
+    /// 节点仍处于编号为 `id` 的排空中
+    pub fn node_drain_is(&self, id: u64) -> bool {
+        self.in_maintenance() && self.node_drain.load(Ordering::Relaxed) == id
+    }
+
+    /// 排空用户：拒绝其新会话，返回排空编号
+    pub fn drain_user(&self, user: &str) -> u64 {
+        let id = self.drain_seq.fetch_add(1, Ordering::Relaxed) + 1;
+        self.drained_users.lock().unwrap().insert(user.to_string(), id);
+        id
+    }
+
+    /// 取消用户排空，返回之前是否在排空中
+    pub fn undrain_user(&self, user: &str) -> bool {
+        self.drained_users.lock().unwrap().remove(user).is_some()
+    }
+
+    pub fn is_user_drained(&self, user: &str) -> bool {
+        self.drained_users.lock().unwrap().contains_key(user)
+    }
+
+    /// 用户仍处于编号为 `id` 的排空中
+    pub fn user_drain_is(&self, user: &str, id: u64) -> bool {
+        self.drained_users.lock().unwrap().get(user) == Some(&id)
+    }
+
+    pub fn drained_users(&self) -> Vec<String> {
+        let mut users: Vec<_> = self.drained_users.lock().unwrap().keys().cloned().collect();
+        users.sort();
+        users
+    }
+}
+
+/// 优雅退出：先标记为未就绪，等待 `drain_secs` 后停止接受新连接并等待现有连接结束
+pub async fn shutdown(state: AppState, handle: axum_server::Handle) {
+    if state.health.draining.swap(true, Ordering::Relaxed) {
+        return;
+    }
+    let drain = state.config.server.drain_secs;
+    if drain > 0 {
+        info!("/readyz 已转为 503，{} 秒后停止接受新连接", drain);
+        tokio::time::sleep(Duration::from_secs(drain)).await;
+    }
+    info!("停止接受新连接，等待现有连接结束...");
+    handle.graceful_shutdown(Some(SHUTDOWN_GRACE));
+}
+
+/// 平滑升级：新进程已接管监听 socket，立即停止接受新连接（无需等待负载均衡摘除）
+pub fn handoff(state: &AppState, handle: &axum_server::Handle) {
+    state.health.draining.store(true, Ordering::Relaxed);
+    info!("停止接受新连接，等待现有会话结束...");
+    handle.graceful_shutdown(Some(SHUTDOWN_GRACE));
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .route("/version", get(version))
+}
+
+/// GET /healthz
+async fn healthz() -> impl IntoResponse {
+    Json(json!({ "status": "ok" }))
+}
+
+/// GET /readyz
+async fn readyz(State(state): State<AppState>) -> impl IntoResponse {
+    let listening = state.health.listening.load(Ordering::Relaxed);
+    let draining = state.health.draining.load(Ordering::Relaxed);
+    let maintenance = state.health.in_maintenance();
+    let (status, text) = match (listening, draining) {
+        (_, true) => (StatusCode::SERVICE_UNAVAILABLE, "draining"),
+        (false, _) => (StatusCode::SERVICE_UNAVAILABLE, "not_ready"),
+        _ if maintenance => (StatusCode::SERVICE_UNAVAILABLE, "maintenance"),
+        (true, false) => (StatusCode::OK, "ready"),
+    };
+    let body = json!({
+        "status": text,
+        "checks": { "listener": listening, "draining": draining, "maintenance": maintenance },
+    });
+    (status, Json(body))
+}
+
+/// GET /version
+async fn version() -> impl IntoResponse {
+    Json(json!({
+        "name": env!("CARGO_PKG_NAME"),
+        "version": env!("CARGO_PKG_VERSION"),
+    }))
+}
@@ -0,0 +1,112 @@
+//! 目标主机限流
+//!
+//! 单个租户集中访问同一上游可能导致 relay 的出口 IP 被上游封禁。`[[host_limits]]` 按目标主机限制
+//! 并发会话数与新建连接速率，在连接目标前检查（目标改写之后），超限时返回结构化错误：
+//!
+//! | 错误码 | 说明 |
+//! |------|------|
+//! | `HOST_SESSION_LIMIT` | 该主机的并发会话数已达 `max_sessions` |
+//! | `HOST_RATE_LIMIT` | 该主机新建连接超过 `max_connects_per_sec`（令牌桶，允许 1 秒的突发量） |
+//!
+//! 会话指 WS 会话、`/mux` 流、SOCKS5 连接与进行中的 REST 请求，所有用户共享计数。
+//! `/ws` 与 `/rest` 返回 429，WS 切换目标与 `/mux` 流以错误码拒绝，SOCKS5 回复 "connection not allowed"。
+//! 规则按顺序匹配，第一条匹配的规则生效；`hosts` 中的每个主机分别计数。
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use crate::{config::HostLimit, header_rules};
+
+/// 超过该数量时清理空闲的主机记录
+const MAX_IDLE_HOSTS: usize = 1024;
+
+pub const SESSION_LIMIT: (&str, &str) = ("HOST_SESSION_LIMIT", "目标主机并发会话数已达上限");
+pub const RATE_LIMIT: (&str, &str) = ("HOST_RATE_LIMIT", "目标主机新建连接过于频繁");
+
+struct Host {
+    active: usize,
+    tokens: f64,
+    refilled: Instant,
+}
+
+#[derive(Default)]
+pub struct HostLimits {
+    rules: Vec<HostLimit>,
+    hosts: Mutex<HashMap<String, Host>>,
+}
+
+/// 占用一个会话名额，drop 时释放
+pub struct HostPermit {
+    limits: Arc<HostLimits>,
+    host: String,
+}
+
+impl HostLimits {
+    pub fn new(rules: &[HostLimit]) -> Self {
+        Self {
+            rules: rules.to_vec(),
+            hosts: Mutex::default(),
+        }
+    }
+
+    /// 连接 `target` 前调用，目标主机没有匹配的规则时返回 `None`
+    pub fn acquire(self: &Arc<Self>, target: &str) -> Result<Option<HostPermit>, (&'static str, &'static str)> {
+        let Some(host) = host_of(target) else {
+            return Ok(None);
+        };
+        let Some(rule) = self
+            .rules
+            .iter()
+            .find(|r| r.hosts.iter().any(|p| header_rules::host_matches(p, &host)))
+        else {
+            return Ok(None);
+        };
+
+        let now = Instant::now();
+        let mut hosts = self.hosts.lock().unwrap();
+        if hosts.len() > MAX_IDLE_HOSTS {
+            hosts.retain(|_, h| h.active > 0 || now.duration_since(h.refilled).as_secs() < 1);
+        }
+        let burst = rule.max_connects_per_sec.map_or(0.0, f64::from);
+        let entry = hosts.entry(host.clone()).or_insert(Host {
+            active: 0,
+            tokens: burst,
+            refilled: now,
+        });
+        if rule.max_sessions.is_some_and(|max| entry.active >= max) {
+            return Err(SESSION_LIMIT);
+        }
+        if rule.max_connects_per_sec.is_some() {
+            let elapsed = now.duration_since(entry.refilled).as_secs_f64();
+            entry.tokens = (entry.tokens + elapsed * burst).min(burst);
+            entry.refilled = now;
+            if entry.tokens < 1.0 {
+                return Err(RATE_LIMIT);
+            }
+            entry.tokens -= 1.0;
+        }
+        entry.active += 1;
+        Ok(Some(HostPermit {
+            limits: self.clone(),
+            host,
+        }))
+    }
+}
+
+impl Drop for HostPermit {
+    fn drop(&mut self) {
+        let mut hosts = self.limits.hosts.lock().unwrap();
+        if let Some(h) = hosts.get_mut(&self.host) {
+            h.active -= 1;
+        }
+    }
+}
+
+/// 目标 URL 中的主机名（小写），`agent:` 等无主机的目标返回 `None`
+fn host_of(target: &str) -> Option<String> {
+    let url = reqwest::Url::parse(target).ok()?;
+    Some(url.host_str()?.to_ascii_lowercase())
+}
@@ -0,0 +1,69 @@
+//! 内置目标
+//!
+//! relay 自行处理的伪目标，无需真实上游即可检查连通性、测量 relay 本身的开销：
+//!
+//! | 目标 | 行为 |
+//! |------|------|
+//! | `internal://echo` | 原样返回每条 text / binary 消息 |
+//! | `internal://latency` | 每条消息立即回复 `{"type":"latency","seq":1,"relay_ts_us":...,"bytes":5}` |
+//!
+//! `relay_ts_us` 为 relay 收到消息时的 Unix 时间（微秒），客户端可据此估算往返与单程延迟。
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Result};
+use serde_json::json;
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::{Error as WsError, Message as TungMessage};
+
+use crate::ws::{TargetRx, TargetTx};
+
+/// 待返回消息队列长度
+const QUEUE: usize = 64;
+
+/// 打开内置目标
+pub fn open(target: &str) -> Result<(TargetTx, TargetRx)> {
+    match target.strip_prefix("internal://") {
+        Some("echo") => Ok(responder(Some)),
+        Some("latency") => {
+            let mut seq = 0u64;
+            Ok(responder(move |msg| {
+                seq += 1;
+                let relay_ts_us = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |d| d.as_micros() as u64);
+                let reply = json!({ "type": "latency", "seq": seq, "relay_ts_us": relay_ts_us, "bytes": msg.len() });
+                Some(TungMessage::Text(reply.to_string().into()))
+            }))
+        }
+        _ => bail!("未知的内置目标: {}", target),
+    }
+}
+
+/// 对每条 text / binary 消息调用 `respond`，回复由接收端返回；close 消息结束会话
+fn responder<F>(respond: F) -> (TargetTx, TargetRx)
+where
+    F: FnMut(TungMessage) -> Option<TungMessage> + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel(QUEUE);
+    let sink = futures_util::sink::unfold((Some(tx), respond), |(tx, mut respond), msg: TungMessage| async move {
+        let Some(sender) = tx else {
+            return Err(WsError::ConnectionClosed);
+        };
+        match msg {
+            TungMessage::Text(_) | TungMessage::Binary(_) => {
+                if let Some(reply) = respond(msg) {
+                    sender.send(reply).await.map_err(|_| WsError::ConnectionClosed)?;
+                }
+                Ok((Some(sender), respond))
+            }
+            TungMessage::Close(_) => Ok((None, respond)),
+            _ => Ok((Some(sender), respond)),
+        }
+    });
+    let stream = futures_util::stream::unfold(rx, |mut rx| async move {
+        let msg = rx.recv().await?;
+        Some((Ok(msg), rx))
+    });
+    (Box::pin(sink), Box::pin(stream))
+}
@@ -0,0 +1,118 @@
+//! OAuth2 token introspection（RFC 7662）
+//!
+//! `auth_mode = "introspection"` 时以客户端凭据（HTTP Basic）向 introspection 端点提交
+//! `token=...&token_type_hint=access_token`，`active: true` 视为通过。
+//! claims 映射与 JWT 相同（`name_claim` / `targets_claim` / `exp`）。
+//! 结果按 token 缓存：通过的不超过 `cache_ttl_secs` 且不超过 `exp`，拒绝的缓存 `negative_ttl_secs`。
+
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use chrono::Utc;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use reqwest::Client;
+use serde_json::{Map, Value};
+use tracing::{debug, error};
+
+use crate::{
+    auth::{hash_token, user_from_claims, Authenticator, Credentials},
+    config::{IntrospectionConfig, User},
+};
+
+/// 缓存条目超过此数量时清理过期项
+const CACHE_SWEEP_THRESHOLD: usize = 10_000;
+
+pub struct Introspection {
+    config: IntrospectionConfig,
+    client: Client,
+    /// token 摘要 → (结果, 过期时间)
+    cache: Mutex<HashMap<String, (Option<User>, Instant)>>,
+}
+
+impl Introspection {
+    pub fn new(config: &IntrospectionConfig) -> Result<Self> {
+        let client = Client::builder()
+            .timeout(Duration::from_millis(config.timeout_ms))
+            .build()?;
+        Ok(Self {
+            config: config.clone(),
+            client,
+            cache: Mutex::default(),
+        })
+    }
+
+    /// 返回 (用户, 缓存时长)
+    async fn call(&self, token: &str) -> Result<Option<(User, Duration)>> {
+        let body = format!(
+            "token={}&token_type_hint=access_token",
+            utf8_percent_encode(token, NON_ALPHANUMERIC)
+        );
+        let resp = self
+            .client
+            .post(&self.config.url)
+            .basic_auth(&self.config.client_id, Some(&self.config.client_secret))
+            .header("content-type", "application/x-www-form-urlencoded")
+            .header("accept", "application/json")
+            .body(body)
+            .send()
+            .await?;
+        if !resp.status().is_success() {
+            bail!("HTTP {}", resp.status());
+        }
+
+        let claims: Map<String, Value> = serde_json::from_slice(&resp.bytes().await?)?;
+        if claims.get("active").and_then(Value::as_bool) != Some(true) {
+            debug!("token 未激活");
+            return Ok(None);
+        }
+
+        let user = user_from_claims(
+            token,
+            &claims,
+            &self.config.name_claim,
+            &self.config.targets_claim,
+            &self.config.default_targets,
+        )?;
+
+        let mut ttl = Duration::from_secs(self.config.cache_ttl_secs);
+        if let Some(exp) = claims.get("exp").and_then(Value::as_i64) {
+            ttl = ttl.min(Duration::from_secs((exp - Utc::now().timestamp()).max(0) as u64));
+        }
+        Ok(Some((user, ttl)))
+    }
+}
+
+#[async_trait]
+impl Authenticator for Introspection {
+    async fn authenticate(&self, creds: &Credentials<'_>) -> Result<User> {
+        let key = hash_token(creds.token);
+        if let Some((user, expires)) = self.cache.lock().unwrap().get(&key) {
+            if *expires > Instant::now() {
+                return user.clone().ok_or_else(|| anyhow!("token 未激活"));
+            }
+        }
+
+        let (user, ttl) = match self.call(creds.token).await {
+            Ok(Some((user, ttl))) => (Some(user), ttl),
+            Ok(None) => (None, Duration::from_secs(self.config.negative_ttl_secs)),
+            Err(e) => {
+                // 端点故障不缓存
+                error!("token introspection 失败: {} - {:#}", self.config.url, e);
+                return Err(e);
+            }
+        };
+
+        let mut cache = self.cache.lock().unwrap();
+        if cache.len() >= CACHE_SWEEP_THRESHOLD {
+            let now = Instant::now();
+            cache.retain(|_, (_, expires)| *expires > now);
+        }
+        cache.insert(key, (user.clone(), Instant::now() + ttl));
+        user.ok_or_else(|| anyhow!("token 未激活"))
+    }
+}
@@ -0,0 +1,124 @@
+//! JWT 认证
+//!
+//! `auth_mode = "jwt"` 时 X-Token / ?token= 为 JWT，使用 HMAC 密钥或 JWKS 公钥验证。
+//! claims 映射：
+//! - `name_claim`（默认 `sub`）→ 用户名
+//! - `targets_claim`（默认 `targets`，字符串数组）→ 允许的目标
+//! - `exp` → 过期时间，同时作为会话时长上限
+
+use std::{
+    sync::RwLock,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use jsonwebtoken::{decode, decode_header, jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use reqwest::Client;
+use serde_json::{Map, Value};
+use tracing::{info, warn};
+
+use crate::{
+    auth::{user_from_claims, Authenticator, Credentials},
+    config::{JwtConfig, User},
+};
+
+/// JWKS 缓存时长
+const JWKS_REFRESH: Duration = Duration::from_secs(300);
+
+/// 遇到未知 kid 时提前刷新的最短间隔（防止伪造 kid 触发频繁拉取）
+const JWKS_MIN_REFRESH: Duration = Duration::from_secs(10);
+
+pub struct JwtAuth {
+    config: JwtConfig,
+    secret: Option<DecodingKey>,
+    client: Client,
+    jwks: RwLock<Option<(JwkSet, Instant)>>,
+}
+
+impl JwtAuth {
+    pub fn new(config: &JwtConfig) -> Result<Self> {
+        if config.secret.is_none() && config.jwks_url.is_none() {
+            bail!("jwt 需要配置 secret 或 jwks_url");
+        }
+        Ok(Self {
+            secret: config.secret.as_ref().map(|s| DecodingKey::from_secret(s.as_bytes())),
+            config: config.clone(),
+            client: Client::new(),
+            jwks: RwLock::default(),
+        })
+    }
+
+    /// 按 kid 查找 JWKS 公钥
+    async fn jwks_key(&self, kid: Option<&str>) -> Result<DecodingKey> {
+        let url = self.config.jwks_url.as_deref().context("未配置 jwks_url")?;
+        let kid = kid.context("JWT 缺少 kid")?;
+
+        let age = self.jwks.read().unwrap().as_ref().map(|(_, at)| at.elapsed());
+        if let Some(age) = age {
+            if age < JWKS_REFRESH {
+                if let Some(key) = self.find_key(kid)? {
+                    return Ok(key);
+                }
+            }
+            if age < JWKS_MIN_REFRESH {
+                bail!("未知 kid: {}", kid);
+            }
+        }
+
+        let set: JwkSet = serde_json::from_slice(
+            &self.client.get(url).send().await?.error_for_status()?.bytes().await?,
+        )?;
+        info!("已刷新 JWKS: {} ({} keys)", url, set.keys.len());
+        *self.jwks.write().unwrap() = Some((set, Instant::now()));
+
+        self.find_key(kid)?.ok_or_else(|| {
+            warn!("JWKS 中未找到 kid: {}", kid);
+            anyhow!("未知 kid: {}", kid)
+        })
+    }
+
+    fn find_key(&self, kid: &str) -> Result<Option<DecodingKey>> {
+        let jwks = self.jwks.read().unwrap();
+        match jwks.as_ref().and_then(|(set, _)| set.find(kid)) {
+            Some(jwk) => Ok(Some(DecodingKey::from_jwk(jwk)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+#[async_trait]
+impl Authenticator for JwtAuth {
+    async fn authenticate(&self, creds: &Credentials<'_>) -> Result<User> {
+        let header = decode_header(creds.token)?;
+        if !self.config.algorithms.contains(&header.alg) {
+            bail!("不允许的算法: {:?}", header.alg);
+        }
+
+        let key = match header.alg {
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => {
+                self.secret.clone().context("未配置 secret")?
+            }
+            _ => self.jwks_key(header.kid.as_deref()).await?,
+        };
+
+        // header.alg 已校验在允许列表中
+        let mut validation = Validation::new(header.alg);
+        if let Some(ref iss) = self.config.issuer {
+            validation.set_issuer(&[iss]);
+        }
+        match self.config.audience {
+            Some(ref aud) => validation.set_audience(&[aud]),
+            None => validation.validate_aud = false,
+        }
+
+        let claims = decode::<Map<String, Value>>(creds.token, &key, &validation)?.claims;
+        user_from_claims(
+            creds.token,
+            &claims,
+            &self.config.name_claim,
+            &self.config.targets_claim,
+            &self.config.default_targets,
+        )
+    }
+}
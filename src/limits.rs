@@ -0,0 +1,93 @@
+//! 按用户解析的会话限制
+//!
+//! `/ws` 与 `/rest` 使用同一份解析结果，用户级配置优先于全局：
+//!
+//! | 限制 | 全局 | 用户级 | `/ws` | `/rest` |
+//! |------|------|------|------|------|
+//! | 空闲超时 | `server.idle_timeout_secs` | `idle_timeout_secs` | 双向均无 text / binary 消息时以 `IDLE_TIMEOUT`（4010）关闭 | 等待上游响应、相邻两次读取响应体的间隔上限，超出返回 504 |
+//! | 消息大小 | `server.max_message_bytes` | `max_message_bytes` | 单条消息上限，目标发来超限消息时以 `MESSAGE_TOO_LARGE`（1009）关闭 | 用户级设置同时限制请求体 / 响应体（取与 `[rest]` 的较小值） |
+//! | 最长时长 | `server.max_session_secs` | `max_session_secs` | 到时以 `MAX_SESSION_DURATION`（4002）关闭 | 不适用，见 `rest.timeout_secs` |
+//!
+//! 消息大小不超过 [`MAX_MESSAGE_BYTES`]（客户端与目标两侧 WebSocket 实现的上限）。
+
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use tokio::time::sleep;
+
+use crate::config::{Config, User};
+
+/// 单条 WS 消息大小的上限（tungstenite 默认值）
+pub const MAX_MESSAGE_BYTES: usize = 64 << 20;
+
+/// 某个用户生效的限制
+#[derive(Debug, Clone)]
+pub struct Limits {
+    pub max_session: Option<Duration>,
+    pub idle_timeout: Option<Duration>,
+    /// 单条 WS 消息上限
+    pub max_message_bytes: usize,
+    /// REST 请求体上限
+    pub max_request_bytes: usize,
+    /// REST 响应体上限
+    pub max_response_bytes: Option<usize>,
+}
+
+impl Limits {
+    pub fn resolve(config: &Config, user: &User) -> Self {
+        let server = &config.server;
+        let rest = &config.rest;
+        let secs = |s: Option<u64>| s.map(Duration::from_secs);
+        Self {
+            max_session: secs(user.max_session_secs.or(server.max_session_secs)),
+            idle_timeout: secs(user.idle_timeout_secs.or(server.idle_timeout_secs)),
+            max_message_bytes: user
+                .max_message_bytes
+                .unwrap_or(server.max_message_bytes)
+                .min(MAX_MESSAGE_BYTES),
+            max_request_bytes: user.max_message_bytes.map_or(rest.max_request_bytes, |n| n.min(rest.max_request_bytes)),
+            max_response_bytes: match (user.max_message_bytes, rest.max_response_bytes) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            },
+        }
+    }
+}
+
+/// 会话最近一次收发消息的时间，用于空闲超时
+pub struct Activity {
+    started: Instant,
+    /// 距 `started` 的毫秒数
+    last_ms: AtomicU64,
+}
+
+impl Activity {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            last_ms: AtomicU64::new(0),
+        }
+    }
+
+    pub fn touch(&self) {
+        let ms = self.started.elapsed().as_millis() as u64;
+        self.last_ms.store(ms, Ordering::Relaxed);
+    }
+
+    /// 空闲达到 `timeout` 时返回，未设置时永不返回
+    pub async fn idle(&self, timeout: Option<Duration>) {
+        let Some(timeout) = timeout else {
+            return std::future::pending().await;
+        };
+        loop {
+            let last = self.started + Duration::from_millis(self.last_ms.load(Ordering::Relaxed));
+            let idle = last.elapsed();
+            if idle >= timeout {
+                return;
+            }
+            sleep(timeout - idle).await;
+        }
+    }
+}
@@ -0,0 +1,63 @@
+//! 监听 socket
+//!
+//! 单个 accept 循环在连接风暴下受限于一个核。`server.workers = N`（N > 1）时在同一地址上以
+//! SO_REUSEPORT 创建 N 个监听 socket，各自运行 accept 循环，由内核按连接哈希分配；
+//! 会话注册表、统计等状态仍为进程内共享。
+//!
+//! SO_REUSEPORT 的负载均衡仅 Linux 支持，其他平台及 systemd socket activation 下只使用一个监听 socket。
+//! 监听队列长度、TCP Fast Open 与缓冲区大小见 `[server.tcp]`（[`crate::tcp`]）。
+
+use std::{
+    io,
+    net::{SocketAddr, TcpListener},
+};
+
+use anyhow::{Context, Result};
+use socket2::SockRef;
+use tokio::net::TcpSocket;
+
+use crate::{config::TcpConfig, tcp};
+
+/// 在 `addr` 上创建 `workers` 个监听 socket
+pub fn bind(addr: SocketAddr, workers: usize, config: &TcpConfig) -> Result<Vec<TcpListener>> {
+    let workers = if cfg!(target_os = "linux") {
+        workers.max(1)
+    } else {
+        if workers > 1 {
+            tracing::warn!("server.workers 仅 Linux 支持，使用单个监听 socket");
+        }
+        1
+    };
+    let listeners = (0..workers)
+        .map(|_| listen(addr, workers > 1, config))
+        .collect::<io::Result<Vec<_>>>()
+        .with_context(|| format!("监听失败: {}", addr))?;
+    if workers > 1 {
+        tracing::info!("监听 socket: {} 个（SO_REUSEPORT）", workers);
+    }
+    Ok(listeners)
+}
+
+fn listen(addr: SocketAddr, reuseport: bool, config: &TcpConfig) -> io::Result<TcpListener> {
+    let socket = match addr {
+        SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+    // 与 std 的 TcpListener::bind 一致，重启时不因 TIME_WAIT 绑定失败
+    #[cfg(unix)]
+    socket.set_reuseaddr(true)?;
+    #[cfg(target_os = "linux")]
+    if reuseport {
+        socket.set_reuseport(true)?;
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = reuseport;
+    // 缓冲区大小须在 listen 前设置，accept 的连接继承并据此协商窗口
+    tcp::tune(SockRef::from(&socket));
+    socket.bind(addr)?;
+    let listener = socket.listen(config.listen_backlog)?;
+    if let Some(queue) = config.fastopen_queue.filter(|&q| q > 0) {
+        tcp::enable_fastopen(&listener, queue);
+    }
+    listener.into_std()
+}
@@ -0,0 +1,143 @@
+//! 运行日志采样与消息级跟踪
+//!
+//! 吞吐较高时每个连接的 info 日志会写满磁盘。`[logging] sample_connections = N` 后每 N 个会话只保留一个的 info 日志，
+//! 其余会话（按 span 判断：`ws_session`、`rest_request`、`mux_stream`、`pubsub_session`、`socks5_session`）
+//! 只输出 warn / error。访问日志、审计日志与指标不受影响。
+//!
+//! 消息级跟踪可经管理 API 按用户或会话随时开关，无需重启：开启后该用户 / 会话在 `/ws` 上转发的每条消息
+//! 以 info 级别、target `ws_relay_core::message_trace` 记录方向、类型、大小与开头部分内容，不受采样影响。
+
+use std::{
+    collections::BTreeSet,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        RwLock,
+    },
+};
+
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use tokio_tungstenite::tungstenite::Message as TungMessage;
+use tracing::{
+    span::{Attributes, Id},
+    Level, Metadata, Subscriber,
+};
+use tracing_subscriber::{
+    layer::{Context, Filter},
+    registry::LookupSpan,
+};
+
+use crate::config::LoggingConfig;
+
+/// 消息级跟踪日志的 target
+pub const TRACE_TARGET: &str = "ws_relay_core::message_trace";
+
+/// 参与采样的会话 span
+const SESSION_SPANS: [&str; 5] = ["ws_session", "rest_request", "mux_stream", "pubsub_session", "socks5_session"];
+
+/// 跟踪日志中消息内容的最大长度（字节）
+const PREVIEW_BYTES: usize = 256;
+
+/// 每 N 个会话保留一个
+static SAMPLE_EVERY: AtomicU64 = AtomicU64::new(1);
+static SESSIONS: AtomicU64 = AtomicU64::new(0);
+
+/// 是否有开启跟踪的用户或会话（快速判断，避免每条消息加锁）
+static TRACING: AtomicBool = AtomicBool::new(false);
+static TRACED: Lazy<RwLock<Traced>> = Lazy::new(Default::default);
+
+/// 开启消息级跟踪的用户与会话
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Traced {
+    pub users: BTreeSet<String>,
+    pub sessions: BTreeSet<String>,
+}
+
+/// 应用 `[logging]`（启动时调用）
+pub fn init(config: &LoggingConfig) {
+    SAMPLE_EVERY.store(config.sample_connections.max(1), Ordering::Relaxed);
+}
+
+/// 会话 span 的运行日志被采样丢弃的标记
+struct SampledOut;
+
+/// 运行日志的采样过滤器，只用于输出到终端 / 文件的 fmt 层
+pub struct Sampling;
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Filter<S> for Sampling {
+    fn enabled(&self, meta: &Metadata<'_>, cx: &Context<'_, S>) -> bool {
+        // warn / error 与消息跟踪始终输出
+        if !meta.is_event() || *meta.level() <= Level::WARN || meta.target() == TRACE_TARGET {
+            return true;
+        }
+        let Some(span) = cx.lookup_current() else {
+            return true;
+        };
+        !span.scope().any(|s| s.extensions().get::<SampledOut>().is_some())
+    }
+
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let every = SAMPLE_EVERY.load(Ordering::Relaxed);
+        if every <= 1 || !SESSION_SPANS.contains(&attrs.metadata().name()) {
+            return;
+        }
+        if !SESSIONS.fetch_add(1, Ordering::Relaxed).is_multiple_of(every) {
+            if let Some(span) = ctx.span(id) {
+                span.extensions_mut().insert(SampledOut);
+            }
+        }
+    }
+}
+
+/// 开启 / 关闭用户的消息级跟踪
+pub fn trace_user(name: &str, on: bool) {
+    update(|t| toggle(&mut t.users, name, on));
+}
+
+/// 开启 / 关闭会话的消息级跟踪
+pub fn trace_session(id: &str, on: bool) {
+    update(|t| toggle(&mut t.sessions, id, on));
+}
+
+pub fn traced() -> Traced {
+    TRACED.read().unwrap().clone()
+}
+
+fn update(f: impl FnOnce(&mut Traced)) {
+    let mut traced = TRACED.write().unwrap();
+    f(&mut traced);
+    TRACING.store(!traced.users.is_empty() || !traced.sessions.is_empty(), Ordering::Relaxed);
+}
+
+fn toggle(set: &mut BTreeSet<String>, key: &str, on: bool) {
+    if on {
+        set.insert(key.to_string());
+    } else {
+        set.remove(key);
+    }
+}
+
+/// 该用户 / 会话是否开启了消息级跟踪
+pub fn is_traced(user: &str, session_id: &str) -> bool {
+    if !TRACING.load(Ordering::Relaxed) {
+        return false;
+    }
+    let traced = TRACED.read().unwrap();
+    traced.users.contains(user) || traced.sessions.contains(session_id)
+}
+
+/// 记录一条转发的消息，`dir` 为 `c2t` / `t2c`
+pub fn trace_message(dir: &str, msg: &TungMessage) {
+    let (kind, preview) = match msg {
+        TungMessage::Text(t) => {
+            let end = (0..=PREVIEW_BYTES.min(t.len())).rev().find(|&i| t.is_char_boundary(i)).unwrap_or(0);
+            ("text", &t[..end])
+        }
+        TungMessage::Binary(_) => ("binary", ""),
+        TungMessage::Ping(_) => ("ping", ""),
+        TungMessage::Pong(_) => ("pong", ""),
+        TungMessage::Close(_) => ("close", ""),
+        TungMessage::Frame(_) => ("frame", ""),
+    };
+    tracing::info!(target: TRACE_TARGET, dir, kind, len = msg.len(), "{}", preview);
+}
@@ -1,64 +1,495 @@
 //! ws-relay-core - 高性能 WebSocket + REST 中继代理
 
+mod access_log;
+mod admin;
+mod agent;
+mod audit;
 mod auth;
+mod auth_webhook;
+mod bench;
+mod buffer_pool;
+mod cache;
+mod capture;
+mod chain;
+mod check;
+mod cli;
+mod cluster;
 mod config;
+mod config_diff;
+mod control;
+mod control_codec;
+mod control_frame;
+mod cookie_jar;
+mod cors;
+mod dns;
+mod early_data;
+mod encoding;
+mod error;
+mod error_reporting;
+mod geoip;
+mod header_rules;
+mod health;
+mod host_limits;
+mod internal;
+mod introspection;
+mod jwt;
+mod limits;
+mod listener;
+mod logging;
+mod mux;
+mod panic_guard;
+mod peer_sync;
+mod pool;
+mod pubsub;
+mod queue;
+mod quota;
+mod resources;
 mod rest;
+mod resume;
+mod scripting;
+mod secret;
+mod session_webhook;
+mod socks5;
+mod state;
+mod static_files;
+mod stats;
+mod status;
+mod store_forward;
+#[cfg(unix)]
+mod systemd;
+mod target_rewrite;
+mod tcp;
+mod telemetry;
+mod tls;
+mod upgrade;
+mod upstream;
+mod usage;
+mod user_db;
+mod watch;
 mod ws;
+mod ws_h2;
 
-use anyhow::Result;
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use anyhow::{Context, Result};
 use axum::{middleware, routing::{any, get}, Router};
 use axum_server::tls_rustls::RustlsConfig;
+use clap::Parser;
+use cli::Command;
 use tracing::info;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer};
+
+/// 优雅退出时等待现有连接的最长时间
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(10);
+
+fn main() -> Result<()> {
+    let cli = cli::Cli::parse();
+
+    // 启动服务时先加载配置（[runtime] 决定运行时，[telemetry] 决定是否挂载 OpenTelemetry 层）
+    let config = match cli.command {
+        None | Some(Command::Run) => Some(cli.load_config()?),
+        _ => None,
+    };
+
+    // 后台运行须在启动 tokio 运行时之前 fork
+    if cli.daemon {
+        daemonize(&cli.log_dir)?;
+    }
+
+    runtime(config.as_ref().map(|c| &c.runtime))?.block_on(run(cli, config))
+}
+
+/// 按 `[runtime]` 构建 tokio 运行时，子命令使用默认配置
+fn runtime(config: Option<&config::RuntimeConfig>) -> Result<tokio::runtime::Runtime> {
+    let config = config.cloned().unwrap_or_default();
+    let mut builder = match config.flavor {
+        config::RuntimeFlavor::MultiThread => tokio::runtime::Builder::new_multi_thread(),
+        config::RuntimeFlavor::CurrentThread => tokio::runtime::Builder::new_current_thread(),
+    };
+    if let Some(n) = config.worker_threads {
+        builder.worker_threads(n);
+    }
+    if let Some(n) = config.max_blocking_threads {
+        builder.max_blocking_threads(n);
+    }
+    Ok(builder.enable_all().build()?)
+}
 
-#[tokio::main]
-async fn main() -> Result<()> {
+async fn run(cli: cli::Cli, config: Option<config::Config>) -> Result<()> {
     // 初始化 TLS crypto provider
     rustls::crypto::ring::default_provider()
         .install_default()
         .expect("Failed to install rustls crypto provider");
 
-    // 初始化日志
+    let telemetry = match config.as_ref().and_then(|c| c.telemetry.as_ref()) {
+        Some(t) => Some(telemetry::Telemetry::init(t)?),
+        None => None,
+    };
+
+    // 初始化日志（--log-level 优先于 RUST_LOG）
+    let filter = match cli.log_level {
+        Some(ref level) => EnvFilter::try_new(level)?,
+        None => EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+    };
+    if let Some(ref config) = config {
+        logging::init(&config.logging);
+    }
     tracing_subscriber::registry()
-        .with(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
-        .with(tracing_subscriber::fmt::layer())
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer().with_ansi(!cli.daemon).with_filter(logging::Sampling))
+        .with(telemetry.as_ref().map(|t| tracing_opentelemetry::layer().with_tracer(t.tracer())))
         .init();
 
-    // 加载配置
-    let config_path = std::env::args().nth(1).unwrap_or_else(|| "config.toml".to_string());
-    let config = config::Config::load(&config_path)?;
+    let Some(config) = config else {
+        return run_command(&cli).await;
+    };
+    let config_path = cli.config_path().to_string();
 
     info!("ws-relay-core v{}", env!("CARGO_PKG_VERSION"));
-    info!(
-        "用户: {}",
-        config.users.iter().map(|u| u.name.as_str()).collect::<Vec<_>>().join(", ")
-    );
+    match config.runtime.flavor {
+        config::RuntimeFlavor::MultiThread => {
+            let workers = tokio::runtime::Handle::current().metrics().num_workers();
+            info!("运行时: multi_thread，{} 个工作线程", workers);
+        }
+        config::RuntimeFlavor::CurrentThread => info!("运行时: current_thread"),
+    }
+
+    // TLS 配置
+    let tls_config = RustlsConfig::from_config(Arc::new(tls::load_tls_config(&config.server)?));
 
-    // 认证状态
-    let auth_state = auth::AuthState::new(&config.users);
+    resources::init(&config.resources);
+    dns::init(&config.dns, &config.dns_overrides)?;
+    tcp::init(&config.server.tcp);
+    if let Some(ref geoip) = config.geoip {
+        geoip::init(geoip)?;
+    }
+    if let Some(ref pool) = config.pool {
+        pool::init(pool, config.server.outbound_bind_address)?;
+    }
+
+    let addr = format!("{}:{}", config.server.host, config.server.port);
+    let pid_file = config.server.pid_file.clone();
+    panic_guard::install_hook();
+    let state = state::AppState::new(config)?.with_overrides(cli.overrides());
+    if let Some(ref reporter) = state.error_reporter {
+        error_reporting::install_panic_hook(reporter.clone());
+    }
+    resources::spawn_monitor(&state.config.resources);
+    resources::spawn_memory_guard(&state.config.resources, state.stats.clone());
+    quota::spawn_flusher(state.quota.clone());
+    cluster::spawn(state.clone());
+    auth::spawn_expiry_check(state.auth.clone(), state.stats.clone());
+
+    let quota = state.quota.clone();
+    let stats = state.stats.clone();
 
     // 构建路由（target URL 通过 X-Target-URL Header 传递）
-    let app = Router::new()
+    let mut app = Router::new()
         .route("/ws", get(ws::handler))
-        .route("/rest", any(rest::handler))
-        .layer(middleware::from_fn_with_state(auth_state, auth::middleware));
+        .route("/agent", get(agent::handler))
+        .route("/rest", any(rest::handler));
+    if state.config.mux.is_some() {
+        app = app.route("/mux", get(mux::handler));
+    }
+    if state.config.pubsub.is_some() {
+        app = app.route("/pubsub", get(pubsub::handler));
+    }
+    app = app.layer(middleware::from_fn_with_state(state.clone(), auth::middleware));
 
-    // TLS 配置
-    let tls_config = RustlsConfig::from_pem_file(
-        &config.server.tls_cert,
-        &config.server.tls_key,
-    )
-    .await?;
+    // 节点间同步（独立认证）
+    if state.config.cluster.as_ref().is_some_and(|c| !c.peers.is_empty()) {
+        app = app.route(peer_sync::PATH, get(peer_sync::handler));
+    }
+
+    // 健康检查与版本（无需认证）
+    app = app.merge(health::router());
+
+    // 管理 API（独立认证）
+    if state.config.admin.is_some() {
+        app = app.merge(admin::router(state.clone()));
+        info!("管理 API: /admin + Header: X-Admin-Token");
+    }
+
+    // 优雅退出
+    let handle = axum_server::Handle::new();
+    tokio::spawn(shutdown_signal(state.clone(), handle.clone()));
+    upgrade::spawn(state.clone(), handle.clone());
+
+    // 本地控制通道
+    if let Some(ref control) = state.config.control {
+        control::spawn(control, state.clone(), config_path.clone(), handle.clone()).await?;
+    }
+
+    // 反向隧道 agent
+    if let Some(ref agent) = state.config.agent {
+        agent::spawn(agent);
+    }
+
+    // SOCKS5 入口
+    if let Some(ref socks5) = state.config.socks5 {
+        socks5::spawn(socks5, state.clone()).await?;
+    }
+
+    // 配置重新加载：SIGHUP 与（可选）文件监听
+    #[cfg(unix)]
+    tokio::spawn(reload_on_sighup(state.clone(), config_path.clone()));
+    if state.config.reload.watch {
+        let debounce = Duration::from_millis(state.config.reload.debounce_ms);
+        watch::spawn(state.clone(), config_path.clone(), debounce)?;
+    }
+
+    if let Some(ref dir) = state.config.server.static_dir {
+        app = app.fallback(static_files::handler);
+        info!("静态文件: {}", dir);
+    }
+
+    // 0-RTT：early data 中只放行 WS 握手
+    app = app.layer(middleware::from_fn(early_data::guard));
+    if state.config.server.max_early_data_size > 0 {
+        info!("TLS 0-RTT: 最多 {} 字节 early data", state.config.server.max_early_data_size);
+    }
+    if !state.config.server.insecure_tls_hosts.is_empty() {
+        tracing::warn!("以下目标不校验 TLS 证书: {}", state.config.server.insecure_tls_hosts.join(", "));
+    }
+
+    // CORS 位于最外层，预检请求无需认证
+    if let Some(ref cors) = state.config.cors {
+        app = app.layer(cors::layer(cors)?);
+        info!("CORS: {}", cors.allowed_origins.join(", "));
+    }
+
+    let health = state.health.clone();
+    let workers = state.config.server.workers;
+    let upgrade_drain = Duration::from_secs(state.config.server.upgrade_drain_secs);
+    let tcp_config = state.config.server.tcp.clone();
+    let app = app.with_state(state);
+
+    if let Some(ref path) = pid_file {
+        std::fs::write(path, std::process::id().to_string())?;
+    }
 
     // 启动服务器
-    let addr = format!("{}:{}", config.server.host, config.server.port);
     info!("服务启动: https://{}", addr);
     info!("WS:   /ws + Header: X-Token, X-Target-URL");
     info!("REST: /rest + Header: X-Token, X-Target-URL");
 
-    axum_server::bind_rustls(addr.parse()?, tls_config)
-        .serve(app.into_make_service())
-        .await?;
+    // 平滑升级时沿用旧进程的 socket，其次是 systemd socket activation
+    let upgraded = upgrade::take(upgrade::Role::Main);
+    upgrade::close_unused();
+    #[cfg(unix)]
+    let inherited = systemd::listener()?;
+    #[cfg(not(unix))]
+    let inherited: Option<std::net::TcpListener> = None;
+
+    let listeners = match inherited {
+        _ if !upgraded.is_empty() => upgraded,
+        Some(listener) => {
+            if workers > 1 {
+                tracing::warn!("使用 systemd socket 时忽略 server.workers");
+            }
+            vec![listener]
+        }
+        None => listener::bind(addr.parse()?, workers, &tcp_config)?,
+    };
+    for listener in &listeners {
+        upgrade::register(upgrade::Role::Main, listener);
+    }
+
+    // 监听就绪后标记 /readyz 并通知 systemd
+    {
+        let handle = handle.clone();
+        let health = health.clone();
+        let quota = quota.clone();
+        tokio::spawn(async move {
+            if handle.listening().await.is_some() {
+                health.set_listening();
+                upgrade::notify_ready(quota);
+                #[cfg(unix)]
+                systemd::notify_ready();
+            }
+        });
+    }
+
+    // 每个监听 socket 一个 accept 循环，共享 handle（优雅退出与连接计数）
+    let service = app.into_make_service_with_connect_info::<SocketAddr>();
+    let servers: Vec<_> = listeners
+        .into_iter()
+        .map(|listener| {
+            let server = axum_server::from_tcp_rustls(listener, tls_config.clone())
+                .map(|tls| early_data::EarlyDataAcceptor::new(tls.acceptor(tcp::TcpAcceptor)))
+                .handle(handle.clone());
+            tokio::spawn(server.serve(service.clone()))
+        })
+        .collect();
+    for server in servers {
+        server.await??;
+    }
+
+    // 平滑升级：新进程已接管，等待 WS 等已升级的会话结束后交出新增的配额用量；配额状态与 pid 文件归新进程
+    if upgrade::handed_off() {
+        upgrade::wait_sessions(&stats, upgrade_drain).await;
+        upgrade::hand_over_quota(&quota).await;
+    } else {
+        quota.save()?;
+        if let Some(ref path) = pid_file {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+    if let Some(t) = telemetry {
+        let _ = tokio::task::spawn_blocking(move || t.shutdown()).await;
+    }
+    info!("服务已停止");
+
+    Ok(())
+}
+
+/// 除 `run` 以外的子命令
+async fn run_command(cli: &cli::Cli) -> Result<()> {
+    match cli.command {
+        None | Some(Command::Run) => Ok(()),
+        Some(Command::Version) => {
+            println!("ws-relay-core {}", env!("CARGO_PKG_VERSION"));
+            Ok(())
+        }
+        Some(Command::Replay { ref capture, ref target }) => capture::replay(capture, target).await,
+        Some(Command::Bench {
+            ref url,
+            ref token,
+            ref target,
+            connections,
+            rate,
+            duration,
+            size,
+            binary,
+            window,
+            timeout,
+        }) => {
+            bench::run(bench::Options {
+                url: url.clone(),
+                token: token.clone(),
+                target: target.clone(),
+                connections,
+                rate,
+                duration: Duration::from_secs(duration),
+                size,
+                binary,
+                window,
+                timeout: Duration::from_secs(timeout),
+            })
+            .await
+        }
+        Some(Command::GenCert {
+            ref names,
+            ref cert,
+            ref key,
+            force,
+        }) => tls::write_self_signed(names, cert, key, force),
+        Some(Command::Check { .. }) => check::check(cli.config_path(), &cli.load_config()?),
+        Some(Command::PrintConfig { .. }) => check::print(&cli.load_config()?),
+        Some(Command::VerifyAudit { ref file }) => {
+            let count = audit::verify(file)?;
+            println!("审计日志完整: {}（{} 条记录）", file, count);
+            Ok(())
+        }
+        Some(Command::Usage {
+            command: cli::UsageCommand::Export { from, to, format },
+        }) => usage::export(&cli.load_config()?, from, to, format),
+        Some(Command::Status {
+            ref url,
+            ref token,
+            interval,
+            insecure,
+            once,
+        }) => {
+            let config = cli.load_config()?;
+            let token = match token {
+                Some(t) => t.clone(),
+                None => {
+                    let admin = config.admin.as_ref().context("未配置 [admin]，请用 --token 指定管理 token")?;
+                    admin.token.expose().to_string()
+                }
+            };
+            let url = url.clone().unwrap_or_else(|| format!("https://localhost:{}", config.server.port));
+            let opts = status::Options {
+                url,
+                token,
+                interval: Duration::from_secs(interval.max(1)),
+                insecure,
+                once,
+            };
+            status::run(&config, opts).await
+        }
+        Some(Command::Reload) => {
+            let config = cli.load_config()?;
+            let control = config.control.context("未配置 [control]，无法通知运行中的实例")?;
+            println!("{}", control::send(&control, "reload").await?);
+            Ok(())
+        }
+    }
+}
+
+/// fork 到后台并脱离终端，stdout/stderr 追加写入 `<log_dir>/ws-relay-core.log`
+#[cfg(unix)]
+fn daemonize(log_dir: &str) -> Result<()> {
+    std::fs::create_dir_all(log_dir)?;
+    let log = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(std::path::Path::new(log_dir).join("ws-relay-core.log"))?;
 
+    // 保持工作目录，配置中的相对路径不受影响
+    daemonize::Daemonize::new()
+        .working_directory(std::env::current_dir()?)
+        .stdout(log.try_clone()?)
+        .stderr(log)
+        .start()?;
     Ok(())
 }
+
+#[cfg(not(unix))]
+fn daemonize(_log_dir: &str) -> Result<()> {
+    anyhow::bail!("--daemon 仅支持 Unix，Windows 下请以服务方式运行");
+}
+
+/// 收到 SIGHUP 时重新加载配置
+#[cfg(unix)]
+async fn reload_on_sighup(state: state::AppState, config_path: String) {
+    let Ok(mut hup) = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) else {
+        return;
+    };
+    while hup.recv().await.is_some() {
+        info!("收到 SIGHUP，重新加载配置");
+        if let Err(e) = state.reload(&config_path, "sighup").await {
+            tracing::warn!("重新加载失败（保留当前配置）: {:#}", e);
+        }
+    }
+}
+
+/// 等待 Ctrl+C / SIGTERM，通知服务器停止接受新连接并等待现有连接结束
+async fn shutdown_signal(state: state::AppState, handle: axum_server::Handle) {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut s) => {
+                s.recv().await;
+            }
+            Err(_) => std::future::pending::<()>().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+
+    info!("收到退出信号");
+    #[cfg(unix)]
+    systemd::notify_stopping();
+    health::shutdown(state, handle).await;
+}
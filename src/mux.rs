@@ -0,0 +1,510 @@
+//! 多路复用
+//!
+//! 在一条 WS 连接上承载多个虚拟流，每条 binary 消息为一帧：
+//!
+//! | 偏移 | 长度 | 含义 |
+//! |------|------|------|
+//! | 0 | 1 | 类型：`1` OPEN / `2` DATA / `3` CLOSE / `4` TEXT |
+//! | 1 | 4 | 流 id（大端） |
+//! | 5 | - | OPEN：目标；DATA / TEXT：binary / text 消息内容；CLOSE：原因（可为空） |
+//!
+//! 发起方分配流 id（两端分别使用奇数、偶数），任一方发送 CLOSE 后流即结束。
+//! 没有逐流的流量控制：某个流的接收方处理过慢时，整条连接的读取都会等待。
+//!
+//! 除反向隧道外，配置 `[mux]` 后客户端可连接 `/mux`：认证一次，之后每个 OPEN 帧（目标同 `X-Target-URL`，
+//! 流 id 用奇数）打开一个独立的目标会话，各自校验 `allowed_targets`、计入配额与访问日志（`kind = "mux"`）。
+//! relay 拒绝或结束流时 CLOSE 原因为错误码（如 `TARGET_NOT_ALLOWED`、`CONNECT_FAILED`、`QUOTA_EXCEEDED`）。
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use axum::{
+    extract::{
+        ws::{Message, WebSocket},
+        ConnectInfo, Extension, State, WebSocketUpgrade,
+    },
+    http::StatusCode,
+    response::Response,
+};
+use bytes::{Buf, BufMut, Bytes};
+use chrono::Utc;
+use futures_util::{SinkExt, StreamExt};
+use tokio::{
+    sync::mpsc::{self, error::TrySendError},
+    time::sleep,
+};
+use tokio_tungstenite::tungstenite::{
+    protocol::{frame::coding::CloseCode, CloseFrame},
+    Error as WsError, Message as TungMessage, Utf8Bytes,
+};
+use tracing::{error, info, info_span, warn, Instrument};
+
+use crate::{
+    access_log::{self, AccessRecord},
+    audit::AuditEvent,
+    buffer_pool,
+    config::{self, User},
+    error, geoip, panic_guard, secret,
+    session_webhook::SessionEvent,
+    state::AppState,
+    stats::{SessionStats, SlotLimit},
+    target_rewrite, telemetry, upstream,
+    ws::{self, EndReason, TargetRx, TargetTx},
+};
+
+const OPEN: u8 = 1;
+const DATA: u8 = 2;
+const CLOSE: u8 = 3;
+const TEXT: u8 = 4;
+
+/// 每个流的接收队列长度（帧）
+const STREAM_QUEUE: usize = 64;
+
+/// 待发送帧队列长度
+const OUT_QUEUE: usize = 256;
+
+/// 多路复用帧
+pub enum Frame {
+    Open { id: u32, target: String },
+    Data { id: u32, data: Bytes },
+    Close { id: u32, reason: String },
+    Text { id: u32, text: Utf8Bytes },
+}
+
+impl Frame {
+    pub fn encode(&self) -> Bytes {
+        let (kind, id, payload) = match self {
+            Self::Open { id, target } => (OPEN, *id, target.as_bytes()),
+            Self::Data { id, data } => (DATA, *id, data.as_ref()),
+            Self::Close { id, reason } => (CLOSE, *id, reason.as_bytes()),
+            Self::Text { id, text } => (TEXT, *id, text.as_bytes()),
+        };
+        let mut buf = buffer_pool::get(5 + payload.len());
+        buf.put_u8(kind);
+        buf.put_u32(id);
+        buf.put_slice(payload);
+        buf.freeze()
+    }
+
+    pub fn decode(mut data: Bytes) -> Option<Self> {
+        if data.len() < 5 {
+            return None;
+        }
+        let kind = data.get_u8();
+        let id = data.get_u32();
+        match kind {
+            OPEN => Some(Self::Open {
+                id,
+                target: String::from_utf8(data.to_vec()).ok()?,
+            }),
+            DATA => Some(Self::Data { id, data }),
+            CLOSE => Some(Self::Close {
+                id,
+                reason: String::from_utf8_lossy(&data).into_owned(),
+            }),
+            TEXT => Some(Self::Text {
+                id,
+                text: Utf8Bytes::try_from(data).ok()?,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// 一条连接上的虚拟流
+///
+/// 待发送的帧写入 `out`，由调用方发往 WS；收到的帧交给 [`Mux::dispatch`]。
+pub struct Mux {
+    out: mpsc::Sender<Frame>,
+    streams: Mutex<HashMap<u32, mpsc::Sender<TungMessage>>>,
+    next_id: AtomicU32,
+}
+
+impl Mux {
+    /// `first_id` 为本端分配的第一个流 id（`1` 或 `2`）
+    pub fn new(out: mpsc::Sender<Frame>, first_id: u32) -> Arc<Self> {
+        Arc::new(Self {
+            out,
+            streams: Mutex::default(),
+            next_id: AtomicU32::new(first_id),
+        })
+    }
+
+    /// 打开新流，返回消息收发两端（close 消息关闭流，其 reason 发给对端）
+    pub async fn open(self: &Arc<Self>, target: &str) -> Result<(TargetTx, TargetRx)> {
+        let id = self.next_id.fetch_add(2, Ordering::Relaxed);
+        let rx = self.register(id);
+        let open = Frame::Open {
+            id,
+            target: target.to_string(),
+        };
+        if self.out.send(open).await.is_err() {
+            self.streams.lock().unwrap().remove(&id);
+            return Err(anyhow!("多路复用连接已断开"));
+        }
+        Ok(self.stream(id, rx))
+    }
+
+    /// 处理收到的帧，对端打开的流返回 (目标, 发送端, 接收端)
+    pub async fn dispatch(self: &Arc<Self>, frame: Frame) -> Option<(String, TargetTx, TargetRx)> {
+        match frame {
+            Frame::Open { id, target } => {
+                let rx = self.register(id);
+                let (tx, rx) = self.stream(id, rx);
+                Some((target, tx, rx))
+            }
+            Frame::Data { id, data } => {
+                self.deliver(id, TungMessage::Binary(data)).await;
+                None
+            }
+            Frame::Text { id, text } => {
+                self.deliver(id, TungMessage::Text(text)).await;
+                None
+            }
+            Frame::Close { id, .. } => {
+                self.streams.lock().unwrap().remove(&id);
+                None
+            }
+        }
+    }
+
+    /// 当前打开的流数
+    pub fn stream_count(&self) -> usize {
+        self.streams.lock().unwrap().len()
+    }
+
+    /// 结束全部流（连接断开时）
+    pub fn close_all(&self) {
+        self.streams.lock().unwrap().clear();
+    }
+
+    async fn deliver(&self, id: u32, msg: TungMessage) {
+        let tx = self.streams.lock().unwrap().get(&id).cloned();
+        if let Some(tx) = tx {
+            let _ = tx.send(msg).await;
+        }
+    }
+
+    fn register(&self, id: u32) -> mpsc::Receiver<TungMessage> {
+        let (tx, rx) = mpsc::channel(STREAM_QUEUE);
+        self.streams.lock().unwrap().insert(id, tx);
+        rx
+    }
+
+    fn stream(self: &Arc<Self>, id: u32, rx: mpsc::Receiver<TungMessage>) -> (TargetTx, TargetRx) {
+        let handle = Arc::new(StreamHandle { id, mux: self.clone() });
+        let rx = futures_util::stream::unfold((rx, handle.clone()), |(mut rx, handle)| async move {
+            let msg = rx.recv().await?;
+            Some((Ok(msg), (rx, handle)))
+        });
+        let tx = futures_util::sink::unfold(handle, |handle, msg: TungMessage| async move {
+            let id = handle.id;
+            let frame = match msg {
+                TungMessage::Binary(data) => Frame::Data { id, data },
+                TungMessage::Text(text) => Frame::Text { id, text },
+                TungMessage::Close(frame) => {
+                    handle.close(frame.map(|f| f.reason.to_string()).unwrap_or_default());
+                    return Ok(handle);
+                }
+                _ => return Ok(handle),
+            };
+            handle.mux.out.send(frame).await.map_err(|_| WsError::ConnectionClosed)?;
+            Ok(handle)
+        });
+        (Box::pin(tx), Box::pin(rx))
+    }
+}
+
+/// 收发两端共享，全部释放时关闭流
+struct StreamHandle {
+    id: u32,
+    mux: Arc<Mux>,
+}
+
+impl StreamHandle {
+    /// 本端关闭：通知对端（流已被对端关闭时不再发送）
+    fn close(&self, reason: String) {
+        if self.mux.streams.lock().unwrap().remove(&self.id).is_none() {
+            return;
+        }
+        let frame = Frame::Close { id: self.id, reason };
+        if let Err(TrySendError::Full(frame)) = self.mux.out.try_send(frame) {
+            let out = self.mux.out.clone();
+            tokio::spawn(async move { out.send(frame).await });
+        }
+    }
+}
+
+impl Drop for StreamHandle {
+    fn drop(&mut self) {
+        self.close(String::new());
+    }
+}
+
+/// 多路复用入口
+/// 路由: /mux（认证后以 OPEN 帧打开目标）
+pub async fn handler(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(user): Extension<User>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    if state.quota.is_exhausted(&user) {
+        warn!("[{}] 流量配额已用尽，拒绝连接", user.name);
+        return error::response(StatusCode::FORBIDDEN, "QUOTA_EXCEEDED", "流量配额已用尽");
+    }
+    info!("[{}] 多路复用连接", user.name);
+    ws.on_upgrade(move |socket| serve(socket, addr, user, state))
+}
+
+async fn serve(socket: WebSocket, addr: SocketAddr, user: User, state: AppState) {
+    let max_streams = state.config.mux.as_ref().map_or(0, |m| m.max_streams);
+    let (out_tx, mut out_rx) = mpsc::channel(OUT_QUEUE);
+    let mux = Mux::new(out_tx, 2);
+    let (mut tx, mut rx) = socket.split();
+    loop {
+        tokio::select! {
+            Some(frame) = out_rx.recv() => {
+                if tx.send(Message::Binary(frame.encode())).await.is_err() {
+                    break;
+                }
+            }
+            msg = rx.next() => match msg {
+                Some(Ok(Message::Binary(data))) => {
+                    let Some(frame) = Frame::decode(data) else {
+                        warn!("[{}] 无效的多路复用帧，断开连接", user.name);
+                        break;
+                    };
+                    let Some((target, stream_tx, stream_rx)) = mux.dispatch(frame).await else {
+                        continue;
+                    };
+                    if mux.stream_count() > max_streams {
+                        reject(stream_tx, "TOO_MANY_STREAMS").await;
+                        continue;
+                    }
+                    let (user, state) = (user.clone(), state.clone());
+                    tokio::spawn(async move {
+                        let session_id = access_log::new_session_id();
+                        let span = info_span!("mux_stream", session_id = %session_id, user = %user.name, target = %target);
+                        serve_stream(session_id, target, stream_tx, stream_rx, addr, user, state)
+                            .instrument(span)
+                            .await
+                    });
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            }
+        }
+    }
+    info!("[{}] 多路复用连接结束（关闭 {} 个流）", user.name, mux.stream_count());
+    mux.close_all();
+}
+
+/// 以 CLOSE 帧结束流，原因为错误码
+async fn reject(mut stream_tx: TargetTx, code: &str) {
+    let frame = CloseFrame {
+        code: CloseCode::Policy,
+        reason: code.into(),
+    };
+    let _ = stream_tx.send(TungMessage::Close(Some(frame))).await;
+}
+
+/// 单个流：校验目标、连接并转发
+async fn serve_stream(
+    session_id: String,
+    target: String,
+    mut stream_tx: TargetTx,
+    stream_rx: TargetRx,
+    addr: SocketAddr,
+    user: User,
+    state: AppState,
+) {
+    // 会话名额随流持有，被拒绝时立即释放
+    let slot = state.reserve_session(&user);
+    let denied = if !user.allows_target(&target) {
+        Some(("TARGET_NOT_ALLOWED", "目标不在允许列表"))
+    } else if target.starts_with("tcp://") && !state.config.server.tcp_targets {
+        Some(("TCP_TARGETS_DISABLED", "未开启 tcp:// 目标（server.tcp_targets）"))
+    } else if config::unix_socket_denied(&state.config.server, &target) {
+        Some(("UNIX_SOCKET_NOT_ALLOWED", ws::UNIX_SOCKET_NOT_ALLOWED))
+    } else if target_rewrite::violates_tls(&state.config, &target) {
+        Some(("TLS_REQUIRED", "仅允许 TLS 目标（server.require_tls_targets）"))
+    } else if state.quota.is_exhausted(&user) {
+        Some(("QUOTA_EXCEEDED", "流量配额已用尽"))
+    } else if matches!(slot, Err(SlotLimit::Total)) {
+        Some(("TOO_MANY_SESSIONS", "节点活跃会话数已达上限"))
+    } else if matches!(slot, Err(SlotLimit::User)) {
+        Some(("USER_SESSION_LIMIT", "活跃会话数已达上限"))
+    } else if user.is_expired(Utc::now()) {
+        Some(("USER_EXPIRED", "账号已过期"))
+    } else {
+        None
+    };
+    state.audit(&AuditEvent::Auth {
+        client_ip: addr.ip(),
+        user: Some(&user.name),
+        target: &secret::redact_url(&target),
+        result: if denied.is_none() { "ok" } else { "denied" },
+        reason: denied.map(|(_, message)| message),
+    });
+    if let Some((code, message)) = denied {
+        warn!("[{}] {}: {}", user.name, message, secret::redact_url(&target));
+        reject(stream_tx, code).await;
+        return;
+    }
+    let target = match upstream::resolve(&state.config.upstreams, &target, &user.name, None) {
+        Ok(t) => t.into_owned(),
+        Err((code, message)) => {
+            warn!("[{}] {}: {}", user.name, message, secret::redact_url(&target));
+            reject(stream_tx, code).await;
+            return;
+        }
+    };
+    // 目标主机限流，名额随流持有
+    let _permit = match state
+        .host_limits
+        .acquire(&target_rewrite::rewrite(&state.config.target_rewrites, &target))
+    {
+        Ok(p) => p,
+        Err((code, message)) => {
+            warn!("[{}] {}: {}", user.name, message, secret::redact_url(&target));
+            reject(stream_tx, code).await;
+            return;
+        }
+    };
+
+    let started = Instant::now();
+    let guard = state.stats.open_session(&session_id, &user.name, &target);
+    state.session_event(&SessionEvent::SessionStart {
+        session_id: &session_id,
+        kind: "mux",
+        user: &user.name,
+        client_ip: addr.ip(),
+        target: &target,
+    });
+    let relayed = async {
+        match ws::open_user_target(&target, user.sni_override.as_deref(), &state, &user, None).await {
+            Ok((target_tx, target_rx, _)) => {
+                info!("已连接目标: {}", secret::redact_url(&target));
+                pump(&mut stream_tx, stream_rx, target_tx, target_rx, &state, &user, &guard.session).await
+            }
+            Err(e) => {
+                error!("连接目标失败: {} - {:#}", secret::redact_url(&target), e);
+                state.report_connect_failure(&user.name, &target, e.as_ref());
+                reject(stream_tx, "CONNECT_FAILED").await;
+                EndReason::ConnectFailed
+            }
+        }
+    };
+    let reason = panic_guard::catch("mux", &session_id, relayed).await.unwrap_or(EndReason::Panicked);
+
+    if let Some((code, message, _)) = reason.close_info() {
+        warn!("[{}] {}，终止流: {}", user.name, message, secret::redact_url(&target));
+        state.audit(&AuditEvent::Disconnect {
+            session_id: &session_id,
+            user: &user.name,
+            target: &secret::redact_url(&target),
+            reason: code,
+        });
+    }
+    let record = AccessRecord {
+        session_id: &session_id,
+        kind: "mux",
+        user: &user.name,
+        client_ip: addr.ip(),
+        country: geoip::country(addr.ip()),
+        target: &target,
+        duration_ms: started.elapsed().as_millis() as u64,
+        bytes_up: guard.session.bytes_up.load(Ordering::Relaxed),
+        bytes_down: guard.session.bytes_down.load(Ordering::Relaxed),
+        close_reason: reason.as_str(),
+        summary: None,
+    };
+    telemetry::record(&record);
+    state.stats.record_end(&record);
+    if let Some(ref log) = state.access_log {
+        log.record(&record);
+    }
+    state.session_event(&SessionEvent::SessionEnd(&record));
+}
+
+/// 流与目标之间双向转发，relay 主动结束时以错误码关闭流
+async fn pump(
+    stream_tx: &mut TargetTx,
+    mut stream_rx: TargetRx,
+    mut target_tx: TargetTx,
+    mut target_rx: TargetRx,
+    state: &AppState,
+    user: &User,
+    session: &SessionStats,
+) -> EndReason {
+    let user_stats = state.stats.user(&user.name);
+    let quota = state.quota.user(user);
+    let max_session = user.max_session_secs.or(state.config.server.max_session_secs);
+
+    let up = async {
+        while let Some(Ok(msg)) = stream_rx.next().await {
+            let len = msg.len() as u64;
+            let data = msg.is_text() || msg.is_binary();
+            user_stats.record(&msg);
+            if target_tx.send(msg).await.is_err() {
+                return EndReason::TargetClosed;
+            }
+            session.add_up(len);
+            if data && !quota.consume_up(len) {
+                return EndReason::QuotaExceeded;
+            }
+        }
+        EndReason::ClientClosed
+    };
+
+    let down = async {
+        while let Some(Ok(msg)) = target_rx.next().await {
+            match msg {
+                TungMessage::Binary(_) | TungMessage::Text(_) => {}
+                TungMessage::Close(_) => break,
+                _ => continue,
+            }
+            let len = msg.len() as u64;
+            user_stats.record(&msg);
+            if stream_tx.send(msg).await.is_err() {
+                return EndReason::ClientClosed;
+            }
+            session.add_down(len);
+            if !quota.consume_down(len) {
+                return EndReason::QuotaExceeded;
+            }
+        }
+        EndReason::TargetClosed
+    };
+
+    let deadline = async {
+        match max_session {
+            Some(secs) => sleep(Duration::from_secs(secs)).await,
+            None => std::future::pending().await,
+        }
+    };
+
+    let reason = tokio::select! {
+        r = up => r,
+        r = down => r,
+        _ = deadline => EndReason::MaxDuration,
+        _ = session.terminate.notified() => session.terminate_reason().into(),
+    };
+    if let Some((code, _, _)) = reason.close_info() {
+        let frame = CloseFrame {
+            code: CloseCode::Policy,
+            reason: code.into(),
+        };
+        let _ = stream_tx.send(TungMessage::Close(Some(frame))).await;
+    }
+    reason
+}
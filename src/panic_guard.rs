@@ -0,0 +1,80 @@
+//! 连接任务的 panic 隔离
+//!
+//! 会话处理中的 panic 原本只会让该任务静默结束：访问日志、指标与会话事件都不会记录。
+//! `/ws`、`/mux`、`/pubsub`、SOCKS5 会话与 REST 请求的处理都经 [`catch`] 执行，panic 时：
+//!
+//! - 以 error 级别输出结构化报告（会话 ID、panic 信息、位置、backtrace）
+//! - 累加 panic 计数（控制通道 `status` 的 `panics`，OpenTelemetry 指标 `relay.panics`）
+//! - 会话按 `internal_error` 正常结束，访问日志、统计与会话事件照常记录；REST 请求返回 500
+//!
+//! backtrace 由 [`install_hook`] 安装的 panic hook 在 panic 所在线程捕获，展开到 [`catch`] 时取出。
+
+use std::{
+    any::Any,
+    backtrace::Backtrace,
+    cell::RefCell,
+    future::Future,
+    panic::AssertUnwindSafe,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use futures_util::FutureExt;
+use tracing::error;
+
+use crate::telemetry;
+
+/// 会话被 panic 中断时访问日志中的结束原因
+pub const CLOSE_REASON: &str = "internal_error";
+
+static PANICS: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    /// 本线程最近一次 panic 的 (位置, backtrace)
+    static LAST: RefCell<Option<(String, Backtrace)>> = const { RefCell::new(None) };
+}
+
+/// 安装 panic hook：记录位置与 backtrace 后交给原有 hook（启动时调用）
+pub fn install_hook() {
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let location = info.location().map(|l| l.to_string()).unwrap_or_default();
+        LAST.with(|last| *last.borrow_mut() = Some((location, Backtrace::force_capture())));
+        previous(info);
+    }));
+}
+
+/// 进程启动以来被捕获的 panic 数
+pub fn count() -> u64 {
+    PANICS.load(Ordering::Relaxed)
+}
+
+/// 执行 `fut`，panic 时输出报告并返回 `None`
+pub async fn catch<F: Future>(kind: &'static str, session_id: &str, fut: F) -> Option<F::Output> {
+    let payload = match AssertUnwindSafe(fut).catch_unwind().await {
+        Ok(output) => return Some(output),
+        Err(payload) => payload,
+    };
+    let (location, backtrace) = LAST
+        .with(|last| last.borrow_mut().take())
+        .map_or((String::new(), String::new()), |(l, b)| (l, b.to_string()));
+    PANICS.fetch_add(1, Ordering::Relaxed);
+    telemetry::record_panic(kind);
+    error!(
+        session_id,
+        kind,
+        panic = %message(payload.as_ref()),
+        location,
+        backtrace,
+        "会话处理 panic，已隔离"
+    );
+    None
+}
+
+/// panic 信息（`&str` / `String` 以外的 payload 无法显示）
+pub fn message(payload: &(dyn Any + Send)) -> &str {
+    match (payload.downcast_ref::<&str>(), payload.downcast_ref::<String>()) {
+        (Some(s), _) => s,
+        (_, Some(s)) => s,
+        _ => "<非字符串 panic>",
+    }
+}
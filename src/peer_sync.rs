@@ -0,0 +1,233 @@
+//! 节点间直接同步（无 Redis）
+//!
+//! `[cluster] peers` 列出其他节点时，每个节点连接各 peer 的 `/cluster/sync`（`X-Cluster-Token` 认证），
+//! 每 `sync_secs` 秒推送一次完整状态，不依赖 Redis，也没有主节点：
+//!
+//! | 状态 | 合并方式 |
+//! |------|------|
+//! | 配额用量 | G-Counter：每个节点只累加自己的计数，合并时逐节点取较大值，合计为各节点之和 |
+//! | 封禁列表 | LWW：每个用户保留最近一次封禁 / 解封的记录 |
+//! | 会话数 | 各节点上报的快照，`node_ttl_secs` 内未更新则不再计入 |
+//!
+//! 快照带上已知的其他节点的用量计数，未直接相连的节点之间也能收敛。计数按配额周期区分，周期切换后旧计数丢弃。
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+use axum::{
+    extract::{ws::Message, State, WebSocketUpgrade},
+    http::{HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use futures_util::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::watch,
+    time::{interval, sleep},
+};
+use tokio_tungstenite::tungstenite::Message as TungMessage;
+use tracing::{debug, info, warn};
+
+use crate::{
+    cluster::BanRecord,
+    config::ClusterConfig,
+    secret::SecretString,
+    state::AppState,
+    stats::TerminateReason,
+    ws::{self, Dial},
+};
+
+pub const TOKEN_HEADER: &str = "X-Cluster-Token";
+
+/// 同步入口路径
+pub const PATH: &str = "/cluster/sync";
+
+const RECONNECT_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_MAX: Duration = Duration::from_secs(30);
+
+/// 从其他节点收到的状态
+#[derive(Default)]
+pub struct PeerState {
+    /// 计数所属的配额周期
+    period: String,
+    /// 其他节点 → 用户 → 本周期用量
+    usage: HashMap<String, HashMap<String, u64>>,
+    /// 其他节点上报的会话数与收到的时间
+    sessions: HashMap<String, (Instant, HashMap<String, usize>)>,
+}
+
+/// 节点推送的完整状态
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    node: String,
+    period: String,
+    /// 节点 → 用户 → 本周期用量（本节点及已知的其他节点）
+    usage: HashMap<String, HashMap<String, u64>>,
+    /// 本节点的活跃会话数
+    sessions: HashMap<String, usize>,
+    bans: HashMap<String, BanRecord>,
+}
+
+/// 节点间同步入口
+/// 路由: /cluster/sync + Header X-Cluster-Token
+pub async fn handler(State(state): State<AppState>, headers: HeaderMap, ws: WebSocketUpgrade) -> Response {
+    let expected = state.config.cluster.as_ref().and_then(|c| c.peer_token.as_ref());
+    let token = headers.get(TOKEN_HEADER).and_then(|v| v.to_str().ok());
+    if !expected.zip(token).is_some_and(|(e, t)| e.matches(t)) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    ws.on_upgrade(move |mut socket| async move {
+        while let Some(Ok(msg)) = socket.next().await {
+            let Message::Text(text) = msg else {
+                continue;
+            };
+            match serde_json::from_str::<Snapshot>(&text) {
+                Ok(snapshot) => merge(&state, snapshot),
+                Err(e) => debug!("集群: 无法解析节点状态 - {}", e),
+            }
+        }
+    })
+}
+
+/// 合并其他节点的状态
+fn merge(state: &AppState, snapshot: Snapshot) {
+    let cluster = &state.cluster;
+    if snapshot.node == cluster.node_id {
+        return;
+    }
+    {
+        let mut peers = cluster.peers.lock().unwrap();
+        if snapshot.period == peers.period {
+            for (node, counts) in snapshot.usage.into_iter().filter(|(n, _)| *n != cluster.node_id) {
+                let known = peers.usage.entry(node).or_default();
+                for (user, n) in counts {
+                    let count = known.entry(user).or_default();
+                    *count = (*count).max(n);
+                }
+            }
+        }
+        peers.sessions.insert(snapshot.node, (Instant::now(), snapshot.sessions));
+    }
+    for user in cluster.merge_bans(snapshot.bans) {
+        let n = state.stats.terminate_user(&user, TerminateReason::Banned);
+        if n > 0 {
+            warn!("[{}] 用户已被封禁，终止 {} 个活跃会话", user, n);
+        }
+    }
+}
+
+/// 按已收到的状态更新配额合计与其他节点的会话数，返回本节点要推送的状态
+fn refresh(state: &AppState, ttl: Duration) -> Snapshot {
+    let cluster = &state.cluster;
+    let (period, own) = state.quota.snapshot();
+    let mut peers = cluster.peers.lock().unwrap();
+    if peers.period != period {
+        peers.period = period.clone();
+        peers.usage.clear();
+    }
+    peers.sessions.retain(|_, (at, _)| at.elapsed() < ttl);
+
+    let mut global = own.clone();
+    for counts in peers.usage.values() {
+        for (user, n) in counts {
+            *global.entry(user.clone()).or_default() += n;
+        }
+    }
+    state.quota.set_global(&period, global);
+
+    let mut remote = HashMap::new();
+    for (_, counts) in peers.sessions.values() {
+        for (user, n) in counts {
+            *remote.entry(user.clone()).or_default() += n;
+        }
+    }
+    cluster.set_remote_sessions(remote);
+    cluster.set_connected(!peers.sessions.is_empty());
+
+    let mut usage = peers.usage.clone();
+    usage.insert(cluster.node_id.clone(), own);
+    Snapshot {
+        node: cluster.node_id.clone(),
+        period,
+        usage,
+        sessions: state.stats.sessions_by_user(),
+        bans: cluster.ban_log(),
+    }
+}
+
+/// 定期更新状态并推送给每个 peer
+pub fn spawn(state: AppState, config: ClusterConfig) {
+    info!("集群: 节点 {}，与 {} 个节点直接同步", state.cluster.node_id, config.peers.len());
+    let token = config.peer_token.clone().unwrap_or_default();
+    let (tx, rx) = watch::channel(String::new());
+    for peer in &config.peers {
+        tokio::spawn(push(peer.clone(), token.clone(), state.clone(), rx.clone()));
+    }
+    let ttl = Duration::from_secs(config.node_ttl_secs);
+    tokio::spawn(async move {
+        let mut interval = interval(Duration::from_secs(config.sync_secs));
+        loop {
+            interval.tick().await;
+            let snapshot = refresh(&state, ttl);
+            match serde_json::to_string(&snapshot) {
+                Ok(json) => {
+                    tx.send_replace(json);
+                }
+                Err(e) => warn!("集群: 序列化节点状态失败 - {}", e),
+            }
+        }
+    });
+}
+
+/// 保持与一个 peer 的连接，断开后退避重连
+async fn push(peer: String, token: SecretString, state: AppState, snapshots: watch::Receiver<String>) {
+    let url = format!("{}{}", peer.trim_end_matches('/'), PATH);
+    let mut delay = RECONNECT_MIN;
+    loop {
+        let started = Instant::now();
+        match push_once(&url, token.expose(), &state, snapshots.clone()).await {
+            Ok(()) => info!("集群: 与节点 {} 的连接已断开，{:?} 后重连", peer, delay),
+            Err(e) => debug!("集群: 连接节点 {} 失败，{:?} 后重试 - {:#}", peer, delay, e),
+        }
+        // 连接维持过一段时间则从最短间隔重新退避
+        if started.elapsed() > RECONNECT_MAX {
+            delay = RECONNECT_MIN;
+        }
+        sleep(delay).await;
+        delay = (delay * 2).min(RECONNECT_MAX);
+    }
+}
+
+async fn push_once(url: &str, token: &str, state: &AppState, mut snapshots: watch::Receiver<String>) -> Result<()> {
+    let mut headers = HeaderMap::new();
+    headers.insert(TOKEN_HEADER, HeaderValue::from_str(token)?);
+    let dial = Dial {
+        sni: None,
+        bind: state.config.server.outbound_bind_address,
+        http2: false,
+        header_rules: &[],
+        headers: Some(&headers),
+        insecure_hosts: &state.config.server.insecure_tls_hosts,
+    };
+    let (mut tx, mut rx) = ws::open_target(url, &dial).await?;
+    info!("集群: 已连接节点 {}", url);
+    loop {
+        tokio::select! {
+            changed = snapshots.changed() => {
+                if changed.is_err() {
+                    return Ok(());
+                }
+                let json = snapshots.borrow_and_update().clone();
+                tx.send(TungMessage::Text(json.into())).await?;
+            }
+            msg = rx.next() => match msg {
+                Some(Ok(_)) => {}
+                Some(Err(e)) => bail!(e),
+                None => return Ok(()),
+            },
+        }
+    }
+}
@@ -0,0 +1,108 @@
+//! 出站连接池
+//!
+//! 配置 `[pool]` 后为 `targets` 中的热门目标预先建立 `size` 个 TCP + TLS 连接（尚未发送 WS 握手），
+//! 新会话连接这些目标时直接取用，只需完成 WS 升级；取走后由后台任务补足。
+//!
+//! - 空闲超过 `ttl_secs` 的连接丢弃重建（上游通常会关闭长时间无请求的连接）
+//! - 仅用于 `wss://` 目标，且未覆盖 SNI、未开启 `ws_over_http2`、出口地址与 `server.outbound_bind_address` 一致
+//! - 取用时已被上游关闭、或 WS 升级失败的连接会被丢弃并重新连接，不影响会话
+//! - TLS 会话票据由全局 TLS 客户端缓存，未入池的目标重连时同样可恢复会话
+
+use std::{
+    collections::{HashMap, VecDeque},
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Context, Result};
+use futures_util::FutureExt;
+use once_cell::sync::OnceCell;
+use tokio::{io::AsyncReadExt, net::TcpStream, sync::Notify, time::timeout};
+use tokio_rustls::client::TlsStream;
+use tracing::{debug, info, warn};
+
+use crate::{config::PoolConfig, ws};
+
+/// 预先建立的连接
+pub type Conn = TlsStream<TcpStream>;
+
+/// 后台补足连接的最长检查间隔
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// 单个目标的空闲连接
+#[derive(Default)]
+struct Slot {
+    idle: Mutex<VecDeque<(Instant, Conn)>>,
+    /// 连接被取走后唤醒补足任务
+    refill: Notify,
+}
+
+struct Pool {
+    slots: HashMap<(String, u16), Arc<Slot>>,
+    bind: Option<IpAddr>,
+    ttl: Duration,
+}
+
+static POOL: OnceCell<Pool> = OnceCell::new();
+
+/// 启动预热任务（启动时调用一次），`bind` 为服务端出口地址
+pub fn init(config: &PoolConfig, bind: Option<IpAddr>) -> Result<()> {
+    let ttl = Duration::from_secs(config.ttl_secs);
+    let mut slots = HashMap::new();
+    for target in &config.targets {
+        let (host, port) = target
+            .rsplit_once(':')
+            .and_then(|(h, p)| Some((h.to_ascii_lowercase(), p.parse::<u16>().ok()?)))
+            .ok_or_else(|| anyhow!("连接池目标须为 host:port: {}", target))
+            .context("pool.targets 无效")?;
+        let slot = Arc::new(Slot::default());
+        tokio::spawn(warm(slot.clone(), host.clone(), port, config.size, ttl, bind));
+        slots.insert((host, port), slot);
+    }
+    info!("连接池: {}（每个目标 {} 个连接）", config.targets.join(", "), config.size);
+    let _ = POOL.set(Pool { slots, bind, ttl });
+    Ok(())
+}
+
+/// 取出一个可用的预建连接
+pub fn take(host: &str, port: u16, bind: Option<IpAddr>) -> Option<Conn> {
+    let pool = POOL.get().filter(|p| p.bind == bind)?;
+    let slot = pool.slots.get(&(host.to_ascii_lowercase(), port))?;
+    let conn = {
+        let mut idle = slot.idle.lock().unwrap();
+        std::iter::from_fn(|| idle.pop_front())
+            .filter(|(at, _)| at.elapsed() < pool.ttl)
+            .map(|(_, conn)| conn)
+            .find_map(|mut conn| is_open(&mut conn).then_some(conn))
+    };
+    slot.refill.notify_one();
+    if conn.is_some() {
+        debug!("使用连接池中的连接: {}:{}", host, port);
+    }
+    conn
+}
+
+/// 连接是否仍可用：非阻塞读取一次，处理上游发来的会话票据等 TLS 记录，
+/// 读到 EOF、错误或意外的应用数据均视为不可用
+fn is_open(conn: &mut Conn) -> bool {
+    let mut buf = [0u8; 1];
+    conn.read(&mut buf).now_or_never().is_none()
+}
+
+/// 保持 `size` 个未过期的空闲连接
+async fn warm(slot: Arc<Slot>, host: String, port: u16, size: usize, ttl: Duration, bind: Option<IpAddr>) {
+    loop {
+        slot.idle.lock().unwrap().retain(|(at, _)| at.elapsed() < ttl);
+        while slot.idle.lock().unwrap().len() < size {
+            match ws::dial_tls(&host, port, bind).await {
+                Ok(conn) => slot.idle.lock().unwrap().push_back((Instant::now(), conn)),
+                Err(e) => {
+                    warn!("连接池预建连接失败: {}:{} - {:#}", host, port, e);
+                    break;
+                }
+            }
+        }
+        let _ = timeout(CHECK_INTERVAL.min(ttl), slot.refill.notified()).await;
+    }
+}
@@ -0,0 +1,310 @@
+//! 内置发布/订阅频道
+//!
+//! 配置 `[pubsub]` 后客户端可连接 `/pubsub`（认证同 `/ws`，无需目标），以 JSON text 消息加入频道并互相广播，
+//! 适合没有上游服务的小型实时应用：
+//!
+//! | 客户端发送 | relay 回复 |
+//! |------|------|
+//! | `{"type":"join","channel":"x"}` | `{"type":"joined","channel":"x","members":2}` |
+//! | `{"type":"leave","channel":"x"}` | `{"type":"left","channel":"x"}` |
+//! | `{"type":"publish","channel":"x","data":...}` | 其他成员收到 `{"type":"message","channel":"x","from":"alice","data":...}` |
+//!
+//! - 频道按 `channel:<名称>` 匹配用户的 `allowed_targets`
+//! - 限制：每频道成员数、每客户端频道数、频道总数、单条消息大小；出错时回复错误 JSON，
+//!   远超消息上限（超出 `COMMAND_OVERHEAD`）的帧不读入，以 `MESSAGE_TOO_LARGE`（1009）断开
+//! - 成员发送队列已满时丢弃发给它的消息，不影响其他成员
+//! - 收发字节计入流量配额与访问日志（`kind = "pubsub"`）
+
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Instant,
+};
+
+use axum::{
+    extract::{
+        ws::{CloseFrame, Message, WebSocket},
+        ConnectInfo, Extension, State, WebSocketUpgrade,
+    },
+    http::StatusCode,
+    response::Response,
+};
+use futures_util::{SinkExt, StreamExt};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+use tracing::{debug, info, info_span, warn, Instrument};
+
+use crate::{
+    access_log::{self, AccessRecord},
+    audit::AuditEvent,
+    config::{PubSubConfig, User},
+    error, geoip, panic_guard,
+    session_webhook::SessionEvent,
+    state::AppState,
+    stats::{SessionStats, SlotLimit},
+    telemetry,
+    ws::{self, EndReason},
+};
+
+/// 每个成员的发送队列长度
+const MEMBER_QUEUE: usize = 256;
+
+/// 访问日志与会话统计中的目标
+const TARGET: &str = "pubsub";
+
+/// 命令中 `data` 以外部分（类型、频道名等）的余量，超出 `max_message_bytes` 加此值的帧不读入即断开
+const COMMAND_OVERHEAD: usize = 4096;
+
+/// 客户端命令
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Command {
+    Join { channel: String },
+    Leave { channel: String },
+    Publish { channel: String, data: Value },
+}
+
+/// 频道成员：连接 id → 发送队列
+type Members = HashMap<u64, mpsc::Sender<String>>;
+
+pub struct Hub {
+    config: PubSubConfig,
+    channels: Mutex<HashMap<String, Members>>,
+    next_id: AtomicU64,
+}
+
+impl Hub {
+    pub fn new(config: &PubSubConfig) -> Self {
+        Self {
+            config: config.clone(),
+            channels: Mutex::default(),
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    /// 加入频道，返回当前成员数
+    fn join(&self, channel: &str, id: u64, tx: &mpsc::Sender<String>) -> Result<usize, (&'static str, &'static str)> {
+        let mut channels = self.channels.lock().unwrap();
+        if !channels.contains_key(channel) && channels.len() >= self.config.max_channels {
+            return Err(("TOO_MANY_CHANNELS", "频道总数已达上限"));
+        }
+        let members = channels.entry(channel.to_string()).or_default();
+        if !members.contains_key(&id) && members.len() >= self.config.max_members {
+            return Err(("CHANNEL_FULL", "频道成员已满"));
+        }
+        members.insert(id, tx.clone());
+        Ok(members.len())
+    }
+
+    fn leave(&self, channel: &str, id: u64) {
+        let mut channels = self.channels.lock().unwrap();
+        if let Some(members) = channels.get_mut(channel) {
+            members.remove(&id);
+            if members.is_empty() {
+                channels.remove(channel);
+            }
+        }
+    }
+
+    /// 发给频道内除发送者外的成员，返回送达数
+    fn publish(&self, channel: &str, from: u64, msg: &str) -> usize {
+        let channels = self.channels.lock().unwrap();
+        let Some(members) = channels.get(channel) else {
+            return 0;
+        };
+        members
+            .iter()
+            .filter(|(id, _)| **id != from)
+            .filter(|(_, tx)| tx.try_send(msg.to_string()).is_ok())
+            .count()
+    }
+}
+
+/// 发布/订阅入口
+/// 路由: /pubsub
+pub async fn handler(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(user): Extension<User>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    if state.quota.is_exhausted(&user) {
+        warn!("[{}] 流量配额已用尽，拒绝连接", user.name);
+        return error::response(StatusCode::FORBIDDEN, "QUOTA_EXCEEDED", "流量配额已用尽");
+    }
+    let slot = match state.reserve_session(&user) {
+        Ok(slot) => slot,
+        Err(SlotLimit::Total) => {
+            warn!("[{}] 节点活跃会话数已达上限，拒绝连接", user.name);
+            return error::response(StatusCode::SERVICE_UNAVAILABLE, "TOO_MANY_SESSIONS", "节点活跃会话数已达上限，请稍后重试");
+        }
+        Err(SlotLimit::User) => {
+            warn!("[{}] 活跃会话数已达上限，拒绝连接", user.name);
+            return error::response(StatusCode::TOO_MANY_REQUESTS, "USER_SESSION_LIMIT", "活跃会话数已达上限");
+        }
+    };
+    info!("[{}] 发布/订阅连接", user.name);
+    let limit = state.pubsub.as_ref().map_or(0, |hub| hub.config.max_message_bytes);
+    let ws = ws.max_message_size(limit + COMMAND_OVERHEAD);
+    ws.on_upgrade(move |socket| async move {
+        let _slot = slot;
+        let session_id = access_log::new_session_id();
+        let started = Instant::now();
+        let guard = state.stats.open_session(&session_id, &user.name, TARGET);
+        state.session_event(&SessionEvent::SessionStart {
+            session_id: &session_id,
+            kind: "pubsub",
+            user: &user.name,
+            client_ip: addr.ip(),
+            target: TARGET,
+        });
+        let span = info_span!("pubsub_session", session_id = %session_id, user = %user.name);
+        let served = serve(socket, &state, &user, &guard.session).instrument(span);
+        let reason = panic_guard::catch("pubsub", &session_id, served).await.unwrap_or(EndReason::Panicked);
+
+        let record = AccessRecord {
+            session_id: &session_id,
+            kind: "pubsub",
+            user: &user.name,
+            client_ip: addr.ip(),
+            country: geoip::country(addr.ip()),
+            target: TARGET,
+            duration_ms: started.elapsed().as_millis() as u64,
+            bytes_up: guard.session.bytes_up.load(Ordering::Relaxed),
+            bytes_down: guard.session.bytes_down.load(Ordering::Relaxed),
+            close_reason: reason.as_str(),
+            summary: None,
+        };
+        telemetry::record(&record);
+        state.stats.record_end(&record);
+        if let Some(ref log) = state.access_log {
+            log.record(&record);
+        }
+        state.session_event(&SessionEvent::SessionEnd(&record));
+    })
+}
+
+async fn serve(socket: WebSocket, state: &AppState, user: &User, session: &SessionStats) -> EndReason {
+    let Some(ref hub) = state.pubsub else {
+        return EndReason::ClientClosed;
+    };
+    let id = hub.next_id.fetch_add(1, Ordering::Relaxed);
+    let quota = state.quota.user(user);
+    let (member_tx, mut member_rx) = mpsc::channel::<String>(MEMBER_QUEUE);
+    let mut joined = HashSet::new();
+    let (mut tx, mut rx) = socket.split();
+
+    let reason = loop {
+        tokio::select! {
+            Some(msg) = member_rx.recv() => {
+                let len = msg.len() as u64;
+                if tx.send(Message::Text(msg.into())).await.is_err() {
+                    break EndReason::ClientClosed;
+                }
+                session.add_down(len);
+                if !quota.consume_down(len) {
+                    break EndReason::QuotaExceeded;
+                }
+            }
+            msg = rx.next() => {
+                let text = match msg {
+                    Some(Ok(Message::Text(t))) => t,
+                    Some(Ok(Message::Binary(_))) => {
+                        let reply = error::to_json("INVALID_MESSAGE", "仅支持 JSON text 消息");
+                        if tx.send(Message::Text(reply.into())).await.is_err() {
+                            break EndReason::ClientClosed;
+                        }
+                        continue;
+                    }
+                    Some(Err(e)) if ws::exceeds_message_size(&e) => break EndReason::MessageTooLarge,
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break EndReason::ClientClosed,
+                    Some(Ok(_)) => continue,
+                };
+                let len = text.len() as u64;
+                session.add_up(len);
+                if !quota.consume_up(len) {
+                    break EndReason::QuotaExceeded;
+                }
+                let reply = match handle(&text, hub, id, &member_tx, &mut joined, user) {
+                    Ok(Some(reply)) => reply.to_string(),
+                    Ok(None) => continue,
+                    Err((code, message)) => error::to_json(code, message),
+                };
+                if tx.send(Message::Text(reply.into())).await.is_err() {
+                    break EndReason::ClientClosed;
+                }
+            }
+            _ = session.terminate.notified() => break session.terminate_reason().into(),
+        }
+    };
+
+    for channel in &joined {
+        hub.leave(channel, id);
+    }
+    if let Some((code, message, close_code)) = reason.close_info() {
+        warn!("[{}] {}，断开发布/订阅连接", user.name, message);
+        state.audit(&AuditEvent::Disconnect {
+            session_id: &session.id,
+            user: &user.name,
+            target: TARGET,
+            reason: code,
+        });
+        let _ = tx.send(Message::Text(error::to_json(code, message).into())).await;
+        let close = CloseFrame {
+            code: close_code,
+            reason: code.into(),
+        };
+        let _ = tx.send(Message::Close(Some(close))).await;
+    }
+    reason
+}
+
+/// 处理一条命令，返回给发送者的回复
+fn handle(
+    text: &str,
+    hub: &Hub,
+    id: u64,
+    member_tx: &mpsc::Sender<String>,
+    joined: &mut HashSet<String>,
+    user: &User,
+) -> Result<Option<Value>, (&'static str, &'static str)> {
+    if text.len() > hub.config.max_message_bytes {
+        return Err(("MESSAGE_TOO_LARGE", "消息超过大小上限"));
+    }
+    let command: Command = serde_json::from_str(text).map_err(|_| ("INVALID_MESSAGE", "无效的命令"))?;
+    match command {
+        Command::Join { channel } => {
+            if !user.allows_target(&format!("channel:{}", channel)) {
+                return Err(("CHANNEL_NOT_ALLOWED", "不允许加入该频道"));
+            }
+            if !joined.contains(&channel) && joined.len() >= hub.config.max_channels_per_client {
+                return Err(("TOO_MANY_CHANNELS", "加入的频道数已达上限"));
+            }
+            let members = hub.join(&channel, id, member_tx)?;
+            debug!("[{}] 加入频道: {}（{} 个成员）", user.name, channel, members);
+            let reply = json!({ "type": "joined", "channel": channel, "members": members });
+            joined.insert(channel);
+            Ok(Some(reply))
+        }
+        Command::Leave { channel } => {
+            if joined.remove(&channel) {
+                hub.leave(&channel, id);
+            }
+            Ok(Some(json!({ "type": "left", "channel": channel })))
+        }
+        Command::Publish { channel, data } => {
+            if !joined.contains(&channel) {
+                return Err(("NOT_JOINED", "未加入该频道"));
+            }
+            let msg = json!({ "type": "message", "channel": channel, "from": user.name, "data": data });
+            let delivered = hub.publish(&channel, id, &msg.to_string());
+            debug!("[{}] 发布到频道: {}（送达 {} 个成员）", user.name, channel, delivered);
+            Ok(None)
+        }
+    }
+}
@@ -0,0 +1,113 @@
+//! 有界发送队列
+//!
+//! relay 每个方向一个队列：读取端 `push`，写入端 `pop` 后发送。
+//! 队列满时按 `SlowConsumerPolicy` 处理：等待（背压）、丢弃最旧的消息、或返回 `Overflow` 由调用方关闭会话。
+
+use std::{collections::VecDeque, sync::Mutex};
+
+use tokio::sync::Notify;
+
+use crate::config::SlowConsumerPolicy;
+
+/// 队列已满且策略为 `close`
+pub struct Overflow;
+
+struct Inner<T> {
+    items: VecDeque<T>,
+    closed: bool,
+    dropped: u64,
+}
+
+/// 单生产者 / 单消费者有界队列
+pub struct SendQueue<T> {
+    inner: Mutex<Inner<T>>,
+    capacity: usize,
+    policy: SlowConsumerPolicy,
+    /// 有新消息或已关闭
+    readable: Notify,
+    /// 有空位
+    writable: Notify,
+}
+
+impl<T> SendQueue<T> {
+    pub fn new(capacity: usize, policy: SlowConsumerPolicy) -> Self {
+        Self {
+            inner: Mutex::new(Inner {
+                items: VecDeque::with_capacity(capacity.min(1024)),
+                closed: false,
+                dropped: 0,
+            }),
+            capacity: capacity.max(1),
+            policy,
+            readable: Notify::new(),
+            writable: Notify::new(),
+        }
+    }
+
+    pub async fn push(&self, item: T) -> Result<(), Overflow> {
+        let mut item = Some(item);
+        loop {
+            {
+                let mut inner = self.inner.lock().unwrap();
+                if inner.items.len() < self.capacity {
+                    inner.items.extend(item.take());
+                    self.readable.notify_one();
+                    return Ok(());
+                }
+                match self.policy {
+                    SlowConsumerPolicy::DropOldest => {
+                        inner.items.pop_front();
+                        inner.items.extend(item.take());
+                        inner.dropped += 1;
+                        self.readable.notify_one();
+                        return Ok(());
+                    }
+                    SlowConsumerPolicy::Close => return Err(Overflow),
+                    SlowConsumerPolicy::Backpressure => {}
+                }
+            }
+            // notify_one 在无等待者时保留许可，不会丢失唤醒
+            self.writable.notified().await;
+        }
+    }
+
+    /// 取出下一条消息，队列关闭且为空时返回 None
+    pub async fn pop(&self) -> Option<T> {
+        loop {
+            {
+                let mut inner = self.inner.lock().unwrap();
+                if let Some(item) = inner.items.pop_front() {
+                    self.writable.notify_one();
+                    return Some(item);
+                }
+                if inner.closed {
+                    return None;
+                }
+            }
+            self.readable.notified().await;
+        }
+    }
+
+    /// 当前没有待发送的消息
+    pub fn is_empty(&self) -> bool {
+        self.inner.lock().unwrap().items.is_empty()
+    }
+
+    /// 读取端结束，写入端发送完剩余消息后退出
+    pub fn close(&self) {
+        self.inner.lock().unwrap().closed = true;
+        self.readable.notify_one();
+    }
+
+    /// 取出全部尚未发送的消息
+    pub fn drain(&self) -> Vec<T> {
+        let items = self.inner.lock().unwrap().items.drain(..).collect();
+        self.writable.notify_one();
+        items
+    }
+
+    /// 因队列满被丢弃的消息数
+    pub fn dropped(&self) -> u64 {
+        self.inner.lock().unwrap().dropped
+    }
+}
@@ -0,0 +1,485 @@
+//! 用户流量配额
+//!
+//! 按用户累计转发的 text / binary 数据字节（默认只计目标 → 客户端方向，`count = "both"` 时双向合计），周期切换时清零，
+//! 用量定期写入 `state_file`，重启后继续累计。会话开始时经 [`QuotaTracker::user`] 取得该用户的计数，
+//! 转发路径上只做原子加法；周期切换与落盘由 [`spawn_flusher`] 定期执行。
+//! 平滑升级时旧进程在交接前保存用量，交接后新增的用量在退出时经就绪通知的 socket 交给新进程（[`QuotaTracker::handoff`]）。
+//! 配置 `[cluster]` 时各节点把新增用量汇总到 Redis（或直接同步给其他节点），按所有节点的合计判断是否超出配额。
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::Duration,
+};
+
+use anyhow::Result;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+use crate::{
+    config::{QuotaConfig, QuotaCount, QuotaReset, User},
+    upgrade,
+};
+
+/// 用量落盘与周期切换检查的间隔
+const FLUSH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// 持久化格式
+#[derive(Default, Serialize, Deserialize)]
+struct Saved {
+    /// 当前周期标识，如 `2024-06`
+    period: String,
+    /// 用户名 → 已用字节
+    usage: HashMap<String, u64>,
+}
+
+/// 平滑升级时旧进程交给新进程的用量
+#[derive(Default, Serialize, Deserialize)]
+struct Handoff {
+    period: String,
+    /// 交接前保存状态文件之后新增的用量
+    usage: HashMap<String, u64>,
+    /// 尚未汇总到集群的用量
+    pending: HashMap<String, u64>,
+}
+
+/// 单个用户本周期的用量
+#[derive(Default)]
+struct Usage {
+    /// 本节点已用字节
+    local: AtomicU64,
+    /// 尚未汇总到集群的用量
+    pending: AtomicU64,
+    /// 最近一次同步时的集群合计
+    global: AtomicU64,
+}
+
+impl Usage {
+    fn with_local(bytes: u64) -> Self {
+        Self {
+            local: AtomicU64::new(bytes),
+            ..Default::default()
+        }
+    }
+
+    /// 本节点与集群合计中较大者
+    fn used(&self) -> u64 {
+        let local = self.local.load(Ordering::Relaxed);
+        let global = self.global.load(Ordering::Relaxed);
+        local.max(global + self.pending.load(Ordering::Relaxed))
+    }
+
+    fn clear(&self) {
+        self.local.store(0, Ordering::Relaxed);
+        self.pending.store(0, Ordering::Relaxed);
+        self.global.store(0, Ordering::Relaxed);
+    }
+}
+
+/// 会话持有的用户配额计数，转发路径上只做原子操作
+pub struct UserQuota {
+    usage: Arc<Usage>,
+    limit: Option<u64>,
+    count: QuotaCount,
+}
+
+impl UserQuota {
+    /// 目标 → 客户端的数据字节，总是计入；返回是否仍在配额内
+    pub fn consume_down(&self, bytes: u64) -> bool {
+        self.consume(bytes)
+    }
+
+    /// 客户端 → 目标的数据字节，仅 `count = "both"` 时计入；返回是否仍在配额内
+    pub fn consume_up(&self, bytes: u64) -> bool {
+        match self.count {
+            QuotaCount::Both => self.consume(bytes),
+            QuotaCount::Egress => self.limit.is_none_or(|limit| self.usage.used() < limit),
+        }
+    }
+
+    fn consume(&self, bytes: u64) -> bool {
+        self.usage.local.fetch_add(bytes, Ordering::Relaxed);
+        self.usage.pending.fetch_add(bytes, Ordering::Relaxed);
+        self.limit.is_none_or(|limit| self.usage.used() < limit)
+    }
+}
+
+/// 配额计数器
+pub struct QuotaTracker {
+    reset: QuotaReset,
+    count: QuotaCount,
+    state_file: String,
+    /// 当前周期标识，由 [`spawn_flusher`] 与集群同步时切换
+    period: Mutex<String>,
+    users: RwLock<HashMap<String, Arc<Usage>>>,
+    /// 平滑升级前保存的用量，交接后据此计算新增的用量
+    baseline: Mutex<Option<Saved>>,
+}
+
+impl QuotaTracker {
+    /// 加载已持久化的用量（文件不存在或周期已过则从零开始）
+    pub fn load(config: &QuotaConfig) -> Self {
+        let period = period_key(config.reset);
+        let saved = match std::fs::read_to_string(&config.state_file) {
+            Ok(content) => match serde_json::from_str::<Saved>(&content) {
+                Ok(s) if s.period == period => s,
+                Ok(_) => Saved::default(),
+                Err(e) => {
+                    error!("配额文件格式错误，忽略: {} - {}", config.state_file, e);
+                    Saved::default()
+                }
+            },
+            Err(_) => Saved::default(),
+        };
+        let users = saved
+            .usage
+            .into_iter()
+            .map(|(name, bytes)| (name, Arc::new(Usage::with_local(bytes))))
+            .collect();
+
+        Self {
+            reset: config.reset,
+            count: config.count,
+            state_file: config.state_file.clone(),
+            period: Mutex::new(period),
+            users: RwLock::new(users),
+            baseline: Mutex::default(),
+        }
+    }
+
+    /// 用户的配额计数（会话开始时取得，在会话期间复用）
+    pub fn user(&self, user: &User) -> UserQuota {
+        UserQuota {
+            usage: self.usage(&user.name),
+            limit: user.monthly_quota_bytes,
+            count: self.count,
+        }
+    }
+
+    fn usage(&self, name: &str) -> Arc<Usage> {
+        if let Some(usage) = self.users.read().unwrap().get(name) {
+            return usage.clone();
+        }
+        self.users.write().unwrap().entry(name.to_string()).or_default().clone()
+    }
+
+    /// 本节点与集群合计中较大者
+    fn used(&self, name: &str) -> u64 {
+        self.users.read().unwrap().get(name).map_or(0, |u| u.used())
+    }
+
+    /// 用户配额是否已用尽
+    pub fn is_exhausted(&self, user: &User) -> bool {
+        user.monthly_quota_bytes.is_some_and(|limit| self.used(&user.name) >= limit)
+    }
+
+    /// 本周期剩余配额，未设置配额时为 None
+    pub fn remaining(&self, user: &User) -> Option<u64> {
+        let limit = user.monthly_quota_bytes?;
+        Some(limit.saturating_sub(self.used(&user.name)))
+    }
+
+    /// 取出尚未汇总到集群的用量，返回当前周期标识
+    pub fn take_pending(&self) -> (String, HashMap<String, u64>) {
+        let period = self.roll();
+        let pending = self
+            .users
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, u)| (name.clone(), u.pending.swap(0, Ordering::Relaxed)))
+            .filter(|&(_, bytes)| bytes > 0)
+            .collect();
+        (period, pending)
+    }
+
+    /// 本节点本周期的用量（节点间同步用），同时清空待汇总的用量
+    pub fn snapshot(&self) -> (String, HashMap<String, u64>) {
+        let period = self.roll();
+        let usage = self
+            .users
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, u)| {
+                u.pending.store(0, Ordering::Relaxed);
+                (name.clone(), u.local.load(Ordering::Relaxed))
+            })
+            .collect();
+        (period, usage)
+    }
+
+    /// 汇总失败，放回待汇总的用量（周期已切换则丢弃）
+    pub fn restore_pending(&self, period: &str, pending: HashMap<String, u64>) {
+        if *self.period.lock().unwrap() != period {
+            return;
+        }
+        for (name, bytes) in pending {
+            self.usage(&name).pending.fetch_add(bytes, Ordering::Relaxed);
+        }
+    }
+
+    /// 更新集群合计
+    pub fn set_global(&self, period: &str, global: HashMap<String, u64>) {
+        if *self.period.lock().unwrap() != period {
+            return;
+        }
+        for (name, u) in self.users.read().unwrap().iter() {
+            u.global.store(global.get(name).copied().unwrap_or_default(), Ordering::Relaxed);
+        }
+        // 只在其他节点上有用量的用户
+        for (name, bytes) in global {
+            self.usage(&name).global.store(bytes, Ordering::Relaxed);
+        }
+    }
+
+    /// 写入持久化文件
+    pub fn save(&self) -> Result<()> {
+        self.write(&self.saved())
+    }
+
+    /// 平滑升级启动新进程前写入持久化文件，并记下此时的用量
+    pub fn save_for_upgrade(&self) -> Result<()> {
+        let saved = self.saved();
+        self.write(&saved)?;
+        *self.baseline.lock().unwrap() = Some(saved);
+        Ok(())
+    }
+
+    /// 交接后旧进程退出前，取出 [`save_for_upgrade`](Self::save_for_upgrade) 之后新增的用量与尚未汇总到集群的用量，
+    /// 由新进程 [`merge_handoff`](Self::merge_handoff)
+    pub fn handoff(&self) -> Result<String> {
+        let period = self.roll();
+        // 周期已切换时新周期的用量都在保存之后产生
+        let baseline = self.baseline.lock().unwrap().take().filter(|b| b.period == period).unwrap_or_default();
+        let users = self.users.read().unwrap();
+        let usage = users
+            .iter()
+            .map(|(name, u)| {
+                let saved = baseline.usage.get(name).copied().unwrap_or_default();
+                (name.clone(), u.local.load(Ordering::Relaxed).saturating_sub(saved))
+            })
+            .filter(|&(_, bytes)| bytes > 0)
+            .collect();
+        let pending = users
+            .iter()
+            .map(|(name, u)| (name.clone(), u.pending.swap(0, Ordering::Relaxed)))
+            .filter(|&(_, bytes)| bytes > 0)
+            .collect();
+        Ok(serde_json::to_string(&Handoff { period, usage, pending })?)
+    }
+
+    /// 合并旧进程交接后新增的用量（周期已切换则丢弃）
+    pub fn merge_handoff(&self, content: &str) -> Result<()> {
+        let handoff: Handoff = serde_json::from_str(content)?;
+        if self.roll() != handoff.period {
+            return Ok(());
+        }
+        for (name, bytes) in handoff.usage {
+            self.usage(&name).local.fetch_add(bytes, Ordering::Relaxed);
+        }
+        for (name, bytes) in handoff.pending {
+            self.usage(&name).pending.fetch_add(bytes, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    fn saved(&self) -> Saved {
+        Saved {
+            period: self.period.lock().unwrap().clone(),
+            usage: self
+                .users
+                .read()
+                .unwrap()
+                .iter()
+                .map(|(name, u)| (name.clone(), u.local.load(Ordering::Relaxed)))
+                .collect(),
+        }
+    }
+
+    fn write(&self, saved: &Saved) -> Result<()> {
+        let content = serde_json::to_string(saved)?;
+        let tmp = format!("{}.tmp", self.state_file);
+        std::fs::write(&tmp, content)?;
+        std::fs::rename(&tmp, &self.state_file)?;
+        Ok(())
+    }
+
+    /// 周期切换时清零（会话持有的计数一并清零），返回当前周期标识
+    fn roll(&self) -> String {
+        let current = period_key(self.reset);
+        let mut period = self.period.lock().unwrap();
+        if *period != current {
+            info!("配额周期切换: {} -> {}", period, current);
+            *period = current.clone();
+            self.users.read().unwrap().values().for_each(|u| u.clear());
+        }
+        current
+    }
+}
+
+/// 后台定期切换周期并落盘
+pub fn spawn_flusher(tracker: Arc<QuotaTracker>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+        loop {
+            interval.tick().await;
+            tracker.roll();
+            // 平滑升级后状态文件由新进程写入，新增的用量在退出时交给新进程
+            if upgrade::handed_off() {
+                return;
+            }
+            // 写文件 + rename 是同步 IO，放到阻塞线程池
+            let saving = tracker.clone();
+            match tokio::task::spawn_blocking(move || saving.save()).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => error!("保存配额用量失败: {} - {}", tracker.state_file, e),
+                Err(e) => error!("保存配额用量任务异常退出: {}", e),
+            }
+        }
+    });
+}
+
+/// 当前周期标识
+fn period_key(reset: QuotaReset) -> String {
+    let now = Utc::now();
+    match reset {
+        QuotaReset::Daily => now.format("%Y-%m-%d").to_string(),
+        QuotaReset::Weekly => now.format("%G-W%V").to_string(),
+        QuotaReset::Monthly => now.format("%Y-%m").to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracker() -> QuotaTracker {
+        tracker_counting(QuotaCount::Egress)
+    }
+
+    fn tracker_counting(count: QuotaCount) -> QuotaTracker {
+        QuotaTracker::load(&QuotaConfig {
+            state_file: "/nonexistent/quota.json".into(),
+            reset: QuotaReset::Monthly,
+            count,
+        })
+    }
+
+    fn user(limit: Option<u64>) -> User {
+        User {
+            name: "alice".into(),
+            monthly_quota_bytes: limit,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn consume_until_limit() {
+        let tracker = tracker();
+        let alice = user(Some(100));
+        let quota = tracker.user(&alice);
+        assert!(quota.consume_down(60));
+        assert_eq!(tracker.remaining(&alice), Some(40));
+        assert!(!quota.consume_down(40));
+        assert!(tracker.is_exhausted(&alice));
+        assert!(!tracker.is_exhausted(&user(None)));
+        assert_eq!(tracker.remaining(&user(None)), None);
+    }
+
+    #[test]
+    fn counted_direction() {
+        let alice = user(Some(100));
+        let tracker = tracker();
+        let quota = tracker.user(&alice);
+        assert!(quota.consume_up(500));
+        assert_eq!(tracker.remaining(&alice), Some(100));
+        assert!(quota.consume_down(30));
+        assert_eq!(tracker.remaining(&alice), Some(70));
+
+        let tracker = tracker_counting(QuotaCount::Both);
+        let quota = tracker.user(&alice);
+        assert!(quota.consume_up(30));
+        assert!(!quota.consume_down(70));
+        assert!(!quota.consume_up(0));
+    }
+
+    #[test]
+    fn cluster_total_counts() {
+        let tracker = tracker();
+        let alice = user(Some(100));
+        let quota = tracker.user(&alice);
+        quota.consume_down(10);
+        let (period, pending) = tracker.take_pending();
+        assert_eq!(pending.get("alice"), Some(&10));
+        assert!(tracker.take_pending().1.is_empty());
+        tracker.set_global(&period, HashMap::from([("alice".to_string(), 95), ("bob".to_string(), 5)]));
+        assert_eq!(tracker.remaining(&alice), Some(5));
+        assert!(!quota.consume_down(5));
+        // 其他周期的合计与待汇总用量被忽略
+        tracker.set_global("1970-01", HashMap::from([("alice".to_string(), 0)]));
+        tracker.restore_pending("1970-01", HashMap::from([("alice".to_string(), 1000)]));
+        assert_eq!(tracker.remaining(&alice), Some(0));
+    }
+
+    #[test]
+    fn period_roll_clears_usage() {
+        let tracker = tracker();
+        let alice = user(Some(100));
+        let quota = tracker.user(&alice);
+        assert!(!quota.consume_down(100));
+        *tracker.period.lock().unwrap() = "1970-01".into();
+        let current = tracker.roll();
+        assert_eq!(current, period_key(QuotaReset::Monthly));
+        // 会话持有的计数一并清零
+        assert!(quota.consume_down(10));
+        assert_eq!(tracker.remaining(&alice), Some(90));
+    }
+
+    #[test]
+    fn upgrade_hands_over_usage_after_save() {
+        let state_file = std::env::temp_dir().join(format!("ws-relay-quota-{}.json", std::process::id()));
+        let config = QuotaConfig {
+            state_file: state_file.to_string_lossy().into_owned(),
+            reset: QuotaReset::Monthly,
+            count: QuotaCount::Egress,
+        };
+        let alice = user(Some(1000));
+        let old = QuotaTracker::load(&config);
+        old.user(&alice).consume_down(100);
+        old.save_for_upgrade().unwrap();
+
+        // 新进程加载保存的用量，旧进程在交接后继续转发
+        let new = QuotaTracker::load(&config);
+        assert_eq!(new.remaining(&alice), Some(900));
+        new.user(&alice).consume_down(10);
+        old.user(&alice).consume_down(30);
+        old.user(&User { name: "bob".into(), ..Default::default() }).consume_down(5);
+
+        new.merge_handoff(&old.handoff().unwrap()).unwrap();
+        assert_eq!(new.remaining(&alice), Some(860));
+        let (_, pending) = new.take_pending();
+        assert_eq!(pending, HashMap::from([("alice".to_string(), 140), ("bob".to_string(), 5)]));
+        new.save().unwrap();
+        assert_eq!(QuotaTracker::load(&config).remaining(&alice), Some(860));
+        let _ = std::fs::remove_file(&state_file);
+
+        // 其他周期的用量被丢弃
+        let handoff = r#"{"period":"1970-01","usage":{"alice":1000},"pending":{"alice":1000}}"#;
+        new.merge_handoff(handoff).unwrap();
+        assert_eq!(new.remaining(&alice), Some(860));
+        assert!(new.merge_handoff("").is_err());
+    }
+
+    #[test]
+    fn period_keys() {
+        let key = period_key(QuotaReset::Monthly);
+        assert_eq!(key.len(), "2024-06".len());
+        assert!(period_key(QuotaReset::Weekly).contains("-W"));
+        assert!(period_key(QuotaReset::Daily).starts_with(&key));
+    }
+}
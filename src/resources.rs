@@ -0,0 +1,258 @@
+//! 进程资源上限：文件描述符与内存
+//!
+//! 每个会话至少占用两个文件描述符（客户端与目标），默认软限制（常为 1024）下几百个会话即可耗尽，
+//! 此后 accept() 以 EMFILE 失败，新连接在监听队列中超时，已有会话重连目标也会失败。
+//! 小内存主机上会话缓冲积压则可能触发 OOM killer，所有会话一起断开。
+//!
+//! | 配置 `[resources]` | 说明 | 默认 |
+//! |------|------|------|
+//! | `raise_nofile` | 启动时把 RLIMIT_NOFILE 软限制提高到硬限制 | 否 |
+//! | `fd_headroom` | 打开的文件描述符距软限制不足此数时拒绝新会话，0 为不限制 | 256 |
+//! | `fd_check_interval_ms` | 文件描述符用量采样间隔 | 1000 |
+//! | `max_rss_mb` | 常驻内存超出此值时拒绝新会话（仅 Linux） | 不检查 |
+//! | `shed_sessions` | 超出 `max_rss_mb` 期间每次采样关闭的吞吐最高的会话数 | 0 |
+//! | `memory_check_interval_ms` | 内存用量采样间隔 | 1000 |
+//!
+//! 文件描述符接近上限时 HTTP 入口返回 503 `TOO_MANY_OPEN_FILES`，内存超限时返回 503 `OVERLOADED`，
+//! SOCKS5 直接关闭连接；被关闭的会话以 `OVERLOADED`（4011）结束。文件描述符回落到距上限 2 × `fd_headroom` 以外、
+//! 内存回落到 `max_rss_mb` 的 90% 以下后恢复。用量从 `/proc/self/fd`（Linux）或 `/dev/fd`（macOS）
+//! 与 `/proc/self/status` 统计，见控制通道 `status` 的 `fds` / `rss_mb` 与指标 `relay.open_fds` / `relay.rss`。
+//!
+//! `server.max_total_sessions = "auto"` 时会话总数上限在启动时按可用内存计算：`/proc/meminfo` 的 MemAvailable
+//! 与 cgroup v2 内存限制的剩余部分取较小值，除以 `server.session_memory_kb`。
+
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use tokio::time::{interval, MissedTickBehavior};
+use tracing::{info, warn};
+
+use crate::{
+    config::{ResourcesConfig, ServerConfig, TotalSessions},
+    stats::{Stats, TerminateReason},
+};
+
+/// 软限制低于此值时提示调高
+const LOW_NOFILE: u64 = 4096;
+
+/// RLIMIT_NOFILE 软限制，0 为未知
+static NOFILE: AtomicU64 = AtomicU64::new(0);
+static OPEN_FDS: AtomicU64 = AtomicU64::new(0);
+static SHEDDING: AtomicBool = AtomicBool::new(false);
+/// 常驻内存（字节）
+static RSS: AtomicU64 = AtomicU64::new(0);
+static OVERLOADED: AtomicBool = AtomicBool::new(false);
+
+/// 读取并按配置提高 RLIMIT_NOFILE（启动时调用）
+pub fn init(config: &ResourcesConfig) {
+    let (soft, hard) = match nofile(config.raise_nofile) {
+        Ok(limits) => limits,
+        Err(e) => {
+            warn!("读取文件描述符上限失败: {}", e);
+            return;
+        }
+    };
+    NOFILE.store(soft, Ordering::Relaxed);
+    info!("文件描述符上限: {}（硬限制 {}）", soft, hard);
+    if soft < LOW_NOFILE {
+        warn!("文件描述符上限较低，可开启 resources.raise_nofile 或调高 ulimit -n / systemd LimitNOFILE");
+    }
+    if config.fd_headroom >= soft {
+        warn!("resources.fd_headroom 不小于文件描述符上限，不会拒绝新连接");
+    }
+}
+
+#[cfg(unix)]
+fn nofile(raise: bool) -> io::Result<(u64, u64)> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: limit 为有效的 rlimit
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // macOS 的硬限制可为 RLIM_INFINITY，软限制不能超过 OPEN_MAX
+    #[cfg(target_os = "macos")]
+    let target = limit.rlim_max.min(libc::OPEN_MAX as libc::rlim_t);
+    #[cfg(not(target_os = "macos"))]
+    let target = limit.rlim_max;
+    if raise && limit.rlim_cur < target {
+        let raised = libc::rlimit {
+            rlim_cur: target,
+            rlim_max: limit.rlim_max,
+        };
+        // SAFETY: raised 为有效的 rlimit
+        if unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &raised) } == 0 {
+            info!("已提高文件描述符上限: {} → {}", limit.rlim_cur, target);
+            limit = raised;
+        } else {
+            warn!("提高文件描述符上限失败: {}", io::Error::last_os_error());
+        }
+    }
+    // rlim_t 在部分平台上不是 u64
+    #[allow(clippy::unnecessary_cast)]
+    Ok((limit.rlim_cur as u64, limit.rlim_max as u64))
+}
+
+#[cfg(not(unix))]
+fn nofile(_raise: bool) -> io::Result<(u64, u64)> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "仅 Unix 支持"))
+}
+
+/// 定期统计打开的文件描述符，接近上限时开始拒绝新连接
+pub fn spawn_monitor(config: &ResourcesConfig) {
+    if count_fds().is_none() {
+        info!("无法统计打开的文件描述符，不按用量拒绝新连接");
+        return;
+    }
+    let headroom = config.fd_headroom;
+    let mut ticker = interval(Duration::from_millis(config.fd_check_interval_ms));
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    tokio::spawn(async move {
+        loop {
+            ticker.tick().await;
+            let Ok(Some(open)) = tokio::task::spawn_blocking(count_fds).await else {
+                continue;
+            };
+            OPEN_FDS.store(open, Ordering::Relaxed);
+            let limit = NOFILE.load(Ordering::Relaxed);
+            if headroom == 0 || limit <= headroom {
+                continue;
+            }
+            let shedding = SHEDDING.load(Ordering::Relaxed);
+            if !shedding && open + headroom >= limit {
+                SHEDDING.store(true, Ordering::Relaxed);
+                warn!("打开的文件描述符 {}/{} 接近上限，拒绝新连接", open, limit);
+            } else if shedding && open + 2 * headroom < limit {
+                SHEDDING.store(false, Ordering::Relaxed);
+                info!("打开的文件描述符回落到 {}/{}，恢复接受新连接", open, limit);
+            }
+        }
+    });
+}
+
+fn count_fds() -> Option<u64> {
+    let dir = if cfg!(target_os = "linux") {
+        "/proc/self/fd"
+    } else if cfg!(target_os = "macos") {
+        "/dev/fd"
+    } else {
+        return None;
+    };
+    std::fs::read_dir(dir).ok().map(|entries| entries.count() as u64)
+}
+
+/// 打开的文件描述符接近上限，应拒绝新连接
+pub fn fd_exhausted() -> bool {
+    SHEDDING.load(Ordering::Relaxed)
+}
+
+/// (打开的文件描述符数, 软限制)，未知时为 0
+pub fn fd_usage() -> (u64, u64) {
+    (OPEN_FDS.load(Ordering::Relaxed), NOFILE.load(Ordering::Relaxed))
+}
+
+/// 定期采样常驻内存，超出 `max_rss_mb` 时拒绝新会话并按 `shed_sessions` 关闭吞吐最高的会话
+pub fn spawn_memory_guard(config: &ResourcesConfig, stats: Arc<Stats>) {
+    if read_rss().is_none() {
+        if config.max_rss_mb.is_some() {
+            warn!("无法读取常驻内存（仅 Linux 支持），resources.max_rss_mb 不生效");
+        }
+        return;
+    }
+    let max = config.max_rss_mb.map(|mb| mb * 1024 * 1024);
+    if let Some(mb) = config.max_rss_mb {
+        info!("内存保护: 常驻内存超出 {} MiB 时拒绝新会话", mb);
+    }
+    let shed = config.shed_sessions;
+    let mut ticker = interval(Duration::from_millis(config.memory_check_interval_ms));
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    tokio::spawn(async move {
+        loop {
+            ticker.tick().await;
+            let Some(rss) = read_rss() else {
+                continue;
+            };
+            RSS.store(rss, Ordering::Relaxed);
+            let Some(max) = max else {
+                continue;
+            };
+            let overloaded = OVERLOADED.load(Ordering::Relaxed);
+            if !overloaded && rss > max {
+                OVERLOADED.store(true, Ordering::Relaxed);
+                warn!("常驻内存 {} MiB 超出上限 {} MiB，拒绝新会话", rss >> 20, max >> 20);
+            } else if overloaded && rss < max / 10 * 9 {
+                OVERLOADED.store(false, Ordering::Relaxed);
+                info!("常驻内存回落到 {} MiB，恢复接受新会话", rss >> 20);
+            }
+            // 回落区间内只拒绝新会话，不再关闭
+            if rss > max && shed > 0 {
+                let n = stats.terminate_heaviest(shed, TerminateReason::Overloaded);
+                if n > 0 {
+                    warn!("常驻内存 {} MiB 超出上限，关闭吞吐最高的 {} 个会话", rss >> 20, n);
+                }
+            }
+        }
+    });
+}
+
+/// `/proc/self/status` 的 VmRSS（字节）
+fn read_rss() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|l| l.starts_with("VmRSS:"))?;
+    let kb: u64 = line.trim_start_matches("VmRSS:").trim().trim_end_matches("kB").trim().parse().ok()?;
+    Some(kb * 1024)
+}
+
+/// 常驻内存超出 `max_rss_mb`，应拒绝新会话
+pub fn overloaded() -> bool {
+    OVERLOADED.load(Ordering::Relaxed)
+}
+
+/// 常驻内存（字节），未知时为 0
+pub fn rss() -> u64 {
+    RSS.load(Ordering::Relaxed)
+}
+
+/// 按 `server.max_total_sessions` 确定节点会话总数上限（启动时调用）
+pub fn session_cap(config: &ServerConfig) -> Option<usize> {
+    let cap = match config.max_total_sessions? {
+        TotalSessions::Fixed(n) => n,
+        TotalSessions::Auto(_) => {
+            let Some(available) = available_memory() else {
+                warn!("无法读取可用内存（仅 Linux 支持），server.max_total_sessions = \"auto\" 不生效");
+                return None;
+            };
+            let cap = (available / (config.session_memory_kb * 1024)).max(1) as usize;
+            info!("可用内存 {} MiB，每会话 {} KiB", available >> 20, config.session_memory_kb);
+            cap
+        }
+    };
+    info!("节点会话总数上限: {}", cap);
+    Some(cap)
+}
+
+/// 可用内存（字节）：MemAvailable 与 cgroup v2 限制的剩余部分取较小值
+fn available_memory() -> Option<u64> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let available = meminfo
+        .lines()
+        .find_map(|l| l.strip_prefix("MemAvailable:"))
+        .and_then(|v| v.trim().trim_end_matches("kB").trim().parse::<u64>().ok())?
+        * 1024;
+    let read = |name: &str| -> Option<u64> {
+        std::fs::read_to_string(format!("/sys/fs/cgroup/{}", name)).ok()?.trim().parse().ok()
+    };
+    // memory.max 为 "max" 时不限制，解析失败即忽略
+    match (read("memory.max"), read("memory.current")) {
+        (Some(max), Some(current)) => Some(available.min(max.saturating_sub(current))),
+        _ => Some(available),
+    }
+}
@@ -1,27 +1,318 @@
 //! REST 反向代理模块
+//!
+//! 普通请求的请求体与响应体整体缓冲后转发；`content-type: application/grpc*`（gRPC / gRPC-Web）
+//! 的请求体与响应体边收边发，保留响应头与 trailers，支持客户端流与服务端流 RPC。
+//! 上游返回 `text/event-stream`（SSE）时响应体边收边发，上游异常断开时补发 `error` 事件后正常结束。
 
 use axum::{
     body::Body,
-    extract::Request,
-    http::{HeaderMap, HeaderValue, StatusCode},
+    extract::{ConnectInfo, Extension, Request, State},
+    http::{self, header, HeaderMap, HeaderValue, Method, StatusCode},
     response::{IntoResponse, Response},
 };
+use bytes::Bytes;
+use futures_util::{StreamExt, TryStreamExt};
+use http_body_util::{BodyExt, LengthLimitError};
 use once_cell::sync::Lazy;
 use reqwest::Client;
-use tracing::{error, info};
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    error::Error as _,
+    future::Future,
+    net::{IpAddr, SocketAddr},
+    sync::{
+        atomic::{AtomicU16, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tracing::{error, info, info_span, warn, Instrument};
+
+use crate::{
+    access_log::{self, AccessRecord},
+    buffer_pool,
+    cache::{Entry, RestCache},
+    config::{self, CircuitBreakerConfig, RetryConfig, Route, User},
+    dns, error, geoip, header_rules,
+    host_limits::HostPermit,
+    limits::Limits,
+    panic_guard,
+    quota::UserQuota,
+    scripting, secret,
+    state::AppState,
+    target_rewrite, tcp, telemetry, upstream,
+};
+
+/// 客户端未带 `User-Agent` 且未配置 `rest.user_agent` 时使用
+const DEFAULT_USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36";
+
+/// 客户端缓存超过此数量时清空
+const MAX_CLIENTS: usize = 1024;
+
+/// 连接参数相同的请求共用一个客户端
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct ClientKey {
+    /// 出口地址
+    bind: Option<IpAddr>,
+    /// SNI 覆盖：(SNI 主机名, 原目标主机名)
+    alias: Option<(String, String)>,
+    /// 仅 HTTP/1.1
+    http1_only: bool,
+    /// h2c（明文 HTTP/2）
+    h2c: bool,
+    /// 连接超时（秒）
+    connect_timeout: Option<u64>,
+    /// 读取超时（用户的空闲超时）
+    read_timeout: Option<Duration>,
+    /// 不校验证书（`server.insecure_tls_hosts`）
+    insecure: bool,
+}
+
+/// HTTP 客户端（连接池复用，使用 `dns` 模块的解析器）
+static CLIENTS: Lazy<Mutex<HashMap<ClientKey, Client>>> = Lazy::new(Default::default);
+
+fn client(key: ClientKey) -> Client {
+    let mut clients = CLIENTS.lock().unwrap();
+    if clients.len() >= MAX_CLIENTS && !clients.contains_key(&key) {
+        clients.clear();
+    }
+    clients
+        .entry(key)
+        .or_insert_with_key(|key| {
+            let mut builder = Client::builder()
+                .dns_resolver(Arc::new(dns::ReqwestResolver { alias: key.alias.clone() }))
+                .local_address(key.bind)
+                .pool_max_idle_per_host(10);
+            if let Some(secs) = key.connect_timeout {
+                builder = builder.connect_timeout(Duration::from_secs(secs));
+            }
+            if let Some(timeout) = key.read_timeout {
+                builder = builder.read_timeout(timeout);
+            }
+            if key.insecure {
+                builder = builder.danger_accept_invalid_certs(true);
+            }
+            if key.h2c {
+                builder = builder.http2_prior_knowledge();
+            } else if key.http1_only {
+                builder = builder.http1_only();
+            }
+            tcp::reqwest(builder).build().expect("Failed to create HTTP client")
+        })
+        .clone()
+}
+
+/// 同一上游的熔断状态
+#[derive(Default)]
+struct Breaker {
+    /// 连续失败次数
+    failures: u32,
+    open_until: Option<Instant>,
+}
+
+/// 按上游（主机:端口）的熔断状态，成功后移除
+static BREAKERS: Lazy<Mutex<HashMap<String, Breaker>>> = Lazy::new(Default::default);
+
+/// 熔断打开时返回剩余时长
+fn breaker_open(upstream: &str) -> Option<Duration> {
+    let breakers = BREAKERS.lock().unwrap();
+    let until = breakers.get(upstream)?.open_until?;
+    until.checked_duration_since(Instant::now())
+}
+
+/// 记录一次上游结果，连续失败达到阈值时打开
+fn breaker_record(upstream: &str, ok: bool, config: &CircuitBreakerConfig) {
+    let mut breakers = BREAKERS.lock().unwrap();
+    if ok {
+        breakers.remove(upstream);
+        return;
+    }
+    if breakers.len() >= MAX_CLIENTS && !breakers.contains_key(upstream) {
+        breakers.clear();
+    }
+    let breaker = breakers.entry(upstream.to_string()).or_default();
+    breaker.failures += 1;
+    if breaker.failures >= config.failure_threshold {
+        breaker.open_until = Some(Instant::now() + Duration::from_secs(config.open_secs));
+        warn!(
+            "上游熔断: {}（连续失败 {} 次，{} 秒内直接拒绝）",
+            upstream, breaker.failures, config.open_secs
+        );
+    }
+}
 
-/// HTTP 客户端（连接池复用）
-static CLIENT: Lazy<Client> = Lazy::new(|| {
-    Client::builder()
-        .pool_max_idle_per_host(10)
-        .user_agent("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36")
-        .build()
-        .expect("Failed to create HTTP client")
-});
+/// 视为上游失败（可重试、计入熔断）的状态码
+const FAILURE_STATUS: [StatusCode; 3] = [
+    StatusCode::BAD_GATEWAY,
+    StatusCode::SERVICE_UNAVAILABLE,
+    StatusCode::GATEWAY_TIMEOUT,
+];
+
+/// 发送请求；给出 `retry` 时失败后按指数退避重试（流式请求体无法重放，不重试）
+async fn send(
+    mut request: reqwest::RequestBuilder,
+    retry: Option<&RetryConfig>,
+    breaker: Option<(&str, &CircuitBreakerConfig)>,
+) -> reqwest::Result<reqwest::Response> {
+    let mut attempt = 0;
+    loop {
+        let next = retry
+            .filter(|r| attempt < r.attempts)
+            .and_then(|_| request.try_clone());
+        let result = request.send().await;
+        let failed = match result {
+            Ok(ref resp) => FAILURE_STATUS.contains(&resp.status()),
+            Err(_) => true,
+        };
+        if let Some((upstream, config)) = breaker {
+            breaker_record(upstream, !failed, config);
+        }
+        let (Some(retry), Some(next)) = (retry, next) else {
+            return result;
+        };
+        if !failed || breaker.is_some_and(|(upstream, _)| breaker_open(upstream).is_some()) {
+            return result;
+        }
+        let delay = retry
+            .backoff_ms
+            .saturating_mul(1 << attempt.min(16))
+            .min(retry.max_backoff_ms);
+        match result {
+            Ok(ref resp) => warn!("上游返回 {}，{} ms 后重试", resp.status(), delay),
+            Err(ref e) => warn!("上游请求失败: {}，{} ms 后重试", e, delay),
+        }
+        tokio::time::sleep(Duration::from_millis(delay)).await;
+        request = next;
+        attempt += 1;
+    }
+}
 
 /// REST 代理处理器
-/// 路由: /rest + Header X-Target-URL
-pub async fn handler(req: Request) -> Response {
+/// 路由: /rest + Header X-Target-URL（可选 X-Target-SNI）
+pub async fn handler(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(user): Extension<User>,
+    mut req: Request,
+) -> Response {
+    let mut target = req
+        .headers()
+        .get("X-Target-URL")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    match scripting::before_connect(state.scripts.as_ref(), Route::Rest, &user.name, addr.ip(), &mut target) {
+        Ok(Some(hooks)) => {
+            // proxy 从请求头读取目标
+            if let Ok(v) = HeaderValue::from_str(&target) {
+                req.headers_mut().insert("x-target-url", v);
+            }
+            for (name, value) in hooks.headers().into_iter().flatten() {
+                req.headers_mut().insert(name, value.clone());
+            }
+        }
+        Ok(None) => {}
+        Err(reason) => return error::response(StatusCode::FORBIDDEN, "SCRIPT_DENIED", &reason),
+    }
+    let exchange = Arc::new(Exchange {
+        session_id: access_log::new_session_id(),
+        started: Instant::now(),
+        client_ip: addr.ip(),
+        target: secret::redact_url(&target).into_owned(),
+        status: AtomicU16::new(0),
+        bytes_up: AtomicU64::new(0),
+        bytes_down: AtomicU64::new(0),
+        quota: state.quota.user(&user),
+        host_permit: Mutex::default(),
+        state,
+        user,
+    });
+    let span = info_span!(
+        "rest_request",
+        session_id = %exchange.session_id,
+        user = %exchange.user.name,
+        target = %exchange.target
+    );
+    let proxied = proxy(&exchange, req).instrument(span);
+    let mut response = match panic_guard::catch("rest", &exchange.session_id, proxied).await {
+        Some(r) => r,
+        None => error::response(StatusCode::INTERNAL_SERVER_ERROR, "INTERNAL_ERROR", "内部错误"),
+    };
+    let host = reqwest::Url::parse(&exchange.target).ok();
+    header_rules::apply_response(
+        &exchange.state.config.header_rules,
+        Route::Rest,
+        host.as_ref().and_then(|u| u.host_str()),
+        response.headers_mut(),
+    );
+    exchange.status.store(response.status().as_u16(), Ordering::Relaxed);
+    response
+}
+
+/// 一次 REST 请求的计量，最后一个引用释放时写访问日志
+///
+/// 缓冲响应在处理器返回时释放；流式响应（gRPC、SSE）由响应体持有，发送完毕或客户端断开时释放。
+/// 目标主机限流的名额随之释放。
+struct Exchange {
+    state: AppState,
+    user: User,
+    session_id: String,
+    started: Instant,
+    client_ip: IpAddr,
+    /// 去掉凭据的目标，用于日志与访问日志
+    target: String,
+    status: AtomicU16,
+    bytes_up: AtomicU64,
+    bytes_down: AtomicU64,
+    quota: UserQuota,
+    /// 目标主机限流名额（`[[host_limits]]`）
+    host_permit: Mutex<Option<HostPermit>>,
+}
+
+impl Exchange {
+    /// 流式请求体：累计并计入配额
+    fn add_up(&self, n: u64) {
+        self.bytes_up.fetch_add(n, Ordering::Relaxed);
+        self.quota.consume_up(n);
+    }
+
+    /// 流式响应体：累计并计入配额
+    fn add_down(&self, n: u64) {
+        self.bytes_down.fetch_add(n, Ordering::Relaxed);
+        self.quota.consume_down(n);
+    }
+}
+
+impl Drop for Exchange {
+    fn drop(&mut self) {
+        let status = self.status.load(Ordering::Relaxed).to_string();
+        let record = AccessRecord {
+            session_id: &self.session_id,
+            kind: "rest",
+            user: &self.user.name,
+            client_ip: self.client_ip,
+            country: geoip::country(self.client_ip),
+            target: &self.target,
+            duration_ms: self.started.elapsed().as_millis() as u64,
+            bytes_up: self.bytes_up.load(Ordering::Relaxed),
+            bytes_down: self.bytes_down.load(Ordering::Relaxed),
+            close_reason: &status,
+            summary: None,
+        };
+        telemetry::record(&record);
+        self.state.stats.record_end(&record);
+        if let Some(ref log) = self.state.access_log {
+            log.record(&record);
+        }
+    }
+}
+
+/// 转发请求，字节数累计到 `exchange`
+async fn proxy(exchange: &Arc<Exchange>, req: Request) -> Response {
+    let state = &exchange.state;
+    let user = &exchange.user;
+
     // 从 Header 获取 target URL
     let target = match req.headers().get("X-Target-URL") {
         Some(v) => match v.to_str() {
@@ -30,47 +321,218 @@ pub async fn handler(req: Request) -> Response {
         },
         None => return (StatusCode::BAD_REQUEST, "Missing X-Target-URL header").into_response(),
     };
+    let sticky_key = req.headers().get(upstream::STICKY_KEY_HEADER).and_then(|v| v.to_str().ok());
+    let target = match upstream::resolve(&state.config.upstreams, &target, &user.name, sticky_key) {
+        Ok(t) => t.into_owned(),
+        Err((code, message)) => return error::response(StatusCode::BAD_REQUEST, code, message),
+    };
+    let target = target_rewrite::apply(&state.config.target_rewrites, &target).into_owned();
+    let shown = secret::redact_url(&target).into_owned();
+    if state.config.server.require_tls_targets && config::is_plaintext_target(&target) {
+        warn!("[{}] 拒绝明文目标: {}", user.name, shown);
+        return error::response(StatusCode::FORBIDDEN, "TLS_REQUIRED", "仅允许 TLS 目标（server.require_tls_targets）");
+    }
 
+    if state.quota.is_exhausted(user) {
+        warn!("[{}] 流量配额已用尽，拒绝请求", user.name);
+        return error::response(StatusCode::FORBIDDEN, "QUOTA_EXCEEDED", "流量配额已用尽");
+    }
+
+    // 目标主机限流，名额随 exchange 持有到响应发送完毕
+    *exchange.host_permit.lock().unwrap() = match state.host_limits.acquire(&target) {
+        Ok(p) => p,
+        Err((code, message)) => {
+            warn!("[{}] {}: {}", user.name, message, shown);
+            return error::response(StatusCode::TOO_MANY_REQUESTS, code, message);
+        }
+    };
+
+    // 出站 TLS 的 SNI（请求头优先于用户配置）
+    let sni = match req.headers().get("X-Target-SNI") {
+        Some(v) => match v.to_str() {
+            Ok(s) => Some(s.to_string()),
+            Err(_) => return (StatusCode::BAD_REQUEST, "Invalid X-Target-SNI header").into_response(),
+        },
+        None => user.sni_override.clone(),
+    };
+
+    let rest = &state.config.rest;
+    let limits = Limits::resolve(&state.config, user);
     let method = req.method().clone();
-    info!("REST: {} {}", method, target);
+    let grpc = is_grpc(req.headers());
+    info!("[{}] REST: {} {}", user.name, method, shown);
 
     // 提取请求头和 body（过滤掉 host，后面会自动设置）
-    let headers = filter_headers(req.headers());
-    let body = match axum::body::to_bytes(req.into_body(), 10 * 1024 * 1024).await {
-        Ok(b) => b,
-        Err(e) => {
-            error!("读取请求体失败: {}", e);
-            return (StatusCode::BAD_REQUEST, "Invalid body").into_response();
+    let mut headers = to_reqwest_headers(&filter_headers(req.headers()));
+    if !headers.contains_key(reqwest::header::USER_AGENT) {
+        let ua = rest.user_agent.as_deref().unwrap_or(DEFAULT_USER_AGENT);
+        if let Ok(v) = reqwest::header::HeaderValue::from_str(ua) {
+            headers.insert(reqwest::header::USER_AGENT, v);
+        }
+    }
+    let target_url = reqwest::Url::parse(&target).ok();
+    let target_host = target_url.as_ref().and_then(|u| u.host_str());
+    header_rules::apply_request(&state.config.header_rules, Route::Rest, target_host, &mut headers);
+
+    // 服务端 cookie jar：附加该用户此前从同一主机收到的 cookie
+    let jar = state.cookie_jar.as_ref().zip(target_url.as_ref());
+    if let Some(cookie) = jar.and_then(|(jar, url)| jar.cookie_header(&user.name, url)) {
+        let cookie = match headers.get(reqwest::header::COOKIE).and_then(|v| v.to_str().ok()) {
+            Some(client) => format!("{}; {}", client, cookie),
+            None => cookie,
+        };
+        if let Ok(v) = reqwest::header::HeaderValue::from_str(&cookie) {
+            headers.insert(reqwest::header::COOKIE, v);
+        }
+    }
+    let body = if grpc {
+        // gRPC：请求体边收边发，gRPC over HTTP/2 要求 `te: trailers`
+        headers.insert(reqwest::header::TE, reqwest::header::HeaderValue::from_static("trailers"));
+        let ex = exchange.clone();
+        reqwest::Body::wrap_stream(
+            req.into_body()
+                .into_data_stream()
+                .inspect_ok(move |chunk| ex.add_up(chunk.len() as u64)),
+        )
+    } else {
+        match axum::body::to_bytes(req.into_body(), limits.max_request_bytes).await {
+            Ok(b) => {
+                exchange.bytes_up.store(b.len() as u64, Ordering::Relaxed);
+                reqwest::Body::from(b)
+            }
+            Err(e) if e.source().is_some_and(|e| e.is::<LengthLimitError>()) => {
+                warn!("请求体超过上限: {} bytes", limits.max_request_bytes);
+                return error::response(StatusCode::PAYLOAD_TOO_LARGE, "REQUEST_TOO_LARGE", "请求体超过上限");
+            }
+            Err(e) => {
+                error!("读取请求体失败: {}", e);
+                return (StatusCode::BAD_REQUEST, "Invalid body").into_response();
+            }
         }
     };
 
+    // 响应缓存（仅 GET）：新鲜则直接返回，过期则带条件请求头重新验证
+    let cache = state.cache.as_ref().filter(|_| method == Method::GET && !grpc);
+    let mut stale = None;
+    if let Some(cache) = cache.filter(|_| !RestCache::bypass(&headers)) {
+        if let Some(entry) = cache.get(&user.name, &target, &headers) {
+            if entry.is_fresh() {
+                return cached_response(exchange, &entry, "HIT");
+            }
+            if entry.has_validators() {
+                entry.add_validators(&mut headers);
+                stale = Some(entry);
+            }
+        }
+    }
+    let req_headers = cache.map(|_| headers.clone());
+
     // 构建并发送请求（reqwest 会自动从 URL 设置正确的 Host header）
-    let resp = match CLIENT
-        .request(method, &target)
-        .headers(to_reqwest_headers(&headers))
-        .body(body)
-        .send()
-        .await
-    {
-        Ok(r) => r,
-        Err(e) => {
-            error!("代理请求失败: {} - {}", target, e);
+    let mut url = target.clone();
+    let mut alias = None;
+    if let Some((sni_url, host, authority)) = sni.and_then(|sni| apply_sni(&target, &sni)) {
+        if let Ok(v) = reqwest::header::HeaderValue::from_str(&authority) {
+            headers.insert(reqwest::header::HOST, v);
+        }
+        alias = sni_url.host_str().map(|sni| (sni.to_string(), host));
+        url = sni_url.to_string();
+    }
+    let key = ClientKey {
+        bind: user
+            .outbound_bind_address
+            .or(state.config.server.outbound_bind_address),
+        alias,
+        http1_only: !rest.http2,
+        h2c: rest.h2c_targets.iter().any(|p| config::target_matches(p, &target)),
+        connect_timeout: rest.connect_timeout_secs,
+        read_timeout: limits.idle_timeout,
+        insecure: target_host.is_some_and(|host| {
+            let insecure = &state.config.server.insecure_tls_hosts;
+            insecure.iter().any(|p| header_rules::host_matches(p, host))
+        }),
+    };
+
+    // 熔断打开时直接拒绝
+    let upstream = target_url
+        .as_ref()
+        .and_then(|u| Some(format!("{}:{}", u.host_str()?, u.port_or_known_default()?)));
+    let breaker = rest.circuit_breaker.as_ref().zip(upstream.as_deref());
+    if let Some(remaining) = breaker.and_then(|(_, upstream)| breaker_open(upstream)) {
+        let mut response = error::response(StatusCode::SERVICE_UNAVAILABLE, "CIRCUIT_OPEN", "上游暂时不可用（熔断中）");
+        let retry_after = remaining.as_secs_f64().ceil() as u64;
+        response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+        return response;
+    }
+    let retry = rest.retry.as_ref().filter(|_| is_idempotent(&method));
+
+    // 总超时覆盖发送请求（含重试）与读取缓冲响应体，gRPC 与 SSE 流不受限
+    let deadline = rest
+        .timeout_secs
+        .filter(|_| !grpc)
+        .map(|secs| tokio::time::Instant::now() + Duration::from_secs(secs));
+    let request = client(key).request(method, url).headers(headers).body(body);
+    let send = send(request, retry, breaker.map(|(config, upstream)| (upstream, config)));
+    let resp = match within(deadline, send).await {
+        Some(Ok(r)) => r,
+        Some(Err(e)) if e.is_timeout() => return timeout_response(&shown),
+        None => return timeout_response(&shown),
+        Some(Err(e)) => {
+            error!("代理请求失败: {} - {}", shown, e);
+            state.report_connect_failure(&user.name, &target, &e);
             return (StatusCode::BAD_GATEWAY, format!("Proxy error: {}", e)).into_response();
         }
     };
 
+    if let Some((jar, url)) = jar {
+        jar.store(&user.name, url, resp.headers());
+    }
+
+    if let (Some(cache), Some(entry)) = (cache, stale) {
+        if resp.status() == StatusCode::NOT_MODIFIED {
+            let entry = cache.refresh(&entry, resp.headers());
+            return cached_response(exchange, &entry, "REVALIDATED");
+        }
+    }
+
+    let streaming = match grpc {
+        true => Some(Streaming::Grpc),
+        false if is_event_stream(resp.headers()) => Some(Streaming::Sse),
+        false => None,
+    };
+    if let Some(kind) = streaming {
+        info!("REST 响应: {} -> {}（流式）", shown, resp.status());
+        return stream_response(resp, exchange.clone(), kind);
+    }
+
     // 构建响应
     let status = resp.status();
     let resp_headers = resp.headers().clone();
-    let body = match resp.bytes().await {
-        Ok(b) => b,
-        Err(e) => {
+    let body = match within(deadline, read_body(resp, limits.max_response_bytes)).await {
+        Some(Ok(Some(b))) => b,
+        Some(Ok(None)) => {
+            warn!("上游响应体超过上限: {}", shown);
+            return error::response(StatusCode::PAYLOAD_TOO_LARGE, "RESPONSE_TOO_LARGE", "上游响应体超过上限");
+        }
+        Some(Err(e)) if e.is_timeout() => return timeout_response(&shown),
+        Some(Err(e)) => {
             error!("读取响应体失败: {}", e);
             return (StatusCode::BAD_GATEWAY, "Failed to read response").into_response();
         }
+        None => return timeout_response(&shown),
     };
 
-    info!("REST 响应: {} -> {} ({} bytes)", target, status, body.len());
+    let req_len = exchange.bytes_up.load(Ordering::Relaxed);
+    let resp_len = body.len() as u64;
+    exchange.bytes_down.store(resp_len, Ordering::Relaxed);
+    info!("REST 响应: {} -> {} ({} bytes)", shown, status, resp_len);
+    exchange.quota.consume_up(req_len);
+    exchange.quota.consume_down(resp_len);
+
+    if let (Some(cache), Some(req_headers)) = (cache, req_headers) {
+        if status == StatusCode::OK {
+            cache.put(&user.name, &target, &req_headers, &resp_headers, body.clone());
+        }
+    }
 
     // 返回响应（只保留安全的响应头）
     let mut response = Response::new(Body::from(body));
@@ -82,10 +544,165 @@ pub async fn handler(req: Request) -> Response {
             response.headers_mut().insert("content-type", v);
         }
     }
+    if cache.is_some() {
+        response.headers_mut().insert("x-cache", HeaderValue::from_static("MISS"));
+    }
 
     response
 }
 
+fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::PUT | Method::DELETE
+    )
+}
+
+/// 在截止时间前完成 `fut`，超时返回 None
+async fn within<F: Future>(deadline: Option<tokio::time::Instant>, fut: F) -> Option<F::Output> {
+    match deadline {
+        Some(deadline) => tokio::time::timeout_at(deadline, fut).await.ok(),
+        None => Some(fut.await),
+    }
+}
+
+fn timeout_response(target: &str) -> Response {
+    warn!("上游请求超时: {}", secret::redact_url(target));
+    error::response(StatusCode::GATEWAY_TIMEOUT, "UPSTREAM_TIMEOUT", "上游请求超时")
+}
+
+/// 读取缓冲响应体，超过 `limit` 时返回 None
+async fn read_body(mut resp: reqwest::Response, limit: Option<usize>) -> reqwest::Result<Option<Bytes>> {
+    let Some(limit) = limit else {
+        return resp.bytes().await.map(Some);
+    };
+    if resp.content_length().is_some_and(|len| len > limit as u64) {
+        return Ok(None);
+    }
+    let mut buf = buffer_pool::get(resp.content_length().unwrap_or(0) as usize);
+    while let Some(chunk) = resp.chunk().await? {
+        if buf.len() + chunk.len() > limit {
+            return Ok(None);
+        }
+        buf.extend_from_slice(&chunk);
+    }
+    Ok(Some(buf.freeze()))
+}
+
+/// 由缓存条目构造响应，`x_cache` 为 HIT / REVALIDATED
+fn cached_response(exchange: &Exchange, entry: &Entry, x_cache: &'static str) -> Response {
+    let req_len = exchange.bytes_up.load(Ordering::Relaxed);
+    let resp_len = entry.body.len() as u64;
+    exchange.bytes_down.store(resp_len, Ordering::Relaxed);
+    exchange.quota.consume_up(req_len);
+    exchange.quota.consume_down(resp_len);
+    info!("REST 缓存 {}: {} ({} bytes)", x_cache, secret::redact_url(&exchange.target), resp_len);
+
+    let mut response = Response::new(Body::from(entry.body.clone()));
+    if let Some(ct) = entry.content_type.as_deref().and_then(|v| HeaderValue::from_str(v).ok()) {
+        response.headers_mut().insert(header::CONTENT_TYPE, ct);
+    }
+    response.headers_mut().insert("x-cache", HeaderValue::from_static(x_cache));
+    response
+}
+
+/// gRPC / gRPC-Web 请求（`application/grpc*`）
+fn is_grpc(headers: &HeaderMap) -> bool {
+    content_type(headers).is_some_and(|ct| ct.starts_with("application/grpc"))
+}
+
+/// SSE 响应（`text/event-stream`）
+fn is_event_stream(headers: &HeaderMap) -> bool {
+    content_type(headers).is_some_and(|ct| ct.starts_with("text/event-stream"))
+}
+
+fn content_type(headers: &HeaderMap) -> Option<&str> {
+    headers.get(header::CONTENT_TYPE).and_then(|v| v.to_str().ok())
+}
+
+/// 流式转发的响应
+#[derive(Clone, Copy)]
+enum Streaming {
+    /// gRPC / gRPC-Web：按 HTTP 帧转发，保留 trailers
+    Grpc,
+    /// Server-Sent Events：上游异常断开时补发一个 `error` 事件后正常结束
+    Sse,
+}
+
+/// 流式转发响应：保留响应头（除 hop-by-hop），字节数边发边计
+fn stream_response(resp: reqwest::Response, exchange: Arc<Exchange>, kind: Streaming) -> Response {
+    const HOP_BY_HOP: &[header::HeaderName] = &[
+        header::CONNECTION,
+        header::TRANSFER_ENCODING,
+        header::UPGRADE,
+        header::TE,
+        header::TRAILER,
+    ];
+
+    let (mut parts, body) = http::Response::from(resp).into_parts();
+    for name in HOP_BY_HOP {
+        parts.headers.remove(name);
+    }
+    parts.headers.remove("keep-alive");
+
+    let body = match kind {
+        Streaming::Grpc => Body::new(body.map_frame(move |frame| {
+            if let Some(data) = frame.data_ref() {
+                exchange.add_down(data.len() as u64);
+            }
+            frame
+        })),
+        Streaming::Sse => {
+            // 禁止中间层（如 nginx）缓冲与缓存
+            parts.headers.remove(header::CONTENT_LENGTH);
+            parts
+                .headers
+                .entry(header::CACHE_CONTROL)
+                .or_insert(HeaderValue::from_static("no-cache"));
+            parts
+                .headers
+                .insert("x-accel-buffering", HeaderValue::from_static("no"));
+
+            let upstream = body.into_data_stream();
+            Body::from_stream(futures_util::stream::unfold(Some(upstream), move |upstream| {
+                let exchange = exchange.clone();
+                async move {
+                    let mut upstream = upstream?;
+                    match upstream.next().await? {
+                        Ok(chunk) => {
+                            exchange.add_down(chunk.len() as u64);
+                            Some((Ok::<_, Infallible>(chunk), Some(upstream)))
+                        }
+                        Err(e) => {
+                            warn!("SSE 上游断开: {} - {}", secret::redact_url(&exchange.target), e);
+                            let msg = error::to_json("UPSTREAM_DISCONNECTED", "上游连接中断");
+                            let event = format!("event: error\ndata: {}\n\n", msg);
+                            Some((Ok(Bytes::from(event)), None))
+                        }
+                    }
+                }
+            }))
+        }
+    };
+    Response::from_parts(parts, body)
+}
+
+/// SNI 覆盖（仅 https）：URL 主机名换成 SNI，连接仍指向原目标
+/// 返回 (新 URL, 原主机名, 原 Host 头)
+fn apply_sni(target: &str, sni: &str) -> Option<(reqwest::Url, String, String)> {
+    let mut url = reqwest::Url::parse(target).ok()?;
+    if url.scheme() != "https" {
+        return None;
+    }
+    let host = url.host_str()?.to_string();
+    let authority = match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.clone(),
+    };
+    url.set_host(Some(sni)).ok()?;
+    Some((url, host, authority))
+}
+
 /// 过滤掉 hop-by-hop headers、认证 header 和 host
 fn filter_headers(headers: &HeaderMap) -> HeaderMap {
     const FILTERED: &[&str] = &[
@@ -99,6 +716,8 @@ fn filter_headers(headers: &HeaderMap) -> HeaderMap {
         "transfer-encoding",
         "upgrade",
         "x-token",     // 移除我们的认证 header
+        "x-target-sni",
+        "x-sticky-key",
         "accept-encoding", // 避免压缩问题
     ];
 
@@ -121,3 +740,143 @@ fn to_reqwest_headers(headers: &HeaderMap) -> reqwest::header::HeaderMap {
     }
     map
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    /// 依次返回 `statuses` 中的状态码（用完后重复最后一个），返回地址与已收到的请求数
+    async fn upstream(statuses: Vec<u16>) -> (String, Arc<AtomicUsize>) {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        let app = axum::Router::new().fallback(move || {
+            let n = counter.fetch_add(1, Ordering::SeqCst);
+            let status = statuses.get(n).or(statuses.last()).copied().unwrap();
+            async move { StatusCode::from_u16(status).unwrap() }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (url, hits)
+    }
+
+    fn request(url: &str) -> reqwest::RequestBuilder {
+        Client::builder().no_proxy().build().unwrap().get(url)
+    }
+
+    const RETRY: RetryConfig = RetryConfig {
+        attempts: 3,
+        backoff_ms: 1,
+        max_backoff_ms: 1,
+    };
+
+    fn breaker(failure_threshold: u32) -> CircuitBreakerConfig {
+        CircuitBreakerConfig {
+            failure_threshold,
+            open_secs: 60,
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_failure_status() {
+        let (url, hits) = upstream(vec![503, 502, 200]).await;
+        let resp = send(request(&url), Some(&RETRY), None).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+
+        // 重试次数用完时返回最后一次的响应
+        let (url, hits) = upstream(vec![504]).await;
+        let resp = send(request(&url), Some(&RETRY), None).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(hits.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn no_retry_for_other_status_or_without_config() {
+        let (url, hits) = upstream(vec![500, 200]).await;
+        let resp = send(request(&url), Some(&RETRY), None).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+
+        let (url, hits) = upstream(vec![503, 200]).await;
+        let resp = send(request(&url), None, None).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn streaming_body_is_not_retried() {
+        let (url, hits) = upstream(vec![503, 200]).await;
+        let body = reqwest::Body::wrap_stream(futures_util::stream::iter([Ok::<_, Infallible>(Bytes::from("x"))]));
+        let resp = send(request(&url).body(body), Some(&RETRY), None).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn only_idempotent_methods_retry() {
+        for method in [Method::GET, Method::HEAD, Method::OPTIONS, Method::PUT, Method::DELETE] {
+            assert!(is_idempotent(&method), "{}", method);
+        }
+        for method in [Method::POST, Method::PATCH, Method::CONNECT] {
+            assert!(!is_idempotent(&method), "{}", method);
+        }
+    }
+
+    #[test]
+    fn breaker_opens_after_threshold() {
+        let upstream = "breaker-opens.test:443";
+        let config = breaker(3);
+        breaker_record(upstream, false, &config);
+        breaker_record(upstream, false, &config);
+        assert!(breaker_open(upstream).is_none());
+        // 成功后重新计数
+        breaker_record(upstream, true, &config);
+        breaker_record(upstream, false, &config);
+        breaker_record(upstream, false, &config);
+        assert!(breaker_open(upstream).is_none());
+        breaker_record(upstream, false, &config);
+        let remaining = breaker_open(upstream).unwrap();
+        assert!(remaining > Duration::from_secs(59) && remaining <= Duration::from_secs(60));
+    }
+
+    #[test]
+    fn breaker_half_open_recovery() {
+        let config = breaker(2);
+        let expire = |upstream: &str| {
+            let past = Instant::now() - Duration::from_secs(1);
+            BREAKERS.lock().unwrap().get_mut(upstream).unwrap().open_until = Some(past);
+        };
+
+        // 到期后放行，成功即恢复
+        let upstream = "breaker-recovers.test:443";
+        breaker_record(upstream, false, &config);
+        breaker_record(upstream, false, &config);
+        assert!(breaker_open(upstream).is_some());
+        expire(upstream);
+        assert!(breaker_open(upstream).is_none());
+        breaker_record(upstream, true, &config);
+        assert!(!BREAKERS.lock().unwrap().contains_key(upstream));
+
+        // 到期后再次失败立即重新打开
+        let upstream = "breaker-reopens.test:443";
+        breaker_record(upstream, false, &config);
+        breaker_record(upstream, false, &config);
+        expire(upstream);
+        assert!(breaker_open(upstream).is_none());
+        breaker_record(upstream, false, &config);
+        assert!(breaker_open(upstream).is_some());
+    }
+
+    #[tokio::test]
+    async fn open_breaker_stops_retries() {
+        let (url, hits) = upstream(vec![503]).await;
+        let upstream = url.trim_start_matches("http://").trim_end_matches('/').to_string();
+        let retry = RetryConfig { attempts: 5, ..RETRY };
+        let resp = send(request(&url), Some(&retry), Some((&upstream, &breaker(2)))).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+        assert!(breaker_open(&upstream).is_some());
+    }
+}
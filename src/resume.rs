@@ -0,0 +1,215 @@
+//! 确认投递与会话恢复
+//!
+//! 客户端在 `/ws` 请求中带 `X-Delivery-Mode: acked` 开启确认投递：
+//!
+//! - 目标 → 客户端的 text / binary 消息隐式编号（本会话发给客户端的第 N 条为 N，从 1 开始），
+//!   relay 保留已发送、未确认的消息
+//! - 客户端发送 `{"type":"ack","seq":N}` 确认第 N 条及之前的消息，relay 释放对应消息
+//! - 未确认的消息超过 `server.max_unacked_bytes` 时暂停向客户端发送，直到收到确认
+//!
+//! `server.resume_secs` 大于 0 时，客户端断开后会话与目标连接保留该时长。连接建立后 relay 先发送
+//! `{"type":"session","resume_token":"...","resume_secs":N}`，客户端以同一用户带 `X-Resume-Token` 重新连接 `/ws`
+//! 即恢复会话：relay 回复 `{"type":"resumed","next_seq":M}`，从第一条未确认的消息（第 M 条）开始重发。
+//! 恢复的会话沿用原会话的目标与设置，断开期间尚未发给客户端的消息一并保留。
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+};
+
+use axum::extract::ws::WebSocket;
+use once_cell::sync::Lazy;
+use rand::{distributions::Alphanumeric, Rng};
+use tokio::sync::{oneshot, Notify};
+use tokio_tungstenite::tungstenite::Message as TungMessage;
+
+/// 开启确认投递：`X-Delivery-Mode: acked`
+pub const MODE_HEADER: &str = "X-Delivery-Mode";
+
+/// 恢复会话
+pub const TOKEN_HEADER: &str = "X-Resume-Token";
+
+const TOKEN_LEN: usize = 32;
+
+/// 等待恢复的会话：用户名与新连接的发送端
+struct Parked {
+    user: String,
+    socket: oneshot::Sender<WebSocket>,
+}
+
+/// resume token → 等待恢复的会话
+static PARKED: Lazy<Mutex<HashMap<String, Parked>>> = Lazy::new(Mutex::default);
+
+pub fn new_token() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(TOKEN_LEN)
+        .map(char::from)
+        .collect()
+}
+
+/// 登记等待恢复的会话，新连接经返回的接收端交给原会话
+pub fn park(token: &str, user: &str) -> oneshot::Receiver<WebSocket> {
+    let (tx, rx) = oneshot::channel();
+    PARKED.lock().unwrap().insert(
+        token.to_string(),
+        Parked {
+            user: user.to_string(),
+            socket: tx,
+        },
+    );
+    rx
+}
+
+/// 放弃等待（超时或会话结束）
+pub fn unpark(token: &str) {
+    PARKED.lock().unwrap().remove(token);
+}
+
+/// 取出等待恢复的会话，须为同一用户
+pub fn take(token: &str, user: &str) -> Option<oneshot::Sender<WebSocket>> {
+    let mut parked = PARKED.lock().unwrap();
+    if parked.get(token).is_none_or(|p| p.user != user) {
+        return None;
+    }
+    parked.remove(token).map(|p| p.socket)
+}
+
+struct Inner {
+    items: VecDeque<TungMessage>,
+    bytes: usize,
+    /// 已确认的最大编号
+    acked: u64,
+}
+
+/// 已发送、未确认的消息
+pub struct Unacked {
+    inner: Mutex<Inner>,
+    max_bytes: usize,
+    /// 收到确认
+    released: Notify,
+}
+
+impl Unacked {
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            inner: Mutex::new(Inner {
+                items: VecDeque::new(),
+                bytes: 0,
+                acked: 0,
+            }),
+            max_bytes,
+            released: Notify::new(),
+        }
+    }
+
+    /// 发送前登记
+    pub fn push(&self, msg: TungMessage) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.bytes += msg.len();
+        inner.items.push_back(msg);
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.inner.lock().unwrap().bytes >= self.max_bytes
+    }
+
+    /// 等待确认释放空间
+    pub async fn wait_space(&self) {
+        while self.is_full() {
+            self.released.notified().await;
+        }
+    }
+
+    /// 累计确认到 `seq`
+    pub fn ack(&self, seq: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        while inner.acked < seq {
+            let Some(msg) = inner.items.pop_front() else {
+                break;
+            };
+            inner.bytes -= msg.len();
+            inner.acked += 1;
+        }
+        self.released.notify_one();
+    }
+
+    /// 第一条未确认消息的编号及全部未确认消息（用于重发）
+    pub fn pending(&self) -> (u64, Vec<TungMessage>) {
+        let inner = self.inner.lock().unwrap();
+        (inner.acked + 1, inner.items.iter().cloned().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::Arc, time::Duration};
+
+    fn msg(len: usize) -> TungMessage {
+        TungMessage::Binary(vec![0; len].into())
+    }
+
+    #[test]
+    fn cumulative_ack_and_pending() {
+        let unacked = Unacked::new(1 << 20);
+        assert_eq!(unacked.pending(), (1, Vec::new()));
+        for len in [10, 20, 30, 40] {
+            unacked.push(msg(len));
+        }
+        assert_eq!(unacked.inner.lock().unwrap().bytes, 100);
+
+        unacked.ack(2);
+        let (next_seq, items) = unacked.pending();
+        assert_eq!(next_seq, 3);
+        assert_eq!(items, vec![msg(30), msg(40)]);
+        assert_eq!(unacked.inner.lock().unwrap().bytes, 70);
+
+        // 重复或更早的确认不生效
+        unacked.ack(1);
+        assert_eq!(unacked.pending().0, 3);
+
+        // 超出已发送范围的确认只释放已有消息，之后的编号照常递增
+        unacked.ack(10);
+        assert_eq!(unacked.pending(), (5, Vec::new()));
+        assert_eq!(unacked.inner.lock().unwrap().bytes, 0);
+        unacked.push(msg(5));
+        assert_eq!(unacked.pending(), (5, vec![msg(5)]));
+    }
+
+    #[tokio::test]
+    async fn wait_space_wakes_on_ack() {
+        let unacked = Arc::new(Unacked::new(100));
+        unacked.push(msg(60));
+        assert!(!unacked.is_full());
+        unacked.wait_space().await;
+        unacked.push(msg(40));
+        assert!(unacked.is_full());
+
+        let waiter = tokio::spawn({
+            let unacked = unacked.clone();
+            async move { unacked.wait_space().await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiter.is_finished());
+        unacked.ack(1);
+        tokio::time::timeout(Duration::from_secs(1), waiter).await.unwrap().unwrap();
+        assert!(!unacked.is_full());
+    }
+
+    #[test]
+    fn take_requires_same_user() {
+        let token = new_token();
+        assert_eq!(token.len(), TOKEN_LEN);
+        let _rx = park(&token, "alice");
+        assert!(take(&token, "bob").is_none());
+        assert!(take("unknown", "alice").is_none());
+        // 被其他用户尝试后仍可由原用户恢复，且只能取出一次
+        assert!(take(&token, "alice").is_some());
+        assert!(take(&token, "alice").is_none());
+
+        let _rx = park(&token, "alice");
+        unpark(&token);
+        assert!(take(&token, "alice").is_none());
+    }
+}
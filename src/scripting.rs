@@ -0,0 +1,257 @@
+//! 脚本钩子（Rhai）
+//!
+//! 配置无法表达的策略（动态改写目标、注入头部、按内容过滤消息）可写成 Rhai 脚本，
+//! 以 `[[scripts]]` 按路由与用户限定范围；脚本只需定义用到的函数，多个脚本按配置顺序依次执行：
+//!
+//! | 函数 | 时机 | 返回值 |
+//! |------|------|------|
+//! | `on_auth(ctx)` | 认证通过后（`/ws`、`/rest`） | `false` 或字符串拒绝（字符串为原因），其余放行 |
+//! | `on_target_select(ctx)` | 连接目标前 | 字符串为新目标；`#{target: "...", headers: #{...}}` 同时追加上游请求头；`()` 不变 |
+//! | `on_message(ctx, dir, data)` | `/ws` 每条 text 消息，`dir` 为 `c2t` / `t2c` | 字符串替换内容，`false` 丢弃，其余原样转发 |
+//! | `on_close(ctx, reason)` | `/ws` 会话结束 | 忽略 |
+//!
+//! `ctx` 为 `#{user, client_ip, route, target}`，`target` 为（改写后的）当前目标。
+//! 单次调用最多执行 10 万步；`on_auth` 出错时拒绝，其余钩子出错时视为不改变。
+
+use std::{net::IpAddr, sync::Arc};
+
+use anyhow::{anyhow, Context, Result};
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+use rhai::{Dynamic, Engine, FuncArgs, Map, Scope, AST};
+use tracing::{debug, info, warn};
+
+use crate::{
+    config::{Route, ScriptConfig},
+    secret,
+};
+
+/// 单次钩子调用的最大执行步数
+const MAX_OPERATIONS: u64 = 100_000;
+
+const ON_AUTH: &str = "on_auth";
+const ON_TARGET_SELECT: &str = "on_target_select";
+const ON_MESSAGE: &str = "on_message";
+const ON_CLOSE: &str = "on_close";
+
+struct Script {
+    path: String,
+    route: Option<Route>,
+    users: Vec<String>,
+    ast: AST,
+    /// 脚本定义的钩子
+    hooks: Vec<&'static str>,
+}
+
+/// 已编译的脚本
+pub struct Scripts {
+    engine: Engine,
+    scripts: Vec<Script>,
+}
+
+impl Scripts {
+    /// 编译全部脚本，任一失败则报错
+    pub fn load(configs: &[ScriptConfig]) -> Result<Self> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.on_print(|s| info!("脚本: {}", s));
+        engine.on_debug(|s, _, pos| debug!("脚本 {}: {}", pos, s));
+
+        let mut scripts = Vec::with_capacity(configs.len());
+        for c in configs {
+            let ast = engine
+                .compile_file(c.path.clone().into())
+                .map_err(|e| anyhow!("{}", e))
+                .with_context(|| format!("脚本编译失败: {}", c.path))?;
+            let hooks: Vec<_> = [ON_AUTH, ON_TARGET_SELECT, ON_MESSAGE, ON_CLOSE]
+                .into_iter()
+                .filter(|h| ast.iter_functions().any(|f| f.name == *h))
+                .collect();
+            info!("脚本: {}（{}）", c.path, hooks.join(", "));
+            scripts.push(Script {
+                path: c.path.clone(),
+                route: c.route,
+                users: c.users.clone(),
+                ast,
+                hooks,
+            });
+        }
+        Ok(Self { engine, scripts })
+    }
+
+    /// 选出适用于本次请求的脚本
+    pub fn hooks(self: &Arc<Self>, route: Route, user: &str, client_ip: IpAddr, target: &str) -> Hooks {
+        let selected = self
+            .scripts
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| s.route.is_none_or(|r| r == route))
+            .filter(|(_, s)| s.users.is_empty() || s.users.iter().any(|u| u == user))
+            .map(|(i, _)| i)
+            .collect();
+        let mut ctx = Map::new();
+        ctx.insert("user".into(), user.into());
+        ctx.insert("client_ip".into(), client_ip.to_string().into());
+        ctx.insert("route".into(), route_name(route).into());
+        ctx.insert("target".into(), target.into());
+        Hooks {
+            scripts: self.clone(),
+            selected,
+            ctx,
+            headers: HeaderMap::new(),
+        }
+    }
+}
+
+fn route_name(route: Route) -> &'static str {
+    match route {
+        Route::Rest => "rest",
+        Route::Ws => "ws",
+    }
+}
+
+/// 消息钩子的处理结果
+pub enum MessageAction {
+    Forward,
+    Replace(String),
+    Drop,
+}
+
+/// 一次请求 / 会话适用的脚本
+pub struct Hooks {
+    scripts: Arc<Scripts>,
+    selected: Vec<usize>,
+    ctx: Map,
+    /// `on_target_select` 追加的上游请求头（WS 为握手请求）
+    headers: HeaderMap,
+}
+
+impl Hooks {
+    /// 适用且定义了 `hook` 的脚本
+    fn with_hook(&self, hook: &'static str) -> impl Iterator<Item = &Script> + '_ {
+        self.selected
+            .iter()
+            .map(|&i| &self.scripts.scripts[i])
+            .filter(move |s| s.hooks.contains(&hook))
+    }
+
+    /// 调用一个脚本的钩子，出错时记录日志并返回 `None`
+    fn run(&self, script: &Script, hook: &str, args: impl FuncArgs) -> Option<Dynamic> {
+        self.scripts
+            .engine
+            .call_fn::<Dynamic>(&mut Scope::new(), &script.ast, hook, args)
+            .inspect_err(|e| warn!("脚本 {} 执行失败: {} - {}", hook, script.path, e))
+            .ok()
+    }
+
+    pub fn has_on_message(&self) -> bool {
+        self.with_hook(ON_MESSAGE).next().is_some()
+    }
+
+    /// 返回 `Err(原因)` 表示拒绝
+    pub fn on_auth(&self) -> Result<(), String> {
+        for script in self.with_hook(ON_AUTH) {
+            let value = self
+                .run(script, ON_AUTH, (self.ctx.clone(),))
+                .ok_or_else(|| "脚本执行失败".to_string())?;
+            if value.as_bool() == Ok(false) {
+                return Err("脚本拒绝".into());
+            }
+            if let Ok(reason) = value.into_string() {
+                return Err(reason);
+            }
+        }
+        Ok(())
+    }
+
+    /// 返回改写后的目标，`None` 表示不变；追加的头部见 [`Hooks::headers`]
+    pub fn on_target_select(&mut self) -> Option<String> {
+        let mut target = None;
+        let mut headers = HeaderMap::new();
+        for script in self.with_hook(ON_TARGET_SELECT) {
+            let mut ctx = self.ctx.clone();
+            if let Some(ref t) = target {
+                ctx.insert("target".into(), Dynamic::from(String::clone(t)));
+            }
+            let Some(value) = self.run(script, ON_TARGET_SELECT, (ctx,)) else {
+                continue;
+            };
+            if value.is_string() {
+                target = value.into_string().ok();
+                continue;
+            }
+            let Some(mut map) = value.try_cast::<Map>() else {
+                continue;
+            };
+            if let Some(next) = map.remove("target").and_then(|t| t.into_string().ok()) {
+                target = Some(next);
+            }
+            let extra = map.remove("headers").and_then(|h| h.try_cast::<Map>());
+            for (name, value) in extra.into_iter().flatten() {
+                match HeaderName::try_from(name.as_str()).ok().zip(HeaderValue::try_from(value.to_string()).ok()) {
+                    Some((name, value)) => {
+                        headers.insert(name, value);
+                    }
+                    None => warn!("脚本返回了无效的头部: {} - {}", script.path, name),
+                }
+            }
+        }
+        self.headers = headers;
+        let target = target?;
+        self.ctx.insert("target".into(), target.clone().into());
+        Some(target)
+    }
+
+    pub fn headers(&self) -> Option<&HeaderMap> {
+        (!self.headers.is_empty()).then_some(&self.headers)
+    }
+
+    /// `dir` 为 `c2t` / `t2c`
+    pub fn on_message(&self, dir: &str, data: &str) -> MessageAction {
+        let mut replaced = None;
+        for script in self.with_hook(ON_MESSAGE) {
+            let current = replaced.clone().unwrap_or_else(|| data.to_string());
+            let Some(value) = self.run(script, ON_MESSAGE, (self.ctx.clone(), dir.to_string(), current)) else {
+                continue;
+            };
+            if value.as_bool() == Ok(false) {
+                return MessageAction::Drop;
+            }
+            if let Ok(text) = value.into_string() {
+                replaced = Some(text);
+            }
+        }
+        match replaced {
+            Some(text) => MessageAction::Replace(text),
+            None => MessageAction::Forward,
+        }
+    }
+
+    pub fn on_close(&self, reason: &str) {
+        for script in self.with_hook(ON_CLOSE) {
+            self.run(script, ON_CLOSE, (self.ctx.clone(), reason.to_string()));
+        }
+    }
+}
+
+/// 认证与目标选择钩子：拒绝时返回原因，改写的目标写回 `target`
+pub fn before_connect(
+    scripts: Option<&Arc<Scripts>>,
+    route: Route,
+    user: &str,
+    client_ip: IpAddr,
+    target: &mut String,
+) -> Result<Option<Hooks>, String> {
+    let Some(scripts) = scripts else {
+        return Ok(None);
+    };
+    let mut hooks = scripts.hooks(route, user, client_ip, target);
+    if let Err(reason) = hooks.on_auth() {
+        warn!("[{}] 脚本拒绝: {} - {}", user, secret::redact_url(target), reason);
+        return Err(reason);
+    }
+    if let Some(next) = hooks.on_target_select() {
+        info!("[{}] 脚本改写目标: {} → {}", user, secret::redact_url(target), secret::redact_url(&next));
+        *target = next;
+    }
+    Ok(Some(hooks))
+}
@@ -0,0 +1,136 @@
+//! 会话事件 webhook
+//!
+//! 配置 `[session_webhook]` 后，`/ws`、`/mux`、`/pubsub` 与 SOCKS5 会话开始和结束时各产生一个事件，
+//! 由后台任务攒批后 POST 到 `url`：
+//!
+//! ```json
+//! {"events":[
+//!   {"event":"session_start","ts":"2026-01-01T00:00:00.000Z","session_id":"3f2a...","kind":"ws","user":"alice","client_ip":"1.2.3.4","target":"wss://..."},
+//!   {"event":"session_end","ts":"2026-01-01T00:00:01.234Z","session_id":"3f2a...","kind":"ws","user":"alice","client_ip":"1.2.3.4","target":"wss://...","duration_ms":1234,"bytes_up":100,"bytes_down":2048,"close_reason":"client_closed"}
+//! ]}
+//! ```
+//!
+//! - 凑满 `batch_size` 个事件或距首个事件 `flush_interval_ms` 后发送
+//! - 非 2xx 或请求失败时按 1s、2s、4s … 重试 `max_retries` 次，仍失败则丢弃该批
+//! - 发送积压超过 `queue_size` 时丢弃新事件，不影响会话本身
+
+use std::{
+    net::IpAddr,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use anyhow::{bail, Result};
+use chrono::{SecondsFormat, Utc};
+use reqwest::Client;
+use serde::Serialize;
+use serde_json::{json, Value};
+use tokio::{
+    sync::mpsc,
+    time::{sleep, timeout_at, Instant},
+};
+use tracing::{debug, error, info, warn};
+
+use crate::{access_log::AccessRecord, config::SessionWebhookConfig};
+
+/// 重试间隔上限
+const RETRY_MAX: Duration = Duration::from_secs(30);
+
+/// 会话事件
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum SessionEvent<'a> {
+    SessionStart {
+        session_id: &'a str,
+        kind: &'static str,
+        user: &'a str,
+        client_ip: IpAddr,
+        target: &'a str,
+    },
+    SessionEnd(&'a AccessRecord<'a>),
+}
+
+pub struct SessionWebhook {
+    tx: mpsc::Sender<Value>,
+    dropped: AtomicU64,
+}
+
+impl SessionWebhook {
+    pub fn new(config: &SessionWebhookConfig) -> Result<Self> {
+        let client = Client::builder()
+            .timeout(Duration::from_millis(config.timeout_ms))
+            .build()?;
+        let (tx, rx) = mpsc::channel(config.queue_size.max(1));
+        tokio::spawn(deliver(client, config.clone(), rx));
+        info!("会话事件 webhook: {}", config.url);
+        Ok(Self {
+            tx,
+            dropped: AtomicU64::new(0),
+        })
+    }
+
+    /// 加入发送队列，队列已满时丢弃
+    pub fn send(&self, event: &SessionEvent) {
+        let Ok(Value::Object(mut entry)) = serde_json::to_value(event) else {
+            return;
+        };
+        entry.insert(
+            "ts".into(),
+            Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true).into(),
+        );
+        if self.tx.try_send(Value::Object(entry)).is_err() {
+            let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+            if dropped % 1000 == 1 {
+                warn!("会话事件 webhook 积压，已丢弃 {} 个事件", dropped);
+            }
+        }
+    }
+}
+
+/// 后台攒批发送
+async fn deliver(client: Client, config: SessionWebhookConfig, mut rx: mpsc::Receiver<Value>) {
+    let batch_size = config.batch_size.max(1);
+    let flush_interval = Duration::from_millis(config.flush_interval_ms);
+    let mut batch = Vec::with_capacity(batch_size);
+    while let Some(event) = rx.recv().await {
+        batch.push(event);
+        let deadline = Instant::now() + flush_interval;
+        while batch.len() < batch_size {
+            match timeout_at(deadline, rx.recv()).await {
+                Ok(Some(event)) => batch.push(event),
+                Ok(None) | Err(_) => break,
+            }
+        }
+
+        let body = json!({ "events": batch }).to_string();
+        let mut delay = Duration::from_secs(1);
+        for attempt in 0..=config.max_retries {
+            match post(&client, &config.url, &body).await {
+                Ok(()) => {
+                    debug!("会话事件已推送: {} 个", batch.len());
+                    break;
+                }
+                Err(e) if attempt < config.max_retries => {
+                    warn!("会话事件推送失败，{:?} 后重试: {} - {:#}", delay, config.url, e);
+                    sleep(delay).await;
+                    delay = (delay * 2).min(RETRY_MAX);
+                }
+                Err(e) => error!("会话事件推送失败，丢弃 {} 个事件: {} - {:#}", batch.len(), config.url, e),
+            }
+        }
+        batch.clear();
+    }
+}
+
+async fn post(client: &Client, url: &str, body: &str) -> Result<()> {
+    let resp = client
+        .post(url)
+        .header("content-type", "application/json")
+        .body(body.to_string())
+        .send()
+        .await?;
+    if !resp.status().is_success() {
+        bail!("HTTP {}", resp.status());
+    }
+    Ok(())
+}
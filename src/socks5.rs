@@ -0,0 +1,550 @@
+//! SOCKS5 入口
+//!
+//! 在 `[socks5] listen` 上接受 SOCKS5 连接（RFC 1928），不支持 WebSocket 的 TCP 程序也可使用 relay：
+//!
+//! - 仅支持用户名/密码认证（RFC 1929）：用户名为用户名，密码为 token，与 `/ws` 使用同一认证链
+//! - 仅支持 `CONNECT`；目标记为 `tcp://host:port`，受 `allowed_targets`、`allowed_ips`、流量配额限制
+//! - 未配置 `peer` 时直接连接目标；配置后经远端 relay 的 `/ws` 转发（远端需开启 `server.tcp_targets`），
+//!   TCP 字节流以 WS binary 消息传输
+//!
+//! 经 `peer` 转发时，远端连接目标失败表现为连接建立后立即关闭。
+
+use std::{
+    io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+use axum::http::{HeaderMap, HeaderValue};
+use futures_util::{SinkExt, StreamExt};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+    time::timeout,
+};
+use tokio_tungstenite::tungstenite::Message as TungMessage;
+use tracing::{debug, info, info_span, warn, Instrument};
+
+use crate::{
+    access_log::{self, AccessRecord},
+    audit::AuditEvent,
+    auth::Credentials,
+    config::{Socks5Config, User},
+    geoip, panic_guard, resources,
+    session_webhook::SessionEvent,
+    state::AppState,
+    stats::{SessionSlot, SessionStats, SlotLimit},
+    telemetry,
+    upgrade,
+    ws::{self, Dial, TargetRx, TargetTx},
+};
+
+const VERSION: u8 = 5;
+const AUTH_VERSION: u8 = 1;
+const METHOD_USER_PASS: u8 = 2;
+const METHOD_NONE_ACCEPTABLE: u8 = 0xff;
+const CMD_CONNECT: u8 = 1;
+const ATYP_IPV4: u8 = 1;
+const ATYP_DOMAIN: u8 = 3;
+const ATYP_IPV6: u8 = 4;
+
+/// 应答码
+const REP_SUCCEEDED: u8 = 0;
+const REP_NOT_ALLOWED: u8 = 2;
+const REP_HOST_UNREACHABLE: u8 = 4;
+const REP_CONNECTION_REFUSED: u8 = 5;
+const REP_COMMAND_NOT_SUPPORTED: u8 = 7;
+const REP_ADDRESS_NOT_SUPPORTED: u8 = 8;
+
+/// 绑定 SOCKS5 端口并在后台处理连接
+pub async fn spawn(config: &Socks5Config, state: AppState) -> Result<()> {
+    let listener = upgrade::bind(upgrade::Role::Socks5, &config.listen)
+        .await
+        .with_context(|| format!("SOCKS5 监听失败: {}", config.listen))?;
+    match config.peer {
+        Some(ref peer) => info!("SOCKS5: {}（经 {} 转发）", config.listen, peer),
+        None => info!("SOCKS5: {}", config.listen),
+    }
+
+    let config = Arc::new(config.clone());
+    tokio::spawn(async move {
+        loop {
+            let accepted = tokio::select! {
+                accepted = listener.accept() => accepted,
+                _ = upgrade::wait_handoff() => return,
+            };
+            let (stream, addr) = match accepted {
+                Ok(s) => s,
+                Err(e) => {
+                    warn!("SOCKS5 accept 失败: {}", e);
+                    continue;
+                }
+            };
+            let (state, config) = (state.clone(), config.clone());
+            tokio::spawn(async move {
+                if let Err(e) = serve(stream, addr, &state, &config).await {
+                    debug!("SOCKS5 连接结束 ({}): {:#}", addr, e);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+async fn serve(mut stream: TcpStream, addr: SocketAddr, state: &AppState, config: &Socks5Config) -> Result<()> {
+    if state.health.in_maintenance() {
+        bail!("维护中，拒绝连接: {}", addr.ip());
+    }
+    if resources::fd_exhausted() {
+        bail!("文件描述符接近上限，拒绝连接: {}", addr.ip());
+    }
+    if resources::overloaded() {
+        bail!("内存用量过高，拒绝连接: {}", addr.ip());
+    }
+    if !geoip::allows_client(addr.ip()) {
+        bail!("客户端所在国家/地区不允许访问: {}", addr.ip());
+    }
+    // 会话名额随连接持有
+    let (user, target, _slot) = handshake(&mut stream, addr, state, config).await?;
+    // 目标主机限流（经 peer 转发时由 peer 检查），名额随连接持有
+    let _permit = match config.peer {
+        Some(_) => None,
+        None => match state.host_limits.acquire(&target) {
+            Ok(p) => p,
+            Err((_, message)) => {
+                reply(&mut stream, REP_NOT_ALLOWED).await?;
+                bail!("[{}] {}: {}", user.name, message, target);
+            }
+        },
+    };
+
+    let (target_tx, target_rx) = match connect(&target, &user, state, config).await {
+        Ok(t) => t,
+        Err(e) => {
+            let refused = e
+                .downcast_ref::<io::Error>()
+                .is_some_and(|e| e.kind() == io::ErrorKind::ConnectionRefused);
+            let rep = if refused { REP_CONNECTION_REFUSED } else { REP_HOST_UNREACHABLE };
+            reply(&mut stream, rep).await?;
+            warn!("[{}] SOCKS5 连接目标失败: {} - {:#}", user.name, target, e);
+            state.report_connect_failure(&user.name, &target, e.as_ref());
+            return Ok(());
+        }
+    };
+    reply(&mut stream, REP_SUCCEEDED).await?;
+    info!("[{}] SOCKS5 已连接: {}", user.name, target);
+
+    let session_id = access_log::new_session_id();
+    let started = Instant::now();
+    let guard = state.stats.open_session(&session_id, &user.name, &target);
+    state.session_event(&SessionEvent::SessionStart {
+        session_id: &session_id,
+        kind: "socks5",
+        user: &user.name,
+        client_ip: addr.ip(),
+        target: &target,
+    });
+    let span = info_span!("socks5_session", session_id = %session_id, user = %user.name, target = %target);
+    let pumped = pump(stream, target_tx, target_rx, state, &user, &guard.session).instrument(span);
+    let reason = panic_guard::catch("socks5", &session_id, pumped)
+        .await
+        .unwrap_or(panic_guard::CLOSE_REASON);
+
+    let record = AccessRecord {
+        session_id: &session_id,
+        kind: "socks5",
+        user: &user.name,
+        client_ip: addr.ip(),
+        country: geoip::country(addr.ip()),
+        target: &target,
+        duration_ms: started.elapsed().as_millis() as u64,
+        bytes_up: guard.session.bytes_up.load(Ordering::Relaxed),
+        bytes_down: guard.session.bytes_down.load(Ordering::Relaxed),
+        close_reason: reason,
+        summary: None,
+    };
+    telemetry::record(&record);
+    state.stats.record_end(&record);
+    if let Some(ref log) = state.access_log {
+        log.record(&record);
+    }
+    state.session_event(&SessionEvent::SessionEnd(&record));
+    Ok(())
+}
+
+/// 认证并读取 CONNECT 请求，检查目标、配额与会话名额；拒绝时已回复客户端
+async fn handshake<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    addr: SocketAddr,
+    state: &AppState,
+    config: &Socks5Config,
+) -> Result<(User, String, SessionSlot)> {
+    let handshake_timeout = Duration::from_secs(config.handshake_timeout_secs);
+    let user = timeout(handshake_timeout, authenticate(stream, addr, state))
+        .await
+        .context("握手超时")??;
+    let target = timeout(handshake_timeout, read_request(stream))
+        .await
+        .context("握手超时")??;
+
+    // 被拒绝时名额立即释放
+    let slot = state.reserve_session(&user);
+    let denied = if !user.allows_target(&target) {
+        Some("目标不在允许列表")
+    } else if state.quota.is_exhausted(&user) {
+        Some("流量配额已用尽")
+    } else if matches!(slot, Err(SlotLimit::Total)) {
+        Some("节点活跃会话数已达上限")
+    } else if matches!(slot, Err(SlotLimit::User)) {
+        Some("活跃会话数已达上限")
+    } else {
+        None
+    };
+    state.audit(&AuditEvent::Auth {
+        client_ip: addr.ip(),
+        user: Some(&user.name),
+        target: &target,
+        result: if denied.is_none() { "ok" } else { "denied" },
+        reason: denied,
+    });
+    match (denied, slot) {
+        (None, Ok(slot)) => Ok((user, target, slot)),
+        (reason, _) => {
+            reply(stream, REP_NOT_ALLOWED).await?;
+            bail!("[{}] {}: {}", user.name, reason.unwrap_or_default(), target);
+        }
+    }
+}
+
+/// 协商认证方式并校验用户名/密码
+async fn authenticate<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    addr: SocketAddr,
+    state: &AppState,
+) -> Result<User> {
+    let [version, n] = read_array(stream).await?;
+    if version != VERSION {
+        bail!("不支持的 SOCKS 版本: {}", version);
+    }
+    let mut methods = vec![0u8; n as usize];
+    stream.read_exact(&mut methods).await?;
+    if !methods.contains(&METHOD_USER_PASS) {
+        stream.write_all(&[VERSION, METHOD_NONE_ACCEPTABLE]).await?;
+        bail!("客户端不支持用户名/密码认证");
+    }
+    stream.write_all(&[VERSION, METHOD_USER_PASS]).await?;
+
+    let [version, len] = read_array(stream).await?;
+    if version != AUTH_VERSION {
+        bail!("不支持的认证子协议版本: {}", version);
+    }
+    let name = read_string(stream, len).await?;
+    let [len] = read_array(stream).await?;
+    let token = read_string(stream, len).await?;
+
+    let creds = Credentials {
+        token: &token,
+        target: "",
+        client_ip: addr.ip(),
+    };
+    let result = match state.auth.authenticate(&creds).await {
+        Ok(user) if user.name != name => Err("用户名与 token 不符".to_string()),
+        Ok(user) if user.suspended => Err("用户已暂停".to_string()),
+        Ok(user) if state.cluster.is_banned(&user.name) => Err("用户已被封禁".to_string()),
+        Ok(user) if state.health.is_user_drained(&user.name) => Err("用户排空中".to_string()),
+        Ok(user) if !user.allows_ip(addr.ip()) => Err("客户端地址不在允许列表".to_string()),
+        Ok(user) if !geoip::allows_user(&user, addr.ip()) => Err("客户端所在国家/地区不允许访问".to_string()),
+        Ok(user) => Ok(user),
+        Err(e) => Err(format!("{:#}", e)),
+    };
+    match result {
+        Ok(user) => {
+            stream.write_all(&[AUTH_VERSION, 0]).await?;
+            Ok(user)
+        }
+        Err(reason) => {
+            state.audit(&AuditEvent::Auth {
+                client_ip: addr.ip(),
+                user: None,
+                target: "",
+                result: "denied",
+                reason: Some(&reason),
+            });
+            stream.write_all(&[AUTH_VERSION, 1]).await?;
+            bail!("认证失败 ({}): {}", addr.ip(), reason);
+        }
+    }
+}
+
+/// 读取 CONNECT 请求，返回 `tcp://host:port`
+async fn read_request<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S) -> Result<String> {
+    let [version, cmd, _, atyp] = read_array(stream).await?;
+    if version != VERSION {
+        bail!("不支持的 SOCKS 版本: {}", version);
+    }
+    if cmd != CMD_CONNECT {
+        reply(stream, REP_COMMAND_NOT_SUPPORTED).await?;
+        bail!("不支持的命令: {}", cmd);
+    }
+    let host = match atyp {
+        ATYP_IPV4 => Ipv4Addr::from(read_array::<4>(stream).await?).to_string(),
+        ATYP_IPV6 => format!("[{}]", Ipv6Addr::from(read_array::<16>(stream).await?)),
+        ATYP_DOMAIN => {
+            let [len] = read_array(stream).await?;
+            read_string(stream, len).await?
+        }
+        _ => {
+            reply(stream, REP_ADDRESS_NOT_SUPPORTED).await?;
+            bail!("不支持的地址类型: {}", atyp);
+        }
+    };
+    let port = u16::from_be_bytes(read_array(stream).await?);
+    Ok(format!("tcp://{}:{}", host, port))
+}
+
+/// 直接或经远端 relay 连接目标
+async fn connect(target: &str, user: &User, state: &AppState, config: &Socks5Config) -> Result<(TargetTx, TargetRx)> {
+    let bind = user.outbound_bind_address.or(state.config.server.outbound_bind_address);
+    let Some(ref peer) = config.peer else {
+        let dial = Dial {
+            sni: None,
+            bind,
+            http2: false,
+            header_rules: &[],
+            headers: None,
+            insecure_hosts: &state.config.server.insecure_tls_hosts,
+        };
+        return ws::open_target(target, &dial).await;
+    };
+
+    let mut headers = HeaderMap::new();
+    headers.insert("x-target-url", HeaderValue::from_str(target)?);
+    if let Some(ref token) = config.peer_token {
+        headers.insert("x-token", HeaderValue::from_str(token.expose())?);
+    }
+    let dial = Dial {
+        sni: None,
+        bind,
+        http2: state.config.server.ws_over_http2,
+        header_rules: &[],
+        headers: Some(&headers),
+        insecure_hosts: &state.config.server.insecure_tls_hosts,
+    };
+    ws::open_target(peer, &dial).await
+}
+
+/// 双向转发，返回结束原因（访问日志 `close_reason`）
+async fn pump(
+    stream: TcpStream,
+    mut target_tx: TargetTx,
+    mut target_rx: TargetRx,
+    state: &AppState,
+    user: &User,
+    session: &SessionStats,
+) -> &'static str {
+    let (mut client_tx, mut client_rx) = ws::tcp_messages(stream);
+    let quota = state.quota.user(user);
+
+    // 客户端 → 目标；客户端关闭写方向后通知目标，继续等待目标的数据
+    let up = async {
+        while let Some(Ok(msg)) = client_rx.next().await {
+            let len = msg.len() as u64;
+            if target_tx.send(msg).await.is_err() {
+                return "target_closed";
+            }
+            session.add_up(len);
+            if !quota.consume_up(len) {
+                return "quota_exceeded";
+            }
+        }
+        let _ = target_tx.send(TungMessage::Close(None)).await;
+        std::future::pending().await
+    };
+
+    // 目标 → 客户端（经 peer 时忽略 text 控制消息）
+    let down = async {
+        while let Some(Ok(msg)) = target_rx.next().await {
+            let msg = match msg {
+                TungMessage::Binary(_) => msg,
+                TungMessage::Close(_) => break,
+                _ => continue,
+            };
+            let len = msg.len() as u64;
+            if client_tx.send(msg).await.is_err() {
+                return "client_closed";
+            }
+            session.add_down(len);
+            if !quota.consume_down(len) {
+                return "quota_exceeded";
+            }
+        }
+        "target_closed"
+    };
+
+    let reason = tokio::select! {
+        r = up => r,
+        r = down => r,
+        _ = session.terminate.notified() => ws::EndReason::from(session.terminate_reason()).as_str(),
+    };
+    if reason != "client_closed" {
+        let _ = client_tx.send(TungMessage::Close(None)).await;
+    }
+    reason
+}
+
+/// SOCKS5 应答（绑定地址填 0.0.0.0:0）
+async fn reply(stream: &mut (impl AsyncWrite + Unpin), rep: u8) -> io::Result<()> {
+    stream.write_all(&[VERSION, rep, 0, ATYP_IPV4, 0, 0, 0, 0, 0, 0]).await
+}
+
+async fn read_array<const N: usize>(stream: &mut (impl AsyncRead + Unpin)) -> io::Result<[u8; N]> {
+    let mut buf = [0u8; N];
+    stream.read_exact(&mut buf).await?;
+    Ok(buf)
+}
+
+async fn read_string(stream: &mut (impl AsyncRead + Unpin), len: u8) -> Result<String> {
+    let mut buf = vec![0u8; len as usize];
+    stream.read_exact(&mut buf).await?;
+    String::from_utf8(buf).context("无效的 UTF-8")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{duplex, DuplexStream};
+
+    fn state() -> AppState {
+        let config: crate::config::Config = toml::from_str(
+            r#"
+            [server]
+            host = "0.0.0.0"
+            port = 443
+            tls_cert = "c"
+            tls_key = "k"
+
+            [quota]
+            state_file = "/nonexistent/quota.json"
+
+            [[users]]
+            name = "alice"
+            token = "alice_token"
+            allowed_targets = ["tcp://example.com:*", "tcp://10.0.0.1:*", "tcp://[::1]:*"]
+            "#,
+        )
+        .unwrap();
+        AppState::new(config).unwrap()
+    }
+
+    fn socks5_config() -> Socks5Config {
+        toml::from_str("listen = \"127.0.0.1:1080\"").unwrap()
+    }
+
+    type Handshake = tokio::task::JoinHandle<Result<(User, String, SessionSlot)>>;
+
+    /// 在后台执行握手，返回客户端一端
+    fn start(state: AppState) -> (DuplexStream, Handshake) {
+        let (client, mut server) = duplex(1024);
+        let addr: SocketAddr = "127.0.0.1:50000".parse().unwrap();
+        let handle = tokio::spawn(async move { handshake(&mut server, addr, &state, &socks5_config()).await });
+        (client, handle)
+    }
+
+    /// 客户端：问候与用户名/密码子协商，返回认证结果
+    async fn login(client: &mut DuplexStream, name: &str, token: &str) -> u8 {
+        client.write_all(&[VERSION, 2, 0, METHOD_USER_PASS]).await.unwrap();
+        assert_eq!(read_array::<2>(client).await.unwrap(), [VERSION, METHOD_USER_PASS]);
+        let mut auth = vec![AUTH_VERSION, name.len() as u8];
+        auth.extend_from_slice(name.as_bytes());
+        auth.push(token.len() as u8);
+        auth.extend_from_slice(token.as_bytes());
+        client.write_all(&auth).await.unwrap();
+        let [version, status] = read_array(client).await.unwrap();
+        assert_eq!(version, AUTH_VERSION);
+        status
+    }
+
+    fn connect_request(cmd: u8, atyp: u8, addr: &[u8], port: u16) -> Vec<u8> {
+        let mut req = vec![VERSION, cmd, 0, atyp];
+        req.extend_from_slice(addr);
+        req.extend_from_slice(&port.to_be_bytes());
+        req
+    }
+
+    fn domain(name: &str) -> Vec<u8> {
+        let mut addr = vec![name.len() as u8];
+        addr.extend_from_slice(name.as_bytes());
+        addr
+    }
+
+    #[tokio::test]
+    async fn connect_succeeds() {
+        let state = state();
+        let (mut client, handle) = start(state.clone());
+        assert_eq!(login(&mut client, "alice", "alice_token").await, 0);
+        client.write_all(&connect_request(CMD_CONNECT, ATYP_DOMAIN, &domain("example.com"), 443)).await.unwrap();
+        let (user, target, slot) = handle.await.unwrap().unwrap();
+        assert_eq!(user.name, "alice");
+        assert_eq!(target, "tcp://example.com:443");
+        assert!(state.stats.reserve("alice", Some(1), None, 0).is_err());
+        drop(slot);
+    }
+
+    #[tokio::test]
+    async fn parses_ip_addresses() {
+        let (mut client, mut server) = duplex(1024);
+        client.write_all(&connect_request(CMD_CONNECT, ATYP_IPV4, &[10, 0, 0, 1], 8080)).await.unwrap();
+        assert_eq!(read_request(&mut server).await.unwrap(), "tcp://10.0.0.1:8080");
+        let ipv6 = Ipv6Addr::LOCALHOST.octets();
+        client.write_all(&connect_request(CMD_CONNECT, ATYP_IPV6, &ipv6, 22)).await.unwrap();
+        assert_eq!(read_request(&mut server).await.unwrap(), "tcp://[::1]:22");
+
+        client.write_all(&connect_request(CMD_CONNECT, 9, &[], 0)).await.unwrap();
+        assert!(read_request(&mut server).await.is_err());
+        assert_eq!(read_array::<2>(&mut client).await.unwrap(), [VERSION, REP_ADDRESS_NOT_SUPPORTED]);
+    }
+
+    #[tokio::test]
+    async fn bad_token_is_rejected() {
+        let (mut client, handle) = start(state());
+        assert_eq!(login(&mut client, "alice", "wrong").await, 1);
+        assert!(handle.await.unwrap().is_err());
+
+        // 用户名与 token 不符
+        let (mut client, handle) = start(state());
+        assert_eq!(login(&mut client, "bob", "alice_token").await, 1);
+        assert!(handle.await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn no_acceptable_method() {
+        let (mut client, handle) = start(state());
+        client.write_all(&[VERSION, 1, 0]).await.unwrap();
+        assert_eq!(read_array::<2>(&mut client).await.unwrap(), [VERSION, METHOD_NONE_ACCEPTABLE]);
+        assert!(handle.await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn disallowed_target_is_refused() {
+        let state = state();
+        let (mut client, handle) = start(state.clone());
+        assert_eq!(login(&mut client, "alice", "alice_token").await, 0);
+        client.write_all(&connect_request(CMD_CONNECT, ATYP_DOMAIN, &domain("evil.net"), 443)).await.unwrap();
+        assert!(handle.await.unwrap().is_err());
+        assert_eq!(read_array::<2>(&mut client).await.unwrap(), [VERSION, REP_NOT_ALLOWED]);
+        // 名额已释放
+        assert!(state.stats.reserve("alice", Some(1), None, 0).is_ok());
+    }
+
+    #[tokio::test]
+    async fn unsupported_command_is_refused() {
+        const CMD_BIND: u8 = 2;
+        let (mut client, handle) = start(state());
+        assert_eq!(login(&mut client, "alice", "alice_token").await, 0);
+        client.write_all(&connect_request(CMD_BIND, ATYP_DOMAIN, &domain("example.com"), 443)).await.unwrap();
+        assert!(handle.await.unwrap().is_err());
+        assert_eq!(read_array::<2>(&mut client).await.unwrap(), [VERSION, REP_COMMAND_NOT_SUPPORTED]);
+    }
+}
@@ -0,0 +1,236 @@
+//! 共享状态
+
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use tracing::{info, warn};
+
+use crate::{
+    access_log::AccessLog,
+    agent::Registry,
+    audit::{AuditEvent, AuditLog},
+    auth::AuthState, cache::RestCache, cluster::Cluster, config::{Config, Overrides, User},
+    config_diff::{self, ReloadSummary}, cookie_jar::CookieJar, error_reporting::ErrorReporter,
+    health::Health, host_limits::HostLimits, pubsub::Hub, quota::QuotaTracker, resources, scripting::Scripts,
+    session_webhook::{SessionEvent, SessionWebhook}, stats::{SessionSlot, SlotLimit, Stats, TerminateReason}, user_db::UserDb,
+};
+
+/// 路由共享状态
+#[derive(Clone)]
+pub struct AppState {
+    pub config: Arc<Config>,
+    pub auth: AuthState,
+    pub user_db: Option<Arc<UserDb>>,
+    pub quota: Arc<QuotaTracker>,
+    pub access_log: Option<Arc<AccessLog>>,
+    pub audit: Option<Arc<AuditLog>>,
+    /// 会话事件推送（`[session_webhook]`）
+    pub session_webhook: Option<Arc<SessionWebhook>>,
+    /// 错误上报（`[error_reporting]`）
+    pub error_reporter: Option<Arc<ErrorReporter>>,
+    pub stats: Arc<Stats>,
+    pub cache: Option<Arc<RestCache>>,
+    pub cookie_jar: Option<Arc<CookieJar>>,
+    pub health: Arc<Health>,
+    /// 目标主机限流（`[[host_limits]]`）
+    pub host_limits: Arc<HostLimits>,
+    /// 已注册的反向隧道 agent
+    pub agents: Arc<Registry>,
+    /// 发布/订阅频道（`[pubsub]`）
+    pub pubsub: Option<Arc<Hub>>,
+    /// 脚本钩子（`[[scripts]]`）
+    pub scripts: Option<Arc<Scripts>>,
+    /// 集群共享的会话数、配额用量与封禁列表（`[cluster]`）
+    pub cluster: Arc<Cluster>,
+    /// 节点活跃会话总数上限（`server.max_total_sessions`）
+    pub session_cap: Option<usize>,
+    /// 最近一次加载的配置（`users` 为当时生效的用户），重新加载时用于比较差异
+    loaded: Arc<Mutex<Config>>,
+    /// 命令行覆盖，重新加载时同样应用
+    overrides: Overrides,
+}
+
+impl AppState {
+    pub fn new(config: Config) -> Result<Self> {
+        let user_db = match config.users_db {
+            Some(ref path) => Some(Arc::new(UserDb::open(path)?)),
+            None => None,
+        };
+
+        let users = match user_db {
+            Some(ref db) => {
+                let users = db.load_all()?;
+                if users.is_empty() && !config.users.is_empty() {
+                    // 首次启动：导入配置文件中的用户
+                    for u in &config.users {
+                        db.insert(u)?;
+                    }
+                    info!("已导入 {} 个用户到用户库", config.users.len());
+                    db.load_all()?
+                } else {
+                    users
+                }
+            }
+            None => config.users.clone(),
+        };
+
+        info!(
+            "用户: {}",
+            users.iter().map(|u| u.name.as_str()).collect::<Vec<_>>().join(", ")
+        );
+
+        let quota = Arc::new(QuotaTracker::load(&config.quota));
+        let access_log = match config.access_log {
+            Some(ref c) => Some(Arc::new(AccessLog::new(c)?)),
+            None => None,
+        };
+        let audit = match config.audit_log {
+            Some(ref c) => Some(Arc::new(AuditLog::open(c)?)),
+            None => None,
+        };
+        let session_webhook = match config.session_webhook {
+            Some(ref c) => Some(Arc::new(SessionWebhook::new(c)?)),
+            None => None,
+        };
+        let error_reporter = match config.error_reporting {
+            Some(ref c) => Some(Arc::new(ErrorReporter::new(c)?)),
+            None => None,
+        };
+        let cache = match config.rest.cache {
+            Some(ref c) => Some(Arc::new(RestCache::new(c)?)),
+            None => None,
+        };
+        let pubsub = config.pubsub.as_ref().map(|c| Arc::new(Hub::new(c)));
+        let scripts = match config.scripts.is_empty() {
+            true => None,
+            false => Some(Arc::new(Scripts::load(&config.scripts)?)),
+        };
+        let host_limits = Arc::new(HostLimits::new(&config.host_limits));
+        let cluster = Arc::new(Cluster::new(&config)?);
+        let cookie_jar = config.rest.cookie_jar.as_ref().map(|c| Arc::new(CookieJar::new(c)));
+        let session_cap = resources::session_cap(&config.server);
+        let loaded = Config {
+            users: users.clone(),
+            ..config.clone()
+        };
+        Ok(Self {
+            auth: AuthState::new(&config, &users)?,
+            config: Arc::new(config),
+            user_db,
+            quota,
+            access_log,
+            audit,
+            session_webhook,
+            error_reporter,
+            stats: Arc::default(),
+            cache,
+            cookie_jar,
+            health: Arc::default(),
+            host_limits,
+            agents: Arc::default(),
+            pubsub,
+            scripts,
+            cluster,
+            session_cap,
+            loaded: Arc::new(Mutex::new(loaded)),
+            overrides: Overrides::default(),
+        })
+    }
+
+    /// 设置命令行覆盖（`config` 应已应用同样的覆盖）
+    pub fn with_overrides(mut self, overrides: Overrides) -> Self {
+        self.overrides = overrides;
+        self
+    }
+
+    /// 从用户库重新加载认证表
+    pub fn reload_users(&self) -> Result<()> {
+        if let Some(ref db) = self.user_db {
+            let users = db.load_all()?;
+            self.auth.replace(&users);
+            self.terminate_suspended(&users);
+        }
+        Ok(())
+    }
+
+    /// 按 `server.terminate_suspended_sessions` 终止已暂停用户的活跃会话
+    fn terminate_suspended(&self, users: &[User]) {
+        if !self.config.server.terminate_suspended_sessions {
+            return;
+        }
+        for user in users.iter().filter(|u| u.suspended) {
+            let n = self.stats.terminate_user(&user.name, TerminateReason::Suspended);
+            if n > 0 {
+                warn!("[{}] 用户已暂停，终止 {} 个活跃会话", user.name, n);
+            }
+        }
+    }
+
+    /// 占用一个会话名额：节点总数 `server.max_total_sessions` 与用户的 `max_sessions`（集群时为所有节点合计）
+    pub fn reserve_session(&self, user: &User) -> Result<SessionSlot, SlotLimit> {
+        let remote = user.max_sessions.map_or(0, |_| self.cluster.remote_sessions(&user.name));
+        self.stats.reserve(&user.name, self.session_cap, user.max_sessions, remote)
+    }
+
+    /// 写入审计日志（如配置）
+    pub fn audit(&self, event: &AuditEvent) {
+        if let Some(ref audit) = self.audit {
+            audit.record(event);
+        }
+    }
+
+    /// 推送会话事件（如配置）
+    pub fn session_event(&self, event: &SessionEvent) {
+        if let Some(ref hook) = self.session_webhook {
+            hook.send(event);
+        }
+    }
+
+    /// 连接目标失败，交给错误上报（如配置）判断是否上报
+    pub fn report_connect_failure(&self, user: &str, target: &str, err: &(dyn std::error::Error + 'static)) {
+        if let Some(ref reporter) = self.error_reporter {
+            reporter.connect_failed(user, target, err);
+        }
+    }
+
+    /// 重新加载：校验配置文件，用户有用户库时从库加载，否则使用配置文件中的 `[[users]]`
+    ///
+    /// 返回与上次加载相比的差异，用户即时生效，其余变化需重启。`source` 为触发方式，记入审计日志。
+    /// 解析配置文件与读取用户库是同步 IO，在阻塞线程池中执行。
+    pub async fn reload(&self, config_path: &str, source: &str) -> Result<ReloadSummary> {
+        let state = self.clone();
+        let config_path = config_path.to_string();
+        let result = tokio::task::spawn_blocking(move || state.load(&config_path))
+            .await
+            .unwrap_or_else(|e| Err(anyhow::anyhow!("重新加载任务异常退出: {}", e)));
+        let (status, detail) = match result {
+            Ok(ref summary) => ("ok", summary.to_string()),
+            Err(ref e) => ("error", format!("{:#}", e)),
+        };
+        self.audit(&AuditEvent::Reload {
+            source,
+            result: status,
+            detail: &detail,
+        });
+        result
+    }
+
+    fn load(&self, config_path: &str) -> Result<ReloadSummary> {
+        let mut config = Config::load(config_path)?;
+        self.overrides.apply(&mut config);
+        if let Some(ref db) = self.user_db {
+            config.users = db.load_all()?;
+        }
+        self.auth.replace(&config.users);
+        self.terminate_suspended(&config.users);
+
+        let mut loaded = self.loaded.lock().unwrap();
+        let summary = config_diff::diff(&loaded, &config);
+        info!("已重新加载 {} 个用户: {}", config.users.len(), summary);
+        if !summary.restart_required.is_empty() {
+            warn!("以下配置需重启才能生效: {}", summary.restart_required.join(", "));
+        }
+        *loaded = config;
+        Ok(summary)
+    }
+}
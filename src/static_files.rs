@@ -0,0 +1,103 @@
+//! 静态文件
+//!
+//! 配置 `server.static_dir` 后，未匹配其他路由的 GET / HEAD 请求从该目录读取文件，
+//! 目录请求返回其中的 `index.html`。不需要认证，路径中的 `..` 一律拒绝。
+
+use std::path::PathBuf;
+
+use axum::{
+    extract::State,
+    http::{header, Method, StatusCode, Uri},
+    response::{IntoResponse, Response},
+};
+use percent_encoding::percent_decode_str;
+
+use crate::{error, state::AppState};
+
+/// 按扩展名的 Content-Type
+const CONTENT_TYPES: &[(&str, &str)] = &[
+    ("html", "text/html; charset=utf-8"),
+    ("htm", "text/html; charset=utf-8"),
+    ("css", "text/css; charset=utf-8"),
+    ("js", "text/javascript; charset=utf-8"),
+    ("mjs", "text/javascript; charset=utf-8"),
+    ("json", "application/json"),
+    ("map", "application/json"),
+    ("txt", "text/plain; charset=utf-8"),
+    ("svg", "image/svg+xml"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("ico", "image/x-icon"),
+    ("wasm", "application/wasm"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+];
+
+/// 路由兜底处理器
+pub async fn handler(State(state): State<AppState>, method: Method, uri: Uri) -> Response {
+    let Some(ref dir) = state.config.server.static_dir else {
+        return not_found();
+    };
+    if method != Method::GET && method != Method::HEAD {
+        return StatusCode::METHOD_NOT_ALLOWED.into_response();
+    }
+    let Some(mut path) = resolve(dir, uri.path()) else {
+        return not_found();
+    };
+    if tokio::fs::metadata(&path).await.is_ok_and(|m| m.is_dir()) {
+        path.push("index.html");
+    }
+
+    let Ok(data) = tokio::fs::read(&path).await else {
+        return not_found();
+    };
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or_default().to_ascii_lowercase();
+    let content_type = CONTENT_TYPES
+        .iter()
+        .find(|(e, _)| *e == ext)
+        .map(|(_, t)| *t)
+        .unwrap_or("application/octet-stream");
+    ([(header::CONTENT_TYPE, content_type)], data).into_response()
+}
+
+/// 请求路径（percent-encoded）对应 `dir` 下的文件，含 `..` 或反斜杠的路径返回 None
+fn resolve(dir: &str, uri_path: &str) -> Option<PathBuf> {
+    let decoded = percent_decode_str(uri_path).decode_utf8().ok()?;
+    let mut path = PathBuf::from(dir);
+    for segment in decoded.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => return None,
+            s if s.contains('\\') => return None,
+            s => path.push(s),
+        }
+    }
+    Some(path)
+}
+
+fn not_found() -> Response {
+    error::response(StatusCode::NOT_FOUND, "NOT_FOUND", "未找到")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_rejects_traversal() {
+        assert_eq!(resolve("www", "/"), Some(PathBuf::from("www")));
+        assert_eq!(resolve("www", "/a/./b.js"), Some(PathBuf::from("www/a/b.js")));
+        assert_eq!(resolve("www", "//a//b"), Some(PathBuf::from("www/a/b")));
+        assert_eq!(resolve("www", "/%E4%B8%AD.html"), Some(PathBuf::from("www/中.html")));
+        assert_eq!(resolve("www", "/../etc/passwd"), None);
+        assert_eq!(resolve("www", "/a/../../etc/passwd"), None);
+        assert_eq!(resolve("www", "/%2e%2e/etc/passwd"), None);
+        assert_eq!(resolve("www", "/a%2f..%2f..%2fetc"), None);
+        assert_eq!(resolve("www", "/..%5c..%5cwindows"), None);
+        assert_eq!(resolve("www", "/a\\b"), None);
+        assert_eq!(resolve("www", "/%ff"), None);
+    }
+}
@@ -0,0 +1,507 @@
+//! 流量统计
+//!
+//! - 每个用户的 WS 消息大小分布（text / binary 分开）
+//! - 活跃会话的实时字节数，用于按吞吐排序的 top talkers
+//! - 会话结束时的统计摘要（各方向消息数、消息速率），写入运行日志、访问日志与会话事件
+//! - 最近 [`RECENT_ERRORS`] 个异常结束的会话 / 请求，供 `status` 子命令显示
+//! - 会话名额：节点总数与每用户上限的检查和占用在同一把锁内完成（[`Stats::reserve`]），
+//!   名额从握手前占用到会话结束，并发握手不会超出上限
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+
+use chrono::{SecondsFormat, Utc};
+use once_cell::sync::OnceCell;
+use serde::Serialize;
+use tokio::sync::Notify;
+use tokio_tungstenite::tungstenite::Message as TungMessage;
+
+use crate::access_log::AccessRecord;
+
+/// 保留的最近异常数
+pub const RECENT_ERRORS: usize = 50;
+
+/// 消息大小分桶上界（字节），最后一桶为更大的消息
+const BUCKETS: [u64; 8] = [64, 256, 1024, 4096, 16384, 65536, 262144, 1048576];
+
+/// 消息大小直方图
+#[derive(Default)]
+pub struct Histogram {
+    counts: [AtomicU64; BUCKETS.len() + 1],
+    total: AtomicU64,
+    bytes: AtomicU64,
+}
+
+impl Histogram {
+    fn record(&self, size: u64) {
+        let idx = BUCKETS.iter().position(|&b| size <= b).unwrap_or(BUCKETS.len());
+        self.counts[idx].fetch_add(1, Ordering::Relaxed);
+        self.total.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(size, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> HistogramSnapshot {
+        let buckets = self
+            .counts
+            .iter()
+            .enumerate()
+            .map(|(i, c)| Bucket {
+                le: BUCKETS.get(i).copied(),
+                count: c.load(Ordering::Relaxed),
+            })
+            .collect();
+        HistogramSnapshot {
+            count: self.total.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            buckets,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Bucket {
+    /// 桶上界，null 表示大于最后一个上界
+    le: Option<u64>,
+    count: u64,
+}
+
+#[derive(Serialize)]
+pub struct HistogramSnapshot {
+    count: u64,
+    bytes: u64,
+    buckets: Vec<Bucket>,
+}
+
+/// 单个用户的消息统计
+#[derive(Default)]
+pub struct UserStats {
+    text: Histogram,
+    binary: Histogram,
+}
+
+impl UserStats {
+    /// 记录一条已转发的消息（控制帧不计）
+    pub fn record(&self, msg: &TungMessage) {
+        match msg {
+            TungMessage::Text(t) => self.text.record(t.len() as u64),
+            TungMessage::Binary(b) => self.binary.record(b.len() as u64),
+            _ => {}
+        }
+    }
+}
+
+/// 活跃会话
+pub struct SessionStats {
+    pub id: String,
+    pub user: String,
+    pub target: String,
+    /// 客户端标识（`/ws` 的 `X-Client-Key`），用于重复会话检测
+    pub client_key: Option<String>,
+    started: Instant,
+    pub bytes_up: AtomicU64,
+    pub bytes_down: AtomicU64,
+    frames_up: AtomicU64,
+    frames_down: AtomicU64,
+    /// 当前一秒的消息数：高 32 位为会话开始后的秒数，低 32 位为计数
+    rate_window: AtomicU64,
+    /// 单秒消息数（双向合计）的峰值
+    rate_peak: AtomicU64,
+    /// relay 主动终止会话（原因见 `reason`）
+    pub terminate: Notify,
+    reason: OnceCell<TerminateReason>,
+}
+
+/// relay 主动终止会话的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TerminateReason {
+    /// 用户已过期
+    Expired,
+    /// 同一客户端建立了新会话
+    Replaced,
+    /// 用户已暂停
+    Suspended,
+    /// 用户被封禁（管理 API）
+    Banned,
+    /// 排空期限已到（管理 API）
+    Drained,
+    /// 内存用量超出 `resources.max_rss_mb`
+    Overloaded,
+}
+
+/// 会话结束时的统计摘要（时长与字节数见访问记录）
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionSummary {
+    pub frames_up: u64,
+    pub frames_down: u64,
+    /// 平均每秒消息数（双向合计）
+    pub msgs_per_sec_avg: f64,
+    /// 单秒消息数峰值（双向合计）
+    pub msgs_per_sec_peak: u64,
+    /// 关闭发起方：client / target / relay
+    pub close_initiator: &'static str,
+}
+
+impl SessionStats {
+    /// 记录一条客户端 → 目标的消息
+    pub fn add_up(&self, len: u64) {
+        self.bytes_up.fetch_add(len, Ordering::Relaxed);
+        self.frames_up.fetch_add(1, Ordering::Relaxed);
+        self.count_frame();
+    }
+
+    /// 记录一条目标 → 客户端的消息
+    pub fn add_down(&self, len: u64) {
+        self.bytes_down.fetch_add(len, Ordering::Relaxed);
+        self.frames_down.fetch_add(1, Ordering::Relaxed);
+        self.count_frame();
+    }
+
+    fn count_frame(&self) {
+        let sec = self.started.elapsed().as_secs() << 32;
+        let mut window = self.rate_window.load(Ordering::Relaxed);
+        loop {
+            let next = match window & !0xffff_ffff == sec {
+                true => window + 1,
+                false => sec | 1,
+            };
+            match self.rate_window.compare_exchange_weak(window, next, Ordering::Relaxed, Ordering::Relaxed) {
+                Ok(_) => {
+                    self.rate_peak.fetch_max(next & 0xffff_ffff, Ordering::Relaxed);
+                    return;
+                }
+                Err(current) => window = current,
+            }
+        }
+    }
+
+    pub fn summary(&self, close_initiator: &'static str) -> SessionSummary {
+        let frames_up = self.frames_up.load(Ordering::Relaxed);
+        let frames_down = self.frames_down.load(Ordering::Relaxed);
+        let avg = (frames_up + frames_down) as f64 / self.started.elapsed().as_secs_f64().max(1.0);
+        SessionSummary {
+            frames_up,
+            frames_down,
+            msgs_per_sec_avg: (avg * 100.0).round() / 100.0,
+            msgs_per_sec_peak: self.rate_peak.load(Ordering::Relaxed),
+            close_initiator,
+        }
+    }
+
+    fn terminate(&self, reason: TerminateReason) {
+        let _ = self.reason.set(reason);
+        self.terminate.notify_one();
+    }
+
+    /// `terminate` 通知后读取
+    pub fn terminate_reason(&self) -> TerminateReason {
+        self.reason.get().copied().unwrap_or(TerminateReason::Expired)
+    }
+}
+
+#[derive(Serialize)]
+pub struct TopTalker {
+    session_id: String,
+    user: String,
+    target: String,
+    duration_secs: u64,
+    bytes_up: u64,
+    bytes_down: u64,
+    /// 平均吞吐（字节/秒，双向合计）
+    bytes_per_sec: u64,
+}
+
+/// 异常结束的会话（WS 类会话非正常关闭、REST 响应 5xx）
+#[derive(Clone, Serialize)]
+pub struct RecentError {
+    ts: String,
+    session_id: String,
+    kind: &'static str,
+    user: String,
+    target: String,
+    /// 访问日志的 `close_reason`
+    reason: String,
+}
+
+#[derive(Default)]
+pub struct Stats {
+    users: Mutex<HashMap<String, Arc<UserStats>>>,
+    sessions: Mutex<HashMap<String, Arc<SessionStats>>>,
+    errors: Mutex<VecDeque<RecentError>>,
+    slots: Mutex<Slots>,
+}
+
+/// 已占用的会话名额
+#[derive(Default)]
+struct Slots {
+    total: usize,
+    users: HashMap<String, usize>,
+}
+
+/// 会话名额已满
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlotLimit {
+    /// 节点总数（`server.max_total_sessions`）
+    Total,
+    /// 用户的 `max_sessions`
+    User,
+}
+
+impl Stats {
+    pub fn user(&self, name: &str) -> Arc<UserStats> {
+        self.users
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_default()
+            .clone()
+    }
+
+    /// 检查并占用一个会话名额，`remote` 为其他节点上该用户的会话数；名额随返回的 guard 释放
+    pub fn reserve(
+        self: &Arc<Self>,
+        user: &str,
+        max_total: Option<usize>,
+        max_user: Option<usize>,
+        remote: usize,
+    ) -> Result<SessionSlot, SlotLimit> {
+        let mut slots = self.slots.lock().unwrap();
+        if max_total.is_some_and(|max| slots.total >= max) {
+            return Err(SlotLimit::Total);
+        }
+        let used = slots.users.get(user).copied().unwrap_or_default();
+        if max_user.is_some_and(|max| used + remote >= max) {
+            return Err(SlotLimit::User);
+        }
+        slots.total += 1;
+        *slots.users.entry(user.to_string()).or_default() += 1;
+        Ok(SessionSlot {
+            stats: self.clone(),
+            user: user.to_string(),
+        })
+    }
+
+    /// 登记活跃会话，guard 释放时移除
+    pub fn open_session(self: &Arc<Self>, id: &str, user: &str, target: &str) -> SessionGuard {
+        self.open_client_session(id, user, target, None)
+    }
+
+    /// 同上，带客户端标识
+    pub fn open_client_session(
+        self: &Arc<Self>,
+        id: &str,
+        user: &str,
+        target: &str,
+        client_key: Option<&str>,
+    ) -> SessionGuard {
+        let session = Arc::new(SessionStats {
+            id: id.to_string(),
+            user: user.to_string(),
+            target: target.to_string(),
+            client_key: client_key.map(str::to_string),
+            started: Instant::now(),
+            bytes_up: AtomicU64::new(0),
+            bytes_down: AtomicU64::new(0),
+            frames_up: AtomicU64::new(0),
+            frames_down: AtomicU64::new(0),
+            rate_window: AtomicU64::new(0),
+            rate_peak: AtomicU64::new(0),
+            terminate: Notify::new(),
+            reason: OnceCell::new(),
+        });
+        self.sessions
+            .lock()
+            .unwrap()
+            .insert(id.to_string(), session.clone());
+        SessionGuard {
+            stats: self.clone(),
+            session,
+        }
+    }
+
+    /// 终止用户的全部活跃会话，返回会话数
+    pub fn terminate_user(&self, user: &str, reason: TerminateReason) -> usize {
+        let sessions = self.sessions.lock().unwrap();
+        let matched = sessions.values().filter(|s| s.user == user);
+        matched.map(|s| s.terminate(reason)).count()
+    }
+
+    /// 终止全部活跃会话，返回会话数
+    pub fn terminate_all(&self, reason: TerminateReason) -> usize {
+        let sessions = self.sessions.lock().unwrap();
+        sessions.values().map(|s| s.terminate(reason)).count()
+    }
+
+    /// 终止平均吞吐最高的 `n` 个会话（已在终止中的除外），返回会话数
+    pub fn terminate_heaviest(&self, n: usize, reason: TerminateReason) -> usize {
+        let sessions = self.sessions.lock().unwrap();
+        let mut candidates: Vec<&Arc<SessionStats>> = sessions.values().filter(|s| s.reason.get().is_none()).collect();
+        candidates.sort_by_key(|s| {
+            let bytes = s.bytes_up.load(Ordering::Relaxed) + s.bytes_down.load(Ordering::Relaxed);
+            std::cmp::Reverse((bytes as f64 / s.started.elapsed().as_secs_f64().max(1.0)) as u64)
+        });
+        candidates.into_iter().take(n).map(|s| s.terminate(reason)).count()
+    }
+
+    /// 活跃会话总数
+    pub fn session_count(&self) -> usize {
+        self.sessions.lock().unwrap().len()
+    }
+
+    /// 用户的活跃会话数
+    pub fn user_sessions(&self, user: &str) -> usize {
+        self.sessions.lock().unwrap().values().filter(|s| s.user == user).count()
+    }
+
+    /// 每个用户的活跃会话数
+    pub fn sessions_by_user(&self) -> HashMap<String, usize> {
+        let mut counts = HashMap::new();
+        for s in self.sessions.lock().unwrap().values() {
+            *counts.entry(s.user.clone()).or_default() += 1;
+        }
+        counts
+    }
+
+    /// 用户以该客户端标识建立的活跃会话数
+    pub fn client_sessions(&self, user: &str, client_key: &str) -> usize {
+        let sessions = self.sessions.lock().unwrap();
+        let same = |s: &&Arc<SessionStats>| s.user == user && s.client_key.as_deref() == Some(client_key);
+        sessions.values().filter(same).count()
+    }
+
+    /// 终止用户以该客户端标识建立的活跃会话（被新会话取代），返回会话数
+    pub fn replace_client_sessions(&self, user: &str, client_key: &str) -> usize {
+        let sessions = self.sessions.lock().unwrap();
+        let same = |s: &&Arc<SessionStats>| s.user == user && s.client_key.as_deref() == Some(client_key);
+        sessions
+            .values()
+            .filter(same)
+            .map(|s| s.terminate(TerminateReason::Replaced))
+            .count()
+    }
+
+    /// 每个用户的 text / binary 消息大小分布
+    pub fn message_sizes(&self) -> HashMap<String, HashMap<&'static str, HistogramSnapshot>> {
+        self.users
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, s)| {
+                let h = HashMap::from([("text", s.text.snapshot()), ("binary", s.binary.snapshot())]);
+                (name.clone(), h)
+            })
+            .collect()
+    }
+
+    /// 会话 / 请求结束，异常时记入最近异常
+    pub fn record_end(&self, record: &AccessRecord) {
+        let failed = match record.kind {
+            "rest" => record.close_reason.parse::<u16>().is_ok_and(|status| status >= 500),
+            _ => !matches!(record.close_reason, "client_closed" | "target_closed" | "switch"),
+        };
+        if !failed {
+            return;
+        }
+        let mut errors = self.errors.lock().unwrap();
+        if errors.len() >= RECENT_ERRORS {
+            errors.pop_back();
+        }
+        errors.push_front(RecentError {
+            ts: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            session_id: record.session_id.to_string(),
+            kind: record.kind,
+            user: record.user.to_string(),
+            target: record.target.to_string(),
+            reason: record.close_reason.to_string(),
+        });
+    }
+
+    /// 最近的异常，新的在前
+    pub fn recent_errors(&self) -> Vec<RecentError> {
+        self.errors.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// 按平均吞吐降序排列的活跃会话
+    pub fn top_talkers(&self, limit: usize) -> Vec<TopTalker> {
+        let mut talkers: Vec<TopTalker> = self
+            .sessions
+            .lock()
+            .unwrap()
+            .values()
+            .map(|s| {
+                let elapsed = s.started.elapsed();
+                let up = s.bytes_up.load(Ordering::Relaxed);
+                let down = s.bytes_down.load(Ordering::Relaxed);
+                TopTalker {
+                    session_id: s.id.clone(),
+                    user: s.user.clone(),
+                    target: s.target.clone(),
+                    duration_secs: elapsed.as_secs(),
+                    bytes_up: up,
+                    bytes_down: down,
+                    bytes_per_sec: ((up + down) as f64 / elapsed.as_secs_f64().max(1.0)) as u64,
+                }
+            })
+            .collect();
+        talkers.sort_by_key(|t| std::cmp::Reverse(t.bytes_per_sec));
+        talkers.truncate(limit);
+        talkers
+    }
+}
+
+/// 活跃会话登记，释放时从统计中移除
+pub struct SessionGuard {
+    stats: Arc<Stats>,
+    pub session: Arc<SessionStats>,
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        self.stats.sessions.lock().unwrap().remove(&self.session.id);
+    }
+}
+
+/// 已占用的会话名额，握手失败或会话结束时释放
+pub struct SessionSlot {
+    stats: Arc<Stats>,
+    user: String,
+}
+
+impl Drop for SessionSlot {
+    fn drop(&mut self) {
+        let mut slots = self.stats.slots.lock().unwrap();
+        slots.total -= 1;
+        if let Some(n) = slots.users.get_mut(&self.user) {
+            *n -= 1;
+            if *n == 0 {
+                slots.users.remove(&self.user);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slots_are_held_until_dropped() {
+        let stats = Arc::new(Stats::default());
+        let alice = stats.reserve("alice", Some(2), Some(1), 0).unwrap();
+        assert_eq!(stats.reserve("alice", Some(2), Some(1), 0).err(), Some(SlotLimit::User));
+        let bob = stats.reserve("bob", Some(2), Some(2), 1).unwrap();
+        assert_eq!(stats.reserve("bob", None, Some(2), 1).err(), Some(SlotLimit::User));
+        assert_eq!(stats.reserve("carol", Some(2), None, 0).err(), Some(SlotLimit::Total));
+
+        drop(alice);
+        assert!(stats.reserve("carol", Some(2), None, 0).is_ok());
+        assert!(stats.reserve("alice", Some(2), Some(1), 0).is_ok());
+        drop(bob);
+        assert!(stats.slots.lock().unwrap().users.is_empty());
+    }
+}
@@ -0,0 +1,208 @@
+//! 终端状态视图（`status` 子命令）
+//!
+//! 定期请求管理 API（`/admin/stats/top`、`/admin/stats/errors`），在终端刷新显示：
+//!
+//! - 活跃会话数与当前上行 / 下行吞吐（按两次刷新之间的字节数计算）
+//! - 当前吞吐最高的会话
+//! - 最近异常结束的会话与 5xx 请求
+//!
+//! 配置了 `[control]` 时同时显示控制通道的 `status`（pid、运行时长、用户数）。`--once` 只输出一次，便于脚本使用。
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use reqwest::Client;
+use serde::Deserialize;
+use tokio::time::{interval, MissedTickBehavior};
+
+use crate::{config::Config, control};
+
+/// 显示的会话数
+const MAX_SESSIONS: usize = 15;
+
+/// 显示的异常数
+const MAX_ERRORS: usize = 10;
+
+/// 目标 URL 显示宽度
+const TARGET_WIDTH: usize = 60;
+
+/// 状态视图参数
+pub struct Options {
+    /// relay 地址，如 https://relay.example.com
+    pub url: String,
+    pub token: String,
+    /// 刷新间隔
+    pub interval: Duration,
+    /// 不校验 relay 的证书（自签名证书）
+    pub insecure: bool,
+    /// 只输出一次
+    pub once: bool,
+}
+
+#[derive(Deserialize)]
+struct Session {
+    session_id: String,
+    user: String,
+    target: String,
+    duration_secs: u64,
+    bytes_up: u64,
+    bytes_down: u64,
+    bytes_per_sec: u64,
+}
+
+#[derive(Deserialize)]
+struct RecentError {
+    ts: String,
+    kind: String,
+    user: String,
+    target: String,
+    reason: String,
+}
+
+/// 上一次刷新时各会话的字节数
+struct Previous {
+    at: Instant,
+    bytes: HashMap<String, (u64, u64)>,
+}
+
+pub async fn run(config: &Config, opts: Options) -> Result<()> {
+    let client = Client::builder()
+        .danger_accept_invalid_certs(opts.insecure)
+        .timeout(Duration::from_secs(5))
+        .build()?;
+    let base = opts.url.trim_end_matches('/');
+    let mut previous: Option<Previous> = None;
+    let mut ticker = interval(opts.interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = tokio::signal::ctrl_c() => return Ok(()),
+        }
+        let top = format!("{}/admin/stats/top?limit={}", base, usize::MAX);
+        let sessions: Vec<Session> = get(&client, &top, &opts.token).await?;
+        let errors: Vec<RecentError> = get(&client, &format!("{}/admin/stats/errors", base), &opts.token).await?;
+        let node = match config.control {
+            Some(ref c) => control::send(c, "status").await.unwrap_or_else(|e| format!("不可用 - {:#}", e)),
+            None => "未配置".into(),
+        };
+        let now = Instant::now();
+        let screen = render(&opts, &node, &sessions, &errors, previous.as_ref());
+        previous = Some(Previous {
+            at: now,
+            bytes: sessions.iter().map(|s| (s.session_id.clone(), (s.bytes_up, s.bytes_down))).collect(),
+        });
+        if opts.once {
+            print!("{}", screen);
+            return Ok(());
+        }
+        // 清屏并回到左上角
+        print!("\x1b[2J\x1b[H{}", screen);
+    }
+}
+
+async fn get<T: for<'de> Deserialize<'de>>(client: &Client, url: &str, token: &str) -> Result<T> {
+    let response = client
+        .get(url)
+        .header("X-Admin-Token", token)
+        .send()
+        .await
+        .with_context(|| format!("无法连接管理 API: {}", url))?
+        .error_for_status()
+        .with_context(|| format!("管理 API 返回错误: {}", url))?;
+    Ok(serde_json::from_slice(&response.bytes().await?)?)
+}
+
+fn render(opts: &Options, node: &str, sessions: &[Session], errors: &[RecentError], prev: Option<&Previous>) -> String {
+    use std::fmt::Write;
+
+    // 每个会话当前的 (上行, 下行) 字节/秒；首次刷新或新会话使用平均吞吐
+    let rate = |s: &Session| match prev.and_then(|p| p.bytes.get(&s.session_id).map(|b| (p.at, b))) {
+        Some((at, &(up, down))) => {
+            let secs = at.elapsed().as_secs_f64().max(0.001);
+            (
+                (s.bytes_up.saturating_sub(up) as f64 / secs) as u64,
+                (s.bytes_down.saturating_sub(down) as f64 / secs) as u64,
+            )
+        }
+        None => {
+            let total = (s.bytes_up + s.bytes_down).max(1);
+            (s.bytes_per_sec * s.bytes_up / total, s.bytes_per_sec * s.bytes_down / total)
+        }
+    };
+    let mut rows: Vec<(&Session, (u64, u64))> = sessions.iter().map(|s| (s, rate(s))).collect();
+    rows.sort_by_key(|(_, (up, down))| std::cmp::Reverse(up + down));
+    let (up, down) = rows.iter().fold((0, 0), |(a, b), (_, (up, down))| (a + up, b + down));
+
+    let mut out = String::new();
+    let refresh = match opts.once {
+        true => String::new(),
+        false => format!("（每 {:?} 刷新，Ctrl-C 退出）", opts.interval),
+    };
+    let _ = writeln!(out, "ws-relay-core {}{}", opts.url, refresh);
+    let _ = writeln!(out, "控制通道: {}", node);
+    let _ = writeln!(out, "活跃会话: {}  上行: {}/s  下行: {}/s", sessions.len(), human(up), human(down));
+    let _ = writeln!(out);
+    let _ = writeln!(
+        out,
+        "{:<16}  {:<16}  {:>8}  {:>11}  {:>11}  target",
+        "session_id", "user", "duration", "up/s", "down/s"
+    );
+    for (s, (up, down)) in rows.iter().take(MAX_SESSIONS) {
+        let _ = writeln!(
+            out,
+            "{:<16}  {:<16}  {:>7}s  {:>11}  {:>11}  {}",
+            s.session_id,
+            truncate(&s.user, 16),
+            s.duration_secs,
+            human(*up),
+            human(*down),
+            truncate(&s.target, TARGET_WIDTH)
+        );
+    }
+    if rows.len() > MAX_SESSIONS {
+        let _ = writeln!(out, "... 另有 {} 个会话", rows.len() - MAX_SESSIONS);
+    }
+    let _ = writeln!(out);
+    let _ = writeln!(out, "最近异常:");
+    if errors.is_empty() {
+        let _ = writeln!(out, "  无");
+    }
+    for e in errors.iter().take(MAX_ERRORS) {
+        let _ = writeln!(
+            out,
+            "  {}  {:<7}  {:<16}  {:<18}  {}",
+            e.ts,
+            e.kind,
+            truncate(&e.user, 16),
+            e.reason,
+            truncate(&e.target, TARGET_WIDTH)
+        );
+    }
+    out
+}
+
+/// 字节数，1024 进制
+fn human(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{} B", bytes),
+        _ => format!("{:.1} {}", value, UNITS[unit]),
+    }
+}
+
+fn truncate(s: &str, width: usize) -> String {
+    match s.chars().count() > width {
+        true => format!("{}…", s.chars().take(width - 1).collect::<String>()),
+        false => s.to_string(),
+    }
+}
@@ -0,0 +1,444 @@
+//! 存储转发
+//!
+//! 遥测采集类上游经常短暂不可用，直接透传时客户端只能自行缓存重发。`[store_forward]` 匹配的 `/ws` 目标改为：
+//!
+//! 1. 客户端的 text / binary 消息按到达顺序编号（从 1 开始），追加到会话的磁盘队列后回复 `stored`
+//! 2. 目标可达时按顺序发送队列中的消息；连接失败或断开后每 `retry_secs` 秒重连，从第一条未送达的消息继续
+//! 3. 积压超过 `max_bytes` 时新消息被拒绝（`rejected`）
+//! 4. 客户端断开后继续发送剩余消息，最长 `drain_timeout_secs` 秒，之后丢弃剩余消息
+//!
+//! | 控制消息 | 说明 |
+//! |------|------|
+//! | `{"type":"stored","seq":N}` | 第 N 条消息已写入队列 |
+//! | `{"type":"rejected","seq":N,"code":"SPOOL_FULL"}` | 队列已满，第 N 条消息被丢弃 |
+//! | `{"type":"delivered","seq":N}` | 第 N 条及之前的消息已发送给目标（每 64 条或队列清空时回复） |
+//! | `{"type":"upstream","status":"connected"}` | 目标已连接；断开时为 `disconnected` |
+//!
+//! 投递为至少一次：发送中断的消息在重连后重发，目标可能收到重复消息。目标发来的消息直接转发给客户端。
+//! 队列文件只在会话期间存在，进程重启后不恢复。脚本钩子、会话录制与切换目标不作用于此模式。
+
+use std::{
+    future::Future,
+    io::SeekFrom,
+    path::{Path, PathBuf},
+    pin::Pin,
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use axum::{
+    extract::ws::{CloseFrame, Message, WebSocket},
+    http::HeaderMap,
+};
+use futures_util::{stream::SplitSink, SinkExt, StreamExt};
+use serde_json::{json, Value};
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+    time::{sleep_until, Instant},
+};
+use tokio_tungstenite::tungstenite::Message as TungMessage;
+use tracing::{error, info, warn};
+
+use crate::{
+    config::{StoreForwardConfig, User},
+    control_codec::ControlCodec,
+    encoding::Encoding,
+    error,
+    host_limits::HostPermit,
+    secret,
+    state::AppState,
+    stats::SessionStats,
+    ws::{self, EndReason, TargetRx, TargetTx},
+};
+
+/// 每送达多少条消息回复一次 `delivered`（队列清空时也回复）
+const DELIVERED_EVERY: u64 = 64;
+
+/// 记录头：1 字节类型 + 8 字节编号 + 4 字节负载长度（大端）
+const HEADER_LEN: u64 = 13;
+const OP_TEXT: u8 = 1;
+const OP_BINARY: u8 = 2;
+
+type Connecting<'a> = Pin<Box<dyn Future<Output = Result<(TargetTx, TargetRx, HeaderMap)>> + Send + 'a>>;
+
+/// 会话的磁盘队列，drop 时删除文件
+struct Spool {
+    path: PathBuf,
+    writer: File,
+    reader: File,
+    /// 第一条未送达消息的位置
+    head: u64,
+    /// 写入位置
+    tail: u64,
+    max_bytes: u64,
+    fsync: bool,
+}
+
+/// 队首消息
+struct Record {
+    seq: u64,
+    msg: TungMessage,
+    size: u64,
+}
+
+impl Spool {
+    async fn create(config: &StoreForwardConfig, session_id: &str) -> Result<Self> {
+        let dir = Path::new(&config.dir);
+        fs::create_dir_all(dir)
+            .await
+            .with_context(|| format!("创建存储转发目录失败: {}", dir.display()))?;
+        let path = dir.join(format!("{}.spool", session_id));
+        let writer = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&path)
+            .await
+            .with_context(|| format!("创建队列文件失败: {}", path.display()))?;
+        let reader = File::open(&path).await?;
+        Ok(Self {
+            path,
+            writer,
+            reader,
+            head: 0,
+            tail: 0,
+            max_bytes: config.max_bytes,
+            fsync: config.fsync,
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.head == self.tail
+    }
+
+    /// 追加一条消息，超过积压上限时返回 `false`
+    async fn push(&mut self, seq: u64, msg: &TungMessage) -> Result<bool> {
+        let (op, payload): (u8, &[u8]) = match msg {
+            TungMessage::Text(t) => (OP_TEXT, t.as_bytes()),
+            TungMessage::Binary(b) => (OP_BINARY, b),
+            _ => return Ok(true),
+        };
+        let size = HEADER_LEN + payload.len() as u64;
+        if self.tail - self.head + size > self.max_bytes {
+            return Ok(false);
+        }
+        let mut buf = Vec::with_capacity(size as usize);
+        buf.push(op);
+        buf.extend_from_slice(&seq.to_be_bytes());
+        buf.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        buf.extend_from_slice(payload);
+        self.writer.write_all(&buf).await?;
+        self.writer.flush().await?;
+        if self.fsync {
+            self.writer.sync_data().await?;
+        }
+        self.tail += size;
+        Ok(true)
+    }
+
+    /// 读取队首消息（不移除）
+    async fn peek(&mut self) -> Result<Option<Record>> {
+        if self.is_empty() {
+            return Ok(None);
+        }
+        self.reader.seek(SeekFrom::Start(self.head)).await?;
+        let mut header = [0u8; HEADER_LEN as usize];
+        self.reader.read_exact(&mut header).await?;
+        let seq = u64::from_be_bytes(header[1..9].try_into()?);
+        let len = u32::from_be_bytes(header[9..13].try_into()?);
+        let mut payload = vec![0u8; len as usize];
+        self.reader.read_exact(&mut payload).await?;
+        let msg = match header[0] {
+            OP_TEXT => TungMessage::Text(String::from_utf8(payload)?.into()),
+            OP_BINARY => TungMessage::Binary(payload.into()),
+            op => bail!("队列记录类型无效: {}", op),
+        };
+        Ok(Some(Record {
+            seq,
+            msg,
+            size: HEADER_LEN + len as u64,
+        }))
+    }
+
+    /// 移除已送达的队首消息，队列清空时截断文件
+    async fn pop(&mut self, size: u64) -> Result<()> {
+        self.head += size;
+        if self.is_empty() {
+            self.writer.set_len(0).await?;
+            self.writer.seek(SeekFrom::Start(0)).await?;
+            (self.head, self.tail) = (0, 0);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Spool {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            warn!("删除队列文件失败: {} - {}", self.path.display(), e);
+        }
+    }
+}
+
+/// 存储转发模式的 `/ws` 会话，字节数在消息送达后累计到 `session`
+#[allow(clippy::too_many_arguments)]
+pub async fn relay(
+    client_ws: WebSocket,
+    target: &str,
+    sni: Option<&str>,
+    state: &AppState,
+    user: &User,
+    session: &SessionStats,
+    handshake: &HeaderMap,
+    encoding: Encoding,
+    config: &StoreForwardConfig,
+    _permit: Option<HostPermit>,
+) -> EndReason {
+    let codec = ControlCodec::from_protocol(client_ws.protocol());
+    let (mut client_tx, mut client_rx) = client_ws.split();
+    let mut spool = match Spool::create(config, &session.id).await {
+        Ok(s) => s,
+        Err(e) => {
+            error!("{:#}", e);
+            let msg = error::to_json("SPOOL_UNAVAILABLE", "存储转发队列不可用");
+            let _ = client_tx.send(codec.encode(msg)).await;
+            return EndReason::ConnectFailed;
+        }
+    };
+
+    let retry = Duration::from_secs(config.retry_secs);
+    let user_stats = state.stats.user(&user.name);
+    let quota = state.quota.user(user);
+    let connect = || -> Connecting<'_> { Box::pin(ws::open_user_target(target, sni, state, user, Some(handshake))) };
+    let mut connecting = Some(connect());
+    let mut retry_at: Option<Instant> = None;
+    let (mut target_tx, mut target_rx): (Option<TargetTx>, Option<TargetRx>) = (None, None);
+    let mut next: Option<Record> = None;
+    let mut client_open = true;
+    let mut drain_deadline: Option<Instant> = None;
+    let (mut seq, mut unreported) = (0, 0);
+
+    let reason = loop {
+        if !client_open && spool.is_empty() {
+            break EndReason::ClientClosed;
+        }
+        if target_tx.is_some() && next.is_none() {
+            next = match spool.peek().await {
+                Ok(r) => r,
+                Err(e) => {
+                    error!("读取队列失败: {:#}", e);
+                    break EndReason::TargetClosed;
+                }
+            };
+        }
+
+        // 目标断开：丢弃连接，稍后重连，队首消息重新读取
+        let mut disconnected = false;
+        // select! 对未启用分支的表达式同样求值，依赖 Option 的分支包在 async 块中
+        tokio::select! {
+            msg = client_rx.next(), if client_open => {
+                let msg = match msg {
+                    Some(Ok(Message::Text(t))) => encoding.decode(TungMessage::Text(t.as_str().into())),
+                    Some(Ok(Message::Binary(b))) => encoding.decode(TungMessage::Binary(b)),
+                    Some(Ok(Message::Ping(_) | Message::Pong(_))) => None,
+                    Some(Ok(Message::Close(_)) | Err(_)) | None => {
+                        client_open = false;
+                        None
+                    }
+                };
+                if let Some(msg) = msg {
+                    seq += 1;
+                    let reply = match spool.push(seq, &msg).await {
+                        Ok(true) => json!({ "type": "stored", "seq": seq }),
+                        Ok(false) => json!({ "type": "rejected", "seq": seq, "code": "SPOOL_FULL" }),
+                        Err(e) => {
+                            error!("写入队列失败: {:#}", e);
+                            break EndReason::TargetClosed;
+                        }
+                    };
+                    notify(&mut client_tx, &mut client_open, codec, reply).await;
+                }
+            }
+            r = async { connecting.as_mut().unwrap().await }, if connecting.is_some() => {
+                connecting = None;
+                match r {
+                    Ok((tx, rx, _)) => {
+                        info!("存储转发: 已连接目标 {}", secret::redact_url(target));
+                        (target_tx, target_rx) = (Some(tx), Some(rx));
+                        let status = json!({ "type": "upstream", "status": "connected" });
+                        notify(&mut client_tx, &mut client_open, codec, status).await;
+                    }
+                    Err(e) => {
+                        warn!("存储转发: 连接目标失败，{} 秒后重试: {:#}", config.retry_secs, e);
+                        retry_at = Some(Instant::now() + retry);
+                    }
+                }
+            }
+            _ = async { sleep_until(retry_at.unwrap()).await }, if retry_at.is_some() => {
+                retry_at = None;
+                connecting = Some(connect());
+            }
+            r = async { target_tx.as_mut().unwrap().send(next.as_ref().unwrap().msg.clone()).await },
+                if target_tx.is_some() && next.is_some() => match r {
+                Ok(()) => {
+                    let record = next.take().unwrap();
+                    let len = record.msg.len() as u64;
+                    user_stats.record(&record.msg);
+                    if let Err(e) = spool.pop(record.size).await {
+                        error!("截断队列失败: {:#}", e);
+                        break EndReason::TargetClosed;
+                    }
+                    session.add_up(len);
+                    if !quota.consume_up(len) {
+                        break EndReason::QuotaExceeded;
+                    }
+                    unreported += 1;
+                    if unreported >= DELIVERED_EVERY || spool.is_empty() {
+                        unreported = 0;
+                        let delivered = json!({ "type": "delivered", "seq": record.seq });
+                        notify(&mut client_tx, &mut client_open, codec, delivered).await;
+                    }
+                }
+                Err(_) => disconnected = true,
+            },
+            msg = async { target_rx.as_mut().unwrap().next().await }, if target_rx.is_some() => match msg {
+                Some(Ok(msg @ (TungMessage::Text(_) | TungMessage::Binary(_)))) => {
+                    let len = msg.len() as u64;
+                    user_stats.record(&msg);
+                    if client_open {
+                        let forwarded = match encoding.encode(msg) {
+                            TungMessage::Text(t) => Message::Text(t.as_str().into()),
+                            TungMessage::Binary(b) => Message::Binary(b),
+                            _ => continue,
+                        };
+                        if client_tx.send(forwarded).await.is_err() {
+                            client_open = false;
+                        }
+                    }
+                    session.add_down(len);
+                    if !quota.consume_down(len) {
+                        break EndReason::QuotaExceeded;
+                    }
+                }
+                Some(Ok(_)) => {}
+                Some(Err(_)) | None => disconnected = true,
+            },
+            _ = async { sleep_until(drain_deadline.unwrap()).await }, if drain_deadline.is_some() => {
+                warn!("[{}] 客户端已断开，{} 字节未送达，丢弃: {}", user.name, spool.tail - spool.head, secret::redact_url(target));
+                break EndReason::ClientClosed;
+            }
+            _ = session.terminate.notified() => break session.terminate_reason().into(),
+        }
+
+        if disconnected {
+            warn!("存储转发: 目标断开，{} 秒后重连: {}", config.retry_secs, secret::redact_url(target));
+            (target_tx, target_rx, next) = (None, None, None);
+            retry_at = Some(Instant::now() + retry);
+            let status = json!({ "type": "upstream", "status": "disconnected" });
+            notify(&mut client_tx, &mut client_open, codec, status).await;
+        }
+        if !client_open && drain_deadline.is_none() {
+            drain_deadline = Some(Instant::now() + Duration::from_secs(config.drain_timeout_secs));
+        }
+    };
+
+    if let (true, Some((code, message, close_code))) = (client_open, reason.close_info()) {
+        warn!("[{}] {}，终止会话: {}", user.name, message, secret::redact_url(target));
+        let _ = client_tx.send(codec.encode(error::to_json(code, message))).await;
+        let _ = client_tx
+            .send(Message::Close(Some(CloseFrame {
+                code: close_code,
+                reason: code.into(),
+            })))
+            .await;
+    }
+    info!("存储转发会话结束: {}", secret::redact_url(target));
+    reason
+}
+
+/// 向客户端发送控制消息，发送失败视为客户端已断开
+async fn notify(client_tx: &mut SplitSink<WebSocket, Message>, open: &mut bool, codec: ControlCodec, msg: Value) {
+    if *open && client_tx.send(codec.encode(msg.to_string())).await.is_err() {
+        *open = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(max_bytes: u64) -> StoreForwardConfig {
+        StoreForwardConfig {
+            dir: std::env::temp_dir()
+                .join(format!("ws-relay-spool-{}", std::process::id()))
+                .to_string_lossy()
+                .into_owned(),
+            targets: Vec::new(),
+            max_bytes,
+            retry_secs: 5,
+            drain_timeout_secs: 30,
+            fsync: false,
+        }
+    }
+
+    async fn pop_front(spool: &mut Spool) -> (u64, TungMessage) {
+        let record = spool.peek().await.unwrap().unwrap();
+        spool.pop(record.size).await.unwrap();
+        (record.seq, record.msg)
+    }
+
+    #[tokio::test]
+    async fn fifo_round_trip() {
+        let mut spool = Spool::create(&config(1 << 20), "fifo").await.unwrap();
+        assert!(spool.peek().await.unwrap().is_none());
+        assert!(spool.push(1, &TungMessage::Text("hello".into())).await.unwrap());
+        assert!(spool.push(2, &TungMessage::Binary(vec![0, 1, 2, 255].into())).await.unwrap());
+        // 控制帧不入队
+        assert!(spool.push(3, &TungMessage::Ping(vec![1].into())).await.unwrap());
+        assert!(spool.push(7, &TungMessage::Text(String::new().into())).await.unwrap());
+
+        // peek 不移除
+        assert_eq!(spool.peek().await.unwrap().unwrap().seq, 1);
+        assert_eq!(pop_front(&mut spool).await, (1, TungMessage::Text("hello".into())));
+        assert_eq!(pop_front(&mut spool).await, (2, TungMessage::Binary(vec![0, 1, 2, 255].into())));
+        assert_eq!(pop_front(&mut spool).await, (7, TungMessage::Text(String::new().into())));
+        assert!(spool.peek().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn push_rejected_past_max_bytes() {
+        let mut spool = Spool::create(&config(2 * (HEADER_LEN + 10)), "full").await.unwrap();
+        let msg = TungMessage::Binary(vec![0; 10].into());
+        assert!(spool.push(1, &msg).await.unwrap());
+        assert!(spool.push(2, &msg).await.unwrap());
+        assert!(!spool.push(3, &msg).await.unwrap());
+        // 送达后腾出空间
+        pop_front(&mut spool).await;
+        assert!(spool.push(3, &msg).await.unwrap());
+        assert_eq!(pop_front(&mut spool).await.0, 2);
+        assert_eq!(pop_front(&mut spool).await.0, 3);
+    }
+
+    #[tokio::test]
+    async fn file_truncated_when_empty_and_removed_on_drop() {
+        let mut spool = Spool::create(&config(1 << 20), "truncate").await.unwrap();
+        let path = spool.path.clone();
+        spool.push(1, &TungMessage::Text("a".repeat(100).into())).await.unwrap();
+        spool.push(2, &TungMessage::Text("b".into())).await.unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 2 * HEADER_LEN + 101);
+
+        pop_front(&mut spool).await;
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 2 * HEADER_LEN + 101);
+        pop_front(&mut spool).await;
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 0);
+
+        // 截断后从文件开头继续写入
+        spool.push(3, &TungMessage::Text("c".into())).await.unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), HEADER_LEN + 1);
+        assert_eq!(pop_front(&mut spool).await, (3, TungMessage::Text("c".into())));
+
+        drop(spool);
+        assert!(!path.exists());
+    }
+}
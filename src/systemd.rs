@@ -0,0 +1,53 @@
+//! systemd 集成（仅 Unix）
+//!
+//! - socket activation：存在 `LISTEN_FDS` 时使用 systemd 传入的第一个 socket，忽略 `host` / `port`
+//! - 监听就绪后发送 `READY=1`（`Type=notify`）
+//! - 单元设置了 `WatchdogSec` 时按其一半间隔发送 `WATCHDOG=1`
+//!
+//! 不在 systemd 下运行时均为空操作。
+
+use std::{
+    net::TcpListener,
+    os::fd::FromRawFd,
+    time::Duration,
+};
+
+use anyhow::Result;
+use sd_notify::NotifyState;
+use tracing::{info, warn};
+
+/// systemd 传入的监听 socket
+pub fn listener() -> Result<Option<TcpListener>> {
+    let Some(fd) = sd_notify::listen_fds()?.next() else {
+        return Ok(None);
+    };
+    // SAFETY: LISTEN_PID 已校验为本进程，fd 由 systemd 传入且只取用一次
+    let listener = unsafe { TcpListener::from_raw_fd(fd) };
+    info!("使用 systemd socket: {}", listener.local_addr()?);
+    Ok(Some(listener))
+}
+
+/// 通知 systemd 服务已就绪，并按需启动 watchdog
+pub fn notify_ready() {
+    if let Err(e) = sd_notify::notify(false, &[NotifyState::Ready]) {
+        warn!("sd_notify READY 失败: {}", e);
+    }
+
+    let mut usec = 0;
+    if sd_notify::watchdog_enabled(false, &mut usec) {
+        let interval = Duration::from_micros(usec) / 2;
+        info!("systemd watchdog: 每 {:?} 发送一次", interval);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                let _ = sd_notify::notify(false, &[NotifyState::Watchdog]);
+            }
+        });
+    }
+}
+
+/// 通知 systemd 正在停止
+pub fn notify_stopping() {
+    let _ = sd_notify::notify(false, &[NotifyState::Stopping]);
+}
@@ -0,0 +1,36 @@
+//! 目标改写
+//!
+//! `[[target_rewrites]]` 在连接目标前以正则改写客户端请求的目标 URL，用于将旧上游透明地迁移到新主机、
+//! 或对特定域名强制使用 `wss://`。规则按顺序匹配，第一条匹配的规则生效，替换第一处匹配。
+//! `allowed_targets` 仍按客户端请求的原始目标校验，访问日志记录原始目标。
+
+use std::borrow::Cow;
+
+use tracing::info;
+
+use crate::{
+    config::{self, Config, TargetRewrite},
+    secret,
+};
+
+/// 返回改写后的目标，无规则匹配时原样返回
+pub fn apply<'a>(rules: &[TargetRewrite], target: &'a str) -> Cow<'a, str> {
+    let rewritten = rewrite(rules, target);
+    if let Cow::Owned(ref r) = rewritten {
+        info!("目标改写: {} → {}", secret::redact_url(target), secret::redact_url(r));
+    }
+    rewritten
+}
+
+/// 同 [`apply`]，不记录日志（用于连接前的校验）
+pub fn rewrite<'a>(rules: &[TargetRewrite], target: &'a str) -> Cow<'a, str> {
+    match rules.iter().find(|r| r.pattern.0.is_match(target)) {
+        Some(rule) => Cow::Owned(rule.pattern.0.replace(target, rule.replace.as_str()).into_owned()),
+        None => Cow::Borrowed(target),
+    }
+}
+
+/// 开启 `server.require_tls_targets` 时，改写后的目标是否为明文
+pub fn violates_tls(config: &Config, target: &str) -> bool {
+    config.server.require_tls_targets && config::is_plaintext_target(&rewrite(&config.target_rewrites, target))
+}
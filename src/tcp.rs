@@ -0,0 +1,147 @@
+//! TCP 连接参数
+//!
+//! `[server.tcp]` 同时作用于两侧连接：
+//!
+//! - 入站：监听 socket（accept 的连接继承）与 accept 后、TLS 握手前的连接
+//! - 出站：连接目标（WS、`tcp://`、连接池）与 REST 上游（仅 keepalive）
+//!
+//! | 配置 | 说明 | 默认 |
+//! |------|------|------|
+//! | `keepalive_idle_secs` / `keepalive_interval_secs` / `keepalive_count` | TCP keepalive | 不开启 |
+//! | `send_buffer_size` / `recv_buffer_size` | SO_SNDBUF / SO_RCVBUF（字节） | 系统设置（自动调节） |
+//! | `priority` | SO_PRIORITY（仅 Linux） | 不设置 |
+//! | `listen_backlog` | 监听队列长度 | 1024 |
+//! | `fastopen_queue` | TCP Fast Open 队列长度（仅 Linux，监听端） | 不开启 |
+//!
+//! 未设置的项不修改系统值，避免与主机的 sysctl 策略冲突。
+//! 客户端网络中断（如移动网络切换）时对端不会发送 FIN，没有 keepalive 的连接会半开数小时，
+//! 开启后约 `idle + interval × count` 秒内检测到并关闭。
+
+use std::{future::Ready, io, time::Duration};
+
+use axum_server::accept::Accept;
+use once_cell::sync::OnceCell;
+use socket2::{SockRef, TcpKeepalive};
+use tokio::net::TcpStream;
+use tracing::{debug, info, warn};
+
+use crate::config::TcpConfig;
+
+static CONFIG: OnceCell<TcpConfig> = OnceCell::new();
+
+/// 记录配置（启动时调用一次）
+pub fn init(config: &TcpConfig) {
+    if let Some(idle) = config.keepalive_idle_secs {
+        info!(
+            "TCP keepalive: 空闲 {}s 后探测，间隔 {}，{} 次无响应断开",
+            idle,
+            config.keepalive_interval_secs.map_or("系统默认".into(), |s| format!("{}s", s)),
+            config.keepalive_count.map_or("系统默认".into(), |n| n.to_string()),
+        );
+    }
+    if !cfg!(target_os = "linux") {
+        if config.priority.is_some() {
+            warn!("server.tcp.priority 仅 Linux 支持，已忽略");
+        }
+        if config.fastopen_queue.is_some() {
+            warn!("server.tcp.fastopen_queue 仅 Linux 支持，已忽略");
+        }
+    }
+    let _ = CONFIG.set(config.clone());
+}
+
+/// 按配置设置 socket 参数，失败只记录日志
+pub fn tune(socket: SockRef<'_>) {
+    let Some(config) = CONFIG.get() else {
+        return;
+    };
+    if let Some(size) = config.send_buffer_size {
+        if let Err(e) = socket.set_send_buffer_size(size) {
+            debug!("设置 SO_SNDBUF 失败: {}", e);
+        }
+    }
+    if let Some(size) = config.recv_buffer_size {
+        if let Err(e) = socket.set_recv_buffer_size(size) {
+            debug!("设置 SO_RCVBUF 失败: {}", e);
+        }
+    }
+    #[cfg(target_os = "linux")]
+    if let Some(priority) = config.priority {
+        if let Err(e) = socket.set_priority(priority) {
+            debug!("设置 SO_PRIORITY 失败: {}", e);
+        }
+    }
+    if let Some(keepalive) = keepalive(config) {
+        if let Err(e) = socket.set_tcp_keepalive(&keepalive) {
+            debug!("设置 TCP keepalive 失败: {}", e);
+        }
+    }
+}
+
+/// 监听端开启 TCP Fast Open（仅 Linux）
+pub fn enable_fastopen(listener: &tokio::net::TcpListener, queue: u32) {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::fd::AsRawFd;
+
+        let queue = queue as libc::c_int;
+        // SAFETY: fd 在 listener 存活期间有效，optval 指向大小匹配的 c_int
+        let ret = unsafe {
+            libc::setsockopt(
+                listener.as_raw_fd(),
+                libc::IPPROTO_TCP,
+                libc::TCP_FASTOPEN,
+                &queue as *const libc::c_int as *const libc::c_void,
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if ret != 0 {
+            warn!("开启 TCP Fast Open 失败: {}", io::Error::last_os_error());
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = (listener, queue);
+}
+
+fn keepalive(config: &TcpConfig) -> Option<TcpKeepalive> {
+    let mut keepalive = TcpKeepalive::new().with_time(Duration::from_secs(config.keepalive_idle_secs?));
+    if let Some(secs) = config.keepalive_interval_secs {
+        keepalive = keepalive.with_interval(Duration::from_secs(secs));
+    }
+    if let Some(count) = config.keepalive_count {
+        keepalive = keepalive.with_retries(count);
+    }
+    Some(keepalive)
+}
+
+/// REST 上游连接（由 reqwest 建立）使用相同参数
+pub fn reqwest(mut builder: reqwest::ClientBuilder) -> reqwest::ClientBuilder {
+    let Some(config) = CONFIG.get() else {
+        return builder;
+    };
+    if let Some(secs) = config.keepalive_idle_secs {
+        builder = builder.tcp_keepalive(Duration::from_secs(secs));
+        if let Some(secs) = config.keepalive_interval_secs {
+            builder = builder.tcp_keepalive_interval(Duration::from_secs(secs));
+        }
+        if let Some(count) = config.keepalive_count {
+            builder = builder.tcp_keepalive_retries(count);
+        }
+    }
+    builder
+}
+
+/// 入站连接：accept 后设置参数，再交给 TLS 握手
+#[derive(Clone, Copy)]
+pub struct TcpAcceptor;
+
+impl<S> Accept<TcpStream, S> for TcpAcceptor {
+    type Stream = TcpStream;
+    type Service = S;
+    type Future = Ready<io::Result<(TcpStream, S)>>;
+
+    fn accept(&self, stream: TcpStream, service: S) -> Self::Future {
+        tune(SockRef::from(&stream));
+        std::future::ready(Ok((stream, service)))
+    }
+}
@@ -0,0 +1,131 @@
+//! OpenTelemetry 导出
+//!
+//! 配置 `[telemetry]` 后通过 OTLP/HTTP 导出：
+//! - span：`auth`（认证）、`ws_session`（WS 会话）、`target_connect`（连接目标）、`rest_request`（REST 请求）
+//! - 指标：`relay.sessions`、`relay.bytes`（`direction` = up / down）、`relay.session.duration`（秒），
+//!   均带 `kind`（ws / rest）与 `close_reason` 属性，`relay.sessions` 与 `relay.session.duration` 另带 `country`（见 [`crate::geoip`]）；
+//!   `relay.panics`（`kind`）为被隔离的会话 panic 数，`relay.open_fds` / `relay.rss` 为打开的文件描述符数与常驻内存（见 [`crate::resources`]）
+//!
+//! 未配置时指标写入 no-op meter，不产生开销。
+
+use anyhow::Result;
+use once_cell::sync::Lazy;
+use opentelemetry::{
+    global,
+    metrics::{Counter, Histogram, ObservableGauge},
+    trace::TracerProvider,
+    KeyValue,
+};
+use opentelemetry_otlp::{MetricExporter, SpanExporter, WithExportConfig, WithHttpConfig};
+use opentelemetry_sdk::{
+    metrics::SdkMeterProvider,
+    trace::{SdkTracer, SdkTracerProvider},
+    Resource,
+};
+use tracing::{info, warn};
+
+use crate::{access_log::AccessRecord, config::TelemetryConfig, resources};
+
+/// 导出器（退出时需 `shutdown` 以发送剩余数据）
+pub struct Telemetry {
+    tracer_provider: SdkTracerProvider,
+    meter_provider: SdkMeterProvider,
+    /// 采集时读取当前用量的仪表
+    _gauges: Vec<ObservableGauge<u64>>,
+}
+
+impl Telemetry {
+    pub fn init(config: &TelemetryConfig) -> Result<Self> {
+        let endpoint = config.endpoint.trim_end_matches('/');
+        let resource = Resource::builder()
+            .with_service_name(config.service_name.clone())
+            .build();
+
+        let spans = SpanExporter::builder()
+            .with_http()
+            .with_endpoint(format!("{}/v1/traces", endpoint))
+            .with_headers(config.headers.clone())
+            .build()?;
+        let tracer_provider = SdkTracerProvider::builder()
+            .with_batch_exporter(spans)
+            .with_resource(resource.clone())
+            .build();
+
+        let metrics = MetricExporter::builder()
+            .with_http()
+            .with_endpoint(format!("{}/v1/metrics", endpoint))
+            .with_headers(config.headers.clone())
+            .build()?;
+        let meter_provider = SdkMeterProvider::builder()
+            .with_periodic_exporter(metrics)
+            .with_resource(resource)
+            .build();
+        global::set_meter_provider(meter_provider.clone());
+        let open_fds = global::meter("ws-relay-core")
+            .u64_observable_gauge("relay.open_fds")
+            .with_callback(|o| o.observe(resources::fd_usage().0, &[]))
+            .build();
+        let rss = global::meter("ws-relay-core")
+            .u64_observable_gauge("relay.rss")
+            .with_unit("By")
+            .with_callback(|o| o.observe(resources::rss(), &[]))
+            .build();
+
+        Ok(Self {
+            tracer_provider,
+            meter_provider,
+            _gauges: vec![open_fds, rss],
+        })
+    }
+
+    pub fn tracer(&self) -> SdkTracer {
+        self.tracer_provider.tracer("ws-relay-core")
+    }
+
+    pub fn shutdown(&self) {
+        if let Err(e) = self.tracer_provider.shutdown() {
+            warn!("span 导出器关闭失败: {}", e);
+        }
+        if let Err(e) = self.meter_provider.shutdown() {
+            warn!("指标导出器关闭失败: {}", e);
+        }
+        info!("遥测已关闭");
+    }
+}
+
+struct Metrics {
+    sessions: Counter<u64>,
+    bytes: Counter<u64>,
+    duration: Histogram<f64>,
+    panics: Counter<u64>,
+}
+
+/// 首次使用时从全局 meter 创建（须在 `Telemetry::init` 之后）
+static METRICS: Lazy<Metrics> = Lazy::new(|| {
+    let meter = global::meter("ws-relay-core");
+    Metrics {
+        sessions: meter.u64_counter("relay.sessions").build(),
+        bytes: meter.u64_counter("relay.bytes").with_unit("By").build(),
+        duration: meter.f64_histogram("relay.session.duration").with_unit("s").build(),
+        panics: meter.u64_counter("relay.panics").build(),
+    }
+});
+
+/// 记录一个已结束的会话 / 请求
+pub fn record(record: &AccessRecord) {
+    let attrs = [
+        KeyValue::new("kind", record.kind),
+        KeyValue::new("close_reason", record.close_reason.to_string()),
+        KeyValue::new("country", record.country.unwrap_or("unknown")),
+    ];
+    let m = &*METRICS;
+    m.sessions.add(1, &attrs);
+    m.duration.record(record.duration_ms as f64 / 1000.0, &attrs);
+    m.bytes.add(record.bytes_up, &[KeyValue::new("kind", record.kind), KeyValue::new("direction", "up")]);
+    m.bytes.add(record.bytes_down, &[KeyValue::new("kind", record.kind), KeyValue::new("direction", "down")]);
+}
+
+/// 记录一次被隔离的会话 panic（见 [`crate::panic_guard`]）
+pub fn record_panic(kind: &'static str) {
+    METRICS.panics.add(1, &[KeyValue::new("kind", kind)]);
+}
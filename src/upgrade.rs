@@ -0,0 +1,336 @@
+//! 平滑升级（仅 Unix）
+//!
+//! 替换磁盘上的二进制后向运行中的进程发送 `SIGUSR2`，以相同的命令行参数启动新进程，
+//! 监听 socket 以文件描述符继承的方式交给新进程，端口始终处于监听状态：
+//!
+//! 1. 旧进程保存配额用量，启动新进程，主端口、SOCKS5 与控制通道的监听 socket 经 `WS_RELAY_UPGRADE_FDS` 传递
+//! 2. 新进程直接使用继承的 socket（不再 bind），开始监听后经 `WS_RELAY_UPGRADE_READY` 通知旧进程
+//! 3. 旧进程停止接受新连接，等待现有会话结束（最长 `server.upgrade_drain_secs`）后退出
+//!
+//! 新进程 `READY_TIMEOUT` 内未就绪（配置错误、启动失败等）时升级中止，旧进程继续服务。
+//! 监听地址沿用旧进程的 socket，修改 `host` / `port` / `listen` 仍需重启。
+//! 交接后旧进程不再写配额状态文件，由新进程接管；旧进程退出时把交接后新增的配额用量经就绪通知的 socket 交给新进程。systemd 下新进程会发送 `MAINPID=`，单元需设置 `NotifyAccess=all`。
+
+use std::{
+    net::TcpListener,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use once_cell::sync::Lazy;
+use tokio::sync::watch;
+#[cfg(unix)]
+use tracing::{info, warn};
+
+use crate::{health, quota::QuotaTracker, state::AppState, stats::Stats};
+#[cfg(unix)]
+use {
+    anyhow::{bail, Context, Result},
+    std::{
+        io::Write,
+        os::{
+            fd::{AsFd, AsRawFd, FromRawFd, OwnedFd, RawFd},
+            unix::net::UnixStream,
+        },
+        path::Path,
+    },
+    tokio::io::{AsyncReadExt, AsyncWriteExt},
+};
+
+#[cfg(unix)]
+const FDS_ENV: &str = "WS_RELAY_UPGRADE_FDS";
+#[cfg(unix)]
+const READY_ENV: &str = "WS_RELAY_UPGRADE_READY";
+
+/// 等待新进程就绪的最长时间
+#[cfg(unix)]
+const READY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// 监听 socket 的用途，新进程据此取用继承的 socket
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Main,
+    Socks5,
+    Control,
+}
+
+impl Role {
+    #[cfg(unix)]
+    fn as_str(self) -> &'static str {
+        match self {
+            Role::Main => "main",
+            Role::Socks5 => "socks5",
+            Role::Control => "control",
+        }
+    }
+}
+
+/// 已交给新进程
+static HANDOFF: Lazy<watch::Sender<bool>> = Lazy::new(|| watch::channel(false).0);
+
+/// 与已就绪的新进程之间的 socket，退出时经此交出配额用量
+#[cfg(unix)]
+static SUCCESSOR: Lazy<Mutex<Option<tokio::net::UnixStream>>> = Lazy::new(Default::default);
+
+/// 本进程的监听 socket（复制的 fd），升级时传给新进程
+#[cfg(unix)]
+static LISTENERS: Lazy<Mutex<Vec<(Role, OwnedFd)>>> = Lazy::new(Default::default);
+
+/// 从旧进程继承的监听 socket 与就绪通知
+#[derive(Default)]
+struct Inherited {
+    listeners: Vec<(Role, TcpListener)>,
+    #[cfg(unix)]
+    ready: Option<UnixStream>,
+}
+
+static INHERITED: Lazy<Mutex<Inherited>> = Lazy::new(|| Mutex::new(inherit()));
+
+#[cfg(unix)]
+fn inherit() -> Inherited {
+    let fd = |v: &str| v.parse::<RawFd>().ok().inspect(|&fd| set_cloexec(fd, true));
+    let listeners = std::env::var(FDS_ENV)
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|entry| {
+            let (role, n) = entry.split_once(':')?;
+            let role = [Role::Main, Role::Socks5, Role::Control].into_iter().find(|r| r.as_str() == role)?;
+            // SAFETY: fd 由旧进程传入，只在此处取用一次
+            Some((role, unsafe { TcpListener::from_raw_fd(fd(n)?) }))
+        })
+        .collect();
+    // SAFETY: 同上
+    let ready = std::env::var(READY_ENV).ok().and_then(|v| fd(&v)).map(|fd| unsafe { UnixStream::from_raw_fd(fd) });
+    Inherited { listeners, ready }
+}
+
+#[cfg(not(unix))]
+fn inherit() -> Inherited {
+    Inherited::default()
+}
+
+/// 是否已交给新进程
+pub fn handed_off() -> bool {
+    *HANDOFF.borrow()
+}
+
+/// 等待交给新进程（SOCKS5、控制通道据此停止 accept）
+pub async fn wait_handoff() {
+    let _ = HANDOFF.subscribe().wait_for(|v| *v).await;
+}
+
+/// 取用从旧进程继承的监听 socket（非升级启动时为空）
+pub fn take(role: Role) -> Vec<TcpListener> {
+    let mut inherited = INHERITED.lock().unwrap();
+    let (matched, rest) = std::mem::take(&mut inherited.listeners).into_iter().partition(|(r, _)| *r == role);
+    inherited.listeners = rest;
+    matched.into_iter().map(|(_, l)| l).collect()
+}
+
+/// 绑定 `listen`，升级启动时优先使用继承的 socket
+pub async fn bind(role: Role, listen: &str) -> std::io::Result<tokio::net::TcpListener> {
+    let listener = match take(role).pop() {
+        Some(listener) => {
+            listener.set_nonblocking(true)?;
+            tokio::net::TcpListener::from_std(listener)?
+        }
+        None => tokio::net::TcpListener::bind(listen).await?,
+    };
+    register(role, &listener);
+    Ok(listener)
+}
+
+/// 关闭未被取用的继承 socket（如新配置去掉了 SOCKS5）
+pub fn close_unused() {
+    let unused = std::mem::take(&mut INHERITED.lock().unwrap().listeners);
+    for (role, listener) in unused {
+        tracing::warn!("新配置未使用继承的监听 socket: {:?} {:?}", role, listener.local_addr());
+    }
+}
+
+/// 登记本进程的监听 socket，升级时传给新进程
+#[cfg(unix)]
+pub fn register(role: Role, listener: &impl AsFd) {
+    match listener.as_fd().try_clone_to_owned() {
+        Ok(fd) => LISTENERS.lock().unwrap().push((role, fd)),
+        Err(e) => warn!("复制监听 socket 失败，平滑升级时不会传递: {}", e),
+    }
+}
+
+#[cfg(not(unix))]
+pub fn register<T>(_role: Role, _listener: &T) {}
+
+/// 新进程开始监听后通知旧进程退出，并在后台接收旧进程交接后新增的配额用量
+pub fn notify_ready(quota: Arc<QuotaTracker>) {
+    #[cfg(unix)]
+    if let Some(mut ready) = INHERITED.lock().unwrap().ready.take() {
+        let _ = ready.write_all(b"1");
+        let _ = sd_notify::notify(false, &[sd_notify::NotifyState::MainPid(std::process::id())]);
+        info!("平滑升级: 已接管监听 socket");
+        tokio::spawn(async move {
+            if let Err(e) = receive_quota(ready, &quota).await {
+                warn!("接收旧进程的配额用量失败: {:#}", e);
+            }
+        });
+    }
+    #[cfg(not(unix))]
+    let _ = quota;
+}
+
+/// 读取旧进程退出时写入的用量（旧进程退出即 EOF）
+#[cfg(unix)]
+async fn receive_quota(ready: UnixStream, quota: &QuotaTracker) -> Result<()> {
+    ready.set_nonblocking(true)?;
+    let mut ready = tokio::net::UnixStream::from_std(ready)?;
+    let mut content = String::new();
+    ready.read_to_string(&mut content).await?;
+    if content.is_empty() {
+        return Ok(());
+    }
+    quota.merge_handoff(&content)?;
+    info!("平滑升级: 已合并旧进程交接后的配额用量");
+    Ok(())
+}
+
+/// 交接后旧进程退出前，把新增的配额用量交给新进程
+pub async fn hand_over_quota(quota: &QuotaTracker) {
+    #[cfg(unix)]
+    {
+        let Some(mut successor) = SUCCESSOR.lock().unwrap().take() else {
+            return;
+        };
+        let result = async {
+            successor.write_all(quota.handoff()?.as_bytes()).await?;
+            successor.shutdown().await?;
+            anyhow::Ok(())
+        };
+        match tokio::time::timeout(READY_TIMEOUT, result).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => warn!("交出配额用量失败，交接后的用量丢失: {:#}", e),
+            Err(_) => warn!("交出配额用量超时，交接后的用量丢失"),
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = quota;
+}
+
+/// 收到 SIGUSR2 时启动新进程，就绪后停止接受新连接
+pub fn spawn(state: AppState, handle: axum_server::Handle) {
+    #[cfg(unix)]
+    tokio::spawn(async move {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        // 启动时记录路径：替换二进制后 /proc/self/exe 指向已删除的旧文件
+        let exe = match std::env::current_exe() {
+            Ok(exe) => exe,
+            Err(e) => {
+                warn!("无法获取可执行文件路径，平滑升级不可用: {}", e);
+                return;
+            }
+        };
+        let Ok(mut usr2) = signal(SignalKind::user_defined2()) else {
+            return;
+        };
+        while usr2.recv().await.is_some() {
+            if state.health.is_draining() {
+                warn!("收到 SIGUSR2，但服务正在退出，忽略");
+                continue;
+            }
+            info!("收到 SIGUSR2，启动新进程: {}", exe.display());
+            match start(&exe, &state).await {
+                Ok(pid) => {
+                    info!("新进程 {} 已就绪", pid);
+                    HANDOFF.send_replace(true);
+                    health::handoff(&state, &handle);
+                    return;
+                }
+                Err(e) => warn!("平滑升级失败，继续由当前进程服务: {:#}", e),
+            }
+        }
+    });
+    #[cfg(not(unix))]
+    let _ = (state, handle);
+}
+
+/// 启动新进程并等待其就绪，返回 pid
+#[cfg(unix)]
+async fn start(exe: &Path, state: &AppState) -> Result<u32> {
+    // 写文件 + rename 是同步 IO，放到阻塞线程池
+    let quota = state.quota.clone();
+    tokio::task::spawn_blocking(move || quota.save_for_upgrade())
+        .await?
+        .context("保存配额用量失败")?;
+
+    let (ours, theirs) = UnixStream::pair()?;
+    let listeners: Vec<(Role, RawFd)> = LISTENERS.lock().unwrap().iter().map(|(r, fd)| (*r, fd.as_raw_fd())).collect();
+    let fds: Vec<String> = listeners.iter().map(|(r, fd)| format!("{}:{}", r.as_str(), fd)).collect();
+    let mut keep: Vec<RawFd> = listeners.iter().map(|(_, fd)| *fd).collect();
+    keep.push(theirs.as_raw_fd());
+
+    let mut cmd = tokio::process::Command::new(exe);
+    cmd.args(std::env::args_os().skip(1))
+        .env(FDS_ENV, fds.join(","))
+        .env(READY_ENV, theirs.as_raw_fd().to_string());
+    // SAFETY: fork 之后只调用 async-signal-safe 的 fcntl，不分配内存
+    unsafe {
+        cmd.pre_exec(move || {
+            for &fd in &keep {
+                set_cloexec(fd, false);
+            }
+            Ok(())
+        });
+    }
+    let mut child = cmd.spawn().context("启动新进程失败")?;
+    // 只保留新进程一端，新进程退出时读到 EOF
+    drop(theirs);
+    let pid = child.id().unwrap_or_default();
+
+    ours.set_nonblocking(true)?;
+    let mut ours = tokio::net::UnixStream::from_std(ours)?;
+    let mut buf = [0u8; 1];
+    match tokio::time::timeout(READY_TIMEOUT, ours.read(&mut buf)).await {
+        Ok(Ok(1)) => {
+            *SUCCESSOR.lock().unwrap() = Some(ours);
+            Ok(pid)
+        }
+        Ok(_) => bail!("新进程 {} 未就绪即退出", pid),
+        Err(_) => {
+            let _ = child.start_kill();
+            bail!("新进程 {} 在 {:?} 内未就绪", pid, READY_TIMEOUT)
+        }
+    }
+}
+
+#[cfg(unix)]
+fn set_cloexec(fd: RawFd, on: bool) {
+    // SAFETY: 只读写 fd 标志
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFD);
+        if flags >= 0 {
+            let flags = if on { flags | libc::FD_CLOEXEC } else { flags & !libc::FD_CLOEXEC };
+            libc::fcntl(fd, libc::F_SETFD, flags);
+        }
+    }
+}
+
+/// 交接后等待现有会话结束，超时后直接退出
+pub async fn wait_sessions(stats: &Stats, limit: Duration) {
+    let deadline = tokio::time::Instant::now() + limit;
+    let mut ticker = tokio::time::interval(Duration::from_secs(1));
+    let mut last = usize::MAX;
+    loop {
+        ticker.tick().await;
+        let remaining = stats.session_count();
+        if remaining == 0 {
+            return;
+        }
+        if tokio::time::Instant::now() >= deadline {
+            tracing::warn!("等待会话结束超时，断开剩余 {} 个会话", remaining);
+            return;
+        }
+        if remaining != last {
+            tracing::info!("等待 {} 个会话结束...", remaining);
+            last = remaining;
+        }
+    }
+}
@@ -0,0 +1,132 @@
+//! 上游组
+//!
+//! `[[upstreams]]` 把多个等价的后端定义为一组，客户端以 `upstream:<name>` 为目标（可带路径，
+//! 如 `upstream:md/v1/stream`，路径追加到后端地址之后），relay 按 `sticky` 选择后端：
+//!
+//! | sticky | 说明 |
+//! |------|------|
+//! | `none`（默认） | 随机选择 |
+//! | `user` | 按用户名哈希，同一用户总是连接同一后端 |
+//! | `key` | 按客户端请求头 `X-Sticky-Key` 哈希，未提供时按用户名 |
+//!
+//! 使用 rendezvous 哈希，后端增减时只有原本落在变化后端上的客户端会改变后端，有状态的上游会话在重连后仍可续用。
+//! `allowed_targets` 按 `upstream:<name>` 检查；目标改写、主机限流与访问日志按选中的后端（REST 访问日志记录原目标）。
+
+use std::borrow::Cow;
+
+use rand::seq::SliceRandom;
+use sha2::{Digest, Sha256};
+use tracing::debug;
+
+use crate::config::{StickyMode, UpstreamGroup};
+
+/// 客户端指定粘滞键的请求头
+pub const STICKY_KEY_HEADER: &str = "X-Sticky-Key";
+
+/// `upstream:` 目标解析为组内的一个后端，其他目标原样返回；组不存在时返回 (错误码, 说明)
+pub fn resolve<'a>(
+    groups: &[UpstreamGroup],
+    target: &'a str,
+    user: &str,
+    key: Option<&str>,
+) -> Result<Cow<'a, str>, (&'static str, &'static str)> {
+    let Some(rest) = target.strip_prefix("upstream:") else {
+        return Ok(Cow::Borrowed(target));
+    };
+    let (name, suffix) = rest.find('/').map_or((rest, ""), |i| rest.split_at(i));
+    let group = groups
+        .iter()
+        .find(|g| g.name == name)
+        .ok_or(("UPSTREAM_NOT_FOUND", "上游组不存在"))?;
+    let sticky = match group.sticky {
+        StickyMode::None => None,
+        StickyMode::User => Some(user),
+        StickyMode::Key => Some(key.unwrap_or(user)),
+    };
+    let backend = match sticky {
+        Some(sticky) => group.targets.iter().max_by_key(|backend| weight(sticky, backend)),
+        None => group.targets.choose(&mut rand::thread_rng()),
+    }
+    .ok_or(("UPSTREAM_NOT_FOUND", "上游组不存在"))?;
+    debug!("上游组 {} → {}", name, backend);
+    Ok(Cow::Owned(match suffix {
+        "" => backend.clone(),
+        _ => format!("{}{}", backend.trim_end_matches('/'), suffix),
+    }))
+}
+
+/// rendezvous 哈希权重（跨进程与重启稳定）
+fn weight(key: &str, backend: &str) -> u64 {
+    let digest = Sha256::new()
+        .chain_update(key.as_bytes())
+        .chain_update([0])
+        .chain_update(backend.as_bytes())
+        .finalize();
+    u64::from_be_bytes(digest[..8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn group(sticky: StickyMode, targets: &[&str]) -> UpstreamGroup {
+        UpstreamGroup { name: "md".into(), targets: targets.iter().map(|t| t.to_string()).collect(), sticky }
+    }
+
+    const BACKENDS: [&str; 4] = ["wss://md-1:8443", "wss://md-2:8443", "wss://md-3:8443", "wss://md-4:8443"];
+
+    #[test]
+    fn sticky_is_deterministic() {
+        let groups = [group(StickyMode::User, &BACKENDS)];
+        for user in ["alice", "bob", "carol"] {
+            let first = resolve(&groups, "upstream:md", user, Some("ignored")).unwrap();
+            assert_eq!(resolve(&groups, "upstream:md", user, None).unwrap(), first);
+        }
+
+        let groups = [group(StickyMode::Key, &BACKENDS)];
+        let by_key = resolve(&groups, "upstream:md", "alice", Some("k1")).unwrap();
+        assert_eq!(resolve(&groups, "upstream:md", "bob", Some("k1")).unwrap(), by_key);
+        // 未提供粘滞键时按用户名
+        assert_eq!(
+            resolve(&groups, "upstream:md", "alice", None).unwrap(),
+            resolve(&[group(StickyMode::User, &BACKENDS)], "upstream:md", "alice", None).unwrap()
+        );
+    }
+
+    #[test]
+    fn removing_backend_only_remaps_its_clients() {
+        let full = [group(StickyMode::User, &BACKENDS)];
+        let reduced = [group(StickyMode::User, &[BACKENDS[0], BACKENDS[1], BACKENDS[3]])];
+        let mut moved = 0;
+        for i in 0..200 {
+            let user = format!("user{}", i);
+            let before = resolve(&full, "upstream:md", &user, None).unwrap();
+            let after = resolve(&reduced, "upstream:md", &user, None).unwrap();
+            if before == BACKENDS[2] {
+                moved += 1;
+                assert_ne!(after, BACKENDS[2]);
+            } else {
+                assert_eq!(before, after, "{}", user);
+            }
+        }
+        assert!(moved > 0);
+    }
+
+    #[test]
+    fn path_suffix_is_appended() {
+        let groups = [group(StickyMode::None, &["wss://md-1:8443/"])];
+        assert_eq!(resolve(&groups, "upstream:md/v1/stream?x=1", "alice", None).unwrap(), "wss://md-1:8443/v1/stream?x=1");
+        assert_eq!(resolve(&groups, "upstream:md", "alice", None).unwrap(), "wss://md-1:8443/");
+        let groups = [group(StickyMode::None, &["wss://md-1:8443/base"])];
+        assert_eq!(resolve(&groups, "upstream:md/v1", "alice", None).unwrap(), "wss://md-1:8443/base/v1");
+    }
+
+    #[test]
+    fn unknown_group_and_plain_targets() {
+        let groups = [group(StickyMode::None, &BACKENDS)];
+        assert_eq!(resolve(&groups, "upstream:other", "alice", None).unwrap_err().0, "UPSTREAM_NOT_FOUND");
+        assert_eq!(resolve(&groups, "upstream:mdx/v1", "alice", None).unwrap_err().0, "UPSTREAM_NOT_FOUND");
+        assert!(matches!(resolve(&groups, "wss://a.example.com/ws", "alice", None), Ok(Cow::Borrowed("wss://a.example.com/ws"))));
+        assert!(BACKENDS.contains(&resolve(&groups, "upstream:md", "alice", None).unwrap().as_ref()));
+    }
+}
@@ -0,0 +1,159 @@
+//! 用量导出（`usage export` 子命令）
+//!
+//! 读取 `[access_log]` 目录下的访问日志（JSON 与 text 格式均可），按用户汇总指定日期范围内的用量，
+//! 输出 CSV 或 JSON，用于对账开票，无需运行中的实例或管理 API：
+//!
+//! | 字段 | 说明 |
+//! |------|------|
+//! | `sessions` | WS 类会话数（ws / mux / pubsub / socks5） |
+//! | `requests` | REST 请求数 |
+//! | `bytes_up` / `bytes_down` | 上行 / 下行字节数 |
+//! | `duration_ms` | WS 类会话时长合计 |
+//!
+//! 日期按记录的 `ts`（UTC）判断，`--from` 与 `--to` 均包含在内。
+
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufRead, BufReader},
+};
+
+use anyhow::{ensure, Context, Result};
+use chrono::NaiveDate;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+
+/// 输出格式
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Format {
+    Csv,
+    Json,
+}
+
+/// 一个用户的用量合计
+#[derive(Default, Serialize)]
+struct Usage {
+    user: String,
+    sessions: u64,
+    requests: u64,
+    bytes_up: u64,
+    bytes_down: u64,
+    duration_ms: u64,
+}
+
+/// 访问日志中导出需要的字段
+#[derive(Deserialize)]
+struct Record {
+    ts: String,
+    kind: String,
+    user: String,
+    duration_ms: u64,
+    bytes_up: u64,
+    bytes_down: u64,
+}
+
+impl Record {
+    /// JSON 或 text 格式（空格分隔，字段顺序见 `access_log`）的一行
+    fn parse(line: &str) -> Option<Self> {
+        if line.starts_with('{') {
+            return serde_json::from_str(line).ok();
+        }
+        let fields: Vec<&str> = line.split(' ').collect();
+        let [ts, _, kind, user, _, _, _, duration, up, down, _] = fields[..] else {
+            return None;
+        };
+        Some(Self {
+            ts: ts.to_string(),
+            kind: kind.to_string(),
+            user: user.to_string(),
+            duration_ms: duration.parse().ok()?,
+            bytes_up: up.parse().ok()?,
+            bytes_down: down.parse().ok()?,
+        })
+    }
+
+    fn date(&self) -> Option<NaiveDate> {
+        self.ts.get(..10)?.parse().ok()
+    }
+}
+
+/// 汇总并输出到 stdout
+pub fn export(config: &Config, from: NaiveDate, to: NaiveDate, format: Format) -> Result<()> {
+    ensure!(from <= to, "--from 不能晚于 --to");
+    let log = config.access_log.as_ref().context("未配置 [access_log]，没有可导出的用量")?;
+
+    let mut files: Vec<_> = std::fs::read_dir(&log.dir)
+        .with_context(|| format!("无法读取访问日志目录: {}", log.dir))?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| {
+            let name = p.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+            name.starts_with(&format!("{}.", log.prefix)) && name.ends_with(".log")
+        })
+        .collect();
+    files.sort();
+
+    let mut totals: BTreeMap<String, Usage> = BTreeMap::new();
+    let mut skipped = 0;
+    for path in &files {
+        let file = File::open(path).with_context(|| format!("无法打开访问日志: {}", path.display()))?;
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let Some(record) = Record::parse(&line) else {
+                skipped += 1;
+                continue;
+            };
+            if !record.date().is_some_and(|d| from <= d && d <= to) {
+                continue;
+            }
+            let usage = totals.entry(record.user.clone()).or_insert_with(|| Usage {
+                user: record.user.clone(),
+                ..Default::default()
+            });
+            match record.kind.as_str() {
+                "rest" => usage.requests += 1,
+                _ => {
+                    usage.sessions += 1;
+                    usage.duration_ms += record.duration_ms;
+                }
+            }
+            usage.bytes_up += record.bytes_up;
+            usage.bytes_down += record.bytes_down;
+        }
+    }
+    if skipped > 0 {
+        eprintln!("跳过 {} 行无法解析的记录", skipped);
+    }
+
+    let rows: Vec<&Usage> = totals.values().collect();
+    match format {
+        Format::Json => println!("{}", serde_json::to_string_pretty(&rows)?),
+        Format::Csv => {
+            println!("user,sessions,requests,bytes_up,bytes_down,duration_ms");
+            for u in rows {
+                println!(
+                    "{},{},{},{},{},{}",
+                    csv_field(&u.user),
+                    u.sessions,
+                    u.requests,
+                    u.bytes_up,
+                    u.bytes_down,
+                    u.duration_ms
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 含逗号、引号或换行时加引号
+fn csv_field(s: &str) -> String {
+    match s.contains([',', '"', '\n']) {
+        true => format!("\"{}\"", s.replace('"', "\"\"")),
+        false => s.to_string(),
+    }
+}
@@ -0,0 +1,78 @@
+//! SQLite 用户库
+//!
+//! 每个用户一行：name / token 单独成列便于唯一约束，其余字段以 JSON 存于 `data`。
+
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::config::User;
+
+pub struct UserDb {
+    conn: Mutex<Connection>,
+}
+
+impl UserDb {
+    /// 打开（或创建）用户库
+    pub fn open(path: &str) -> Result<Self> {
+        let conn = Connection::open(path).with_context(|| format!("打开用户库失败: {}", path))?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS users (
+                name  TEXT PRIMARY KEY,
+                token TEXT NOT NULL UNIQUE,
+                data  TEXT NOT NULL
+            )",
+        )?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// 读取全部用户
+    pub fn load_all(&self) -> Result<Vec<User>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT data FROM users ORDER BY name")?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        rows.map(|data| Ok(serde_json::from_str(&data?)?)).collect()
+    }
+
+    /// 读取单个用户
+    pub fn get(&self, name: &str) -> Result<Option<User>> {
+        let conn = self.conn.lock().unwrap();
+        let data: Option<String> = conn
+            .query_row("SELECT data FROM users WHERE name = ?1", [name], |row| row.get(0))
+            .optional()?;
+        Ok(match data {
+            Some(d) => Some(serde_json::from_str(&d)?),
+            None => None,
+        })
+    }
+
+    /// 新增用户（重名或 token 重复时报错）
+    pub fn insert(&self, user: &User) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO users (name, token, data) VALUES (?1, ?2, ?3)",
+            params![user.name, user.token.expose(), serde_json::to_string(user)?],
+        )?;
+        Ok(())
+    }
+
+    /// 覆盖已有用户，返回是否存在
+    pub fn update(&self, user: &User) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        let n = conn.execute(
+            "UPDATE users SET token = ?2, data = ?3 WHERE name = ?1",
+            params![user.name, user.token.expose(), serde_json::to_string(user)?],
+        )?;
+        Ok(n > 0)
+    }
+
+    /// 删除用户，返回是否存在
+    pub fn delete(&self, name: &str) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        let n = conn.execute("DELETE FROM users WHERE name = ?1", [name])?;
+        Ok(n > 0)
+    }
+}
@@ -0,0 +1,49 @@
+//! 配置文件监听
+//!
+//! `[reload] watch = true` 时监听配置文件所在目录（兼容先写临时文件再 rename 的原子替换），
+//! 配置文件变化后等待 `debounce_ms` 内不再变化，再按 SIGHUP 相同的流程校验并重新加载；
+//! 新配置无效时保留当前配置并记录错误。
+
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use notify::{RecursiveMode, Watcher};
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+
+use crate::state::AppState;
+
+pub fn spawn(state: AppState, config_path: String, debounce: Duration) -> Result<()> {
+    let path = std::fs::canonicalize(&config_path).with_context(|| format!("无法监听配置文件: {}", config_path))?;
+    let dir = path.parent().map(Path::to_path_buf).unwrap_or_else(|| PathBuf::from("."));
+    let file_name = path.file_name().map(|n| n.to_os_string());
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| match res {
+        // 忽略读取（重新加载本身会读取配置文件）
+        Ok(event) if !event.kind.is_access() && event.paths.iter().any(|p| p.file_name() == file_name.as_deref()) => {
+            let _ = tx.send(());
+        }
+        Ok(_) => {}
+        Err(e) => warn!("配置文件监听出错: {}", e),
+    })?;
+    watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+    info!("监听配置文件: {}", path.display());
+
+    tokio::spawn(async move {
+        // watcher 随任务存活
+        let _watcher = watcher;
+        while rx.recv().await.is_some() {
+            // 去抖：直到 debounce 内没有新事件
+            while let Ok(Some(())) = tokio::time::timeout(debounce, rx.recv()).await {}
+            debug!("配置文件已变化: {}", config_path);
+            if let Err(e) = state.reload(&config_path, "watch").await {
+                warn!("配置文件变化，重新加载失败（保留当前配置）: {:#}", e);
+            }
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,61 @@
+//! WebSocket over HTTP/2（RFC 8441）
+//!
+//! 与 wss 目标通过 ALPN 协商到 h2 后，以扩展 CONNECT（`:protocol = websocket`）建立 WS 流。
+//! 目标未开启 `SETTINGS_ENABLE_CONNECT_PROTOCOL` 或拒绝请求时返回错误，由调用方回退到 HTTP/1.1 升级。
+
+use anyhow::{bail, Result};
+use bytes::Bytes;
+use http_body_util::Empty;
+use hyper::{
+    client::conn::http2, ext::Protocol, header, upgrade::Upgraded, HeaderMap, Method, Request,
+    StatusCode, Version,
+};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tracing::debug;
+
+/// 仅用于 HTTP/1.1 升级的握手头
+const H1_ONLY: [header::HeaderName; 4] = [
+    header::HOST,
+    header::CONNECTION,
+    header::UPGRADE,
+    header::SEC_WEBSOCKET_KEY,
+];
+
+/// 在已协商 h2 的连接上发起扩展 CONNECT，返回 WS 字节流与响应头
+///
+/// `request` 为 HTTP/1.1 形式的 WS 握手请求，其余请求头原样带上。
+pub async fn upgrade<S>(io: S, request: &Request<()>) -> Result<(TokioIo<Upgraded>, HeaderMap)>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut sender, conn) = http2::handshake(TokioExecutor::new(), TokioIo::new(io)).await?;
+    // 连接在 WS 流结束、SendRequest 释放后自行关闭
+    tokio::spawn(async move {
+        if let Err(e) = conn.await {
+            debug!("HTTP/2 连接结束: {}", e);
+        }
+    });
+
+    let uri = request.uri();
+    let authority = uri.authority().map(|a| a.as_str()).unwrap_or_default();
+    let path = uri.path_and_query().map(|p| p.as_str()).unwrap_or("/");
+    let mut req = Request::builder()
+        .method(Method::CONNECT)
+        .version(Version::HTTP_2)
+        .uri(format!("https://{}{}", authority, path))
+        .body(Empty::<Bytes>::new())?;
+    for (name, value) in request.headers() {
+        if !H1_ONLY.contains(name) {
+            req.headers_mut().append(name, value.clone());
+        }
+    }
+    req.extensions_mut().insert(Protocol::from_static("websocket"));
+
+    let resp = sender.send_request(req).await?;
+    if resp.status() != StatusCode::OK {
+        bail!("扩展 CONNECT 被拒绝: {}", resp.status());
+    }
+    let headers = resp.headers().clone();
+    Ok((TokioIo::new(hyper::upgrade::on(resp).await?), headers))
+}